@@ -0,0 +1,167 @@
+//! Addresses (phone numbers and alphanumeric senders) along with their
+//! Type Of Number and Numbering Plan Indicator, and rules for bringing
+//! the many ways of writing the same number into one canonical form.
+//! See https://smpp.org/SMPP_v3_4_Issue1_2.pdf sections 5.2.5 and 5.2.6.
+
+use clap::Clap;
+use std::fmt;
+
+use crate::logging;
+
+pub const TON_UNKNOWN: u8 = 0;
+pub const TON_INTERNATIONAL: u8 = 1;
+pub const TON_NATIONAL: u8 = 2;
+
+pub const NPI_UNKNOWN: u8 = 0;
+pub const NPI_ISDN: u8 = 1;
+
+/// An address as it appears in a PDU: source_addr or destination_addr
+/// together with the matching _ton and _npi fields.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Address {
+    pub ton: u8,
+    pub npi: u8,
+    pub value: String,
+}
+
+impl Address {
+    pub fn new(ton: u8, npi: u8, value: &str) -> Self {
+        Self {
+            ton,
+            npi,
+            value: String::from(value),
+        }
+    }
+
+    /// As it may appear in logs and errors, redacted by --log-redaction
+    pub fn logged(&self) -> String {
+        format!(
+            "{} (TON={} NPI={})",
+            logging::redact(&self.value, logging::redaction()),
+            self.ton,
+            self.npi
+        )
+    }
+
+    /// An address whose TON and NPI we don't know
+    pub fn unknown(value: &str) -> Self {
+        Self::new(TON_UNKNOWN, NPI_UNKNOWN, value)
+    }
+
+    /// Rewrite this address in canonical form, if we can work out which
+    /// E.164 number it is.  Numbers become TON=1 NPI=1 with the digits
+    /// only (no "+"), so "+447700900123" (TON=1), "447700900123" (TON=1)
+    /// and "07700900123" (TON=0 NPI=1, with country_code 44) all
+    /// normalize to the same Address.  Anything we don't understand,
+    /// e.g. alphanumeric senders, is returned unchanged.
+    pub fn normalize(&self, rules: &NormalizationRules) -> Self {
+        let value: String = self
+            .value
+            .chars()
+            .filter(|c| !matches!(c, ' ' | '-' | '(' | ')' | '.'))
+            .collect();
+
+        let international = match self.ton {
+            TON_INTERNATIONAL => Some(
+                value
+                    .strip_prefix('+')
+                    .or_else(|| rules.strip_international_prefix(&value))
+                    .unwrap_or(&value)
+                    .to_string(),
+            ),
+            TON_NATIONAL => rules.national_to_international(&value),
+            TON_UNKNOWN if self.npi == NPI_UNKNOWN || self.npi == NPI_ISDN => {
+                if let Some(digits) = value
+                    .strip_prefix('+')
+                    .or_else(|| rules.strip_international_prefix(&value))
+                {
+                    Some(digits.to_string())
+                } else if rules.has_national_prefix(&value) {
+                    rules.national_to_international(&value)
+                } else {
+                    // Already includes the country code
+                    Some(value.clone())
+                }
+            }
+            _ => None,
+        };
+
+        match international {
+            Some(digits)
+                if !digits.is_empty()
+                    && digits.chars().all(|c| c.is_ascii_digit()) =>
+            {
+                Self {
+                    ton: TON_INTERNATIONAL,
+                    npi: NPI_ISDN,
+                    value: digits,
+                }
+            }
+            _ => self.clone(),
+        }
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (TON={} NPI={})", self.value, self.ton, self.npi)
+    }
+}
+
+/// How numbers are written in the country this SMSC serves
+#[derive(Clap, Clone, Debug)]
+pub struct NormalizationRules {
+    /// Country calling code added to national numbers, e.g. 44.  If not
+    /// set, national numbers are left as they are.
+    #[clap(long, env = "COUNTRY_CODE")]
+    pub country_code: Option<String>,
+
+    /// Prefix dialled before a national number, e.g. the 0 in 07700900123
+    #[clap(long, default_value = "0", env = "NATIONAL_PREFIX")]
+    pub national_prefix: String,
+
+    /// Prefix dialled before an international number, e.g. the 00 in
+    /// 00447700900123
+    #[clap(long, default_value = "00", env = "INTERNATIONAL_PREFIX")]
+    pub international_prefix: String,
+}
+
+impl Default for NormalizationRules {
+    fn default() -> Self {
+        Self {
+            country_code: None,
+            national_prefix: String::from("0"),
+            international_prefix: String::from("00"),
+        }
+    }
+}
+
+impl NormalizationRules {
+    fn strip_international_prefix<'a>(
+        &self,
+        value: &'a str,
+    ) -> Option<&'a str> {
+        if self.international_prefix.is_empty() {
+            None
+        } else {
+            value.strip_prefix(self.international_prefix.as_str())
+        }
+    }
+
+    fn has_national_prefix(&self, value: &str) -> bool {
+        !self.national_prefix.is_empty()
+            && value.starts_with(self.national_prefix.as_str())
+    }
+
+    /// Turn a national number into an international one, or None if we
+    /// don't know our country code.
+    fn national_to_international(&self, value: &str) -> Option<String> {
+        let country_code = self.country_code.as_ref()?;
+        let subscriber = if self.has_national_prefix(value) {
+            &value[self.national_prefix.len()..]
+        } else {
+            value
+        };
+        Some(format!("{}{}", country_code, subscriber))
+    }
+}
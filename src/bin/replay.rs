@@ -0,0 +1,30 @@
+use clap::Clap;
+use log::*;
+use std::process;
+
+use smpp::logging;
+use smpp::logging::LogFormat;
+use smpp::replay;
+use smpp::replay::ReplayConfig;
+
+fn main() {
+    let replay_config = ReplayConfig::parse();
+
+    logging::init(&replay_config.log_level, LogFormat::Text);
+
+    match replay::run(replay_config) {
+        Ok(differences) if differences.is_empty() => {
+            println!("No differences")
+        }
+        Ok(differences) => {
+            for difference in &differences {
+                println!("{}", difference);
+            }
+            process::exit(1);
+        }
+        Err(e) => {
+            error!("Error replaying: {}", e);
+            process::exit(2);
+        }
+    };
+}
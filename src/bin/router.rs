@@ -0,0 +1,27 @@
+use log::*;
+use std::process;
+
+use smpp::logging;
+use smpp::router;
+use smpp::router::RouterConfig;
+use smpp::smsc::ConfigError;
+
+fn main() {
+    let router_config = RouterConfig::load().unwrap_or_else(|e| match e {
+        ConfigError::Args(e) => e.exit(),
+        e => {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+    });
+
+    logging::init(&router_config.smsc.log_level, router_config.smsc.log_format);
+    logging::set_redaction(router_config.smsc.log_redaction);
+
+    let res = router::run(router_config);
+
+    match res {
+        Ok(_) => info!("Done"),
+        Err(e) => error!("Error launching: {}", e),
+    };
+}
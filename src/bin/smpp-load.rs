@@ -0,0 +1,19 @@
+use clap::Clap;
+use log::*;
+use std::process;
+
+use smpp::load;
+use smpp::load::LoadConfig;
+use smpp::logging;
+use smpp::logging::LogFormat;
+
+fn main() {
+    let load_config = LoadConfig::parse();
+
+    logging::init(&load_config.log_level, LogFormat::Text);
+
+    if let Err(e) = load::run(load_config) {
+        error!("Error running load test: {}", e);
+        process::exit(2);
+    };
+}
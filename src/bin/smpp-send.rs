@@ -0,0 +1,23 @@
+use clap::Clap;
+use log::*;
+use std::process;
+
+use smpp::logging;
+use smpp::logging::LogFormat;
+use smpp::send;
+use smpp::send::SendConfig;
+
+fn main() {
+    let send_config = SendConfig::parse();
+
+    logging::init(&send_config.log_level, LogFormat::Text);
+
+    match send::run(send_config) {
+        Ok(0) => {}
+        Ok(_) => process::exit(1),
+        Err(e) => {
+            error!("Error sending: {}", e);
+            process::exit(2);
+        }
+    };
+}
@@ -1,18 +1,40 @@
-use clap::Clap;
-use env_logger::Env;
 use log::*;
+use std::path::Path;
+use std::process;
 
-use smpp::examples::smsc_drs_after_1_sec::DrsAfter1Sec;
+use smpp::logging;
+use smpp::script::{Script, Scripted};
+use smpp::simulator::Simulator;
 use smpp::smsc;
-use smpp::smsc::SmscConfig;
+use smpp::smsc::{ConfigError, SmscConfig};
 
 fn main() {
-    let smsc_config = SmscConfig::parse();
+    let smsc_config = SmscConfig::load().unwrap_or_else(|e| match e {
+        ConfigError::Args(e) => e.exit(),
+        e => {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+    });
 
-    env_logger::Builder::from_env(Env::default().default_filter_or("info"))
-        .init();
+    logging::init(&smsc_config.log_level, smsc_config.log_format);
+    logging::set_redaction(smsc_config.log_redaction);
 
-    let res = smsc::run(smsc_config, DrsAfter1Sec::new());
+    let logic = Simulator::new(&smsc_config).unwrap_or_else(|e| {
+        error!("{}", e);
+        process::exit(2);
+    });
+
+    let res = match &smsc_config.script {
+        Some(path) => match Script::load(Path::new(path)) {
+            Ok(script) => smsc::run(smsc_config, Scripted::new(script, logic)),
+            Err(e) => {
+                error!("{}", e);
+                process::exit(2);
+            }
+        },
+        None => smsc::run(smsc_config, logic),
+    };
 
     match res {
         Ok(_) => info!("Done"),
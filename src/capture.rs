@@ -0,0 +1,676 @@
+//! Capture of the exact bytes of the PDUs on chosen connections, for
+//! debugging interop problems.  Capture is switched on and off while
+//! running, per ESME or per peer address, and each captured session gets
+//! its own files, either pcap or a hex trace.
+//!
+//! pcap files hold raw IP packets (LINKTYPE_RAW) with made-up TCP headers
+//! around each PDU, so Wireshark reassembles the stream and its SMPP
+//! dissector decodes it.  Use "Decode As..." if the SMSC is not on port
+//! 2775.
+//!
+//! The files are written by a thread of their own, so a slow disk never
+//! holds up a session.
+
+use clap::Clap;
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::extra_pdu::read_header;
+use crate::smpp_connection::EsmeId;
+use crate::smsc::metrics::{command_name, Direction};
+
+const PCAP_MAGIC: u32 = 0xa1b2c3d4;
+const PCAP_SNAPLEN: u32 = 65535;
+const LINKTYPE_RAW: u32 = 101;
+const IPPROTO_TCP: u8 = 6;
+const TCP_PSH_ACK: u8 = 0x18;
+/// PDUs bigger than this are split over several packets
+const MAX_SEGMENT: usize = 65000;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CaptureFormat {
+    /// Packets Wireshark can read
+    Pcap,
+    /// A text file with a hex dump of each PDU
+    Hex,
+}
+
+impl FromStr for CaptureFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pcap" => Ok(CaptureFormat::Pcap),
+            "hex" => Ok(CaptureFormat::Hex),
+            _ => Err(format!("unknown capture format '{}'", s)),
+        }
+    }
+}
+
+/// Where and how to write captures
+#[derive(Clap, Clone, Debug)]
+pub struct CaptureConfig {
+    /// Directory to write PDU captures to.  Capture is switched on for
+    /// chosen ESMEs or peers with the admin API.  If not set, nothing can
+    /// be captured.
+    #[clap(long, env = "CAPTURE_DIR")]
+    pub capture_dir: Option<String>,
+
+    /// "pcap" for files Wireshark can read, or "hex" for a text hex dump
+    #[clap(long, default_value = "pcap", env = "CAPTURE_FORMAT")]
+    pub capture_format: CaptureFormat,
+
+    /// Start a new capture file once one reaches this many bytes
+    #[clap(long, default_value = "10485760", env = "CAPTURE_MAX_FILE_BYTES")]
+    pub capture_max_file_bytes: u64,
+
+    /// Delete the oldest capture files once there are more than this many
+    #[clap(long, default_value = "20", env = "CAPTURE_MAX_FILES")]
+    pub capture_max_files: usize,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        Self {
+            capture_dir: None,
+            capture_format: CaptureFormat::Pcap,
+            capture_max_file_bytes: 10485760,
+            capture_max_files: 20,
+        }
+    }
+}
+
+/// Which sessions to capture.  A session is captured if it matches every
+/// field that is set.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CaptureTarget {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_type: Option<String>,
+    /// An IP address, matching any port, or an IP address and port
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peer: Option<String>,
+}
+
+impl CaptureTarget {
+    /// Every session bound with this system_id
+    pub fn esme(system_id: &str) -> Self {
+        Self {
+            system_id: Some(String::from(system_id)),
+            ..Self::default()
+        }
+    }
+
+    /// Every session from this IP address, or IP address and port
+    pub fn peer(peer: &str) -> Self {
+        Self {
+            peer: Some(String::from(peer)),
+            ..Self::default()
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.system_id.is_none() && self.peer.is_none() {
+            return Err(String::from("a capture needs a system_id or a peer"));
+        }
+        if let Some(peer) = &self.peer {
+            if peer.parse::<SocketAddr>().is_err()
+                && peer.parse::<IpAddr>().is_err()
+            {
+                return Err(format!("peer '{}' is not an IP address", peer));
+            }
+        }
+        Ok(())
+    }
+
+    fn matches(&self, peer: SocketAddr, esme_id: Option<&EsmeId>) -> bool {
+        let esme_matches =
+            |wanted: &Option<String>, actual: fn(&EsmeId) -> &str| {
+                wanted.as_ref().is_none_or(|wanted| {
+                    esme_id.is_some_and(|esme_id| actual(esme_id) == wanted)
+                })
+            };
+        let peer_matches = self.peer.as_ref().is_none_or(|wanted| {
+            match wanted.parse::<SocketAddr>() {
+                Ok(wanted) => wanted == peer,
+                Err(_) => wanted.parse::<IpAddr>() == Ok(peer.ip()),
+            }
+        });
+        esme_matches(&self.system_id, |e| e.system_id.as_str())
+            && esme_matches(&self.system_type, |e| e.system_type.as_str())
+            && peer_matches
+    }
+}
+
+/// Decides which sessions are captured, and keeps the number of capture
+/// files within CaptureConfig::capture_max_files.  One is shared by all
+/// of an Smsc's connections.
+pub struct Capturer {
+    config: Mutex<CaptureConfig>,
+    targets: Mutex<Vec<CaptureTarget>>,
+    /// To the thread that writes the files
+    ops: mpsc::Sender<CaptureOp>,
+}
+
+impl Capturer {
+    pub fn new(config: CaptureConfig) -> Self {
+        let (ops, receiver) = mpsc::channel();
+        thread::Builder::new()
+            .name(String::from("capture"))
+            .spawn(move || CaptureWriter::default().run(receiver))
+            .expect("Failed to start the capture thread");
+        Self {
+            config: Mutex::new(config),
+            targets: Mutex::new(Vec::new()),
+            ops,
+        }
+    }
+
+    /// Use a new config from the next PDU captured
+    pub fn configure(&self, config: CaptureConfig) {
+        *self.config.lock().unwrap() = config;
+    }
+
+    /// Capture sessions matching target, from their next PDU
+    pub fn start(&self, target: CaptureTarget) -> Result<(), String> {
+        if self.config.lock().unwrap().capture_dir.is_none() {
+            return Err(String::from(
+                "capture_dir must be set to capture sessions",
+            ));
+        }
+        target.validate()?;
+        info!("Capturing {:?}", target);
+        let mut targets = self.targets.lock().unwrap();
+        if !targets.contains(&target) {
+            targets.push(target);
+        }
+        Ok(())
+    }
+
+    /// Stop capturing sessions matching target, unless another target
+    /// matches them.  Returns false if target was not being captured.
+    pub fn stop(&self, target: &CaptureTarget) -> bool {
+        let mut targets = self.targets.lock().unwrap();
+        let len = targets.len();
+        targets.retain(|t| t != target);
+        let stopped = targets.len() != len;
+        if stopped {
+            info!("Stopped capturing {:?}", target);
+        }
+        stopped
+    }
+
+    pub fn targets(&self) -> Vec<CaptureTarget> {
+        self.targets.lock().unwrap().clone()
+    }
+
+    /// Wait until everything recorded so far is in the files.  This
+    /// blocks, so is meant for tests and shutdown rather than sessions.
+    pub fn flush(&self) {
+        let (done, wait) = mpsc::channel();
+        if self.ops.send(CaptureOp::Flush(done)).is_ok() {
+            let _ = wait.recv();
+        }
+    }
+
+    /// Write bytes, one PDU received or sent on a session, to the session's
+    /// capture file if the session should be captured.  Opens file if
+    /// needed, and closes it if the session is no longer captured.  The
+    /// writing itself is queued for the capture thread.
+    pub(crate) fn record(
+        &self,
+        file: &mut Option<CaptureFile>,
+        session: &CapturedSession,
+        direction: Direction,
+        bytes: &[u8],
+    ) {
+        let capturing = self
+            .targets
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|t| t.matches(session.peer, session.esme_id.as_ref()));
+        let config = if capturing {
+            self.config.lock().unwrap().clone()
+        } else {
+            CaptureConfig::default()
+        };
+        let dir = match &config.capture_dir {
+            Some(dir) => dir,
+            None => {
+                if file.take().is_some() {
+                    info!("Stopped capturing session {}", session.id);
+                }
+                return;
+            }
+        };
+
+        // Rotate to part 2, 3... once full, keeping the TCP sequence
+        let next = match file {
+            Some(f) if f.written >= config.capture_max_file_bytes => {
+                Some((f.part + 1, f.sequences))
+            }
+            Some(_) => None,
+            None => Some((1, TcpSequences::default())),
+        };
+        if let Some((part, sequences)) = next {
+            // Close the full part first, so it can be deleted
+            file.take();
+            let path = file_path(Path::new(dir), session, &config, part);
+            *file = Some(CaptureFile::create(
+                path,
+                &config,
+                part,
+                sequences,
+                self.ops.clone(),
+            ));
+        }
+        file.as_mut().unwrap().write(session, direction, bytes);
+    }
+}
+
+/// What the capture thread is asked to do
+enum CaptureOp {
+    /// Start a new file, and delete the oldest files beyond max_files
+    Create {
+        path: PathBuf,
+        max_files: usize,
+    },
+    Append {
+        path: PathBuf,
+        bytes: Vec<u8>,
+    },
+    Close(PathBuf),
+    /// Say when everything before this has been written
+    Flush(mpsc::Sender<()>),
+}
+
+/// The file I/O for a Capturer, run on its own thread
+#[derive(Default)]
+struct CaptureWriter {
+    open: HashMap<PathBuf, BufWriter<File>>,
+    /// Files we have written, oldest first
+    files: VecDeque<PathBuf>,
+    max_files: usize,
+}
+
+impl CaptureWriter {
+    /// Until every Capturer and CaptureFile sending ops has gone
+    fn run(mut self, ops: mpsc::Receiver<CaptureOp>) {
+        while let Ok(op) = ops.recv() {
+            let mut flushed = Vec::new();
+            self.apply(op, &mut flushed);
+            // Flush once for everything queued, rather than once per PDU
+            while let Ok(op) = ops.try_recv() {
+                self.apply(op, &mut flushed);
+            }
+            // So the files can be read while we are still capturing
+            self.open.retain(|path, writer| match writer.flush() {
+                Ok(()) => true,
+                Err(e) => {
+                    warn!("Failed to capture to {}: {}", path.display(), e);
+                    false
+                }
+            });
+            for done in flushed {
+                let _ = done.send(());
+            }
+        }
+    }
+
+    fn apply(&mut self, op: CaptureOp, flushed: &mut Vec<mpsc::Sender<()>>) {
+        match op {
+            CaptureOp::Create { path, max_files } => {
+                match create_file(&path) {
+                    Ok(writer) => {
+                        info!("Capturing to {}", path.display());
+                        self.open.insert(path.clone(), writer);
+                        self.files.push_back(path);
+                    }
+                    Err(e) => {
+                        warn!("Failed to capture to {}: {}", path.display(), e)
+                    }
+                }
+                self.max_files = max_files;
+                self.keep_files();
+            }
+            CaptureOp::Append { path, bytes } => {
+                if let Some(writer) = self.open.get_mut(&path) {
+                    if let Err(e) = writer.write_all(&bytes) {
+                        warn!("Failed to capture to {}: {}", path.display(), e);
+                        self.open.remove(&path);
+                    }
+                }
+            }
+            CaptureOp::Close(path) => {
+                if let Some(mut writer) = self.open.remove(&path) {
+                    if let Err(e) = writer.flush() {
+                        warn!("Failed to capture to {}: {}", path.display(), e);
+                    }
+                }
+                self.keep_files();
+            }
+            CaptureOp::Flush(done) => flushed.push(done),
+        }
+    }
+
+    /// Delete the oldest files beyond max_files.  Files still open for a
+    /// session are skipped, and deleted once closed if still too many.
+    fn keep_files(&mut self) {
+        while self.files.len() > self.max_files {
+            let open = &self.open;
+            let oldest =
+                match self.files.iter().position(|f| !open.contains_key(f)) {
+                    Some(i) => self.files.remove(i).unwrap(),
+                    None => break,
+                };
+            if let Err(e) = fs::remove_file(&oldest) {
+                if e.kind() != io::ErrorKind::NotFound {
+                    warn!("Failed to delete {}: {}", oldest.display(), e);
+                }
+            }
+        }
+    }
+}
+
+fn create_file(path: &Path) -> io::Result<BufWriter<File>> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    Ok(BufWriter::new(File::create(path)?))
+}
+
+/// What a Capturer needs to know about a session
+pub(crate) struct CapturedSession {
+    pub id: u64,
+    pub started: SystemTime,
+    pub local: SocketAddr,
+    pub peer: SocketAddr,
+    pub esme_id: Option<EsmeId>,
+}
+
+/// e.g. acme-1634567890-s12-001.pcap, or 10.0.0.1_5000-... if not bound
+fn file_path(
+    dir: &Path,
+    session: &CapturedSession,
+    config: &CaptureConfig,
+    part: u32,
+) -> PathBuf {
+    let label = match &session.esme_id {
+        Some(esme_id) => esme_id.system_id.to_string(),
+        None => format!("{}_{}", session.peer.ip(), session.peer.port()),
+    };
+    let label: String = label
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let extension = match config.capture_format {
+        CaptureFormat::Pcap => "pcap",
+        CaptureFormat::Hex => "txt",
+    };
+    dir.join(format!(
+        "{}-{}-s{}-{:03}.{}",
+        label,
+        unix_time(session.started).as_secs(),
+        session.id,
+        part,
+        extension
+    ))
+}
+
+/// One file of a session's capture.  Its bytes are sent to the capture
+/// thread, which closes the file when this is dropped.
+pub(crate) struct CaptureFile {
+    path: PathBuf,
+    format: CaptureFormat,
+    written: u64,
+    part: u32,
+    sequences: TcpSequences,
+    ops: mpsc::Sender<CaptureOp>,
+}
+
+/// The next TCP sequence number in each direction, for the made-up TCP
+/// headers in pcap files
+#[derive(Clone, Copy)]
+struct TcpSequences {
+    from_peer: u32,
+    to_peer: u32,
+}
+
+impl Default for TcpSequences {
+    fn default() -> Self {
+        Self {
+            from_peer: 1,
+            to_peer: 1,
+        }
+    }
+}
+
+impl CaptureFile {
+    fn create(
+        path: PathBuf,
+        config: &CaptureConfig,
+        part: u32,
+        sequences: TcpSequences,
+        ops: mpsc::Sender<CaptureOp>,
+    ) -> Self {
+        let _ = ops.send(CaptureOp::Create {
+            path: path.clone(),
+            max_files: config.capture_max_files,
+        });
+        let mut file = Self {
+            path,
+            format: config.capture_format,
+            written: 0,
+            part,
+            sequences,
+            ops,
+        };
+        if file.format == CaptureFormat::Pcap {
+            let mut header = Vec::with_capacity(24);
+            header.extend(&PCAP_MAGIC.to_le_bytes());
+            header.extend(&2u16.to_le_bytes());
+            header.extend(&4u16.to_le_bytes());
+            header.extend(&0i32.to_le_bytes());
+            header.extend(&0u32.to_le_bytes());
+            header.extend(&PCAP_SNAPLEN.to_le_bytes());
+            header.extend(&LINKTYPE_RAW.to_le_bytes());
+            file.append(header);
+        }
+        file
+    }
+
+    fn write(
+        &mut self,
+        session: &CapturedSession,
+        direction: Direction,
+        bytes: &[u8],
+    ) {
+        let now = unix_time(SystemTime::now());
+        let record = match self.format {
+            CaptureFormat::Pcap => pcap_records(
+                now,
+                session,
+                direction,
+                bytes,
+                &mut self.sequences,
+            ),
+            CaptureFormat::Hex => hex_record(now, session, direction, bytes),
+        };
+        self.append(record);
+    }
+
+    fn append(&mut self, bytes: Vec<u8>) {
+        self.written += bytes.len() as u64;
+        let _ = self.ops.send(CaptureOp::Append {
+            path: self.path.clone(),
+            bytes,
+        });
+    }
+}
+
+impl Drop for CaptureFile {
+    fn drop(&mut self) {
+        let _ = self.ops.send(CaptureOp::Close(self.path.clone()));
+    }
+}
+
+fn unix_time(time: SystemTime) -> Duration {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default()
+}
+
+/// Raw IP packets (IPv4, or IPv6 if either end is) carrying bytes in
+/// TCP segments, each with its pcap record header
+fn pcap_records(
+    now: Duration,
+    session: &CapturedSession,
+    direction: Direction,
+    bytes: &[u8],
+    sequences: &mut TcpSequences,
+) -> Vec<u8> {
+    let (from, to) = match direction {
+        Direction::In => (session.peer, session.local),
+        Direction::Out => (session.local, session.peer),
+    };
+    let mut records = Vec::new();
+    for segment in bytes.chunks(MAX_SEGMENT) {
+        let (seq, ack) = match direction {
+            Direction::In => (&mut sequences.from_peer, sequences.to_peer),
+            Direction::Out => (&mut sequences.to_peer, sequences.from_peer),
+        };
+        let mut tcp = Vec::with_capacity(20 + segment.len());
+        tcp.extend(&from.port().to_be_bytes());
+        tcp.extend(&to.port().to_be_bytes());
+        tcp.extend(&seq.to_be_bytes());
+        tcp.extend(&ack.to_be_bytes());
+        // Header length of 5 words, PSH and ACK, a full window, and no
+        // checksum (Wireshark does not check it by default)
+        tcp.extend(&[5 << 4, TCP_PSH_ACK, 0xff, 0xff, 0, 0, 0, 0]);
+        tcp.extend(segment);
+        *seq = seq.wrapping_add(segment.len() as u32);
+
+        let packet = ip_packet(from.ip(), to.ip(), &tcp);
+        records.extend(&(now.as_secs() as u32).to_le_bytes());
+        records.extend(&now.subsec_micros().to_le_bytes());
+        records.extend(&(packet.len() as u32).to_le_bytes());
+        records.extend(&(packet.len() as u32).to_le_bytes());
+        records.extend(packet);
+    }
+    records
+}
+
+fn ip_packet(from: IpAddr, to: IpAddr, tcp: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(40 + tcp.len());
+    match (from, to) {
+        (IpAddr::V4(from), IpAddr::V4(to)) => {
+            packet.extend(&[0x45, 0]);
+            packet.extend(&((20 + tcp.len()) as u16).to_be_bytes());
+            // No ID, Don't Fragment, TTL 64
+            packet.extend(&[0, 0, 0x40, 0, 64, IPPROTO_TCP, 0, 0]);
+            packet.extend(&from.octets());
+            packet.extend(&to.octets());
+            let checksum = ipv4_checksum(&packet);
+            packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+        }
+        _ => {
+            let v6 = |ip: IpAddr| match ip {
+                IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+                IpAddr::V6(ip) => ip,
+            };
+            packet.extend(&[0x60, 0, 0, 0]);
+            packet.extend(&(tcp.len() as u16).to_be_bytes());
+            packet.extend(&[IPPROTO_TCP, 64]);
+            packet.extend(&v6(from).octets());
+            packet.extend(&v6(to).octets());
+        }
+    }
+    packet.extend(tcp);
+    packet
+}
+
+fn ipv4_checksum(header: &[u8]) -> u16 {
+    let mut sum: u32 = header
+        .chunks(2)
+        .map(|pair| u32::from(u16::from_be_bytes([pair[0], pair[1]])))
+        .sum();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+fn hex_record(
+    now: Duration,
+    session: &CapturedSession,
+    direction: Direction,
+    bytes: &[u8],
+) -> Vec<u8> {
+    let (arrow, from, to) = match direction {
+        Direction::In => ("<=", session.peer, session.local),
+        Direction::Out => ("=>", session.local, session.peer),
+    };
+    let command = read_header(bytes)
+        .map(|(command_id, command_status, sequence_number)| {
+            format!(
+                "{} command_status=0x{:08x} sequence_number={}",
+                command_name(command_id),
+                command_status,
+                sequence_number
+            )
+        })
+        .unwrap_or_else(|| String::from("(no header)"));
+    let mut text = format!(
+        "{}.{:06} {} {} -> {} {} ({} bytes)\n",
+        now.as_secs(),
+        now.subsec_micros(),
+        arrow,
+        from,
+        to,
+        command,
+        bytes.len()
+    );
+    for (i, line) in bytes.chunks(16).enumerate() {
+        let hex: Vec<String> =
+            line.iter().map(|b| format!("{:02x}", b)).collect();
+        let ascii: String = line
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        text.push_str(&format!(
+            "  {:04x}  {:<47}  |{}|\n",
+            i * 16,
+            hex.join(" "),
+            ascii
+        ));
+    }
+    text.push('\n');
+    text.into_bytes()
+}
+
+/// The address to use for the SMSC end when the socket's is unknown
+pub(crate) fn unknown_local_addr() -> SocketAddr {
+    SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0)
+}
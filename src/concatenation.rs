@@ -0,0 +1,150 @@
+//! Reading and writing the information that ties together the parts of a
+//! concatenated (long) message.  Parts are identified either by a User
+//! Data Header at the start of short_message, or by the sar_* TLVs.
+//! See https://smpp.org/SMPP_v3_4_Issue1_2.pdf sections 5.2.12 and 5.3.2.22-24
+//! and 3GPP TS 23.040 section 9.2.3.24.
+
+use smpp_pdu::pdu::data::sm_data::SmData;
+use smpp_pdu::pdu::tlvs::KnownTlvTag;
+
+/// esm_class bit indicating that short_message starts with a UDH
+pub const ESM_CLASS_UDHI: u8 = 0b0100_0000;
+
+/// UDH Information Element: concatenated message, 8-bit reference
+pub const IEI_CONCAT_8_BIT: u8 = 0x00;
+
+/// UDH Information Element: concatenated message, 16-bit reference
+pub const IEI_CONCAT_16_BIT: u8 = 0x08;
+
+/// Where a ConcatInfo was found
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ConcatSource {
+    Udh8Bit,
+    Udh16Bit,
+    Sar,
+}
+
+/// Identifies one part of a concatenated message
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ConcatInfo {
+    pub source: ConcatSource,
+    /// Reference shared by all parts of the same message.  8-bit references
+    /// are stored in the low byte.
+    pub reference: u16,
+    pub total_parts: u8,
+    /// 1-based position of this part within the message
+    pub part_number: u8,
+    /// The number of bytes at the start of short_message that make up the
+    /// UDH (including the UDHL byte).  Zero for SAR.
+    pub header_length: usize,
+}
+
+impl ConcatInfo {
+    /// Find concatenation information in a submit_sm or deliver_sm body, if
+    /// it is present and makes sense.  A message that claims to be part 0,
+    /// or part 3 of 2, is treated as not being concatenated at all.
+    pub fn from_sm_data(sm_data: &SmData) -> Option<Self> {
+        let info = if sm_data.esm_class.value & ESM_CLASS_UDHI != 0 {
+            Self::from_udh(&sm_data.short_message.value)
+        } else {
+            Self::from_sar_tlvs(sm_data)
+        };
+        info.filter(|i| i.part_number >= 1 && i.part_number <= i.total_parts)
+    }
+
+    fn from_udh(short_message: &[u8]) -> Option<Self> {
+        let udhl = usize::from(*short_message.first()?);
+        let header_length = udhl + 1;
+        let udh = short_message.get(1..header_length)?;
+
+        let mut i = 0;
+        while i + 1 < udh.len() {
+            let iei = udh[i];
+            let iedl = usize::from(udh[i + 1]);
+            let ie = udh.get(i + 2..i + 2 + iedl)?;
+            match (iei, iedl) {
+                (IEI_CONCAT_8_BIT, 3) => {
+                    return Some(Self {
+                        source: ConcatSource::Udh8Bit,
+                        reference: u16::from(ie[0]),
+                        total_parts: ie[1],
+                        part_number: ie[2],
+                        header_length,
+                    })
+                }
+                (IEI_CONCAT_16_BIT, 4) => {
+                    return Some(Self {
+                        source: ConcatSource::Udh16Bit,
+                        reference: u16::from_be_bytes([ie[0], ie[1]]),
+                        total_parts: ie[2],
+                        part_number: ie[3],
+                        header_length,
+                    })
+                }
+                _ => i += 2 + iedl,
+            }
+        }
+        None
+    }
+
+    fn from_sar_tlvs(sm_data: &SmData) -> Option<Self> {
+        let reference = sm_data.tlvs.get(KnownTlvTag::sar_msg_ref_num)?;
+        let total = sm_data.tlvs.get(KnownTlvTag::sar_total_segments)?;
+        let seqnum = sm_data.tlvs.get(KnownTlvTag::sar_segment_seqnum)?;
+        if reference.value.len() != 2
+            || total.value.len() != 1
+            || seqnum.value.len() != 1
+        {
+            return None;
+        }
+        Some(Self {
+            source: ConcatSource::Sar,
+            reference: u16::from_be_bytes([
+                reference.value[0],
+                reference.value[1],
+            ]),
+            total_parts: total.value[0],
+            part_number: seqnum.value[0],
+            header_length: 0,
+        })
+    }
+
+    /// The user data of this part, without any UDH
+    pub fn user_data<'a>(&self, short_message: &'a [u8]) -> &'a [u8] {
+        short_message.get(self.header_length..).unwrap_or(&[])
+    }
+}
+
+/// Build a UDH containing only a concatenation Information Element.
+/// Sar parts carry this information in TLVs instead, so for Sar the UDH
+/// is empty.
+pub fn concat_udh(
+    source: ConcatSource,
+    reference: u16,
+    total_parts: u8,
+    part_number: u8,
+) -> Vec<u8> {
+    match source {
+        ConcatSource::Udh8Bit => vec![
+            0x05,
+            IEI_CONCAT_8_BIT,
+            0x03,
+            reference as u8,
+            total_parts,
+            part_number,
+        ],
+        ConcatSource::Udh16Bit => {
+            let [ref_hi, ref_lo] = reference.to_be_bytes();
+            vec![
+                0x06,
+                IEI_CONCAT_16_BIT,
+                0x04,
+                ref_hi,
+                ref_lo,
+                total_parts,
+                part_number,
+            ]
+        }
+        ConcatSource::Sar => Vec::new(),
+    }
+}
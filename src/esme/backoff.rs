@@ -0,0 +1,39 @@
+use rand::Rng;
+use std::time::Duration;
+
+/// Delays between reconnection attempts.  Each delay is chosen at random
+/// between half and all of a ceiling that doubles after every attempt, up
+/// to max_delay, so that many clients disconnected at once don't all
+/// reconnect at the same moment.
+pub struct Backoff {
+    initial_delay: Duration,
+    max_delay: Duration,
+    attempt: u32,
+}
+
+impl Backoff {
+    pub fn new(initial_delay: Duration, max_delay: Duration) -> Self {
+        Self {
+            initial_delay,
+            max_delay,
+            attempt: 0,
+        }
+    }
+
+    pub fn next_delay(&mut self) -> Duration {
+        let ceiling = self
+            .initial_delay
+            .checked_mul(1 << self.attempt.min(31))
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+        self.attempt = self.attempt.saturating_add(1);
+
+        let half = ceiling / 2;
+        half + rand::thread_rng().gen_range(Duration::ZERO..=ceiling - half)
+    }
+
+    /// Start again from initial_delay, e.g. after we connected successfully
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
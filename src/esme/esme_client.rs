@@ -0,0 +1,694 @@
+use log::*;
+use smpp_pdu::pdu::data::bind_data::BindData;
+use smpp_pdu::pdu::{
+    EnquireLinkPdu, EnquireLinkRespPdu, GenericNackPdu, Pdu, PduBody,
+    PduParseError, PduStatus, SubmitSmPdu,
+};
+use std::collections::HashMap;
+use std::error;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::{oneshot, watch, Mutex, Notify, Semaphore};
+use tokio::task::JoinHandle;
+use tokio::time::{self, sleep};
+use tracing::Instrument;
+
+use crate::esme::{
+    Backoff, BindType, EsmeConfig, EsmeLogic, UnacknowledgedPolicy,
+};
+use crate::extra_pdu::{AnyPdu, ExtraPdu, ExtraPduBody};
+use crate::logging::{pdu_span, LoggedPdu};
+use crate::sm_fields::SmFields;
+use crate::smpp_connection::SmppConnection;
+
+/// The SMPP version we speak
+const INTERFACE_VERSION: u8 = 0x34;
+
+#[derive(Debug)]
+pub enum EsmeError {
+    /// The SMSC rejected our bind with this command_status
+    BindFailed(u32),
+    /// The SMSC responded to a request with this command_status
+    ErrorResponse(u32),
+    /// The SMSC responded with a PDU of the wrong type
+    UnexpectedResponse(u32),
+    /// The connection closed before we got a response
+    ConnectionClosed,
+    /// No response arrived within response_timeout_secs, or we could not
+    /// connect and bind within connect_timeout_secs
+    Timeout,
+    IoError(io::Error),
+    PduParseError(PduParseError),
+}
+
+impl From<io::Error> for EsmeError {
+    fn from(e: io::Error) -> Self {
+        EsmeError::IoError(e)
+    }
+}
+
+impl From<PduParseError> for EsmeError {
+    fn from(e: PduParseError) -> Self {
+        EsmeError::PduParseError(e)
+    }
+}
+
+impl Display for EsmeError {
+    fn fmt(
+        &self,
+        formatter: &mut Formatter,
+    ) -> std::result::Result<(), std::fmt::Error> {
+        let s = match self {
+            EsmeError::BindFailed(status) => {
+                format!("Bind failed with command_status={:#010X}", status)
+            }
+            EsmeError::ErrorResponse(status) => {
+                format!("Error response with command_status={:#010X}", status)
+            }
+            EsmeError::UnexpectedResponse(command_id) => format!(
+                "Unexpected response type (command_id={:#010X})",
+                command_id
+            ),
+            EsmeError::ConnectionClosed => {
+                String::from("Connection closed while awaiting response")
+            }
+            EsmeError::Timeout => String::from("Timed out awaiting response"),
+            EsmeError::IoError(e) => e.to_string(),
+            EsmeError::PduParseError(e) => e.to_string(),
+        };
+        formatter.write_str(&s)
+    }
+}
+
+impl error::Error for EsmeError {}
+
+/// The state of an EsmeClient's link to the SMSC
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ConnectionState {
+    /// Connected and bound: requests can be sent
+    Bound,
+    /// The connection was lost, and we are waiting before reconnecting
+    Disconnected,
+    /// Trying to connect and bind
+    Connecting,
+    /// unbind was called, so we will not reconnect
+    Unbound,
+}
+
+/// A link to an SMSC, bound as a transmitter, receiver or transceiver.
+/// Requests may be made concurrently from several tasks: each one waits
+/// for its own response.  Incoming deliver_sm PDUs are passed to the
+/// EsmeLogic, and we send enquire_link regularly.  If the connection is
+/// lost, or enquire_link gets no response, we reconnect and bind again
+/// with the same credentials, waiting longer after each failed attempt.
+pub struct EsmeClient {
+    inner: Arc<ClientInner>,
+    supervisor: JoinHandle<()>,
+}
+
+impl EsmeClient {
+    /// Connect to the SMSC and bind.  Returns once the SMSC has accepted
+    /// our bind, or with an error if it refuses.  We only reconnect
+    /// automatically after this first bind has succeeded.
+    pub async fn connect<L: EsmeLogic + Send + 'static>(
+        config: EsmeConfig,
+        esme_logic: L,
+    ) -> Result<Self, EsmeError> {
+        Self::connect_with_shared_logic(
+            config,
+            Arc::new(Mutex::new(esme_logic)),
+        )
+        .await
+    }
+
+    /// Like connect, but several clients may pass deliver_sm to the same
+    /// logic, as in an EsmePool.
+    pub(crate) async fn connect_with_shared_logic<L>(
+        config: EsmeConfig,
+        logic: Arc<Mutex<L>>,
+    ) -> Result<Self, EsmeError>
+    where
+        L: EsmeLogic + Send + 'static,
+    {
+        let (session, read_task) =
+            Session::connect(&config, Arc::clone(&logic)).await?;
+
+        let (state_tx, state_rx) = watch::channel(ConnectionState::Bound);
+        let inner = Arc::new(ClientInner {
+            config,
+            session: std::sync::Mutex::new(Some(session)),
+            state_tx,
+            state_rx,
+            unbinding: AtomicBool::new(false),
+        });
+        let supervisor =
+            tokio::spawn(supervise(Arc::clone(&inner), logic, read_task));
+
+        Ok(Self { inner, supervisor })
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        *self.inner.state_rx.borrow()
+    }
+
+    /// Watch for changes in our connection to the SMSC
+    pub fn state_changes(&self) -> watch::Receiver<ConnectionState> {
+        self.inner.state_rx.clone()
+    }
+
+    /// Send a submit_sm and wait for the response.  Returns the message_id
+    /// the SMSC gave this message.  If we are not bound, or the connection
+    /// is lost before the response arrives, we either wait and try again
+    /// or fail, depending on config.unacknowledged_policy.
+    pub async fn submit_sm(
+        &self,
+        pdu: SubmitSmPdu,
+    ) -> Result<String, EsmeError> {
+        let requeue = self.inner.config.unacknowledged_policy
+            == UnacknowledgedPolicy::Requeue;
+        // SubmitSmPdu can't be cloned, so keep a copy in case we resend
+        let fields = SmFields::from_sm_data(&pdu.0);
+        let mut pdu = Some(pdu);
+        loop {
+            let session = self.inner.bound_session().await?;
+            let this_pdu = match pdu.take() {
+                Some(pdu) => pdu,
+                None => fields.to_submit_sm()?,
+            };
+            match session.submit_sm(this_pdu).await {
+                Err(EsmeError::ConnectionClosed)
+                | Err(EsmeError::IoError(_))
+                    if requeue =>
+                {
+                    info!("Connection lost before submit_sm_resp: requeuing");
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Send an enquire_link and wait for the response
+    pub async fn enquire_link(&self) -> Result<(), EsmeError> {
+        self.inner.bound_session().await?.enquire_link().await
+    }
+
+    /// Unbind and close the connection.  We will not reconnect after this.
+    pub async fn unbind(&self) -> Result<(), EsmeError> {
+        self.inner.unbinding.store(true, Ordering::Relaxed);
+        let session = self.inner.session.lock().unwrap().take();
+        let result = match session {
+            Some(session) => session.unbind().await,
+            None => Ok(()),
+        };
+        self.inner.set_state(ConnectionState::Unbound);
+        result
+    }
+}
+
+impl Drop for EsmeClient {
+    fn drop(&mut self) {
+        self.supervisor.abort();
+        if let Some(session) = self.inner.session.lock().unwrap().take() {
+            session.shutdown.notify_one();
+        }
+    }
+}
+
+struct ClientInner {
+    config: EsmeConfig,
+    /// The current session, if we are bound
+    session: std::sync::Mutex<Option<Arc<Session>>>,
+    state_tx: watch::Sender<ConnectionState>,
+    /// Kept so that state_tx always has a receiver, and for cloning
+    state_rx: watch::Receiver<ConnectionState>,
+    unbinding: AtomicBool,
+}
+
+impl ClientInner {
+    fn set_state(&self, state: ConnectionState) {
+        info!("Connection to {}: {:?}", self.config.smsc_address, state);
+        // Can't fail, because we hold a receiver
+        let _ = self.state_tx.send(state);
+    }
+
+    /// The current session, waiting for us to reconnect if we are not
+    /// bound and the policy is to requeue.
+    async fn bound_session(&self) -> Result<Arc<Session>, EsmeError> {
+        let mut state_rx = self.state_rx.clone();
+        loop {
+            state_rx.borrow_and_update();
+            if self.unbinding.load(Ordering::Relaxed) {
+                return Err(EsmeError::ConnectionClosed);
+            }
+            if let Some(session) = &*self.session.lock().unwrap() {
+                if !session.closed.load(Ordering::Relaxed) {
+                    return Ok(Arc::clone(session));
+                }
+            }
+            if self.config.unacknowledged_policy == UnacknowledgedPolicy::Fail {
+                return Err(EsmeError::ConnectionClosed);
+            }
+            state_rx
+                .changed()
+                .await
+                .map_err(|_| EsmeError::ConnectionClosed)?;
+        }
+    }
+}
+
+/// Wait for the session to end, and reconnect, until unbind is called
+async fn supervise<L: EsmeLogic + Send + 'static>(
+    inner: Arc<ClientInner>,
+    logic: Arc<Mutex<L>>,
+    mut read_task: JoinHandle<()>,
+) {
+    let mut backoff = Backoff::new(
+        Duration::from_millis(inner.config.reconnect_initial_delay_ms),
+        Duration::from_millis(inner.config.reconnect_max_delay_ms),
+    );
+    loop {
+        // The read task ends when the connection closes
+        let _ = (&mut read_task).await;
+        if inner.unbinding.load(Ordering::Relaxed) {
+            return;
+        }
+        inner.session.lock().unwrap().take();
+        inner.set_state(ConnectionState::Disconnected);
+
+        loop {
+            sleep(backoff.next_delay()).await;
+            if inner.unbinding.load(Ordering::Relaxed) {
+                return;
+            }
+            inner.set_state(ConnectionState::Connecting);
+            match Session::connect(&inner.config, Arc::clone(&logic)).await {
+                Ok((session, task)) => {
+                    // Checked with the session locked, so either unbind
+                    // takes this session or we see that it was called
+                    let unbinding = {
+                        let mut current = inner.session.lock().unwrap();
+                        let unbinding = inner.unbinding.load(Ordering::Relaxed);
+                        if !unbinding {
+                            *current = Some(Arc::clone(&session));
+                        }
+                        unbinding
+                    };
+                    if unbinding {
+                        if let Err(e) = session.unbind().await {
+                            warn!(
+                                "Failed to unbind from {}: {}",
+                                inner.config.smsc_address, e
+                            );
+                        }
+                        return;
+                    }
+                    read_task = task;
+                    inner.set_state(ConnectionState::Bound);
+                    backoff.reset();
+                    break;
+                }
+                Err(_) if inner.unbinding.load(Ordering::Relaxed) => return,
+                Err(e) => {
+                    warn!(
+                        "Failed to reconnect to {}: {}",
+                        inner.config.smsc_address, e
+                    );
+                    inner.set_state(ConnectionState::Disconnected);
+                }
+            }
+        }
+    }
+}
+
+/// One connection to the SMSC, from bind until it closes
+struct Session {
+    connection: SmppConnection,
+    pending: std::sync::Mutex<HashMap<u32, oneshot::Sender<AnyPdu>>>,
+    /// Limits how many submit_sm may await a response at once
+    window: Semaphore,
+    response_timeout: Duration,
+    /// Notified to make read_loop stop and close the connection
+    shutdown: Notify,
+    /// Set once read_loop has stopped
+    closed: AtomicBool,
+}
+
+impl Session {
+    /// Send unbind, then close the connection whatever the response
+    async fn unbind(&self) -> Result<(), EsmeError> {
+        let result = self
+            .request(|seq| {
+                Ok(ExtraPdu::new(0, seq, ExtraPduBody::Unbind)?.into())
+            })
+            .await;
+        self.shutdown.notify_one();
+        result.map(|_| ())
+    }
+
+    /// Connect and bind, then start reading PDUs and sending enquire_link.
+    /// Returns the session and the read task, which ends when the
+    /// connection closes.
+    async fn connect<L: EsmeLogic + Send + 'static>(
+        config: &EsmeConfig,
+        logic: Arc<Mutex<L>>,
+    ) -> Result<(Arc<Self>, JoinHandle<()>), EsmeError> {
+        info!("Connecting to SMSC {}", config.smsc_address);
+        let connect_timeout = Duration::from_secs(config.connect_timeout_secs);
+        let tcp_stream = time::timeout(
+            connect_timeout,
+            TcpStream::connect(&config.smsc_address),
+        )
+        .await
+        .map_err(|_| EsmeError::Timeout)??;
+        let socket_addr = tcp_stream.peer_addr()?;
+        let session = Arc::new(Session {
+            connection: SmppConnection::new(tcp_stream, socket_addr),
+            pending: std::sync::Mutex::new(HashMap::new()),
+            window: Semaphore::new(config.window_size),
+            response_timeout: Duration::from_secs(config.response_timeout_secs),
+            shutdown: Notify::new(),
+            closed: AtomicBool::new(false),
+        });
+
+        time::timeout(connect_timeout, session.bind(config))
+            .await
+            .map_err(|_| EsmeError::Timeout)??;
+
+        let span = session.connection.span().clone();
+        let read_task = tokio::spawn(
+            read_loop(Arc::clone(&session), logic).instrument(span),
+        );
+        tokio::spawn(enquire_link_loop(
+            Arc::downgrade(&session),
+            Duration::from_secs(config.enquire_link_interval_secs),
+            Duration::from_secs(config.enquire_link_timeout_secs),
+        ));
+        Ok((session, read_task))
+    }
+
+    async fn submit_sm(&self, pdu: SubmitSmPdu) -> Result<String, EsmeError> {
+        // Wait for a free slot in the window.  The permit is released when
+        // we get a response, or give up waiting for one.
+        let _permit = self
+            .window
+            .acquire()
+            .await
+            .map_err(|_| EsmeError::ConnectionClosed)?;
+        let resp = self
+            .request(|seq| Ok(Pdu::new(0, seq, pdu.into())?.into()))
+            .await?;
+        match resp {
+            AnyPdu::Pdu(resp) => match resp.body() {
+                PduBody::SubmitSmResp(body) => {
+                    Ok(body.message_id().unwrap_or_default())
+                }
+                _ => {
+                    Err(EsmeError::UnexpectedResponse(resp.command_id().value))
+                }
+            },
+            AnyPdu::Extra(resp) => {
+                Err(EsmeError::UnexpectedResponse(resp.command_id()))
+            }
+        }
+    }
+
+    /// Write the PDU made by build_pdu, and wait for the response with the
+    /// same sequence_number.  Responses may arrive in any order.  Error
+    /// responses are returned as Err, as is no response within
+    /// response_timeout.
+    async fn request<F>(&self, build_pdu: F) -> Result<AnyPdu, EsmeError>
+    where
+        F: FnOnce(u32) -> Result<AnyPdu, PduParseError>,
+    {
+        let (tx, rx) = oneshot::channel();
+        let sequence_number = {
+            let mut pending = self.pending.lock().unwrap();
+            // After wrapping round, skip any request still awaiting a
+            // response
+            let mut sequence_number = self.connection.next_sequence_number();
+            while pending.contains_key(&sequence_number) {
+                sequence_number = self.connection.next_sequence_number();
+            }
+            pending.insert(sequence_number, tx);
+            sequence_number
+        };
+        let pdu = match build_pdu(sequence_number) {
+            Ok(pdu) => pdu,
+            Err(e) => {
+                self.pending.lock().unwrap().remove(&sequence_number);
+                return Err(e.into());
+            }
+        };
+
+        if let Err(e) = self.connection.write_any_pdu(&pdu).await {
+            self.pending.lock().unwrap().remove(&sequence_number);
+            return Err(e.into());
+        }
+
+        let resp = match time::timeout(self.response_timeout, rx).await {
+            Ok(resp) => resp.map_err(|_| EsmeError::ConnectionClosed)?,
+            Err(_) => {
+                self.pending.lock().unwrap().remove(&sequence_number);
+                return Err(EsmeError::Timeout);
+            }
+        };
+        match resp.command_status() {
+            0 => Ok(resp),
+            status => Err(EsmeError::ErrorResponse(status)),
+        }
+    }
+
+    async fn enquire_link(&self) -> Result<(), EsmeError> {
+        self.request(|seq| {
+            Ok(Pdu::new(0, seq, EnquireLinkPdu::new().into())?.into())
+        })
+        .await
+        .map(|_| ())
+    }
+
+    /// Send a bind PDU and wait for the response.  We do this before
+    /// starting read_loop, so we read the response ourselves.
+    async fn bind(&self, config: &EsmeConfig) -> Result<(), EsmeError> {
+        let bind_data = BindData::new(
+            &config.system_id,
+            &config.password,
+            &config.system_type,
+            INTERFACE_VERSION,
+            0,
+            0,
+            "",
+        )?;
+        let body = match config.bind_type {
+            BindType::Transmitter => ExtraPduBody::BindTransmitter(bind_data),
+            BindType::Receiver => ExtraPduBody::BindReceiver(bind_data),
+            BindType::Transceiver => ExtraPduBody::BindTransceiver(bind_data),
+        };
+        let bind =
+            ExtraPdu::new(0, self.connection.next_sequence_number(), body)?;
+        self.connection.write_extra_pdu(&bind).await?;
+
+        let resp = self
+            .connection
+            .read_any_pdu()
+            .await?
+            .ok_or(EsmeError::ConnectionClosed)?;
+        info!(
+            "<= {} {}",
+            self.connection.socket_addr,
+            LoggedPdu::from(&resp)
+        );
+        let expected_command_id = bind.command_id() | 0x80000000;
+        if resp.command_status() != 0 {
+            Err(EsmeError::BindFailed(resp.command_status()))
+        } else if resp.command_id() != expected_command_id {
+            Err(EsmeError::UnexpectedResponse(resp.command_id()))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Hand a response to whoever is waiting for it.  Returns false if
+    /// nobody was.
+    fn complete_request(&self, resp: AnyPdu) -> bool {
+        let tx = self.pending.lock().unwrap().remove(&resp.sequence_number());
+        match tx {
+            Some(tx) => {
+                // If the receiver has gone away, nobody needs the response
+                let _ = tx.send(resp);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Read PDUs from the SMSC until the connection closes, passing responses
+/// to the requests waiting for them, and dealing with requests from the
+/// SMSC.
+async fn read_loop<L: EsmeLogic>(session: Arc<Session>, logic: Arc<Mutex<L>>) {
+    loop {
+        let pdu = tokio::select! {
+            pdu = session.connection.read_any_pdu() => pdu,
+            _ = session.shutdown.notified() => break,
+        };
+        match pdu {
+            Ok(Some(pdu)) => {
+                let span = pdu_span(&pdu);
+                match handle_pdu(&session, &logic, pdu).instrument(span).await {
+                    Ok(true) => {}
+                    Ok(false) => break,
+                    Err(e) => {
+                        error!("Error handling PDU from SMSC: {}", e);
+                        break;
+                    }
+                }
+            }
+            Ok(None) => {
+                info!("Connection {} - closed", session.connection.socket_addr);
+                break;
+            }
+            Err(e) => {
+                error!(
+                    "Connection {} - closed due to error: {}",
+                    session.connection.socket_addr, e
+                );
+                break;
+            }
+        }
+    }
+
+    session.closed.store(true, Ordering::Relaxed);
+    // Dropping the senders wakes up anyone still waiting, with an error
+    session.pending.lock().unwrap().clear();
+    session.connection.disconnect().await;
+}
+
+/// Returns false if we should stop reading
+async fn handle_pdu<L: EsmeLogic>(
+    session: &Session,
+    logic: &Mutex<L>,
+    pdu: AnyPdu,
+) -> Result<bool, EsmeError> {
+    info!(
+        "<= {} {}",
+        session.connection.socket_addr,
+        LoggedPdu::from(&pdu)
+    );
+    let sequence_number = pdu.sequence_number();
+    if pdu.is_response() {
+        let is_unbind_resp = matches!(
+            &pdu,
+            AnyPdu::Extra(ExtraPdu {
+                body: ExtraPduBody::UnbindResp,
+                ..
+            })
+        );
+        if !session.complete_request(pdu) {
+            warn!("Received response to unknown sequence_number");
+        }
+        return Ok(!is_unbind_resp);
+    }
+
+    match &pdu {
+        AnyPdu::Pdu(pdu) => match pdu.body() {
+            PduBody::DeliverSm(body) => {
+                let command_status =
+                    match logic.lock().await.deliver_sm(body).await {
+                        Ok(()) => PduStatus::ESME_ROK,
+                        Err(e) => e.into(),
+                    };
+                session
+                    .connection
+                    .write_extra_pdu(&ExtraPdu::new(
+                        command_status as u32,
+                        sequence_number,
+                        ExtraPduBody::DeliverSmResp,
+                    )?)
+                    .await?;
+            }
+            PduBody::EnquireLink(_) => {
+                session
+                    .connection
+                    .write_pdu(&Pdu::new(
+                        PduStatus::ESME_ROK as u32,
+                        sequence_number,
+                        EnquireLinkRespPdu::new().into(),
+                    )?)
+                    .await?;
+            }
+            _ => write_generic_nack(session, sequence_number).await?,
+        },
+        AnyPdu::Extra(ExtraPdu {
+            body: ExtraPduBody::Unbind,
+            ..
+        }) => {
+            session
+                .connection
+                .write_extra_pdu(&ExtraPdu::new(
+                    PduStatus::ESME_ROK as u32,
+                    sequence_number,
+                    ExtraPduBody::UnbindResp,
+                )?)
+                .await?;
+            return Ok(false);
+        }
+        AnyPdu::Extra(_) => {
+            write_generic_nack(session, sequence_number).await?
+        }
+    }
+    Ok(true)
+}
+
+async fn write_generic_nack(
+    session: &Session,
+    sequence_number: u32,
+) -> Result<(), EsmeError> {
+    session
+        .connection
+        .write_pdu(&Pdu::new(
+            PduStatus::ESME_RINVCMDID as u32,
+            sequence_number,
+            GenericNackPdu::new_error().into(),
+        )?)
+        .await
+        .map_err(|e| e.into())
+}
+
+/// Send enquire_link every interval until the session goes away.  If the
+/// SMSC doesn't respond within timeout, close the connection.
+async fn enquire_link_loop(
+    session: Weak<Session>,
+    interval: Duration,
+    timeout: Duration,
+) {
+    loop {
+        sleep(interval).await;
+        let session = match session.upgrade() {
+            Some(session) => session,
+            None => return,
+        };
+        if session.closed.load(Ordering::Relaxed) {
+            return;
+        }
+        match time::timeout(timeout, session.enquire_link()).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                warn!("enquire_link failed: {}", e);
+                session.shutdown.notify_one();
+                return;
+            }
+            Err(_) => {
+                warn!(
+                    "No enquire_link_resp from {} after {:?}",
+                    session.connection.socket_addr, timeout
+                );
+                session.shutdown.notify_one();
+                return;
+            }
+        }
+    }
+}
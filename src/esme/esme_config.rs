@@ -0,0 +1,146 @@
+use clap::{ArgSettings, Clap};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// How to bind to the SMSC
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BindType {
+    /// Send submit_sm only
+    Transmitter,
+    /// Receive deliver_sm only
+    Receiver,
+    /// Both send and receive
+    Transceiver,
+}
+
+impl FromStr for BindType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "transmitter" => Ok(BindType::Transmitter),
+            "receiver" => Ok(BindType::Receiver),
+            "transceiver" => Ok(BindType::Transceiver),
+            _ => Err(format!(
+                "Unknown bind type '{}': expected transmitter, receiver or \
+                transceiver",
+                s
+            )),
+        }
+    }
+}
+
+/// What to do with a submit_sm we sent but did not get a response to,
+/// when the connection is lost
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum UnacknowledgedPolicy {
+    /// Send it again once we have reconnected.  The SMSC may have received
+    /// it the first time, so this can cause duplicate messages.
+    Requeue,
+    /// Fail the submit_sm with EsmeError::ConnectionClosed
+    Fail,
+}
+
+impl FromStr for UnacknowledgedPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "requeue" => Ok(UnacknowledgedPolicy::Requeue),
+            "fail" => Ok(UnacknowledgedPolicy::Fail),
+            _ => {
+                Err(format!("Unknown policy '{}': expected requeue or fail", s))
+            }
+        }
+    }
+}
+
+/// External Short Messaging Entity (ESME) client in Rust
+#[derive(Clap, Clone, Debug)]
+#[clap(name = "esme")]
+pub struct EsmeConfig {
+    /// Address of the SMSC to connect to
+    #[clap(long, default_value = "127.0.0.1:8080", env = "SMSC_ADDRESS")]
+    pub smsc_address: String,
+
+    /// system_id to bind with
+    #[clap(long, env = "ESME_SYSTEM_ID")]
+    pub system_id: String,
+
+    /// Password to bind with
+    #[clap(long, env = "ESME_PASSWORD")]
+    pub password: String,
+
+    /// system_type to bind with
+    #[clap(
+        long,
+        default_value = "",
+        env = "ESME_SYSTEM_TYPE",
+        setting = ArgSettings::AllowEmptyValues
+    )]
+    pub system_type: String,
+
+    /// transmitter, receiver or transceiver
+    #[clap(long, default_value = "transceiver", env = "BIND_TYPE")]
+    pub bind_type: BindType,
+
+    /// How often to send enquire_link to keep the connection alive
+    #[clap(long, default_value = "30", env = "ENQUIRE_LINK_INTERVAL_SECS")]
+    pub enquire_link_interval_secs: u64,
+
+    /// How long to wait for enquire_link_resp before deciding the
+    /// connection is dead and reconnecting
+    #[clap(long, default_value = "10", env = "ENQUIRE_LINK_TIMEOUT_SECS")]
+    pub enquire_link_timeout_secs: u64,
+
+    /// Delay before the first attempt to reconnect.  Later attempts wait
+    /// up to twice as long as the one before.
+    #[clap(long, default_value = "1000", env = "RECONNECT_INITIAL_DELAY_MS")]
+    pub reconnect_initial_delay_ms: u64,
+
+    /// The longest we will wait between attempts to reconnect
+    #[clap(long, default_value = "60000", env = "RECONNECT_MAX_DELAY_MS")]
+    pub reconnect_max_delay_ms: u64,
+
+    /// requeue or fail: what to do with unacknowledged submit_sm when the
+    /// connection is lost.  With fail, submit_sm also fails immediately
+    /// while we are reconnecting, instead of waiting.
+    #[clap(long, default_value = "fail", env = "UNACKNOWLEDGED_POLICY")]
+    pub unacknowledged_policy: UnacknowledgedPolicy,
+
+    /// How many submit_sm may await a response at once.  Further submits
+    /// wait for a slot.  enquire_link does not use a slot.
+    #[clap(long, default_value = "10", env = "WINDOW_SIZE")]
+    pub window_size: usize,
+
+    /// How long to wait for the response to a request before failing it
+    #[clap(long, default_value = "30", env = "RESPONSE_TIMEOUT_SECS")]
+    pub response_timeout_secs: u64,
+
+    /// How long to wait for the TCP connection, and then for bind_resp,
+    /// before the attempt fails and we back off
+    #[clap(long, default_value = "10", env = "CONNECT_TIMEOUT_SECS")]
+    pub connect_timeout_secs: u64,
+}
+
+impl EsmeConfig {
+    /// A transceiver bind with the same defaults as the command line
+    pub fn new(smsc_address: &str, system_id: &str, password: &str) -> Self {
+        Self {
+            smsc_address: String::from(smsc_address),
+            system_id: String::from(system_id),
+            password: String::from(password),
+            system_type: String::new(),
+            bind_type: BindType::Transceiver,
+            enquire_link_interval_secs: 30,
+            enquire_link_timeout_secs: 10,
+            reconnect_initial_delay_ms: 1000,
+            reconnect_max_delay_ms: 60000,
+            unacknowledged_policy: UnacknowledgedPolicy::Fail,
+            window_size: 10,
+            response_timeout_secs: 30,
+            connect_timeout_secs: 10,
+        }
+    }
+}
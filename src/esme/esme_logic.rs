@@ -0,0 +1,30 @@
+use async_trait::async_trait;
+use smpp_pdu::pdu::{DeliverSmPdu, PduStatus};
+
+pub enum DeliverSmError {
+    /// The SMSC should try again later
+    TemporaryError,
+    /// The SMSC should not try again
+    PermanentError,
+    InternalError,
+}
+
+impl From<DeliverSmError> for PduStatus {
+    fn from(e: DeliverSmError) -> PduStatus {
+        match e {
+            DeliverSmError::TemporaryError => PduStatus::ESME_RX_T_APPN,
+            DeliverSmError::PermanentError => PduStatus::ESME_RX_P_APPN,
+            DeliverSmError::InternalError => PduStatus::ESME_RSYSERR,
+        }
+    }
+}
+
+#[async_trait]
+pub trait EsmeLogic: Send {
+    /// Called for each deliver_sm (MO or delivery receipt) the SMSC sends
+    /// us.  The EsmeClient responds with deliver_sm_resp once this returns.
+    async fn deliver_sm(
+        &mut self,
+        pdu: &DeliverSmPdu,
+    ) -> Result<(), DeliverSmError>;
+}
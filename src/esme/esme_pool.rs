@@ -0,0 +1,280 @@
+use clap::Clap;
+use log::*;
+use smpp_pdu::pdu::SubmitSmPdu;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+
+use crate::esme::{
+    Backoff, ConnectionState, EsmeClient, EsmeConfig, EsmeError, EsmeLogic,
+};
+use crate::rate_limiter::RateLimiter;
+
+/// How an EsmePool chooses which bind to send each submit_sm on
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Balancing {
+    /// Each bind in turn
+    RoundRobin,
+    /// The bind with the fewest submit_sm awaiting a response
+    LeastOutstanding,
+}
+
+impl FromStr for Balancing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "round-robin" => Ok(Balancing::RoundRobin),
+            "least-outstanding" => Ok(Balancing::LeastOutstanding),
+            _ => Err(format!(
+                "Unknown balancing '{}': expected round-robin or \
+                least-outstanding",
+                s
+            )),
+        }
+    }
+}
+
+/// Several binds to one SMSC, used as one
+#[derive(Clap, Clone, Debug)]
+#[clap(name = "esme-pool")]
+pub struct EsmePoolConfig {
+    #[clap(flatten)]
+    pub esme: EsmeConfig,
+
+    /// How many binds to keep open.  bind_type should be transmitter or
+    /// transceiver.
+    #[clap(long, default_value = "1", env = "POOL_BINDS")]
+    pub binds: usize,
+
+    /// round-robin or least-outstanding: how to spread submit_sm over the
+    /// binds
+    #[clap(long, default_value = "round-robin", env = "POOL_BALANCING")]
+    pub balancing: Balancing,
+
+    /// The most submit_sm to send per second, across all binds
+    #[clap(long, env = "POOL_MAX_SUBMITS_PER_SEC")]
+    pub max_submits_per_sec: Option<u32>,
+}
+
+/// Keeps several binds to one SMSC, and spreads submit_sm over those that
+/// are currently bound.  Offers the same requests as a single EsmeClient.
+/// Binds that fail when we start are retried in the background, and join
+/// the rotation once they succeed.
+pub struct EsmePool {
+    members: Arc<std::sync::Mutex<Vec<Arc<Member>>>>,
+    balancing: Balancing,
+    rate_limiter: Option<RateLimiter>,
+    next_member: AtomicUsize,
+    retry_tasks: Vec<JoinHandle<()>>,
+}
+
+struct Member {
+    client: EsmeClient,
+    /// submit_sm sent on this bind that are awaiting a response
+    outstanding: AtomicUsize,
+}
+
+/// Counts a submit_sm as outstanding on a bind until dropped, which
+/// happens even if the caller stops waiting for the response
+struct Outstanding<'a>(&'a AtomicUsize);
+
+impl<'a> Outstanding<'a> {
+    fn start(count: &'a AtomicUsize) -> Self {
+        count.fetch_add(1, Ordering::Relaxed);
+        Self(count)
+    }
+}
+
+impl Drop for Outstanding<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl EsmePool {
+    /// Open all the binds.  Fails only if none of them succeed.
+    pub async fn connect<L: EsmeLogic + Send + 'static>(
+        config: EsmePoolConfig,
+        esme_logic: L,
+    ) -> Result<Self, EsmeError> {
+        let logic = Arc::new(Mutex::new(esme_logic));
+        let members = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut retry_tasks = Vec::new();
+        let mut last_error = None;
+
+        for i in 0..config.binds.max(1) {
+            match EsmeClient::connect_with_shared_logic(
+                config.esme.clone(),
+                Arc::clone(&logic),
+            )
+            .await
+            {
+                Ok(client) => members.lock().unwrap().push(Arc::new(Member {
+                    client,
+                    outstanding: AtomicUsize::new(0),
+                })),
+                Err(e) => {
+                    warn!(
+                        "Bind {} to {} failed: {}",
+                        i, config.esme.smsc_address, e
+                    );
+                    last_error = Some(e);
+                    retry_tasks.push(tokio::spawn(retry_bind(
+                        config.esme.clone(),
+                        Arc::clone(&logic),
+                        Arc::clone(&members),
+                    )));
+                }
+            }
+        }
+
+        if members.lock().unwrap().is_empty() {
+            for task in &retry_tasks {
+                task.abort();
+            }
+            // We tried at least once, so there is an error
+            return Err(last_error.unwrap());
+        }
+
+        Ok(Self {
+            members,
+            balancing: config.balancing,
+            rate_limiter: config.max_submits_per_sec.map(RateLimiter::new),
+            next_member: AtomicUsize::new(0),
+            retry_tasks,
+        })
+    }
+
+    /// Bound if any of our binds is bound, otherwise the state of the
+    /// first one.
+    pub fn state(&self) -> ConnectionState {
+        let members = self.members.lock().unwrap();
+        if members
+            .iter()
+            .any(|m| m.client.state() == ConnectionState::Bound)
+        {
+            ConnectionState::Bound
+        } else {
+            members
+                .first()
+                .map(|m| m.client.state())
+                .unwrap_or(ConnectionState::Connecting)
+        }
+    }
+
+    /// How many of our binds are currently bound
+    pub fn bound_count(&self) -> usize {
+        self.members
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|m| m.client.state() == ConnectionState::Bound)
+            .count()
+    }
+
+    /// Send a submit_sm on one of our binds, once the rate limit allows,
+    /// and wait for the response.
+    pub async fn submit_sm(
+        &self,
+        pdu: SubmitSmPdu,
+    ) -> Result<String, EsmeError> {
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.acquire().await;
+        }
+        let member = self.choose_member()?;
+        let _outstanding = Outstanding::start(&member.outstanding);
+        member.client.submit_sm(pdu).await
+    }
+
+    /// Send an enquire_link on one of our binds and wait for the response
+    pub async fn enquire_link(&self) -> Result<(), EsmeError> {
+        self.choose_member()?.client.enquire_link().await
+    }
+
+    /// Unbind all our binds.  Returns the first error, if any.
+    pub async fn unbind(&self) -> Result<(), EsmeError> {
+        for task in &self.retry_tasks {
+            task.abort();
+        }
+        let members = self.members.lock().unwrap().clone();
+        let mut result = Ok(());
+        for member in members {
+            if let Err(e) = member.client.unbind().await {
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
+        result
+    }
+
+    /// Pick a bound member according to our balancing.  If none are bound,
+    /// we pick one anyway, and it waits or fails according to its
+    /// unacknowledged_policy.
+    fn choose_member(&self) -> Result<Arc<Member>, EsmeError> {
+        let members = self.members.lock().unwrap();
+        if members.is_empty() {
+            return Err(EsmeError::ConnectionClosed);
+        }
+        let start = self.next_member.fetch_add(1, Ordering::Relaxed);
+        let mut bound = (0..members.len())
+            .map(|i| &members[(start + i) % members.len()])
+            .filter(|m| m.client.state() == ConnectionState::Bound);
+        let chosen = match self.balancing {
+            Balancing::RoundRobin => bound.next(),
+            Balancing::LeastOutstanding => {
+                bound.min_by_key(|m| m.outstanding.load(Ordering::Relaxed))
+            }
+        };
+        Ok(Arc::clone(
+            chosen.unwrap_or(&members[start % members.len()]),
+        ))
+    }
+}
+
+impl Drop for EsmePool {
+    fn drop(&mut self) {
+        for task in &self.retry_tasks {
+            task.abort();
+        }
+    }
+}
+
+/// Keep trying to open a bind that failed when the pool started, and add
+/// it to the pool once it succeeds.
+async fn retry_bind<L: EsmeLogic + Send + 'static>(
+    config: EsmeConfig,
+    logic: Arc<Mutex<L>>,
+    members: Arc<std::sync::Mutex<Vec<Arc<Member>>>>,
+) {
+    let mut backoff = Backoff::new(
+        Duration::from_millis(config.reconnect_initial_delay_ms),
+        Duration::from_millis(config.reconnect_max_delay_ms),
+    );
+    loop {
+        sleep(backoff.next_delay()).await;
+        match EsmeClient::connect_with_shared_logic(
+            config.clone(),
+            Arc::clone(&logic),
+        )
+        .await
+        {
+            Ok(client) => {
+                info!("Bind to {} succeeded on retry", config.smsc_address);
+                members.lock().unwrap().push(Arc::new(Member {
+                    client,
+                    outstanding: AtomicUsize::new(0),
+                }));
+                return;
+            }
+            Err(e) => {
+                warn!("Bind to {} failed again: {}", config.smsc_address, e)
+            }
+        }
+    }
+}
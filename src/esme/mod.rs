@@ -0,0 +1,11 @@
+pub mod backoff;
+pub mod esme_client;
+pub mod esme_config;
+pub mod esme_logic;
+pub mod esme_pool;
+
+pub use backoff::Backoff;
+pub use esme_client::{ConnectionState, EsmeClient, EsmeError};
+pub use esme_config::{BindType, EsmeConfig, UnacknowledgedPolicy};
+pub use esme_logic::{DeliverSmError, EsmeLogic};
+pub use esme_pool::{Balancing, EsmePool, EsmePoolConfig};
@@ -9,6 +9,7 @@ use tokio::sync::Mutex;
 use crate::message_unique_key::MessageUniqueKey;
 use crate::smsc::{BindData, BindError, Smsc, SmscLogic, SubmitSmError};
 
+#[derive(Default)]
 pub struct AllMtsFail {}
 
 impl AllMtsFail {
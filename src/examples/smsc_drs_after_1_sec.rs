@@ -1,24 +1,42 @@
 //! Logic for an SMSC that returns DRs after approximately 1 second's delay
-//! and allows you to bind if system_id==password.
+//! and allows you to bind if system_id==password, or if you are in its
+//! AccountStore when it has one.  On reload, the AccountStore is read
+//! again from accounts_file.
 
 use async_trait::async_trait;
 use smpp_pdu::pdu::tlvs::{KnownTlvTag, Tlv, Tlvs};
 use smpp_pdu::pdu::{
     DeliverEsmClass, DeliverSmPdu, Pdu, SubmitSmPdu, SubmitSmRespPdu,
 };
+use std::path::Path;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::Mutex;
 use tokio::time;
 
+use crate::async_result::AsyncResult;
 use crate::message_unique_key::MessageUniqueKey;
-use crate::smsc::{BindData, BindError, Smsc, SmscLogic, SubmitSmError};
+use crate::smpp_connection::EsmeId;
+use crate::smsc::{
+    AccountStore, BindContext, BindData, BindError, Smsc, SmscConfig,
+    SmscLogic, SubmitSmError,
+};
 
-pub struct DrsAfter1Sec {}
+#[derive(Default)]
+pub struct DrsAfter1Sec {
+    accounts: Option<AccountStore>,
+}
 
 impl DrsAfter1Sec {
     pub fn new() -> Self {
-        Self {}
+        Self { accounts: None }
+    }
+
+    /// Only allow binds from the accounts in accounts
+    pub fn with_accounts(accounts: AccountStore) -> Self {
+        Self {
+            accounts: Some(accounts),
+        }
     }
 }
 
@@ -32,14 +50,39 @@ impl SmscLogic for DrsAfter1Sec {
         }
     }
 
+    async fn bind_with_context(
+        &mut self,
+        bind_data: &BindData,
+        context: &BindContext,
+    ) -> Result<(), BindError> {
+        match &self.accounts {
+            Some(accounts) => accounts.authenticate(bind_data, context).await,
+            None => self.bind(bind_data).await,
+        }
+    }
+
+    async fn reload(&mut self, config: &SmscConfig) -> AsyncResult<()> {
+        self.accounts = match &config.accounts_file {
+            Some(path) => Some(AccountStore::load(Path::new(path))?),
+            None => None,
+        };
+        Ok(())
+    }
+
+    async fn may_stay_bound(&mut self, esme_id: &EsmeId) -> bool {
+        self.accounts
+            .as_ref()
+            .is_none_or(|accounts| accounts.allows(esme_id.system_id.as_str()))
+    }
+
     async fn submit_sm(
         &mut self,
         smsc: Arc<Mutex<Smsc>>,
         pdu: &SubmitSmPdu,
-        sequence_number: u32,
+        _sequence_number: u32,
     ) -> Result<(SubmitSmRespPdu, MessageUniqueKey), SubmitSmError> {
         let message_id = "abc"; // TODO
-        let deliver_sm = create_deliver_sm(message_id, sequence_number, pdu);
+        let deliver_sm = create_deliver_sm(message_id, pdu);
         tokio::spawn(async move {
             time::sleep(Duration::from_secs(1)).await;
             smsc.lock()
@@ -48,24 +91,21 @@ impl SmscLogic for DrsAfter1Sec {
                 .await
         });
         Ok((
-            SubmitSmRespPdu::new(message_id).unwrap().into(),
-            MessageUniqueKey::new(
+            SubmitSmRespPdu::new(message_id).unwrap(),
+            MessageUniqueKey::from_submit_sm(
                 String::from("MySupplier"),
                 String::from(message_id),
-                pdu.destination_addr(),
+                pdu,
             ),
         ))
     }
 }
 
-fn create_deliver_sm(
-    message_id: &str,
-    sequence_number: u32,
-    submit_sm: &SubmitSmPdu,
-) -> Pdu {
+fn create_deliver_sm(message_id: &str, submit_sm: &SubmitSmPdu) -> Pdu {
     Pdu::new(
         0x00,
-        sequence_number,
+        // Smsc replaces this with the next sequence_number for the ESME
+        1,
         DeliverSmPdu::new(
             "",
             submit_sm.dest_addr_ton(),
@@ -0,0 +1,284 @@
+//! PDUs that smpp_pdu can't read or write yet.  smpp_pdu can parse bind
+//! PDUs but not write them, and doesn't know about deliver_sm_resp,
+//! unbind, unbind_resp, outbind or (for reading) generic_nack.  We handle
+//! those here, and AnyPdu lets us pass around either kind.
+//! See https://smpp.org/SMPP_v3_4_Issue1_2.pdf section 4.
+
+use smpp_pdu::pdu::data::bind_data::BindData;
+use smpp_pdu::pdu::{Pdu, PduParseError, PduParseErrorBody};
+use std::convert::TryInto;
+
+pub const GENERIC_NACK: u32 = 0x80000000;
+pub const BIND_RECEIVER: u32 = 0x00000001;
+pub const BIND_TRANSMITTER: u32 = 0x00000002;
+pub const DELIVER_SM_RESP: u32 = 0x80000005;
+pub const UNBIND: u32 = 0x00000006;
+pub const UNBIND_RESP: u32 = 0x80000006;
+pub const BIND_TRANSCEIVER: u32 = 0x00000009;
+pub const OUTBIND: u32 = 0x0000000B;
+
+const HEADER_LENGTH: usize = 16;
+const MAX_LENGTH_SYSTEM_ID: usize = 16;
+const MAX_LENGTH_PASSWORD: usize = 9;
+
+#[derive(Debug)]
+pub enum ExtraPduBody {
+    /// Write-only: incoming binds are parsed by smpp_pdu
+    BindReceiver(BindData),
+    /// Write-only: incoming binds are parsed by smpp_pdu
+    BindTransmitter(BindData),
+    /// Write-only: incoming binds are parsed by smpp_pdu
+    BindTransceiver(BindData),
+    /// The message_id of deliver_sm_resp is unused, so we always leave it
+    /// empty.
+    DeliverSmResp,
+    GenericNack,
+    Outbind {
+        system_id: String,
+        password: String,
+    },
+    Unbind,
+    UnbindResp,
+}
+
+#[derive(Debug)]
+pub struct ExtraPdu {
+    pub command_status: u32,
+    pub sequence_number: u32,
+    pub body: ExtraPduBody,
+}
+
+impl ExtraPdu {
+    pub fn new(
+        command_status: u32,
+        sequence_number: u32,
+        body: ExtraPduBody,
+    ) -> Result<Self, PduParseError> {
+        // A generic_nack for a PDU whose header could not be read has
+        // sequence_number 0
+        let min = match body {
+            ExtraPduBody::GenericNack => 0x00000000,
+            _ => 0x00000001,
+        };
+        if !(min..=0x7fffffff).contains(&sequence_number) {
+            return Err(PduParseError::new(
+                PduParseErrorBody::InvalidSequenceNumber,
+            ));
+        }
+        Ok(Self {
+            command_status,
+            sequence_number,
+            body,
+        })
+    }
+
+    /// True if PDUs with this command_id should be parsed by
+    /// ExtraPdu::parse rather than Pdu::parse.
+    pub fn parses_command_id(command_id: u32) -> bool {
+        matches!(
+            command_id,
+            GENERIC_NACK | DELIVER_SM_RESP | UNBIND | UNBIND_RESP | OUTBIND
+        )
+    }
+
+    pub fn command_id(&self) -> u32 {
+        match self.body {
+            ExtraPduBody::BindReceiver(_) => BIND_RECEIVER,
+            ExtraPduBody::BindTransmitter(_) => BIND_TRANSMITTER,
+            ExtraPduBody::BindTransceiver(_) => BIND_TRANSCEIVER,
+            ExtraPduBody::DeliverSmResp => DELIVER_SM_RESP,
+            ExtraPduBody::GenericNack => GENERIC_NACK,
+            ExtraPduBody::Outbind { .. } => OUTBIND,
+            ExtraPduBody::Unbind => UNBIND,
+            ExtraPduBody::UnbindResp => UNBIND_RESP,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        match &self.body {
+            ExtraPduBody::BindReceiver(bind_data)
+            | ExtraPduBody::BindTransmitter(bind_data)
+            | ExtraPduBody::BindTransceiver(bind_data) => {
+                write_c_octet_string(
+                    &mut body,
+                    bind_data.system_id.value.as_str(),
+                );
+                write_c_octet_string(
+                    &mut body,
+                    bind_data.password.value.as_str(),
+                );
+                write_c_octet_string(
+                    &mut body,
+                    bind_data.system_type.value.as_str(),
+                );
+                body.push(bind_data.interface_version.value);
+                body.push(bind_data.addr_ton.value);
+                body.push(bind_data.addr_npi.value);
+                write_c_octet_string(
+                    &mut body,
+                    bind_data.address_range.value.as_str(),
+                );
+            }
+            ExtraPduBody::DeliverSmResp => {
+                // A response with an error status has no body
+                if self.command_status == 0 {
+                    write_c_octet_string(&mut body, "");
+                }
+            }
+            ExtraPduBody::Outbind {
+                system_id,
+                password,
+            } => {
+                write_c_octet_string(&mut body, system_id);
+                write_c_octet_string(&mut body, password);
+            }
+            ExtraPduBody::GenericNack
+            | ExtraPduBody::Unbind
+            | ExtraPduBody::UnbindResp => {}
+        }
+
+        let mut ret = Vec::with_capacity(HEADER_LENGTH + body.len());
+        let command_length = (HEADER_LENGTH + body.len()) as u32;
+        ret.extend_from_slice(&command_length.to_be_bytes());
+        ret.extend_from_slice(&self.command_id().to_be_bytes());
+        ret.extend_from_slice(&self.command_status.to_be_bytes());
+        ret.extend_from_slice(&self.sequence_number.to_be_bytes());
+        ret.extend(body);
+        ret
+    }
+
+    /// Parse a whole PDU, whose length has already been checked, and
+    /// whose command_id is one that parses_command_id accepts.
+    pub fn parse(bytes: &[u8]) -> Result<Self, PduParseError> {
+        let (command_id, command_status, sequence_number) = read_header(bytes)
+            .ok_or_else(|| {
+                PduParseError::new(PduParseErrorBody::NotEnoughBytes)
+            })?;
+        let with_header = |e: PduParseError| {
+            e.into_with_header(
+                Some(command_id),
+                Some(command_status),
+                Some(sequence_number),
+            )
+        };
+
+        let mut body = &bytes[HEADER_LENGTH..];
+        let body = match command_id {
+            // deliver_sm_resp with an error status may or may not have
+            // a message_id, and it is unused, so we ignore the body.
+            DELIVER_SM_RESP => ExtraPduBody::DeliverSmResp,
+            GENERIC_NACK => ExtraPduBody::GenericNack,
+            OUTBIND => {
+                let system_id =
+                    read_c_octet_string(&mut body, MAX_LENGTH_SYSTEM_ID)
+                        .map_err(with_header)?;
+                let password =
+                    read_c_octet_string(&mut body, MAX_LENGTH_PASSWORD)
+                        .map_err(with_header)?;
+                ExtraPduBody::Outbind {
+                    system_id,
+                    password,
+                }
+            }
+            UNBIND => ExtraPduBody::Unbind,
+            UNBIND_RESP => ExtraPduBody::UnbindResp,
+            _ => {
+                return Err(with_header(PduParseError::new(
+                    PduParseErrorBody::UnknownCommandId,
+                )))
+            }
+        };
+
+        Self::new(command_status, sequence_number, body).map_err(with_header)
+    }
+}
+
+/// Either a PDU that smpp_pdu understands, or one of ours
+#[derive(Debug)]
+pub enum AnyPdu {
+    Pdu(Pdu),
+    Extra(ExtraPdu),
+}
+
+impl AnyPdu {
+    pub fn command_id(&self) -> u32 {
+        match self {
+            AnyPdu::Pdu(pdu) => pdu.command_id().value,
+            AnyPdu::Extra(pdu) => pdu.command_id(),
+        }
+    }
+
+    pub fn command_status(&self) -> u32 {
+        match self {
+            AnyPdu::Pdu(pdu) => pdu.command_status.value,
+            AnyPdu::Extra(pdu) => pdu.command_status,
+        }
+    }
+
+    pub fn sequence_number(&self) -> u32 {
+        match self {
+            AnyPdu::Pdu(pdu) => pdu.sequence_number.value,
+            AnyPdu::Extra(pdu) => pdu.sequence_number,
+        }
+    }
+
+    /// True for responses (including generic_nack)
+    pub fn is_response(&self) -> bool {
+        self.command_id() & 0x80000000 != 0
+    }
+
+    pub async fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            AnyPdu::Pdu(pdu) => {
+                let mut ret = Vec::new();
+                // Writing to a Vec can't fail
+                pdu.write(&mut ret).await.unwrap();
+                ret
+            }
+            AnyPdu::Extra(pdu) => pdu.to_bytes(),
+        }
+    }
+}
+
+impl From<Pdu> for AnyPdu {
+    fn from(pdu: Pdu) -> Self {
+        AnyPdu::Pdu(pdu)
+    }
+}
+
+impl From<ExtraPdu> for AnyPdu {
+    fn from(pdu: ExtraPdu) -> Self {
+        AnyPdu::Extra(pdu)
+    }
+}
+
+/// Read command_id, command_status and sequence_number from the start of
+/// a PDU.
+pub fn read_header(bytes: &[u8]) -> Option<(u32, u32, u32)> {
+    let word = |i: usize| {
+        bytes
+            .get(i..i + 4)
+            .map(|b| u32::from_be_bytes(b.try_into().unwrap()))
+    };
+    Some((word(4)?, word(8)?, word(12)?))
+}
+
+fn write_c_octet_string(buf: &mut Vec<u8>, value: &str) {
+    buf.extend_from_slice(value.as_bytes());
+    buf.push(0);
+}
+
+fn read_c_octet_string(
+    bytes: &mut &[u8],
+    max_len: usize,
+) -> Result<String, PduParseError> {
+    let end = bytes
+        .iter()
+        .take(max_len)
+        .position(|b| *b == 0)
+        .ok_or_else(|| PduParseError::new(PduParseErrorBody::NotEnoughBytes))?;
+    let value = String::from_utf8_lossy(&bytes[..end]).into_owned();
+    *bytes = &bytes[end + 1..];
+    Ok(value)
+}
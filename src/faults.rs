@@ -0,0 +1,358 @@
+//! Fault injection, for testing how ESMEs cope with a badly-behaved SMSC.
+//! A FaultInjector sits in the write path of each SmppConnection, where
+//! it can delay, drop, reorder or replace responses, send DRs twice, slip
+//! malformed or oversized PDUs into the stream, or close the connection
+//! half way through a PDU.  Each fault has its own FaultRate: a
+//! probability, or a schedule of every Nth PDU it could apply to.
+
+use clap::Clap;
+use log::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use smpp_pdu::pdu::{PduBody, PduStatus};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::extra_pdu::{read_header, AnyPdu, GENERIC_NACK};
+
+const DELIVER_SM: u32 = 0x00000005;
+const ESM_CLASS_MESSAGE_TYPE_MASK: u8 = 0b0011_1100;
+const ESM_CLASS_DELIVERY_RECEIPT: u8 = 0b0000_0100;
+
+/// Bigger than any valid PDU can be, since a TLV holds at most 65535 bytes
+const OVERSIZED_PDU_LENGTH: usize = 100_000;
+
+/// sequence_number of the PDUs we make up, which no real request uses
+const INJECTED_SEQUENCE_NUMBER: u32 = 0x7fffffff;
+
+/// How often a fault happens
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FaultRate {
+    Never,
+    /// To each PDU it could apply to, with this probability from 0 to 1
+    Probability(f64),
+    /// To every Nth PDU it could apply to, counting from the first
+    Every(u64),
+}
+
+impl FromStr for FaultRate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "Invalid fault rate '{}': expected a probability from 0 to 1, \
+                or every:N",
+                s
+            )
+        };
+        if let Some(n) = s.strip_prefix("every:") {
+            return match n.parse::<u64>() {
+                Ok(n) if n > 0 => Ok(FaultRate::Every(n)),
+                _ => Err(invalid()),
+            };
+        }
+        match s.parse::<f64>() {
+            Ok(0.0) => Ok(FaultRate::Never),
+            Ok(p) if (0.0..=1.0).contains(&p) => Ok(FaultRate::Probability(p)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// The faults a FaultInjector can inject
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Fault {
+    DelayResponse,
+    DropResponse,
+    ReorderResponse,
+    DuplicateDr,
+    MalformedPdu,
+    OversizedPdu,
+    CloseMidPdu,
+    GenericNack,
+}
+
+const ALL_FAULTS: [Fault; 8] = [
+    Fault::DelayResponse,
+    Fault::DropResponse,
+    Fault::ReorderResponse,
+    Fault::DuplicateDr,
+    Fault::MalformedPdu,
+    Fault::OversizedPdu,
+    Fault::CloseMidPdu,
+    Fault::GenericNack,
+];
+
+/// Which faults to inject into the PDUs the SMSC writes.  Rates are a
+/// probability from 0 to 1, or every:N for every Nth PDU the fault could
+/// apply to.  By default there are none.
+#[derive(Clap, Clone, Debug)]
+pub struct FaultConfig {
+    /// How often to delay a response by fault-delay-ms
+    #[clap(long, default_value = "0", env = "FAULT_DELAY_RESPONSE")]
+    pub fault_delay_response: FaultRate,
+
+    /// How long to delay responses for, and the most to hold one back
+    /// for, in milliseconds
+    #[clap(long, default_value = "5000", env = "FAULT_DELAY_MS")]
+    pub fault_delay_ms: u64,
+
+    /// How often to drop a response instead of sending it
+    #[clap(long, default_value = "0", env = "FAULT_DROP_RESPONSE")]
+    pub fault_drop_response: FaultRate,
+
+    /// How often to hold a response back and send it after the next one,
+    /// or on its own after fault-delay-ms if no other response comes
+    #[clap(long, default_value = "0", env = "FAULT_REORDER_RESPONSE")]
+    pub fault_reorder_response: FaultRate,
+
+    /// How often to send a DR a second time, with a new sequence_number
+    #[clap(long, default_value = "0", env = "FAULT_DUPLICATE_DR")]
+    pub fault_duplicate_dr: FaultRate,
+
+    /// How often to send a deliver_sm with a body that cannot be parsed
+    /// before a PDU
+    #[clap(long, default_value = "0", env = "FAULT_MALFORMED_PDU")]
+    pub fault_malformed_pdu: FaultRate,
+
+    /// How often to send a deliver_sm bigger than any valid PDU before a
+    /// PDU
+    #[clap(long, default_value = "0", env = "FAULT_OVERSIZED_PDU")]
+    pub fault_oversized_pdu: FaultRate,
+
+    /// How often to send only the first half of a PDU and then close the
+    /// connection
+    #[clap(long, default_value = "0", env = "FAULT_CLOSE_MID_PDU")]
+    pub fault_close_mid_pdu: FaultRate,
+
+    /// How often to answer a request with generic_nack (ESME_RSYSERR)
+    /// instead of its proper response
+    #[clap(long, default_value = "0", env = "FAULT_GENERIC_NACK")]
+    pub fault_generic_nack: FaultRate,
+}
+
+impl Default for FaultConfig {
+    /// The same as the flags' defaults: no faults
+    fn default() -> Self {
+        Self {
+            fault_delay_response: FaultRate::Never,
+            fault_delay_ms: 5000,
+            fault_drop_response: FaultRate::Never,
+            fault_reorder_response: FaultRate::Never,
+            fault_duplicate_dr: FaultRate::Never,
+            fault_malformed_pdu: FaultRate::Never,
+            fault_oversized_pdu: FaultRate::Never,
+            fault_close_mid_pdu: FaultRate::Never,
+            fault_generic_nack: FaultRate::Never,
+        }
+    }
+}
+
+impl FaultConfig {
+    pub fn rate(&self, fault: Fault) -> FaultRate {
+        match fault {
+            Fault::DelayResponse => self.fault_delay_response,
+            Fault::DropResponse => self.fault_drop_response,
+            Fault::ReorderResponse => self.fault_reorder_response,
+            Fault::DuplicateDr => self.fault_duplicate_dr,
+            Fault::MalformedPdu => self.fault_malformed_pdu,
+            Fault::OversizedPdu => self.fault_oversized_pdu,
+            Fault::CloseMidPdu => self.fault_close_mid_pdu,
+            Fault::GenericNack => self.fault_generic_nack,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        ALL_FAULTS
+            .iter()
+            .all(|fault| self.rate(*fault) == FaultRate::Never)
+    }
+}
+
+/// What to write in place of one PDU
+#[derive(Debug, Default, PartialEq)]
+pub struct FaultPlan {
+    /// Wait this long before writing anything
+    pub delay: Option<Duration>,
+    /// Write each of these, in order
+    pub writes: Vec<Vec<u8>>,
+    /// Close the connection after the writes
+    pub close: bool,
+    /// A response was held back: send it on its own after this long if
+    /// no other response has taken it along
+    pub flush_held_after: Option<Duration>,
+}
+
+/// Decides, PDU by PDU, which faults to inject.  Shared by every
+/// connection, so schedules count PDUs across all of them.
+pub struct FaultInjector {
+    state: Mutex<FaultState>,
+}
+
+struct FaultState {
+    config: FaultConfig,
+    rng: StdRng,
+    /// How many PDUs each scheduled fault could have applied to
+    counts: HashMap<Fault, u64>,
+}
+
+impl FaultInjector {
+    pub fn new(config: FaultConfig) -> Self {
+        Self {
+            state: Mutex::new(FaultState {
+                config,
+                rng: StdRng::from_entropy(),
+                counts: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Use a new config from the next PDU written, restarting schedules
+    pub fn configure(&self, config: FaultConfig) {
+        let mut state = self.state.lock().unwrap();
+        state.config = config;
+        state.counts.clear();
+    }
+
+    /// How to write the PDU in bytes.  held is the connection's response
+    /// being held back to be sent after the next one.
+    pub fn plan(
+        &self,
+        bytes: Vec<u8>,
+        held: &mut Option<Vec<u8>>,
+    ) -> FaultPlan {
+        let mut plan = FaultPlan::default();
+        let mut state = self.state.lock().unwrap();
+        if state.config.is_empty() {
+            plan.writes
+                .extend(Some(bytes).into_iter().chain(held.take()));
+            return plan;
+        }
+
+        let (command_id, _, sequence_number) =
+            read_header(&bytes).unwrap_or_default();
+        if state.fires(Fault::MalformedPdu) {
+            info!("Fault: sending a malformed PDU");
+            plan.writes.push(malformed_pdu());
+        }
+        if state.fires(Fault::OversizedPdu) {
+            info!("Fault: sending an oversized PDU");
+            plan.writes.push(oversized_pdu());
+        }
+        let is_response = command_id & GENERIC_NACK != 0;
+        let mut bytes = bytes;
+        if is_response {
+            if state.fires(Fault::DropResponse) {
+                info!("Fault: dropping response {:#010x}", command_id);
+                return plan;
+            }
+            if state.fires(Fault::GenericNack) {
+                info!("Fault: generic_nack instead of {:#010x}", command_id);
+                bytes = generic_nack(sequence_number);
+            }
+            if state.fires(Fault::DelayResponse) {
+                let ms = state.config.fault_delay_ms;
+                info!("Fault: delaying response {:#010x} {}ms", command_id, ms);
+                plan.delay = Some(Duration::from_millis(ms));
+            }
+        }
+        if state.fires(Fault::CloseMidPdu) {
+            info!("Fault: closing connection mid-PDU {:#010x}", command_id);
+            bytes.truncate(bytes.len() / 2);
+            plan.writes.push(bytes);
+            plan.close = true;
+            return plan;
+        }
+        if is_response && held.is_none() && state.fires(Fault::ReorderResponse)
+        {
+            info!("Fault: holding back response {:#010x}", command_id);
+            *held = Some(bytes);
+            plan.flush_held_after =
+                Some(Duration::from_millis(state.config.fault_delay_ms));
+            return plan;
+        }
+
+        plan.writes.push(bytes);
+        if is_response {
+            plan.writes.extend(held.take());
+        }
+        plan
+    }
+
+    /// True if pdu is a DR that should be sent twice
+    pub fn duplicate_dr(&self, pdu: &AnyPdu) -> bool {
+        is_dr(pdu) && self.state.lock().unwrap().fires(Fault::DuplicateDr)
+    }
+}
+
+impl FaultState {
+    fn fires(&mut self, fault: Fault) -> bool {
+        match self.config.rate(fault) {
+            FaultRate::Never => false,
+            FaultRate::Probability(p) => self.rng.gen_bool(p),
+            FaultRate::Every(n) => {
+                let count = self.counts.entry(fault).or_insert(0);
+                *count += 1;
+                (*count).is_multiple_of(n)
+            }
+        }
+    }
+}
+
+fn is_dr(pdu: &AnyPdu) -> bool {
+    match pdu {
+        AnyPdu::Pdu(pdu) => match pdu.body() {
+            PduBody::DeliverSm(body) => {
+                body.0.esm_class.value & ESM_CLASS_MESSAGE_TYPE_MASK
+                    == ESM_CLASS_DELIVERY_RECEIPT
+            }
+            _ => false,
+        },
+        AnyPdu::Extra(_) => false,
+    }
+}
+
+fn header(
+    command_length: usize,
+    command_id: u32,
+    command_status: u32,
+    sequence_number: u32,
+) -> Vec<u8> {
+    let mut ret = Vec::with_capacity(command_length);
+    ret.extend_from_slice(&(command_length as u32).to_be_bytes());
+    ret.extend_from_slice(&command_id.to_be_bytes());
+    ret.extend_from_slice(&command_status.to_be_bytes());
+    ret.extend_from_slice(&sequence_number.to_be_bytes());
+    ret
+}
+
+/// A deliver_sm whose service_type never ends, so it can't be parsed
+fn malformed_pdu() -> Vec<u8> {
+    let mut ret = header(24, DELIVER_SM, 0, INJECTED_SEQUENCE_NUMBER);
+    ret.extend_from_slice(&[0xff; 8]);
+    ret
+}
+
+/// A deliver_sm of OVERSIZED_PDU_LENGTH bytes, which is all there
+fn oversized_pdu() -> Vec<u8> {
+    let mut ret = header(
+        OVERSIZED_PDU_LENGTH,
+        DELIVER_SM,
+        0,
+        INJECTED_SEQUENCE_NUMBER,
+    );
+    ret.resize(OVERSIZED_PDU_LENGTH, 0);
+    ret
+}
+
+fn generic_nack(sequence_number: u32) -> Vec<u8> {
+    header(
+        16,
+        GENERIC_NACK,
+        PduStatus::ESME_RSYSERR as u32,
+        sequence_number,
+    )
+}
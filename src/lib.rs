@@ -1,6 +1,26 @@
+pub mod address;
 pub mod async_result;
+pub mod capture;
+pub mod concatenation;
+pub mod esme;
 pub mod examples;
+pub mod extra_pdu;
+pub mod faults;
+pub mod load;
+pub mod logging;
+pub mod message_id_generator;
 pub mod message_unique_key;
+pub mod rate_limiter;
+pub mod replay;
+pub mod router;
+pub mod routing;
+pub mod script;
+pub mod segmentation;
+pub mod send;
+pub mod sequence_number_generator;
+pub mod simulator;
+pub mod sm_fields;
 pub mod smpp_connection;
 pub mod smsc;
+#[allow(dead_code)]
 mod unittest_utils;
@@ -0,0 +1,214 @@
+use async_trait::async_trait;
+use futures::FutureExt;
+use log::*;
+use smpp_pdu::pdu::DeliverSmPdu;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{Notify, Semaphore};
+use tokio::task::JoinSet;
+use tokio::time::{timeout_at, Instant};
+
+use crate::async_result::AsyncResult;
+use crate::esme::{DeliverSmError, EsmeError, EsmeLogic, EsmePool};
+use crate::load::load_config::LoadConfig;
+use crate::load::report::{LoadReport, Percentiles};
+use crate::rate_limiter::RateLimiter;
+use crate::sm_fields::SmFields;
+
+/// Run the load test in config, and print its report to stdout
+pub fn run(config: LoadConfig) -> AsyncResult<()> {
+    let rt = tokio::runtime::Runtime::new()?;
+    let report = rt.block_on(load(&config))?;
+    let mut stdout = std::io::stdout();
+    if config.json {
+        writeln!(stdout, "{}", serde_json::to_string_pretty(&report)?)?;
+    } else {
+        write!(stdout, "{}", report)?;
+    }
+    Ok(())
+}
+
+/// Bind config.sessions times, send config.count submit_sm at config.rate
+/// per second, keeping at most window_size awaiting a response on each
+/// bind, then wait for the DRs if we asked for them.
+pub async fn load(config: &LoadConfig) -> AsyncResult<LoadReport> {
+    let fields = SmFields {
+        source_addr_ton: 5,
+        source_addr: config.source_addr.clone(),
+        dest_addr_ton: 1,
+        dest_addr_npi: 1,
+        destination_addr: config.destination_addr.clone(),
+        registered_delivery: config.registered_delivery,
+        short_message: config.message.as_bytes().to_vec(),
+        ..Default::default()
+    };
+    // Fail before binding if the message cannot be sent
+    fields.to_submit_sm()?;
+
+    let tracker = Arc::new(Mutex::new(Tracker::default()));
+    let dr_arrived = Arc::new(Notify::new());
+    let logic = TrackingLogic {
+        tracker: Arc::clone(&tracker),
+        dr_arrived: Arc::clone(&dr_arrived),
+    };
+    let pool = Arc::new(EsmePool::connect(config.pool_config(), logic).await?);
+
+    // Waiting for a window slot here, rather than inside submit_sm, keeps
+    // that wait out of the latencies and slows our rate to what the SMSC
+    // can take.
+    let window = Arc::new(Semaphore::new(
+        config.sessions.max(1) * config.esme.window_size.max(1),
+    ));
+    let rate_limiter = RateLimiter::new(config.rate);
+    let started = Instant::now();
+    let mut tasks = JoinSet::new();
+    for _ in 0..config.count {
+        rate_limiter.acquire().await;
+        let permit = Arc::clone(&window).acquire_owned().await?;
+        let pool = Arc::clone(&pool);
+        let tracker = Arc::clone(&tracker);
+        let pdu = fields.to_submit_sm()?;
+        tasks.spawn(async move {
+            let sent = Instant::now();
+            let result = pool.submit_sm(pdu).await;
+            drop(permit);
+            tracker.lock().unwrap().submit_sm_resp(sent, result);
+        });
+        // Collect the finished ones as we go, so a long run does not keep
+        // a task for every message
+        while let Some(Some(finished)) = tasks.join_next().now_or_never() {
+            finished?;
+        }
+    }
+    while let Some(finished) = tasks.join_next().await {
+        finished?;
+    }
+    let elapsed = started.elapsed();
+
+    if config.wants_drs() {
+        let deadline =
+            Instant::now() + Duration::from_secs(config.dr_wait_secs);
+        while !tracker.lock().unwrap().awaiting_drs.is_empty() {
+            if timeout_at(deadline, dr_arrived.notified()).await.is_err() {
+                break;
+            }
+        }
+    }
+
+    if let Err(e) = pool.unbind().await {
+        warn!("Failed to unbind: {}", e);
+    }
+
+    let tracker = tracker.lock().unwrap();
+    Ok(tracker.report(config, elapsed))
+}
+
+/// What we have seen so far
+#[derive(Default)]
+struct Tracker {
+    submit_sm_resp_latencies: Vec<Duration>,
+    accepted: u64,
+    errors: BTreeMap<String, u64>,
+    /// When we sent each accepted message whose DR has not arrived
+    awaiting_drs: HashMap<String, Instant>,
+    /// DRs that arrived before the submit_sm_resp for their message, and
+    /// when
+    early_drs: HashMap<String, Instant>,
+    dr_latencies: Vec<Duration>,
+}
+
+impl Tracker {
+    fn submit_sm_resp(
+        &mut self,
+        sent: Instant,
+        result: Result<String, EsmeError>,
+    ) {
+        let now = Instant::now();
+        match result {
+            Ok(message_id) => {
+                self.submit_sm_resp_latencies.push(now - sent);
+                self.accepted += 1;
+                match self.early_drs.remove(&message_id) {
+                    Some(arrived) => self.dr_latencies.push(arrived - sent),
+                    None => {
+                        self.awaiting_drs.insert(message_id, sent);
+                    }
+                }
+            }
+            Err(e) => {
+                let error = match e {
+                    EsmeError::ErrorResponse(status) => {
+                        self.submit_sm_resp_latencies.push(now - sent);
+                        format!("{:#010x}", status)
+                    }
+                    e => e.to_string(),
+                };
+                *self.errors.entry(error).or_insert(0) += 1;
+            }
+        }
+    }
+
+    fn dr(&mut self, message_id: String) {
+        match self.awaiting_drs.remove(&message_id) {
+            Some(sent) => self.dr_latencies.push(sent.elapsed()),
+            None => {
+                self.early_drs.insert(message_id, Instant::now());
+            }
+        }
+    }
+
+    fn report(&self, config: &LoadConfig, elapsed: Duration) -> LoadReport {
+        let failed = self.errors.values().sum::<u64>();
+        let submitted = self.accepted + failed;
+        let elapsed_secs = elapsed.as_secs_f64();
+        LoadReport {
+            sessions: config.sessions,
+            submitted,
+            accepted: self.accepted,
+            failed,
+            errors: self.errors.clone(),
+            elapsed_secs,
+            throughput: if elapsed_secs > 0.0 {
+                submitted as f64 / elapsed_secs
+            } else {
+                0.0
+            },
+            submit_sm_resp_ms: Percentiles::from_durations(
+                &self.submit_sm_resp_latencies,
+            ),
+            drs_received: self.dr_latencies.len() as u64,
+            drs_missing: if config.wants_drs() {
+                self.awaiting_drs.len() as u64
+            } else {
+                0
+            },
+            // DRs we could not match to a submit_sm_resp by the end are
+            // not for our messages
+            drs_unmatched: self.early_drs.len() as u64,
+            dr_ms: Percentiles::from_durations(&self.dr_latencies),
+        }
+    }
+}
+
+/// Times each DR against the submit_sm it is for
+struct TrackingLogic {
+    tracker: Arc<Mutex<Tracker>>,
+    dr_arrived: Arc<Notify>,
+}
+
+#[async_trait]
+impl EsmeLogic for TrackingLogic {
+    async fn deliver_sm(
+        &mut self,
+        pdu: &DeliverSmPdu,
+    ) -> Result<(), DeliverSmError> {
+        // Anything else is an MO, which we ignore
+        if let Some(message_id) = pdu.extract_receipted_message_id() {
+            self.tracker.lock().unwrap().dr(message_id);
+            self.dr_arrived.notify_one();
+        }
+        Ok(())
+    }
+}
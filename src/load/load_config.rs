@@ -0,0 +1,70 @@
+use clap::Clap;
+
+use crate::esme::{Balancing, EsmeConfig, EsmePoolConfig};
+
+/// Load generator: submits messages to an SMSC over several binds at a
+/// target rate, and reports throughput, latencies and errors
+#[derive(Clap, Clone, Debug)]
+#[clap(name = "smpp-load")]
+pub struct LoadConfig {
+    #[clap(flatten)]
+    pub esme: EsmeConfig,
+
+    /// How many binds to submit over
+    #[clap(long, default_value = "1")]
+    pub sessions: usize,
+
+    /// The number of submit_sm to send per second, across all sessions
+    #[clap(long, default_value = "100")]
+    pub rate: u32,
+
+    /// How many submit_sm to send in all
+    #[clap(long, default_value = "1000")]
+    pub count: u64,
+
+    /// destination_addr of every message
+    #[clap(long = "to", default_value = "447700900000")]
+    pub destination_addr: String,
+
+    /// source_addr of every message
+    #[clap(long = "from", default_value = "LoadTest")]
+    pub source_addr: String,
+
+    /// The text of every message
+    #[clap(long, default_value = "Load test message")]
+    pub message: String,
+
+    /// 1 to ask for a DR for every message, and time its arrival
+    #[clap(long, default_value = "1")]
+    pub registered_delivery: u8,
+
+    /// How long to wait for outstanding DRs after the last
+    /// submit_sm_resp
+    #[clap(long, default_value = "30")]
+    pub dr_wait_secs: u64,
+
+    /// Write the report as JSON
+    #[clap(long)]
+    pub json: bool,
+
+    #[clap(long, default_value = "warn")]
+    pub log_level: String,
+}
+
+impl LoadConfig {
+    /// The binds to submit over.  We do our own rate limiting, so that
+    /// the time spent waiting for it does not count as latency.
+    pub fn pool_config(&self) -> EsmePoolConfig {
+        EsmePoolConfig {
+            esme: self.esme.clone(),
+            binds: self.sessions,
+            balancing: Balancing::LeastOutstanding,
+            max_submits_per_sec: None,
+        }
+    }
+
+    /// Whether the SMSC should send a DR for every message
+    pub fn wants_drs(&self) -> bool {
+        self.registered_delivery & 0x03 == 1
+    }
+}
@@ -0,0 +1,11 @@
+//! smpp-load: binds several times to an SMSC, submits messages at a
+//! target rate and reports throughput, latencies and errors.
+
+#[allow(clippy::module_inception)]
+pub mod load;
+pub mod load_config;
+pub mod report;
+
+pub use load::{load, run};
+pub use load_config::LoadConfig;
+pub use report::{LoadReport, Percentiles};
@@ -0,0 +1,121 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+
+/// What happened in a run of the load generator
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct LoadReport {
+    pub sessions: usize,
+    /// submit_sm that got a response or failed
+    pub submitted: u64,
+    /// submit_sm_resp with command_status 0
+    pub accepted: u64,
+    pub failed: u64,
+    /// Failed submit_sm by command_status (e.g. "0x00000058"), or by
+    /// what went wrong if there was no response
+    pub errors: BTreeMap<String, u64>,
+    /// From the first submit_sm to the last response
+    pub elapsed_secs: f64,
+    /// submit_sm answered per second
+    pub throughput: f64,
+    /// From sending each submit_sm to its response, not counting the wait
+    /// for a window slot before it is sent
+    pub submit_sm_resp_ms: Option<Percentiles>,
+    /// DRs for messages we sent
+    pub drs_received: u64,
+    /// Accepted messages whose DR did not come in time
+    pub drs_missing: u64,
+    /// DRs whose message_id was not one of ours
+    pub drs_unmatched: u64,
+    /// From sending each submit_sm to its DR arriving
+    pub dr_ms: Option<Percentiles>,
+}
+
+/// Latencies in milliseconds
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Percentiles {
+    pub count: usize,
+    pub min: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+impl Percentiles {
+    /// None if there are no durations
+    pub fn from_durations(durations: &[Duration]) -> Option<Self> {
+        let mut sorted = durations.to_vec();
+        sorted.sort();
+        let at = |fraction: f64| {
+            // Nearest rank: the smallest value with at least fraction of
+            // the values at or below it
+            let rank = (fraction * sorted.len() as f64).ceil() as usize;
+            sorted[rank.clamp(1, sorted.len()) - 1].as_secs_f64() * 1000.0
+        };
+        if sorted.is_empty() {
+            return None;
+        }
+        Some(Self {
+            count: sorted.len(),
+            min: at(0.0),
+            p50: at(0.5),
+            p90: at(0.9),
+            p99: at(0.99),
+            max: at(1.0),
+        })
+    }
+}
+
+impl Display for Percentiles {
+    fn fmt(
+        &self,
+        formatter: &mut Formatter,
+    ) -> std::result::Result<(), std::fmt::Error> {
+        formatter.write_str(&format!(
+            "min {:.1}ms  p50 {:.1}ms  p90 {:.1}ms  p99 {:.1}ms  \
+            max {:.1}ms",
+            self.min, self.p50, self.p90, self.p99, self.max
+        ))
+    }
+}
+
+impl Display for LoadReport {
+    fn fmt(
+        &self,
+        formatter: &mut Formatter,
+    ) -> std::result::Result<(), std::fmt::Error> {
+        let latencies = |percentiles: &Option<Percentiles>| {
+            percentiles
+                .as_ref()
+                .map_or_else(|| String::from("none"), Percentiles::to_string)
+        };
+        let mut s = format!(
+            "Sessions:        {}\n\
+            Submitted:       {} in {:.2}s ({:.1}/s)\n\
+            Accepted:        {}\n\
+            Failed:          {}\n",
+            self.sessions,
+            self.submitted,
+            self.elapsed_secs,
+            self.throughput,
+            self.accepted,
+            self.failed,
+        );
+        for (error, count) in &self.errors {
+            s += &format!("  {}: {}\n", error, count);
+        }
+        s += &format!(
+            "submit_sm_resp:  {}\n\
+            DRs:             {} received, {} missing, {} unmatched\n\
+            DR arrival:      {}\n",
+            latencies(&self.submit_sm_resp_ms),
+            self.drs_received,
+            self.drs_missing,
+            self.drs_unmatched,
+            latencies(&self.dr_ms),
+        );
+        formatter.write_str(&s)
+    }
+}
@@ -0,0 +1,365 @@
+//! Logging setup for the binaries, with a level that can be changed while
+//! running, and formatting of PDUs for the log that can hide what is
+//! private in them.
+//!
+//! Logs go through tracing, so each line carries the session and PDU
+//! spans it was written in.  Lines from the log macros are forwarded to
+//! tracing, so they get the spans too.
+
+use log::*;
+use smpp_pdu::pdu::data::bind_data::BindData;
+use smpp_pdu::pdu::tlvs::Tlv;
+use smpp_pdu::pdu::{Pdu, PduBody};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::OnceLock;
+use tracing::{info_span, Span};
+use tracing_subscriber::filter::LevelFilter as TracingLevelFilter;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+use crate::extra_pdu::{AnyPdu, ExtraPdu, ExtraPduBody};
+use crate::sm_fields::SmFields;
+use crate::smsc::metrics::command_name;
+
+/// How log lines are written
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LogFormat {
+    /// Human-readable lines
+    Text,
+    /// One JSON object per line, with the span fields as keys
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format '{}'", s)),
+        }
+    }
+}
+
+/// How much of the private parts of PDUs (phone numbers, message content
+/// and passwords) is logged
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Redaction {
+    /// Log PDUs whole
+    None,
+    /// Log only the last 4 characters of addresses, and only the length
+    /// of message content
+    Mask,
+    /// Log neither addresses nor message content
+    Hide,
+}
+
+impl FromStr for Redaction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Redaction::None),
+            "mask" => Ok(Redaction::Mask),
+            "hide" => Ok(Redaction::Hide),
+            _ => Err(format!("unknown log redaction '{}'", s)),
+        }
+    }
+}
+
+static REDACTION: AtomicU8 = AtomicU8::new(0);
+
+type LevelHandle = reload::Handle<TracingLevelFilter, Registry>;
+
+static LEVEL_HANDLE: OnceLock<LevelHandle> = OnceLock::new();
+
+/// Log at level in format, unless RUST_LOG is set, in which case it
+/// decides the level
+pub fn init(level: &str, format: LogFormat) {
+    let env_filter =
+        std::env::var_os("RUST_LOG").map(|_| EnvFilter::from_default_env());
+    let (level_filter, handle) = reload::Layer::new(TracingLevelFilter::TRACE);
+    let fmt_layer = match format {
+        LogFormat::Text => tracing_subscriber::fmt::layer().boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .with_current_span(false)
+            .with_span_list(true)
+            .boxed(),
+    };
+    tracing_subscriber::registry()
+        .with(level_filter)
+        .with(env_filter)
+        .with(fmt_layer)
+        .init();
+    // Only keep the handle if it is ours to change
+    if std::env::var_os("RUST_LOG").is_none() {
+        let _ = LEVEL_HANDLE.set(handle);
+    }
+    set_level(level);
+}
+
+/// Change the level given to init.  Does nothing if RUST_LOG is set.
+pub fn set_level(level: &str) {
+    if std::env::var_os("RUST_LOG").is_some() {
+        return;
+    }
+    match level.parse::<LevelFilter>() {
+        Ok(level) => {
+            if let Some(handle) = LEVEL_HANDLE.get() {
+                let tracing_level = level
+                    .to_string()
+                    .parse()
+                    .unwrap_or(TracingLevelFilter::TRACE);
+                if let Err(e) = handle.reload(tracing_level) {
+                    warn!("Failed to change the log level: {}", e);
+                }
+            }
+            log::set_max_level(level);
+        }
+        Err(_) => warn!("Ignoring unknown log level '{}'", level),
+    }
+}
+
+/// Change how PDUs are redacted when logged with LoggedPdu
+pub fn set_redaction(redaction: Redaction) {
+    REDACTION.store(redaction as u8, Ordering::Relaxed);
+}
+
+pub fn redaction() -> Redaction {
+    match REDACTION.load(Ordering::Relaxed) {
+        1 => Redaction::Mask,
+        2 => Redaction::Hide,
+        _ => Redaction::None,
+    }
+}
+
+/// The span for handling a PDU we received, inside its session's span
+pub fn pdu_span(pdu: &AnyPdu) -> Span {
+    info_span!(
+        "pdu",
+        command = %command_name(pdu.command_id()),
+        sequence_number = pdu.sequence_number(),
+    )
+}
+
+/// A PDU to log, formatted with the redaction set by set_redaction
+pub enum LoggedPdu<'a> {
+    Pdu(&'a Pdu),
+    Extra(&'a ExtraPdu),
+}
+
+impl<'a> From<&'a Pdu> for LoggedPdu<'a> {
+    fn from(pdu: &'a Pdu) -> Self {
+        LoggedPdu::Pdu(pdu)
+    }
+}
+
+impl<'a> From<&'a ExtraPdu> for LoggedPdu<'a> {
+    fn from(pdu: &'a ExtraPdu) -> Self {
+        LoggedPdu::Extra(pdu)
+    }
+}
+
+impl<'a> From<&'a AnyPdu> for LoggedPdu<'a> {
+    fn from(pdu: &'a AnyPdu) -> Self {
+        match pdu {
+            AnyPdu::Pdu(pdu) => LoggedPdu::Pdu(pdu),
+            AnyPdu::Extra(pdu) => LoggedPdu::Extra(pdu),
+        }
+    }
+}
+
+impl LoggedPdu<'_> {
+    /// Format with an explicit redaction, rather than the one set
+    pub fn with_redaction(&self, redaction: Redaction) -> String {
+        Redacted {
+            pdu: self,
+            redaction,
+        }
+        .to_string()
+    }
+}
+
+impl fmt::Display for LoggedPdu<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Redacted {
+            pdu: self,
+            redaction: redaction(),
+        }
+        .fmt(f)
+    }
+}
+
+struct Redacted<'a, 'b> {
+    pdu: &'a LoggedPdu<'b>,
+    redaction: Redaction,
+}
+
+impl fmt::Display for Redacted<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let redaction = self.redaction;
+        if redaction == Redaction::None {
+            return match self.pdu {
+                LoggedPdu::Pdu(pdu) => write!(f, "{:?}", pdu),
+                LoggedPdu::Extra(pdu) => write!(f, "{:?}", pdu),
+            };
+        }
+        match self.pdu {
+            LoggedPdu::Pdu(pdu) => {
+                let header = Header {
+                    command_status: pdu.command_status.value,
+                    sequence_number: pdu.sequence_number.value,
+                };
+                match pdu.body() {
+                    PduBody::SubmitSm(body) => header.sm(
+                        f,
+                        "SubmitSm",
+                        &SmFields::from_sm_data(&body.0),
+                        redaction,
+                    ),
+                    PduBody::DeliverSm(body) => header.sm(
+                        f,
+                        "DeliverSm",
+                        &SmFields::from_sm_data(&body.0),
+                        redaction,
+                    ),
+                    PduBody::BindReceiver(body) => {
+                        header.bind(f, "BindReceiver", &body.0)
+                    }
+                    PduBody::BindTransmitter(body) => {
+                        header.bind(f, "BindTransmitter", &body.0)
+                    }
+                    PduBody::BindTransceiver(body) => {
+                        header.bind(f, "BindTransceiver", &body.0)
+                    }
+                    _ => write!(f, "{:?}", pdu),
+                }
+            }
+            LoggedPdu::Extra(pdu) => {
+                let header = Header {
+                    command_status: pdu.command_status,
+                    sequence_number: pdu.sequence_number,
+                };
+                match &pdu.body {
+                    ExtraPduBody::BindReceiver(bind_data) => {
+                        header.bind(f, "BindReceiver", bind_data)
+                    }
+                    ExtraPduBody::BindTransmitter(bind_data) => {
+                        header.bind(f, "BindTransmitter", bind_data)
+                    }
+                    ExtraPduBody::BindTransceiver(bind_data) => {
+                        header.bind(f, "BindTransceiver", bind_data)
+                    }
+                    ExtraPduBody::Outbind { system_id, .. } => f
+                        .debug_struct("Outbind")
+                        .field("command_status", &header.command_status)
+                        .field("sequence_number", &header.sequence_number)
+                        .field("system_id", system_id)
+                        .field("password", &Hidden)
+                        .finish(),
+                    _ => write!(f, "{:?}", pdu),
+                }
+            }
+        }
+    }
+}
+
+struct Header {
+    command_status: u32,
+    sequence_number: u32,
+}
+
+impl Header {
+    fn sm(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        name: &str,
+        fields: &SmFields,
+        redaction: Redaction,
+    ) -> fmt::Result {
+        let tags: Vec<TlvTag> = fields.tlvs.iter().map(TlvTag).collect();
+        f.debug_struct(name)
+            .field("command_status", &self.command_status)
+            .field("sequence_number", &self.sequence_number)
+            .field("service_type", &fields.service_type)
+            .field("source_addr_ton", &fields.source_addr_ton)
+            .field("source_addr_npi", &fields.source_addr_npi)
+            .field("source_addr", &redact(&fields.source_addr, redaction))
+            .field("dest_addr_ton", &fields.dest_addr_ton)
+            .field("dest_addr_npi", &fields.dest_addr_npi)
+            .field(
+                "destination_addr",
+                &redact(&fields.destination_addr, redaction),
+            )
+            .field("esm_class", &fields.esm_class)
+            .field("registered_delivery", &fields.registered_delivery)
+            .field("data_coding", &fields.data_coding)
+            .field("short_message", &Length(fields.short_message.len()))
+            .field("tlvs", &tags)
+            .finish()
+    }
+
+    fn bind(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        name: &str,
+        bind_data: &BindData,
+    ) -> fmt::Result {
+        f.debug_struct(name)
+            .field("command_status", &self.command_status)
+            .field("sequence_number", &self.sequence_number)
+            .field("system_id", &bind_data.system_id.value.to_string())
+            .field("password", &Hidden)
+            .field("system_type", &bind_data.system_type.value.to_string())
+            .field("interface_version", &bind_data.interface_version.value)
+            .field("address_range", &bind_data.address_range.value.to_string())
+            .finish()
+    }
+}
+
+/// An address as it should be logged
+pub fn redact(address: &str, redaction: Redaction) -> String {
+    match redaction {
+        Redaction::None => String::from(address),
+        Redaction::Mask => {
+            let len = address.chars().count();
+            // Never show more than half, so short addresses stay hidden
+            let shown = 4.min(len / 2);
+            address
+                .chars()
+                .enumerate()
+                .map(|(i, c)| if i < len - shown { '*' } else { c })
+                .collect()
+        }
+        Redaction::Hide => String::from("<hidden>"),
+    }
+}
+
+struct Hidden;
+
+impl fmt::Debug for Hidden {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("<hidden>")
+    }
+}
+
+struct Length(usize);
+
+impl fmt::Debug for Length {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<{} bytes>", self.0)
+    }
+}
+
+/// A TLV with its value left out, since that may be message_payload
+struct TlvTag<'a>(&'a Tlv);
+
+impl fmt::Debug for TlvTag<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{:04x} <{} bytes>", self.0.raw_tag, self.0.value.len())
+    }
+}
@@ -0,0 +1,33 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Hands out message IDs for messages we accept.  IDs are unique within
+/// this process, and since we start counting from the current time they
+/// are very unlikely to repeat after a restart.
+pub struct MessageIdGenerator {
+    next: AtomicU64,
+}
+
+impl MessageIdGenerator {
+    pub fn new() -> Self {
+        let start = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        Self {
+            // Leave room for 1000 messages per millisecond before we overlap
+            // with a later restart.
+            next: AtomicU64::new(start * 1000),
+        }
+    }
+
+    pub fn next_message_id(&self) -> String {
+        format!("{:X}", self.next.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl Default for MessageIdGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,8 +1,10 @@
-use smpp_pdu::pdu::DeliverSmPdu;
+use smpp_pdu::pdu::{DeliverSmPdu, SubmitSmPdu};
+
+use crate::address::{Address, NormalizationRules};
 
 /// A way to identify this message based on the message ID provided by
 /// some remove system.
-#[derive(Debug, Eq, Hash, PartialEq)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub struct MessageUniqueKey {
     /// An identifier for the system which generated the message_id.  For
     /// systems that produce sufficiently unique IDs, this serves as a
@@ -18,14 +20,30 @@ pub struct MessageUniqueKey {
     /// In a SMSC delivery receipt:
     ///   "The destination address will be taken from the source address of
     ///   the original short message which generated the delivery receipt."
-    pub destination_addr: String,
+    /// The SMSC normalizes this before using it, so it matches however
+    /// the supplier formats the number.
+    pub destination_addr: Address,
 }
 
 impl MessageUniqueKey {
+    /// Create a key for an address whose TON and NPI are unknown.  Prefer
+    /// from_submit_sm or with_address when the TON and NPI are available.
     pub fn new(
         namespace_id: String,
         message_id: String,
         destination_addr: String,
+    ) -> Self {
+        Self::with_address(
+            namespace_id,
+            message_id,
+            Address::unknown(&destination_addr),
+        )
+    }
+
+    pub fn with_address(
+        namespace_id: String,
+        message_id: String,
+        destination_addr: Address,
     ) -> Self {
         Self {
             namespace_id,
@@ -34,10 +52,34 @@ impl MessageUniqueKey {
         }
     }
 
+    /// Create a key using the destination address (with its TON and NPI)
+    /// of the supplied MT.
+    pub fn from_submit_sm(
+        namespace_id: String,
+        message_id: String,
+        pdu: &SubmitSmPdu,
+    ) -> Self {
+        let sm_data = &pdu.0;
+        Self::with_address(
+            namespace_id,
+            message_id,
+            Address::new(
+                sm_data.dest_addr_ton.value,
+                sm_data.dest_addr_npi.value,
+                sm_data.destination_addr.value.as_str(),
+            ),
+        )
+    }
+
     pub fn from_dr(namespace_id: String, pdu: &DeliverSmPdu) -> Option<Self> {
         // Use the source_addr from the DR as the destination_addr.  See
         // section 2.11 of https://smpp.org/SMPP_v3_4_Issue1_2.pdf
-        let destination_addr = pdu.source_addr();
+        let sm_data = &pdu.0;
+        let destination_addr = Address::new(
+            sm_data.source_addr_ton.value,
+            sm_data.source_addr_npi.value,
+            sm_data.source_addr.value.as_str(),
+        );
         let message_id = pdu.extract_receipted_message_id();
         message_id.map(|message_id| Self {
             namespace_id,
@@ -45,4 +87,13 @@ impl MessageUniqueKey {
             destination_addr,
         })
     }
+
+    /// The same key, with destination_addr in canonical form
+    pub fn normalize(&self, rules: &NormalizationRules) -> Self {
+        Self {
+            namespace_id: self.namespace_id.clone(),
+            message_id: self.message_id.clone(),
+            destination_addr: self.destination_addr.normalize(rules),
+        }
+    }
 }
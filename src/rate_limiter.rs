@@ -0,0 +1,46 @@
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::{sleep_until, Instant};
+
+/// Spaces out events so that no more than max_per_sec happen each second,
+/// across all the tasks that share this limiter.
+pub struct RateLimiter {
+    interval: Duration,
+    /// The earliest time the next event may happen
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(max_per_sec: u32) -> Self {
+        Self {
+            interval: Duration::from_secs(1) / max_per_sec.max(1),
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Wait until we may go ahead with the next event
+    pub async fn acquire(&self) {
+        let at = {
+            let mut next = self.next.lock().await;
+            let at = (*next).max(Instant::now());
+            *next = at + self.interval;
+            at
+        };
+        sleep_until(at).await;
+    }
+
+    /// Go ahead with the next event now if that keeps within the limit,
+    /// allowing bursts of up to one second's worth of events.  Returns
+    /// false, and counts nothing, if not.
+    pub async fn try_acquire(&self) -> bool {
+        let now = Instant::now();
+        let mut next = self.next.lock().await;
+        let at = (*next).max(now);
+        if at + self.interval <= now + Duration::from_secs(1) {
+            *next = at + self.interval;
+            true
+        } else {
+            false
+        }
+    }
+}
@@ -0,0 +1,12 @@
+//! Replay: re-send the ESME's side of a captured session (see
+//! crate::capture) to an SMSC, and report how the SMSC's side differs
+//! from the capture.
+
+#[allow(clippy::module_inception)]
+pub mod replay;
+pub mod replay_config;
+pub mod trace;
+
+pub use replay::{compare, replay, run, Difference, ReplayError};
+pub use replay_config::ReplayConfig;
+pub use trace::{parse_trace, read_trace, Side, TraceError, TracedPdu};
@@ -0,0 +1,640 @@
+use log::*;
+use smpp_pdu::pdu::data::bind_data::BindData;
+use smpp_pdu::pdu::tlvs::{KnownTlvTag, Tlv};
+use smpp_pdu::pdu::DeliverSmPdu;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::error;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::io::Cursor;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::OwnedReadHalf;
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, Mutex};
+use tokio::time::{sleep_until, timeout_at, Instant};
+
+use crate::async_result::AsyncResult;
+use crate::extra_pdu::{
+    ExtraPdu, ExtraPduBody, BIND_RECEIVER, BIND_TRANSCEIVER, BIND_TRANSMITTER,
+    DELIVER_SM_RESP, GENERIC_NACK,
+};
+use crate::replay::replay_config::ReplayConfig;
+use crate::replay::trace::{read_trace, Side, TracedPdu};
+use crate::sm_fields::SmFields;
+
+const RESPONSE: u32 = 0x80000000;
+const DELIVER_SM: u32 = 0x00000005;
+const UNBIND: u32 = 0x00000006;
+const SUBMIT_SM_RESP: u32 = 0x80000004;
+const ENQUIRE_LINK: u32 = 0x00000015;
+const SUBMIT_MULTI_RESP: u32 = 0x80000021;
+const DATA_SM_RESP: u32 = 0x80000103;
+const ESME_RINVCMDID: u32 = 0x00000003;
+const INTERFACE_VERSION: u8 = 0x34;
+const MAX_PDU_LENGTH: usize = 0x10000;
+const ESM_CLASS_MESSAGE_TYPE_MASK: u8 = 0b0011_1100;
+const ESM_CLASS_DELIVERY_RECEIPT: u8 = 0b0000_0100;
+
+/// Fields of a DR's text that differ from one run to the next
+const DR_VARYING_FIELDS: [&str; 3] = ["id:", "submit date:", "done date:"];
+
+/// How the SMSC's PDUs in a replay differed from the trace
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Difference {
+    /// A recorded PDU that never came
+    Missing(String),
+    /// A PDU that came but was not recorded
+    Unexpected(String),
+    /// A different command came in place of a recorded one
+    Command { expected: String, actual: String },
+    CommandStatus {
+        pdu: String,
+        expected: u32,
+        actual: u32,
+    },
+    /// The bodies differ, given here in hex
+    Content {
+        pdu: String,
+        expected: String,
+        actual: String,
+    },
+    /// The PDUs that came in both came in a different order
+    Order {
+        expected: Vec<String>,
+        actual: Vec<String>,
+    },
+}
+
+impl Display for Difference {
+    fn fmt(
+        &self,
+        formatter: &mut Formatter,
+    ) -> std::result::Result<(), std::fmt::Error> {
+        let s = match self {
+            Difference::Missing(pdu) => format!("Missing {}", pdu),
+            Difference::Unexpected(pdu) => format!("Unexpected {}", pdu),
+            Difference::Command { expected, actual } => {
+                format!("Expected {}, got {}", expected, actual)
+            }
+            Difference::CommandStatus {
+                pdu,
+                expected,
+                actual,
+            } => format!(
+                "{}: expected command_status 0x{:08x}, got 0x{:08x}",
+                pdu, expected, actual
+            ),
+            Difference::Content {
+                pdu,
+                expected,
+                actual,
+            } => format!(
+                "{}: expected body [{}], got [{}]",
+                pdu, expected, actual
+            ),
+            Difference::Order { expected, actual } => format!(
+                "Expected PDUs in the order {}, got {}",
+                expected.join(", "),
+                actual.join(", ")
+            ),
+        };
+        formatter.write_str(&s)
+    }
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    /// The trace has no bind, and no credentials were given to make one
+    NoBind,
+    /// A bind could not be made or rewritten
+    BadBind(String),
+    BadSpeed(f64),
+}
+
+impl Display for ReplayError {
+    fn fmt(
+        &self,
+        formatter: &mut Formatter,
+    ) -> std::result::Result<(), std::fmt::Error> {
+        let s = match self {
+            ReplayError::NoBind => String::from(
+                "The trace does not include the bind, so --system-id and \
+                --password must be given",
+            ),
+            ReplayError::BadBind(message) => {
+                format!("Unable to make the bind: {}", message)
+            }
+            ReplayError::BadSpeed(speed) => {
+                format!("Invalid speed {}: must be 0 or more", speed)
+            }
+        };
+        formatter.write_str(&s)
+    }
+}
+
+impl error::Error for ReplayError {}
+
+/// Replay the trace named in config, returning how the SMSC's PDUs
+/// differed from it
+pub fn run(config: ReplayConfig) -> AsyncResult<Vec<Difference>> {
+    let trace = read_trace(Path::new(&config.trace))?;
+    let rt = tokio::runtime::Runtime::new()?;
+    rt.block_on(replay(&config, trace))
+}
+
+enum Event {
+    Received(TracedPdu),
+    AllSent,
+}
+
+/// Connect to config.address and send the ESME's requests from trace, on
+/// its timeline scaled by config.speed, answering the SMSC's requests as
+/// the ESME did.  Then compare what the SMSC sent with what it sent in
+/// the trace.
+pub async fn replay(
+    config: &ReplayConfig,
+    trace: Vec<TracedPdu>,
+) -> AsyncResult<Vec<Difference>> {
+    let speed = config.speed;
+    if !(speed.is_finite() && speed >= 0.0) {
+        return Err(ReplayError::BadSpeed(speed).into());
+    }
+    let (pdus, added_bind) = prepare(config, trace)?;
+    let (requests, answers): (Vec<TracedPdu>, Vec<TracedPdu>) = pdus
+        .iter()
+        .filter(|pdu| pdu.side == Side::Esme)
+        .cloned()
+        .partition(|pdu| !pdu.is_response());
+    let expected: Vec<TracedPdu> = pdus
+        .into_iter()
+        .filter(|pdu| pdu.side == Side::Smsc)
+        .collect();
+
+    let (mut reader, writer) =
+        TcpStream::connect(&config.address).await?.into_split();
+    let writer = Arc::new(Mutex::new(writer));
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let start = Instant::now();
+
+    let reader_tx = tx.clone();
+    let reader_writer = Arc::clone(&writer);
+    let reader_task = tokio::spawn(async move {
+        let mut answers = VecDeque::from(answers);
+        loop {
+            let bytes = match read_pdu(&mut reader).await {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    debug!("Stopped reading: {}", e);
+                    break;
+                }
+            };
+            let pdu = TracedPdu {
+                at: start.elapsed(),
+                side: Side::Smsc,
+                bytes,
+            };
+            debug!("<= {} {}", pdu.command(), pdu.sequence_number());
+            if !pdu.is_response() {
+                let answer = answer(&pdu, &mut answers);
+                let mut writer = reader_writer.lock().await;
+                if let Err(e) = writer.write_all(&answer).await {
+                    warn!("Failed to answer {}: {}", pdu.command(), e);
+                }
+            }
+            if reader_tx.send(Event::Received(pdu)).is_err() {
+                break;
+            }
+        }
+    });
+
+    tokio::spawn(async move {
+        for pdu in requests {
+            if speed > 0.0 {
+                sleep_until(start + pdu.at.div_f64(speed)).await;
+            }
+            debug!("=> {} {}", pdu.command(), pdu.sequence_number());
+            if let Err(e) = writer.lock().await.write_all(&pdu.bytes).await {
+                warn!("Failed to send {}: {}", pdu.command(), e);
+                break;
+            }
+        }
+        let _ = tx.send(Event::AllSent);
+    });
+
+    // Everything the SMSC sent in the trace, plus the response to a bind
+    // we added
+    let expected_count = expected.len() + usize::from(added_bind.is_some());
+    let wait = Duration::from_secs(config.wait_secs);
+    let mut deadline = None;
+    let mut received = Vec::new();
+    loop {
+        let event = match deadline {
+            Some(deadline) => match timeout_at(deadline, rx.recv()).await {
+                Ok(event) => event,
+                Err(_) => break,
+            },
+            None => rx.recv().await,
+        };
+        match event {
+            Some(Event::Received(pdu)) => received.push(pdu),
+            Some(Event::AllSent) => deadline = Some(Instant::now() + wait),
+            None => break,
+        }
+        if deadline.is_some() && received.len() >= expected_count {
+            break;
+        }
+    }
+    reader_task.abort();
+
+    received.retain(|pdu| {
+        !(pdu.is_response() && Some(pdu.sequence_number()) == added_bind)
+    });
+    Ok(compare(&expected, &received, config.ignore_content))
+}
+
+/// The trace with its binds rewritten to use the credentials in config,
+/// and a bind added if the trace starts after it.  If the trace had no
+/// bind or response to one, we bind as a transceiver first, and return
+/// the sequence_number of that bind, whose response is not compared.
+fn prepare(
+    config: &ReplayConfig,
+    trace: Vec<TracedPdu>,
+) -> Result<(Vec<TracedPdu>, Option<u32>), ReplayError> {
+    let mut pdus: Vec<TracedPdu> = Vec::with_capacity(trace.len() + 1);
+    for pdu in trace {
+        if pdu.side == Side::Esme && is_bind(pdu.command_id()) {
+            let bytes = rewrite_bind(config, &pdu)?;
+            pdus.push(TracedPdu { bytes, ..pdu });
+        } else if pdu.side == Side::Smsc
+            && pdu.is_response()
+            && is_bind(pdu.command_id() & !RESPONSE)
+            && !pdus.iter().any(|request| {
+                request.side == Side::Esme
+                    && !request.is_response()
+                    && request.sequence_number() == pdu.sequence_number()
+            })
+        {
+            // Captures of an ESME's system_id start after its bind
+            pdus.push(new_bind(
+                config,
+                pdu.command_id() & !RESPONSE,
+                pdu.sequence_number(),
+                pdu.at,
+            )?);
+            pdus.push(pdu);
+        } else {
+            pdus.push(pdu);
+        }
+    }
+
+    if pdus.iter().any(|pdu| is_bind(pdu.command_id())) {
+        return Ok((pdus, None));
+    }
+    let sequence_number = pdus
+        .iter()
+        .filter(|pdu| pdu.side == Side::Esme && !pdu.is_response())
+        .map(|pdu| pdu.sequence_number())
+        .max()
+        .unwrap_or(0)
+        % 0x7fffffff
+        + 1;
+    let bind = new_bind(
+        config,
+        BIND_TRANSCEIVER,
+        sequence_number,
+        Duration::from_secs(0),
+    )?;
+    pdus.insert(0, bind);
+    Ok((pdus, Some(sequence_number)))
+}
+
+fn is_bind(command_id: u32) -> bool {
+    matches!(
+        command_id,
+        BIND_RECEIVER | BIND_TRANSMITTER | BIND_TRANSCEIVER
+    )
+}
+
+/// A bind using the credentials in config
+fn new_bind(
+    config: &ReplayConfig,
+    command_id: u32,
+    sequence_number: u32,
+    at: Duration,
+) -> Result<TracedPdu, ReplayError> {
+    let (system_id, password) = match (&config.system_id, &config.password) {
+        (Some(system_id), Some(password)) => (system_id, password),
+        _ => return Err(ReplayError::NoBind),
+    };
+    let bind_data = BindData::new(
+        system_id,
+        password,
+        config.system_type.as_deref().unwrap_or(""),
+        INTERFACE_VERSION,
+        0,
+        0,
+        "",
+    )
+    .map_err(|e| ReplayError::BadBind(e.to_string()))?;
+    bind_pdu(command_id, sequence_number, bind_data, at)
+}
+
+/// The recorded bind, with any credentials given in config in place of
+/// the recorded ones
+fn rewrite_bind(
+    config: &ReplayConfig,
+    pdu: &TracedPdu,
+) -> Result<Vec<u8>, ReplayError> {
+    if config.system_id.is_none()
+        && config.password.is_none()
+        && config.system_type.is_none()
+    {
+        return Ok(pdu.bytes.clone());
+    }
+    let malformed = || {
+        ReplayError::BadBind(format!(
+            "the recorded {} is malformed",
+            pdu.command()
+        ))
+    };
+    // system_id, password and system_type, then interface_version,
+    // addr_ton, addr_npi and address_range
+    let fields: Vec<&[u8]> = pdu.body().splitn(4, |b| *b == 0).collect();
+    let rest = match fields.get(3) {
+        Some(rest) if rest.len() >= 4 => rest,
+        _ => return Err(malformed()),
+    };
+    let string = |bytes: &[u8]| String::from_utf8_lossy(bytes).into_owned();
+    let address_range = rest[3..].split(|b| *b == 0).next().unwrap_or(&[]);
+    let bind_data = BindData::new(
+        config.system_id.as_deref().unwrap_or(&string(fields[0])),
+        config.password.as_deref().unwrap_or(&string(fields[1])),
+        config.system_type.as_deref().unwrap_or(&string(fields[2])),
+        rest[0],
+        rest[1],
+        rest[2],
+        &string(address_range),
+    )
+    .map_err(|e| ReplayError::BadBind(e.to_string()))?;
+    Ok(
+        bind_pdu(pdu.command_id(), pdu.sequence_number(), bind_data, pdu.at)?
+            .bytes,
+    )
+}
+
+fn bind_pdu(
+    command_id: u32,
+    sequence_number: u32,
+    bind_data: BindData,
+    at: Duration,
+) -> Result<TracedPdu, ReplayError> {
+    let body = match command_id {
+        BIND_RECEIVER => ExtraPduBody::BindReceiver(bind_data),
+        BIND_TRANSMITTER => ExtraPduBody::BindTransmitter(bind_data),
+        _ => ExtraPduBody::BindTransceiver(bind_data),
+    };
+    let pdu = ExtraPdu::new(0, sequence_number, body)
+        .map_err(|e| ReplayError::BadBind(e.to_string()))?;
+    Ok(TracedPdu {
+        at,
+        side: Side::Esme,
+        bytes: pdu.to_bytes(),
+    })
+}
+
+async fn read_pdu(reader: &mut OwnedReadHalf) -> io::Result<Vec<u8>> {
+    let mut length = [0; 4];
+    reader.read_exact(&mut length).await?;
+    let command_length = u32::from_be_bytes(length) as usize;
+    if !(16..=MAX_PDU_LENGTH).contains(&command_length) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("command_length {}", command_length),
+        ));
+    }
+    let mut bytes = vec![0; command_length];
+    bytes[..4].copy_from_slice(&length);
+    reader.read_exact(&mut bytes[4..]).await?;
+    Ok(bytes)
+}
+
+/// The ESME's recorded answer to the SMSC's next request, or a plain one
+/// if the recording has none for it
+fn answer(request: &TracedPdu, answers: &mut VecDeque<TracedPdu>) -> Vec<u8> {
+    let response_id = request.command_id() | RESPONSE;
+    let mut bytes = match answers.front() {
+        Some(answer) if answer.command_id() == response_id => {
+            answers.pop_front().unwrap().bytes
+        }
+        _ => default_answer(request.command_id()),
+    };
+    bytes[12..16].copy_from_slice(&request.sequence_number().to_be_bytes());
+    bytes
+}
+
+fn default_answer(command_id: u32) -> Vec<u8> {
+    let (command_id, command_status, body): (u32, u32, &[u8]) = match command_id
+    {
+        DELIVER_SM => (DELIVER_SM_RESP, 0, b"\0"),
+        ENQUIRE_LINK | UNBIND => (command_id | RESPONSE, 0, b""),
+        _ => (GENERIC_NACK, ESME_RINVCMDID, b""),
+    };
+    let mut bytes = Vec::with_capacity(16 + body.len());
+    bytes.extend_from_slice(&(16 + body.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(&command_id.to_be_bytes());
+    bytes.extend_from_slice(&command_status.to_be_bytes());
+    // The sequence_number is filled in by answer
+    bytes.extend_from_slice(&0u32.to_be_bytes());
+    bytes.extend_from_slice(body);
+    bytes
+}
+
+/// What matches a PDU from the SMSC in a replay to one in the trace:
+/// responses by sequence_number, and requests by how many requests came
+/// before them, since the SMSC numbers those itself.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+enum Key {
+    Response(u32),
+    Request(usize),
+}
+
+fn keyed(pdus: &[TracedPdu]) -> Vec<(Key, &TracedPdu)> {
+    let mut requests = 0;
+    pdus.iter()
+        .map(|pdu| {
+            let key = if pdu.is_response() {
+                Key::Response(pdu.sequence_number())
+            } else {
+                requests += 1;
+                Key::Request(requests - 1)
+            };
+            (key, pdu)
+        })
+        .collect()
+}
+
+/// How the PDUs an SMSC sent in a replay differ from those it sent in the
+/// trace.  message_ids in responses are not compared, since the SMSC
+/// makes new ones.
+pub fn compare(
+    expected: &[TracedPdu],
+    actual: &[TracedPdu],
+    ignore_content: bool,
+) -> Vec<Difference> {
+    let expected = keyed(expected);
+    let actual = keyed(actual);
+    let expected_by_key: HashMap<Key, &TracedPdu> =
+        expected.iter().cloned().collect();
+    let actual_keys: HashSet<Key> =
+        actual.iter().map(|(key, _)| *key).collect();
+    let mut differences = Vec::new();
+
+    for (key, pdu) in &expected {
+        if !actual_keys.contains(key) {
+            differences.push(Difference::Missing(describe(pdu)));
+        }
+    }
+    for (key, pdu) in &actual {
+        match expected_by_key.get(key) {
+            Some(expected) => {
+                differences.extend(compare_pdus(expected, pdu, ignore_content))
+            }
+            None => differences.push(Difference::Unexpected(describe(pdu))),
+        }
+    }
+
+    // The order of what came in both, described as recorded
+    let expected_order: Vec<String> = expected
+        .iter()
+        .filter(|(key, _)| actual_keys.contains(key))
+        .map(|(_, pdu)| describe(pdu))
+        .collect();
+    let actual_order: Vec<String> = actual
+        .iter()
+        .filter_map(|(key, _)| expected_by_key.get(key))
+        .map(|pdu| describe(pdu))
+        .collect();
+    if expected_order != actual_order {
+        differences.push(Difference::Order {
+            expected: expected_order,
+            actual: actual_order,
+        });
+    }
+    differences
+}
+
+fn compare_pdus(
+    expected: &TracedPdu,
+    actual: &TracedPdu,
+    ignore_content: bool,
+) -> Vec<Difference> {
+    if expected.command_id() != actual.command_id() {
+        return vec![Difference::Command {
+            expected: describe(expected),
+            actual: describe(actual),
+        }];
+    }
+    let mut differences = Vec::new();
+    if expected.command_status() != actual.command_status() {
+        differences.push(Difference::CommandStatus {
+            pdu: describe(expected),
+            expected: expected.command_status(),
+            actual: actual.command_status(),
+        });
+    }
+    let (expected_body, actual_body) =
+        (comparable_body(expected), comparable_body(actual));
+    if !ignore_content && expected_body != actual_body {
+        differences.push(Difference::Content {
+            pdu: describe(expected),
+            expected: hex(&expected_body),
+            actual: hex(&actual_body),
+        });
+    }
+    differences
+}
+
+/// The body, without the parts that change from run to run: the
+/// message_id at the start of a response, and a DR's message ID and dates
+fn comparable_body(pdu: &TracedPdu) -> Vec<u8> {
+    let body = pdu.body();
+    match pdu.command_id() {
+        SUBMIT_SM_RESP | SUBMIT_MULTI_RESP | DATA_SM_RESP => {
+            match body.iter().position(|b| *b == 0) {
+                Some(end) => body[end + 1..].to_vec(),
+                None => Vec::new(),
+            }
+        }
+        DELIVER_SM => normalized_dr(body).unwrap_or_else(|| body.to_vec()),
+        _ => body.to_vec(),
+    }
+}
+
+/// A deliver_sm body with the message ID and dates in its text, and its
+/// receipted_message_id, replaced by "*".  None if it is not a DR.
+fn normalized_dr(body: &[u8]) -> Option<Vec<u8>> {
+    let pdu = DeliverSmPdu::parse(&mut Cursor::new(body), 0).ok()?;
+    if pdu.0.esm_class.value & ESM_CLASS_MESSAGE_TYPE_MASK
+        != ESM_CLASS_DELIVERY_RECEIPT
+    {
+        return None;
+    }
+    let mut fields = SmFields::from_sm_data(&pdu.0);
+    if fields.tlv(KnownTlvTag::receipted_message_id).is_some() {
+        fields.set_tlv(Tlv::new(KnownTlvTag::receipted_message_id, b"*"));
+    }
+    for name in DR_VARYING_FIELDS {
+        fields.short_message = masked_field(&fields.short_message, name);
+    }
+
+    let mut bytes = Vec::new();
+    // Writing to a Vec never waits, so it is safe to block here.
+    futures::executor::block_on(fields.to_deliver_sm().ok()?.write(&mut bytes))
+        .ok()?;
+    Some(bytes)
+}
+
+/// text with the value of its first field called name replaced by "*".
+/// The name is case-insensitive, and must start the text or follow
+/// whitespace.  The value runs to the next whitespace.
+fn masked_field(text: &[u8], name: &str) -> Vec<u8> {
+    let name = name.as_bytes();
+    let start = (0..text.len()).find(|&i| {
+        text[i..].len() >= name.len()
+            && text[i..i + name.len()].eq_ignore_ascii_case(name)
+            && (i == 0 || text[i - 1].is_ascii_whitespace())
+    });
+    let start = match start {
+        Some(start) => start + name.len(),
+        None => return text.to_vec(),
+    };
+    let end = text[start..]
+        .iter()
+        .position(|b| b.is_ascii_whitespace())
+        .map(|len| start + len)
+        .unwrap_or(text.len());
+
+    let mut ret = text[..start].to_vec();
+    ret.push(b'*');
+    ret.extend_from_slice(&text[end..]);
+    ret
+}
+
+fn describe(pdu: &TracedPdu) -> String {
+    format!(
+        "{} sequence_number={}",
+        pdu.command(),
+        pdu.sequence_number()
+    )
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
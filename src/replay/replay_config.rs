@@ -0,0 +1,47 @@
+use clap::Clap;
+
+/// Replay the ESME side of a captured SMPP session against an SMSC, and
+/// report where its responses differ from the capture
+#[derive(Clap, Clone, Debug)]
+#[clap(name = "replay")]
+pub struct ReplayConfig {
+    /// The pcap file or hex trace to replay, as written by the SMSC's
+    /// capture
+    pub trace: String,
+
+    /// Address of the SMSC to replay to
+    #[clap(short, long, default_value = "127.0.0.1:8080")]
+    pub address: String,
+
+    /// How much faster than recorded to send PDUs.  0 sends them all
+    /// without waiting.
+    #[clap(long, default_value = "1")]
+    pub speed: f64,
+
+    /// system_id to bind with, instead of the recorded one.  Needed if
+    /// the trace does not include the bind.
+    #[clap(long)]
+    pub system_id: Option<String>,
+
+    /// Password to bind with, instead of the recorded one.  Needed if the
+    /// trace does not include the bind.
+    #[clap(long)]
+    pub password: Option<String>,
+
+    /// system_type to bind with, instead of the recorded one
+    #[clap(long)]
+    pub system_type: Option<String>,
+
+    /// How long to wait for responses after the last PDU is sent
+    #[clap(long, default_value = "5")]
+    pub wait_secs: u64,
+
+    /// Compare only commands, statuses and ordering, not the bodies of
+    /// PDUs
+    #[clap(long)]
+    pub ignore_content: bool,
+
+    /// Log level for the replay itself
+    #[clap(long, default_value = "warn")]
+    pub log_level: String,
+}
@@ -0,0 +1,430 @@
+//! Reading the PDUs of one SMPP session from a pcap file or a hex trace,
+//! as written by crate::capture.  pcap files written by tcpdump work too,
+//! if they hold raw IP, Ethernet or Linux "any" (SLL) packets.
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::error;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::extra_pdu::read_header;
+use crate::smsc::metrics::command_name;
+
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const IPPROTO_TCP: u8 = 6;
+const HEADER_LENGTH: usize = 16;
+
+/// Which end of the session sent a PDU
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Side {
+    Esme,
+    Smsc,
+}
+
+impl Side {
+    fn other(self) -> Self {
+        match self {
+            Side::Esme => Side::Smsc,
+            Side::Smsc => Side::Esme,
+        }
+    }
+}
+
+/// A PDU from a trace, with when it was seen
+#[derive(Clone, Debug)]
+pub struct TracedPdu {
+    /// Since the first PDU in the trace
+    pub at: Duration,
+    pub side: Side,
+    pub bytes: Vec<u8>,
+}
+
+impl TracedPdu {
+    pub fn command_id(&self) -> u32 {
+        self.header().0
+    }
+
+    pub fn command_status(&self) -> u32 {
+        self.header().1
+    }
+
+    pub fn sequence_number(&self) -> u32 {
+        self.header().2
+    }
+
+    pub fn is_response(&self) -> bool {
+        self.command_id() & 0x80000000 != 0
+    }
+
+    /// Everything after the header
+    pub fn body(&self) -> &[u8] {
+        &self.bytes[HEADER_LENGTH..]
+    }
+
+    pub fn command(&self) -> String {
+        command_name(self.command_id())
+    }
+
+    fn header(&self) -> (u32, u32, u32) {
+        // Traces only hold PDUs at least as long as a header
+        read_header(&self.bytes).unwrap()
+    }
+}
+
+#[derive(Debug)]
+pub enum TraceError {
+    Io(PathBuf, io::Error),
+    /// The file is neither pcap nor a hex trace, or is cut short
+    Invalid(String),
+}
+
+impl Display for TraceError {
+    fn fmt(
+        &self,
+        formatter: &mut Formatter,
+    ) -> std::result::Result<(), std::fmt::Error> {
+        let s = match self {
+            TraceError::Io(path, e) => {
+                format!("Unable to read {}: {}", path.display(), e)
+            }
+            TraceError::Invalid(message) => {
+                format!("Invalid trace: {}", message)
+            }
+        };
+        formatter.write_str(&s)
+    }
+}
+
+impl error::Error for TraceError {}
+
+fn invalid(message: impl ToString) -> TraceError {
+    TraceError::Invalid(message.to_string())
+}
+
+/// Read the PDUs in a pcap file or hex trace, telling which is which by
+/// its first bytes
+pub fn read_trace(path: &Path) -> Result<Vec<TracedPdu>, TraceError> {
+    let bytes =
+        fs::read(path).map_err(|e| TraceError::Io(path.to_path_buf(), e))?;
+    parse_trace(&bytes)
+}
+
+pub fn parse_trace(bytes: &[u8]) -> Result<Vec<TracedPdu>, TraceError> {
+    if PcapReader::new(bytes).is_some() {
+        parse_pcap(bytes)
+    } else {
+        parse_hex(
+            std::str::from_utf8(bytes)
+                .map_err(|_| invalid("not pcap, and not a text hex trace"))?,
+        )
+    }
+}
+
+/// A hex trace is a line per PDU, giving the time and direction ("<=" for
+/// PDUs from the ESME), followed by lines of hex.  See crate::capture.
+fn parse_hex(text: &str) -> Result<Vec<TracedPdu>, TraceError> {
+    let mut pdus: Vec<(Duration, Side, Vec<u8>)> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let bad_line = || invalid(format!("line {}: '{}'", number + 1, line));
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with(' ') {
+            let (_, _, bytes) = pdus.last_mut().ok_or_else(bad_line)?;
+            for token in line.split_whitespace().skip(1) {
+                if token.starts_with('|') {
+                    break;
+                }
+                bytes.push(
+                    u8::from_str_radix(token, 16).map_err(|_| bad_line())?,
+                );
+            }
+        } else {
+            let mut words = line.split(' ');
+            let time = words
+                .next()
+                .and_then(|t| t.parse::<f64>().ok())
+                .filter(|t| t.is_finite() && *t >= 0.0)
+                .ok_or_else(bad_line)?;
+            let side = match words.next() {
+                Some("<=") => Side::Esme,
+                Some("=>") => Side::Smsc,
+                _ => return Err(bad_line()),
+            };
+            pdus.push((Duration::from_secs_f64(time), side, Vec::new()));
+        }
+    }
+    pdus.into_iter()
+        .map(|(at, side, bytes)| traced_pdu(at, side, bytes))
+        .collect::<Result<Vec<_>, _>>()
+        .map(from_first)
+}
+
+fn traced_pdu(
+    at: Duration,
+    side: Side,
+    bytes: Vec<u8>,
+) -> Result<TracedPdu, TraceError> {
+    if bytes.len() < HEADER_LENGTH {
+        return Err(invalid(format!("a PDU of only {} bytes", bytes.len())));
+    }
+    Ok(TracedPdu { at, side, bytes })
+}
+
+/// Make times relative to the first PDU
+fn from_first(mut pdus: Vec<TracedPdu>) -> Vec<TracedPdu> {
+    if let Some(start) = pdus.iter().map(|p| p.at).min() {
+        for pdu in &mut pdus {
+            pdu.at -= start;
+        }
+    }
+    pdus
+}
+
+struct PcapReader<'a> {
+    bytes: &'a [u8],
+    little_endian: bool,
+    nanoseconds: bool,
+    linktype: u32,
+    position: usize,
+}
+
+impl<'a> PcapReader<'a> {
+    fn new(bytes: &'a [u8]) -> Option<Self> {
+        let magic = bytes.get(0..4)?;
+        let (little_endian, nanoseconds) = match magic {
+            [0xd4, 0xc3, 0xb2, 0xa1] => (true, false),
+            [0x4d, 0x3c, 0xb2, 0xa1] => (true, true),
+            [0xa1, 0xb2, 0xc3, 0xd4] => (false, false),
+            [0xa1, 0xb2, 0x3c, 0x4d] => (false, true),
+            _ => return None,
+        };
+        let mut reader = Self {
+            bytes,
+            little_endian,
+            nanoseconds,
+            linktype: 0,
+            position: 24,
+        };
+        reader.linktype = reader.u32_at(20)?;
+        Some(reader)
+    }
+
+    fn u32_at(&self, i: usize) -> Option<u32> {
+        let b: [u8; 4] = self.bytes.get(i..i + 4)?.try_into().ok()?;
+        Some(if self.little_endian {
+            u32::from_le_bytes(b)
+        } else {
+            u32::from_be_bytes(b)
+        })
+    }
+
+    /// The next packet and when it was captured
+    fn next_packet(
+        &mut self,
+    ) -> Result<Option<(Duration, &'a [u8])>, TraceError> {
+        if self.position >= self.bytes.len() {
+            return Ok(None);
+        }
+        let short = || invalid("pcap file is cut short");
+        let secs = self.u32_at(self.position).ok_or_else(short)?;
+        let fraction = self.u32_at(self.position + 4).ok_or_else(short)?;
+        let len = self.u32_at(self.position + 8).ok_or_else(short)? as usize;
+        let start = self.position + 16;
+        let packet = self.bytes.get(start..start + len).ok_or_else(short)?;
+        self.position = start + len;
+        let nanos = if self.nanoseconds {
+            fraction
+        } else {
+            fraction.saturating_mul(1000)
+        };
+        Ok(Some((Duration::new(u64::from(secs), nanos), packet)))
+    }
+}
+
+/// A TCP segment with a payload
+struct Segment<'a> {
+    at: Duration,
+    from: SocketAddr,
+    to: SocketAddr,
+    payload: &'a [u8],
+}
+
+fn parse_pcap(bytes: &[u8]) -> Result<Vec<TracedPdu>, TraceError> {
+    let mut reader =
+        PcapReader::new(bytes).ok_or_else(|| invalid("not pcap"))?;
+    let linktype = reader.linktype;
+    let mut segments = Vec::new();
+    while let Some((at, packet)) = reader.next_packet()? {
+        let ip_packet = match linktype {
+            LINKTYPE_RAW => Some(packet),
+            LINKTYPE_ETHERNET => link_payload(packet, 12, 14),
+            LINKTYPE_LINUX_SLL => link_payload(packet, 14, 16),
+            _ => {
+                return Err(invalid(format!(
+                    "unsupported pcap link type {}",
+                    linktype
+                )))
+            }
+        };
+        if let Some(segment) = ip_packet.and_then(|p| tcp_segment(at, p)) {
+            segments.push(segment);
+        }
+    }
+    pdus_from_segments(&segments)
+}
+
+/// The IP packet in a link-layer frame, whose EtherType is at
+/// ethertype_at and header is header_len long
+fn link_payload(
+    frame: &[u8],
+    ethertype_at: usize,
+    header_len: usize,
+) -> Option<&[u8]> {
+    let ethertype = u16::from_be_bytes(
+        frame.get(ethertype_at..ethertype_at + 2)?.try_into().ok()?,
+    );
+    match ethertype {
+        ETHERTYPE_IPV4 | ETHERTYPE_IPV6 => frame.get(header_len..),
+        _ => None,
+    }
+}
+
+fn tcp_segment(at: Duration, packet: &[u8]) -> Option<Segment<'_>> {
+    let (from_ip, to_ip, tcp): (IpAddr, IpAddr, &[u8]) = match packet.first()?
+        >> 4
+    {
+        4 => {
+            let header_len = usize::from(packet[0] & 0x0f) * 4;
+            let total_len =
+                usize::from(u16::from_be_bytes([*packet.get(2)?, packet[3]]));
+            if *packet.get(9)? != IPPROTO_TCP {
+                return None;
+            }
+            let from: [u8; 4] = packet.get(12..16)?.try_into().ok()?;
+            let to: [u8; 4] = packet.get(16..20)?.try_into().ok()?;
+            (
+                Ipv4Addr::from(from).into(),
+                Ipv4Addr::from(to).into(),
+                packet.get(header_len..total_len.min(packet.len()))?,
+            )
+        }
+        6 => {
+            let payload_len =
+                usize::from(u16::from_be_bytes([*packet.get(4)?, packet[5]]));
+            if *packet.get(6)? != IPPROTO_TCP {
+                return None;
+            }
+            let from: [u8; 16] = packet.get(8..24)?.try_into().ok()?;
+            let to: [u8; 16] = packet.get(24..40)?.try_into().ok()?;
+            (
+                Ipv6Addr::from(from).into(),
+                Ipv6Addr::from(to).into(),
+                packet.get(40..(40 + payload_len).min(packet.len()))?,
+            )
+        }
+        _ => return None,
+    };
+    let port =
+        |i: usize| Some(u16::from_be_bytes([*tcp.get(i)?, *tcp.get(i + 1)?]));
+    let data_offset = usize::from(tcp.get(12)? >> 4) * 4;
+    let payload = tcp.get(data_offset..)?;
+    if payload.is_empty() {
+        return None;
+    }
+    Some(Segment {
+        at,
+        from: SocketAddr::new(from_ip, port(0)?),
+        to: SocketAddr::new(to_ip, port(2)?),
+        payload,
+    })
+}
+
+/// Split the two byte streams of the first TCP connection in segments
+/// into PDUs, and work out which end is the ESME
+fn pdus_from_segments(
+    segments: &[Segment],
+) -> Result<Vec<TracedPdu>, TraceError> {
+    let first = segments.first().ok_or_else(|| invalid("no TCP payloads"))?;
+    let ends = (first.from, first.to);
+
+    // Each PDU, by the sending end, with the index of the segment that
+    // completed it so the two directions can be merged in order
+    let mut streams: HashMap<SocketAddr, Vec<u8>> = HashMap::new();
+    let mut pdus: Vec<(usize, Duration, SocketAddr, Vec<u8>)> = Vec::new();
+    for (index, segment) in segments.iter().enumerate() {
+        let connection = (segment.from, segment.to);
+        if connection != ends && connection != (ends.1, ends.0) {
+            continue;
+        }
+        let stream = streams.entry(segment.from).or_default();
+        stream.extend_from_slice(segment.payload);
+        while let Some(len) = stream
+            .get(0..4)
+            .map(|b| u32::from_be_bytes(b.try_into().unwrap()) as usize)
+        {
+            if len < HEADER_LENGTH {
+                return Err(invalid(format!(
+                    "command_length {} from {}",
+                    len, segment.from
+                )));
+            }
+            if stream.len() < len {
+                break;
+            }
+            let bytes: Vec<u8> = stream.drain(..len).collect();
+            pdus.push((index, segment.at, segment.from, bytes));
+        }
+    }
+
+    let esme = pdus
+        .iter()
+        .find_map(|(_, _, from, bytes)| {
+            let (command_id, _, _) = read_header(bytes)?;
+            Some(match sender(command_id)? {
+                Side::Esme => *from,
+                Side::Smsc => {
+                    if *from == ends.0 {
+                        ends.1
+                    } else {
+                        ends.0
+                    }
+                }
+            })
+        })
+        .ok_or_else(|| invalid("cannot tell which end is the ESME"))?;
+
+    pdus.sort_by_key(|(index, _, _, _)| *index);
+    pdus.into_iter()
+        .map(|(_, at, from, bytes)| {
+            let side = if from == esme { Side::Esme } else { Side::Smsc };
+            traced_pdu(at, side, bytes)
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map(from_first)
+}
+
+/// Which end sends a PDU, for PDUs only one end may send
+fn sender(command_id: u32) -> Option<Side> {
+    match command_id {
+        // bind_*, query_sm, submit_sm, replace_sm, cancel_sm, submit_multi
+        0x01 | 0x02 | 0x03 | 0x04 | 0x07 | 0x08 | 0x09 | 0x21 => {
+            Some(Side::Esme)
+        }
+        // deliver_sm, outbind, alert_notification
+        0x05 | 0x0b | 0x102 => Some(Side::Smsc),
+        // generic_nack could come from either
+        0x80000000 => None,
+        id if id & 0x80000000 != 0 => sender(id & !0x80000000).map(Side::other),
+        _ => None,
+    }
+}
@@ -0,0 +1,12 @@
+//! Router mode: ESMEs bind to our Smsc, and we forward their MTs over a
+//! client bind to an upstream SMSC.  DRs from the upstream come back
+//! through Smsc::receive_pdu, so they reach the ESME that sent the MT.
+
+#[allow(clippy::module_inception)]
+pub mod router;
+pub mod router_config;
+pub mod router_logic;
+
+pub use router::{run, Router};
+pub use router_config::RouterConfig;
+pub use router_logic::{RouterLogic, UpstreamLogic};
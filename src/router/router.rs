@@ -0,0 +1,71 @@
+use log::*;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::sleep;
+
+use crate::async_result::AsyncResult;
+use crate::esme::EsmePool;
+use crate::router::{RouterConfig, RouterLogic, UpstreamLogic};
+use crate::smsc::{start_admin, start_metrics, Smsc};
+
+/// Run a router until it stops, with the admin API and metrics if their
+/// addresses are set.  On SIGHUP or POST /reload, the ESME-facing side
+/// is reconfigured like the SMSC's; upstream settings need a restart.
+pub fn run(config: RouterConfig) -> AsyncResult<()> {
+    let rt = tokio::runtime::Runtime::new()?;
+    rt.block_on(async move {
+        let admin_address = config.smsc.admin_address.clone();
+        let metrics_address = config.smsc.metrics_address.clone();
+        let router = Router::start(config).await?;
+        if let Some(admin_address) = admin_address {
+            start_admin(Arc::clone(&router.smsc), &admin_address, || {
+                RouterConfig::load().map(|config| config.smsc)
+            })
+            .await?;
+        }
+        if let Some(metrics_address) = metrics_address {
+            start_metrics(Arc::clone(&router.smsc), &metrics_address).await?;
+        }
+        #[cfg(unix)]
+        tokio::spawn(crate::smsc::reload_on_hangup(
+            Arc::clone(&router.smsc),
+            || RouterConfig::load().map(|config| config.smsc),
+        ));
+        loop {
+            sleep(Duration::from_millis(100)).await;
+        }
+    })
+}
+
+pub struct Router {
+    pub smsc: Arc<Mutex<Smsc>>,
+    pub upstream: Arc<EsmePool>,
+}
+
+impl Router {
+    /// Bind to the upstream SMSC, then start accepting ESMEs
+    pub async fn start(config: RouterConfig) -> AsyncResult<Self> {
+        info!("Connecting to upstream SMSC {}", config.upstream_address);
+        let smsc_slot = Arc::new(std::sync::Mutex::new(None));
+        let upstream = Arc::new(
+            EsmePool::connect(
+                config.upstream_pool_config(),
+                UpstreamLogic::new(
+                    Arc::clone(&smsc_slot),
+                    &config.upstream_namespace,
+                ),
+            )
+            .await?,
+        );
+
+        let smsc = Smsc::start(
+            config.smsc.clone(),
+            RouterLogic::new(Arc::clone(&upstream), &config.upstream_namespace),
+        )
+        .await?;
+        *smsc_slot.lock().unwrap() = Some(Arc::clone(&smsc));
+
+        Ok(Self { smsc, upstream })
+    }
+}
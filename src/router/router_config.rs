@@ -0,0 +1,83 @@
+use clap::{ArgSettings, Clap};
+use std::ffi::OsString;
+
+use crate::esme::{Balancing, EsmeConfig, EsmePoolConfig};
+use crate::smsc::config_file::parse_with_config_file;
+use crate::smsc::{ConfigError, SmscConfig};
+
+/// SMPP router: accepts MTs from ESMEs and forwards them to an upstream
+/// SMSC
+#[derive(Clap, Clone, Debug)]
+#[clap(name = "router")]
+pub struct RouterConfig {
+    #[clap(flatten)]
+    pub smsc: SmscConfig,
+
+    /// Address of the upstream SMSC to forward MTs to
+    #[clap(long, env = "UPSTREAM_ADDRESS")]
+    pub upstream_address: String,
+
+    /// system_id to bind to the upstream SMSC with
+    #[clap(long, env = "UPSTREAM_SYSTEM_ID")]
+    pub upstream_system_id: String,
+
+    /// Password to bind to the upstream SMSC with
+    #[clap(long, env = "UPSTREAM_PASSWORD")]
+    pub upstream_password: String,
+
+    /// system_type to bind to the upstream SMSC with
+    #[clap(
+        long,
+        default_value = "",
+        env = "UPSTREAM_SYSTEM_TYPE",
+        setting = ArgSettings::AllowEmptyValues
+    )]
+    pub upstream_system_type: String,
+
+    /// How many binds to keep open to the upstream SMSC
+    #[clap(long, default_value = "1", env = "UPSTREAM_BINDS")]
+    pub upstream_binds: usize,
+
+    /// The namespace_id of the upstream's message IDs, used to match its
+    /// DRs to the MTs we forwarded
+    #[clap(long, default_value = "upstream", env = "UPSTREAM_NAMESPACE")]
+    pub upstream_namespace: String,
+}
+
+impl RouterConfig {
+    /// Parse the command line and environment, reading the file named by
+    /// --config (if any) for the SMSC settings they don't set.
+    pub fn load() -> Result<Self, ConfigError> {
+        Self::load_from(std::env::args_os())
+    }
+
+    pub fn load_from<I, T>(args: I) -> Result<Self, ConfigError>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let (mut config, config_file) =
+            parse_with_config_file::<Self, _, _>(args)?;
+        if let Some(config_file) = config_file {
+            config.smsc.apply_config_file(config_file);
+        }
+        Ok(config)
+    }
+
+    /// How to bind to the upstream SMSC.  We bind as a transceiver, so
+    /// DRs come back over the same binds.
+    pub fn upstream_pool_config(&self) -> EsmePoolConfig {
+        let mut esme = EsmeConfig::new(
+            &self.upstream_address,
+            &self.upstream_system_id,
+            &self.upstream_password,
+        );
+        esme.system_type = self.upstream_system_type.clone();
+        EsmePoolConfig {
+            esme,
+            binds: self.upstream_binds,
+            balancing: Balancing::RoundRobin,
+            max_submits_per_sec: None,
+        }
+    }
+}
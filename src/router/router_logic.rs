@@ -0,0 +1,187 @@
+use async_trait::async_trait;
+use futures::FutureExt;
+use log::*;
+use smpp_pdu::pdu::{DeliverSmPdu, Pdu, SubmitSmPdu, SubmitSmRespPdu};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+use crate::address::Address;
+use crate::esme::{DeliverSmError, EsmeError, EsmeLogic, EsmePool};
+use crate::message_unique_key::MessageUniqueKey;
+use crate::routing::RoutingDecision;
+use crate::sm_fields::SmFields;
+use crate::smpp_connection::EsmeId;
+use crate::smsc::{
+    BindData, BindError, Smsc, SmscLogic, SubmitSmError, SubmitSmFuture,
+};
+
+/// SMSC logic that forwards each MT to the upstream SMSC.  We respond with
+/// a message_id of our own, and replace the upstream's message ID with it
+/// in the upstream's DRs.
+pub struct RouterLogic {
+    upstream: Arc<EsmePool>,
+    upstream_namespace: String,
+}
+
+impl RouterLogic {
+    pub fn new(upstream: Arc<EsmePool>, upstream_namespace: &str) -> Self {
+        Self {
+            upstream,
+            upstream_namespace: String::from(upstream_namespace),
+        }
+    }
+}
+
+#[async_trait]
+impl SmscLogic for RouterLogic {
+    async fn bind(&mut self, _bind_data: &BindData) -> Result<(), BindError> {
+        // Later: check credentials against a list of accounts
+        Ok(())
+    }
+
+    async fn submit_sm(
+        &mut self,
+        smsc: Arc<Mutex<Smsc>>,
+        pdu: &SubmitSmPdu,
+        _sequence_number: u32,
+    ) -> Result<(SubmitSmRespPdu, MessageUniqueKey), SubmitSmError> {
+        self.forward(smsc, pdu).await
+    }
+
+    /// Forward with the logic unlocked, so MTs from different sessions
+    /// share the pool's binds and windows instead of waiting in turn
+    fn submit_sm_unlocked(
+        &mut self,
+        smsc: Arc<Mutex<Smsc>>,
+        pdu: &SubmitSmPdu,
+        _esme_id: &EsmeId,
+        _route: Option<&RoutingDecision>,
+    ) -> Option<SubmitSmFuture> {
+        Some(self.forward(smsc, pdu))
+    }
+}
+
+impl RouterLogic {
+    /// Submit pdu upstream, and map the upstream's message ID to our own.
+    /// Holds no reference to self, so it can run with the logic unlocked.
+    fn forward(
+        &self,
+        smsc: Arc<Mutex<Smsc>>,
+        pdu: &SubmitSmPdu,
+    ) -> SubmitSmFuture {
+        let upstream = Arc::clone(&self.upstream);
+        let upstream_namespace = self.upstream_namespace.clone();
+        let sm_data = &pdu.0;
+        // DRs come back from the upstream to the MT's destination
+        let destination = Address::new(
+            sm_data.dest_addr_ton.value,
+            sm_data.dest_addr_npi.value,
+            sm_data.destination_addr.value.as_str(),
+        );
+        let forwarded = SmFields::from_sm_data(sm_data).to_submit_sm();
+        async move {
+            let forwarded =
+                forwarded.map_err(|_| SubmitSmError::InternalError)?;
+            let upstream_message_id =
+                upstream.submit_sm(forwarded).await.map_err(|e| {
+                    warn!("Forwarding submit_sm upstream failed: {}", e);
+                    submit_sm_error(e)
+                })?;
+            let upstream_key = MessageUniqueKey::with_address(
+                upstream_namespace,
+                upstream_message_id,
+                destination,
+            );
+
+            let message_id = {
+                let mut smsc = smsc.lock().await;
+                let message_id = smsc.next_message_id();
+                smsc.map_message_id(upstream_key.clone(), message_id.clone());
+                message_id
+            };
+
+            Ok((
+                SubmitSmRespPdu::new(&message_id)
+                    .map_err(|_| SubmitSmError::InternalError)?,
+                upstream_key,
+            ))
+        }
+        .boxed()
+    }
+}
+
+/// Pass the upstream's error back to the ESME where we can
+fn submit_sm_error(e: EsmeError) -> SubmitSmError {
+    match e {
+        EsmeError::ErrorResponse(status) => match status {
+            0x00000001 => SubmitSmError::InvalidMessageLength,
+            0x00000006 => SubmitSmError::InvalidPriorityFlag,
+            0x00000007 => SubmitSmError::InvalidRegisteredDelivery,
+            0x0000000A => SubmitSmError::InvalidSourceAddress,
+            0x0000000B => SubmitSmError::InvalidDestinationAddress,
+            0x00000014 => SubmitSmError::MessageQueueFull,
+            0x00000043 => SubmitSmError::InvalidEsmClass,
+            0x00000048 => SubmitSmError::InvalidSourceTon,
+            0x00000049 => SubmitSmError::InvalidSourceNpi,
+            0x00000050 => SubmitSmError::InvalidDestinationTon,
+            0x00000051 => SubmitSmError::InvalidDestinationNpi,
+            0x00000058 => SubmitSmError::Throttled,
+            _ => SubmitSmError::SubmitFailed,
+        },
+        _ => SubmitSmError::InternalError,
+    }
+}
+
+/// ESME logic for our binds to the upstream SMSC: passes DRs into our
+/// Smsc, which sends them to the ESME that sent the MT.
+pub struct UpstreamLogic {
+    smsc: Arc<std::sync::Mutex<Option<Arc<Mutex<Smsc>>>>>,
+    upstream_namespace: String,
+}
+
+impl UpstreamLogic {
+    /// smsc is filled in once our Smsc has started.  Until then, DRs are
+    /// refused with a temporary error.
+    pub fn new(
+        smsc: Arc<std::sync::Mutex<Option<Arc<Mutex<Smsc>>>>>,
+        upstream_namespace: &str,
+    ) -> Self {
+        Self {
+            smsc,
+            upstream_namespace: String::from(upstream_namespace),
+        }
+    }
+}
+
+#[async_trait]
+impl EsmeLogic for UpstreamLogic {
+    async fn deliver_sm(
+        &mut self,
+        pdu: &DeliverSmPdu,
+    ) -> Result<(), DeliverSmError> {
+        let smsc = self
+            .smsc
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or(DeliverSmError::TemporaryError)?;
+        let deliver_sm = SmFields::from_sm_data(&pdu.0)
+            .to_deliver_sm()
+            .map_err(|_| DeliverSmError::InternalError)?;
+        // Smsc gives this its own sequence_number when it sends it on
+        let pdu = Pdu::new(0, 1, deliver_sm.into())
+            .map_err(|_| DeliverSmError::InternalError)?;
+
+        // Later: Issue#5: the ESME may reconnect, so the upstream retrying
+        // after a temporary error may succeed
+        let result = smsc
+            .lock()
+            .await
+            .receive_pdu(&self.upstream_namespace, pdu)
+            .await;
+        result.map_err(|e| {
+            warn!("Could not route DR from upstream: {}", e);
+            DeliverSmError::TemporaryError
+        })
+    }
+}
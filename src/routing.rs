@@ -0,0 +1,189 @@
+//! Choosing which supplier to send each MT to, by destination prefix,
+//! sender ID and account.
+
+use log::*;
+use serde::Deserialize;
+use std::cmp::Reverse;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::address::Address;
+use crate::logging;
+
+/// One supplier that a route sends a share of its traffic to
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RouteTarget {
+    pub supplier: String,
+    /// This target's share of the route's traffic, relative to the others
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+}
+
+impl RouteTarget {
+    pub fn new(supplier: &str, weight: u32) -> Self {
+        Self {
+            supplier: String::from(supplier),
+            weight,
+        }
+    }
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+/// A rule saying which suppliers MTs matching it should go to
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Route {
+    /// Matches normalized destination_addr values (international format,
+    /// digits only) starting with this.  Empty matches everything.
+    #[serde(default)]
+    pub prefix: String,
+    /// If set, only matches MTs from the ESME with this system_id
+    #[serde(default)]
+    pub account: Option<String>,
+    /// If set, only matches MTs with this source_addr
+    #[serde(default)]
+    pub sender_id: Option<String>,
+    /// Suppliers to split traffic between, by weight
+    pub targets: Vec<RouteTarget>,
+    /// Suppliers to try, in order, if the chosen target fails
+    #[serde(default)]
+    pub failover: Vec<String>,
+    /// What it costs to send one message this way
+    #[serde(default)]
+    pub cost: f64,
+}
+
+impl Route {
+    /// A route for prefix that sends everything to supplier
+    pub fn new(prefix: &str, supplier: &str, cost: f64) -> Self {
+        Self {
+            prefix: String::from(prefix),
+            account: None,
+            sender_id: None,
+            targets: vec![RouteTarget::new(supplier, 1)],
+            failover: Vec::new(),
+            cost,
+        }
+    }
+
+    fn matches(
+        &self,
+        account: &str,
+        sender_id: &str,
+        destination: &str,
+    ) -> bool {
+        destination.starts_with(&self.prefix)
+            && self.account.as_deref().is_none_or(|a| a == account)
+            && self.sender_id.as_deref().is_none_or(|s| s == sender_id)
+    }
+}
+
+/// Where to send one MT
+#[derive(Clone, Debug, PartialEq)]
+pub struct RoutingDecision {
+    /// The route that matched
+    pub route: Route,
+    /// The supplier chosen from the route's targets
+    pub supplier: String,
+    /// Suppliers to try in order if supplier fails
+    pub failover: Vec<String>,
+}
+
+/// A list of routes, and the logic to pick the best one for an MT
+pub struct RoutingTable {
+    routes: Vec<Route>,
+    /// For each route, how many MTs it has routed, used to split traffic
+    /// between its targets by weight
+    counters: Vec<AtomicU64>,
+}
+
+impl RoutingTable {
+    pub fn new(routes: Vec<Route>) -> Self {
+        let counters = routes.iter().map(|_| AtomicU64::new(0)).collect();
+        Self { routes, counters }
+    }
+
+    pub fn routes(&self) -> &[Route] {
+        &self.routes
+    }
+
+    /// Choose a supplier for an MT from account (the ESME's system_id) to
+    /// destination, which should already be normalized.  Of the routes
+    /// that match, per-account routes win over those for all accounts,
+    /// then the longest prefix wins, then routes for a specific sender,
+    /// then the cheapest.  Returns None if no route matches.
+    pub fn route(
+        &self,
+        account: &str,
+        sender_id: &str,
+        destination: &Address,
+    ) -> Option<RoutingDecision> {
+        let (index, route) = self
+            .routes
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| r.matches(account, sender_id, &destination.value))
+            .min_by(|(_, a), (_, b)| {
+                let key = |r: &Route| {
+                    (
+                        Reverse(r.account.is_some()),
+                        Reverse(r.prefix.len()),
+                        Reverse(r.sender_id.is_some()),
+                    )
+                };
+                key(a).cmp(&key(b)).then(
+                    a.cost
+                        .partial_cmp(&b.cost)
+                        .unwrap_or(std::cmp::Ordering::Equal),
+                )
+            })?;
+
+        let supplier = self.pick_target(index, route)?;
+        let failover = route
+            .failover
+            .iter()
+            .filter(|s| **s != supplier)
+            .cloned()
+            .collect();
+
+        let decision = RoutingDecision {
+            route: route.clone(),
+            supplier,
+            failover,
+        };
+        info!(
+            "Routing decision: account={} sender_id={} destination={} \
+            prefix='{}' supplier={} failover={:?} cost={}",
+            account,
+            logging::redact(sender_id, logging::redaction()),
+            destination.logged(),
+            decision.route.prefix,
+            decision.supplier,
+            decision.failover,
+            decision.route.cost,
+        );
+        Some(decision)
+    }
+
+    /// Split traffic by weight: of every total_weight MTs on this route,
+    /// each target gets its weight.
+    fn pick_target(&self, index: usize, route: &Route) -> Option<String> {
+        let total_weight: u64 =
+            route.targets.iter().map(|t| u64::from(t.weight)).sum();
+        if total_weight == 0 {
+            return route.targets.first().map(|t| t.supplier.clone());
+        }
+        let mut pick =
+            self.counters[index].fetch_add(1, Ordering::Relaxed) % total_weight;
+        for target in &route.targets {
+            if pick < u64::from(target.weight) {
+                return Some(target.supplier.clone());
+            }
+            pick -= u64::from(target.weight);
+        }
+        None
+    }
+}
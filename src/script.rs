@@ -0,0 +1,521 @@
+//! Scripted SMSC behaviour for integration tests: rules read from a file
+//! say how to answer the MTs that match them, by account, addresses,
+//! text, data_coding and TLVs.  MTs that match no rule are passed to
+//! another SmscLogic.
+
+use async_trait::async_trait;
+use log::*;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use regex::Regex;
+use serde::Deserialize;
+use smpp_pdu::pdu::{Pdu, SubmitSmPdu, SubmitSmRespPdu};
+use std::convert::TryFrom;
+use std::future::Future;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::sync::Mutex;
+use tokio::time::sleep;
+
+use crate::async_result::AsyncResult;
+use crate::message_id_generator::MessageIdGenerator;
+use crate::message_unique_key::MessageUniqueKey;
+use crate::routing::RoutingDecision;
+use crate::send::send_config::{parse_hex, parse_tag};
+use crate::simulator::{create_dr, DrDelay, DrOutcome, DrState, PendingSends};
+use crate::sm_fields::SmFields;
+use crate::smpp_connection::EsmeId;
+use crate::smsc::config_file::parse_file;
+use crate::smsc::{
+    BindContext, BindData, BindError, ConcatenatedSm, ConfigError, Smsc,
+    SmscConfig, SmscLogic, SubmitSmError, SubmitSmFuture,
+};
+
+/// The namespace_id of the MessageUniqueKeys for the MTs a rule accepts
+pub const SCRIPT_NAMESPACE: &str = "script";
+
+/// The contents of a rules file
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Script {
+    /// Tried in order: the first that matches an MT decides what happens
+    pub rules: Vec<ScriptRule>,
+}
+
+impl Script {
+    /// Read a TOML or YAML rules file, choosing which by extension
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        parse_file(path)
+    }
+
+    /// The first rule matching an MT from account
+    pub fn rule_for(
+        &self,
+        account: &str,
+        fields: &SmFields,
+    ) -> Option<&ScriptRule> {
+        self.rules.iter().find(|r| r.matches(account, fields))
+    }
+}
+
+/// What to do with the MTs that match.  Every condition that is set must
+/// match, so a rule with none matches every MT.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ScriptRule {
+    /// The system_id of the ESME that sent the MT
+    #[serde(default)]
+    pub account: Option<String>,
+    /// A leading "+" is ignored, here and in the MT
+    #[serde(default)]
+    pub source_addr: Option<String>,
+    /// A leading "+" is ignored, here and in the MT
+    #[serde(default)]
+    pub destination_addr: Option<String>,
+    /// Matches anywhere in the text of the short_message or
+    /// message_payload, unless anchored with ^ or $
+    #[serde(default)]
+    pub text: Option<TextPattern>,
+    #[serde(default)]
+    pub data_coding: Option<u8>,
+    /// TLVs the MT must have, as TAG or TAG=HEX_VALUE
+    #[serde(default)]
+    pub tlvs: Vec<TlvPattern>,
+
+    /// Reject the MT with this command_status, e.g. ESME_RINVDSTADR.
+    /// Otherwise it is accepted.
+    #[serde(default)]
+    pub command_status: Option<SubmitSmError>,
+    /// The DR to send, as STATE[:ERR], if registered_delivery asks for
+    /// one.  Default DELIVRD.
+    #[serde(default)]
+    pub dr: Option<DrOutcome>,
+    /// How long to wait before sending the DR, as for --sim-dr-delay.
+    /// Default no wait.
+    #[serde(default)]
+    pub dr_delay: Option<DrDelay>,
+    /// Text of an MO to send back to the ESME, from the MT's
+    /// destination_addr
+    #[serde(default)]
+    pub mo_reply: Option<String>,
+    /// How long to wait before sending mo_reply.  Default no wait.
+    #[serde(default)]
+    pub mo_delay: Option<DrDelay>,
+}
+
+impl ScriptRule {
+    fn matches(&self, account: &str, fields: &SmFields) -> bool {
+        let address_matches = |expected: &Option<String>, actual: &str| {
+            expected.as_deref().is_none_or(|expected| {
+                expected.trim_start_matches('+')
+                    == actual.trim_start_matches('+')
+            })
+        };
+        let text_matches = |text: &TextPattern| text.0.is_match(&fields.text());
+        self.account.as_deref().is_none_or(|a| a == account)
+            && address_matches(&self.source_addr, &fields.source_addr)
+            && address_matches(&self.destination_addr, &fields.destination_addr)
+            && self.data_coding.is_none_or(|d| d == fields.data_coding)
+            && self.tlvs.iter().all(|tlv| tlv.matches(fields))
+            && self.text.as_ref().is_none_or(text_matches)
+    }
+}
+
+/// A regular expression, as in the regex crate
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "String")]
+pub struct TextPattern(pub Regex);
+
+impl PartialEq for TextPattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl FromStr for TextPattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Regex::new(s)
+            .map(TextPattern)
+            .map_err(|e| format!("Invalid text pattern '{}': {}", s, e))
+    }
+}
+
+impl TryFrom<String> for TextPattern {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// A TLV an MT must have, with any value unless value is set
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(try_from = "String")]
+pub struct TlvPattern {
+    pub raw_tag: u16,
+    pub value: Option<Vec<u8>>,
+}
+
+impl TlvPattern {
+    fn matches(&self, fields: &SmFields) -> bool {
+        fields.tlvs.iter().any(|tlv| {
+            tlv.raw_tag == self.raw_tag
+                && self.value.as_ref().is_none_or(|v| *v == tlv.value)
+        })
+    }
+}
+
+impl FromStr for TlvPattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad = || {
+            format!(
+                "Invalid TLV '{}': expected TAG or TAG=HEX_VALUE, e.g. \
+                0x1400=01",
+                s
+            )
+        };
+        let (tag, value) = match s.split_once('=') {
+            Some((tag, value)) => {
+                (tag, Some(parse_hex(value).ok_or_else(bad)?))
+            }
+            None => (s, None),
+        };
+        Ok(Self {
+            raw_tag: parse_tag(tag).ok_or_else(bad)?,
+            value,
+        })
+    }
+}
+
+impl TryFrom<String> for TlvPattern {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Answers MTs by the rules in a Script, and leaves everything else,
+/// including binds and MTs that match no rule, to inner.  Reassembled
+/// concatenated MTs are matched as a whole.  On reload, the
+/// rules are read again from the SmscConfig's script, if it is set.
+pub struct Scripted<L> {
+    script: Script,
+    inner: L,
+    message_ids: MessageIdGenerator,
+    rng: StdRng,
+    pending: PendingSends,
+}
+
+impl<L: SmscLogic> Scripted<L> {
+    pub fn new(script: Script, inner: L) -> Self {
+        Self {
+            script,
+            inner,
+            message_ids: MessageIdGenerator::new(),
+            rng: StdRng::from_entropy(),
+            pending: PendingSends::default(),
+        }
+    }
+
+    /// What the first matching rule says to do with pdu, or None if no
+    /// rule matches
+    fn run_script(
+        &mut self,
+        smsc: &Arc<Mutex<Smsc>>,
+        pdu: &SubmitSmPdu,
+        account: &str,
+    ) -> Option<Result<(SubmitSmRespPdu, MessageUniqueKey), SubmitSmError>>
+    {
+        let fields = SmFields::from_sm_data(&pdu.0);
+        let rule = self.script.rule_for(account, &fields)?.clone();
+        debug!("Script rule {:?} matches MT from {}", rule, account);
+        if let Some(command_status) = rule.command_status {
+            return Some(Err(command_status));
+        }
+
+        let message_id = self.message_ids.next_message_id();
+        let message_unique_key = MessageUniqueKey::from_submit_sm(
+            String::from(SCRIPT_NAMESPACE),
+            message_id.clone(),
+            pdu,
+        );
+        let outcome = rule.dr.unwrap_or(DrOutcome {
+            state: DrState::Delivered,
+            err: 0,
+            weight: 1,
+        });
+        let wants_dr = match fields.registered_delivery & 0x03 {
+            1 => true,
+            2 => outcome.state != DrState::Delivered,
+            _ => false,
+        };
+        if wants_dr {
+            let dr = create_dr(&message_id, pdu, &outcome, SystemTime::now());
+            let delay = rule.dr_delay.map(|d| d.sample(&mut self.rng));
+            match dr {
+                Ok(dr) => self.pending.add(
+                    message_unique_key.clone(),
+                    send_later(smsc, delay, None, dr),
+                ),
+                Err(e) => return Some(Err(internal_error(e))),
+            }
+        }
+        if let Some(reply) = &rule.mo_reply {
+            let delay = rule.mo_delay.map(|d| d.sample(&mut self.rng));
+            match mo_reply(&fields, reply) {
+                Ok(mo) => self.pending.add(
+                    message_unique_key.clone(),
+                    send_later(smsc, delay, Some(account), mo),
+                ),
+                Err(e) => return Some(Err(internal_error(e))),
+            }
+        }
+
+        let resp = match SubmitSmRespPdu::new(&message_id) {
+            Ok(resp) => resp,
+            Err(e) => return Some(Err(internal_error(e))),
+        };
+        Some(Ok((resp, message_unique_key)))
+    }
+
+    /// run_script for the whole of a concatenated message.  Its parts
+    /// were acknowledged already, so a DR the rule sends goes to the ESME
+    /// with the first part's message_id.
+    async fn run_script_joined(
+        &mut self,
+        smsc: &Arc<Mutex<Smsc>>,
+        message: &ConcatenatedSm,
+    ) -> Option<Result<MessageUniqueKey, SubmitSmError>> {
+        let pdu = match message.joined_pdu() {
+            Ok(pdu) => pdu,
+            Err(e) => return Some(Err(internal_error(e))),
+        };
+        let account = message.esme_id.system_id.as_str();
+        match self.run_script(smsc, &pdu, account)? {
+            Ok((_, message_unique_key)) => {
+                if let Some(first) = message.parts.first() {
+                    smsc.lock().await.map_message_id(
+                        message_unique_key.clone(),
+                        first.message_id.clone(),
+                    );
+                }
+                Some(Ok(message_unique_key))
+            }
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+fn internal_error<E: std::fmt::Display>(e: E) -> SubmitSmError {
+    error!("Unable to follow script rule: {}", e);
+    SubmitSmError::InternalError
+}
+
+/// After delay, pass pdu to the Smsc: as a DR if account is None, or as
+/// an MO to account
+fn send_later(
+    smsc: &Arc<Mutex<Smsc>>,
+    delay: Option<std::time::Duration>,
+    account: Option<&str>,
+    pdu: Pdu,
+) -> impl Future<Output = ()> + Send + 'static {
+    let smsc = Arc::clone(smsc);
+    let account = account.map(String::from);
+    async move {
+        if let Some(delay) = delay {
+            sleep(delay).await;
+        }
+        let mut smsc = smsc.lock().await;
+        let result = match &account {
+            None => smsc.receive_pdu(SCRIPT_NAMESPACE, pdu).await,
+            Some(account) => smsc.deliver_mo(account, pdu).await,
+        };
+        if let Err(e) = result {
+            warn!("Unable to send scripted deliver_sm: {}", e);
+        }
+    }
+}
+
+/// An MO with text, from the recipient of mt back to its sender.  The
+/// text is sent as ASCII if it can be, otherwise as UCS2.
+fn mo_reply(mt: &SmFields, text: &str) -> AsyncResult<Pdu> {
+    let (data_coding, short_message) = if text.is_ascii() {
+        (0x00, text.as_bytes().to_vec())
+    } else {
+        (
+            0x08,
+            text.encode_utf16()
+                .flat_map(|unit| unit.to_be_bytes())
+                .collect(),
+        )
+    };
+    let mo = SmFields {
+        source_addr_ton: mt.dest_addr_ton,
+        source_addr_npi: mt.dest_addr_npi,
+        source_addr: mt.destination_addr.clone(),
+        dest_addr_ton: mt.source_addr_ton,
+        dest_addr_npi: mt.source_addr_npi,
+        destination_addr: mt.source_addr.clone(),
+        data_coding,
+        short_message,
+        ..Default::default()
+    };
+    // Smsc replaces the sequence_number with the next one for the ESME
+    Ok(Pdu::new(0, 1, mo.to_deliver_sm()?.into())?)
+}
+
+#[async_trait]
+impl<L: SmscLogic> SmscLogic for Scripted<L> {
+    async fn bind(&mut self, bind_data: &BindData) -> Result<(), BindError> {
+        self.inner.bind(bind_data).await
+    }
+
+    async fn bind_with_context(
+        &mut self,
+        bind_data: &BindData,
+        context: &BindContext,
+    ) -> Result<(), BindError> {
+        self.inner.bind_with_context(bind_data, context).await
+    }
+
+    async fn submit_sm(
+        &mut self,
+        smsc: Arc<Mutex<Smsc>>,
+        pdu: &SubmitSmPdu,
+        sequence_number: u32,
+    ) -> Result<(SubmitSmRespPdu, MessageUniqueKey), SubmitSmError> {
+        match self.run_script(&smsc, pdu, "") {
+            Some(result) => result,
+            None => self.inner.submit_sm(smsc, pdu, sequence_number).await,
+        }
+    }
+
+    async fn submit_sm_with_route(
+        &mut self,
+        smsc: Arc<Mutex<Smsc>>,
+        pdu: &SubmitSmPdu,
+        sequence_number: u32,
+        route: Option<&RoutingDecision>,
+    ) -> Result<(SubmitSmRespPdu, MessageUniqueKey), SubmitSmError> {
+        match self.run_script(&smsc, pdu, "") {
+            Some(result) => result,
+            None => {
+                self.inner
+                    .submit_sm_with_route(smsc, pdu, sequence_number, route)
+                    .await
+            }
+        }
+    }
+
+    async fn submit_sm_from_esme(
+        &mut self,
+        smsc: Arc<Mutex<Smsc>>,
+        pdu: &SubmitSmPdu,
+        sequence_number: u32,
+        esme_id: &EsmeId,
+        route: Option<&RoutingDecision>,
+    ) -> Result<(SubmitSmRespPdu, MessageUniqueKey), SubmitSmError> {
+        match self.run_script(&smsc, pdu, esme_id.system_id.as_str()) {
+            Some(result) => result,
+            None => {
+                self.inner
+                    .submit_sm_from_esme(
+                        smsc,
+                        pdu,
+                        sequence_number,
+                        esme_id,
+                        route,
+                    )
+                    .await
+            }
+        }
+    }
+
+    fn submit_sm_unlocked(
+        &mut self,
+        smsc: Arc<Mutex<Smsc>>,
+        pdu: &SubmitSmPdu,
+        esme_id: &EsmeId,
+        route: Option<&RoutingDecision>,
+    ) -> Option<SubmitSmFuture> {
+        // MTs a rule matches are answered in submit_sm_from_esme
+        let fields = SmFields::from_sm_data(&pdu.0);
+        if self
+            .script
+            .rule_for(esme_id.system_id.as_str(), &fields)
+            .is_some()
+        {
+            return None;
+        }
+        self.inner.submit_sm_unlocked(smsc, pdu, esme_id, route)
+    }
+
+    async fn submit_sm_responded(
+        &mut self,
+        smsc: Arc<Mutex<Smsc>>,
+        message_unique_keys: &[MessageUniqueKey],
+    ) {
+        self.pending.release(message_unique_keys);
+        self.inner
+            .submit_sm_responded(smsc, message_unique_keys)
+            .await;
+    }
+
+    async fn reload(&mut self, config: &SmscConfig) -> AsyncResult<()> {
+        let script = match &config.script {
+            Some(path) => Some(Script::load(Path::new(path))?),
+            None => None,
+        };
+        self.inner.reload(config).await?;
+        if let Some(script) = script {
+            self.script = script;
+        }
+        Ok(())
+    }
+
+    async fn may_stay_bound(&mut self, esme_id: &EsmeId) -> bool {
+        self.inner.may_stay_bound(esme_id).await
+    }
+
+    async fn submit_concatenated_sm(
+        &mut self,
+        smsc: Arc<Mutex<Smsc>>,
+        message: &ConcatenatedSm,
+    ) -> Result<Vec<MessageUniqueKey>, SubmitSmError> {
+        match self.run_script_joined(&smsc, message).await {
+            Some(result) => result.map(|key| vec![key]),
+            None => self.inner.submit_concatenated_sm(smsc, message).await,
+        }
+    }
+
+    async fn concatenated_sm_expired(
+        &mut self,
+        smsc: Arc<Mutex<Smsc>>,
+        message: &ConcatenatedSm,
+    ) {
+        match self.run_script_joined(&smsc, message).await {
+            // No submit_sm_resp follows, so record the message and let
+            // the DR or MO go now
+            Some(Ok(message_unique_key)) => {
+                smsc.lock().await.add_message(
+                    message_unique_key.clone(),
+                    message.esme_id.clone(),
+                );
+                self.pending.release(&[message_unique_key]);
+            }
+            Some(Err(e)) => info!(
+                "Script rule rejected incomplete concatenated message from \
+                {} with {:?}",
+                message.esme_id.system_id, e
+            ),
+            None => self.inner.concatenated_sm_expired(smsc, message).await,
+        }
+    }
+}
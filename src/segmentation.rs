@@ -0,0 +1,267 @@
+//! Splitting messages that are too long for a single short_message into
+//! several PDUs.
+
+use smpp_pdu::pdu::tlvs::{KnownTlvTag, Tlv};
+use smpp_pdu::pdu::{Pdu, PduParseError};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU16, Ordering};
+
+use crate::concatenation::{concat_udh, ConcatSource, ESM_CLASS_UDHI};
+use crate::sm_fields::SmFields;
+use crate::smpp_connection::EsmeId;
+
+/// The number of octets available in one SMS
+const MAX_OCTETS: usize = 140;
+
+/// The largest message we will put in a message_payload TLV
+const MAX_PAYLOAD_OCTETS: usize = 64 * 1024 - 1;
+
+/// GSM 03.38 escape to the extension table: must stay with the next septet
+const GSM_ESCAPE: u8 = 0x1b;
+
+/// How an ESME would like to receive messages that are too long for one
+/// short_message.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SegmentationMode {
+    /// Several PDUs, each with a UDH containing an 8-bit reference
+    Udh8Bit,
+    /// Several PDUs, each with a UDH containing a 16-bit reference
+    Udh16Bit,
+    /// Several PDUs, each with sar_* TLVs
+    Sar,
+    /// One PDU with the whole message in a message_payload TLV
+    MessagePayload,
+}
+
+impl FromStr for SegmentationMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "udh8" => Ok(SegmentationMode::Udh8Bit),
+            "udh16" => Ok(SegmentationMode::Udh16Bit),
+            "sar" => Ok(SegmentationMode::Sar),
+            "payload" => Ok(SegmentationMode::MessagePayload),
+            _ => Err(format!(
+                "Unknown segmentation mode '{}': expected udh8, udh16, sar \
+                or payload",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum SegmentationError {
+    TooLong,
+    PduError(String),
+}
+
+impl From<PduParseError> for SegmentationError {
+    fn from(e: PduParseError) -> Self {
+        SegmentationError::PduError(e.to_string())
+    }
+}
+
+/// Builds the PDUs needed to send a message of any length, remembering
+/// how each ESME prefers to receive long messages.
+pub struct Segmenter {
+    default_mode: SegmentationMode,
+    preferences: HashMap<EsmeId, SegmentationMode>,
+    next_reference: AtomicU16,
+}
+
+impl Segmenter {
+    pub fn new(default_mode: SegmentationMode) -> Self {
+        Self {
+            default_mode,
+            preferences: HashMap::new(),
+            next_reference: AtomicU16::new(1),
+        }
+    }
+
+    pub fn set_preference(&mut self, esme_id: EsmeId, mode: SegmentationMode) {
+        self.preferences.insert(esme_id, mode);
+    }
+
+    pub fn mode_for(&self, esme_id: &EsmeId) -> SegmentationMode {
+        self.preferences
+            .get(esme_id)
+            .copied()
+            .unwrap_or(self.default_mode)
+    }
+
+    /// Split message into as many parts as needed, based on template.
+    /// template's data_coding decides how many bytes fit in one part, and
+    /// its short_message is ignored.  If message fits in one part, we
+    /// return just one, without any UDH or SAR TLVs.
+    pub fn segment(
+        &self,
+        template: &SmFields,
+        message: &[u8],
+        mode: SegmentationMode,
+    ) -> Result<Vec<SmFields>, SegmentationError> {
+        let coding = Coding::from_data_coding(template.data_coding);
+        if message.len() <= coding.max_bytes(0) {
+            let mut fields = template.clone();
+            fields.short_message = message.to_vec();
+            return Ok(vec![fields]);
+        }
+
+        let source = match mode {
+            SegmentationMode::MessagePayload => {
+                if message.len() > MAX_PAYLOAD_OCTETS {
+                    return Err(SegmentationError::TooLong);
+                }
+                let mut fields = template.clone();
+                fields.short_message = Vec::new();
+                fields.set_tlv(Tlv::new(KnownTlvTag::message_payload, message));
+                return Ok(vec![fields]);
+            }
+            SegmentationMode::Udh8Bit => ConcatSource::Udh8Bit,
+            SegmentationMode::Udh16Bit => ConcatSource::Udh16Bit,
+            SegmentationMode::Sar => ConcatSource::Sar,
+        };
+
+        let header_length = concat_udh(source, 0, 0, 0).len();
+        let chunks = coding.split(message, coding.max_bytes(header_length));
+        if chunks.len() > usize::from(u8::MAX) {
+            return Err(SegmentationError::TooLong);
+        }
+
+        let reference = self.new_reference(source);
+        let total_parts = chunks.len() as u8;
+        Ok(chunks
+            .into_iter()
+            .enumerate()
+            .map(|(i, chunk)| {
+                let part_number = (i + 1) as u8;
+                let mut fields = template.clone();
+                fields.short_message =
+                    concat_udh(source, reference, total_parts, part_number);
+                fields.short_message.extend_from_slice(chunk);
+                if source == ConcatSource::Sar {
+                    fields.set_tlv(Tlv::new(
+                        KnownTlvTag::sar_msg_ref_num,
+                        &reference.to_be_bytes(),
+                    ));
+                    fields.set_tlv(Tlv::new(
+                        KnownTlvTag::sar_total_segments,
+                        &[total_parts],
+                    ));
+                    fields.set_tlv(Tlv::new(
+                        KnownTlvTag::sar_segment_seqnum,
+                        &[part_number],
+                    ));
+                } else {
+                    fields.esm_class |= ESM_CLASS_UDHI;
+                }
+                fields
+            })
+            .collect())
+    }
+
+    /// Build deliver_sm PDUs to send message to esme_id, split according
+    /// to that ESME's preference.  The PDUs are numbered consecutively,
+    /// starting at sequence_number.
+    pub fn deliver_sm_pdus(
+        &self,
+        esme_id: &EsmeId,
+        template: &SmFields,
+        message: &[u8],
+        sequence_number: u32,
+    ) -> Result<Vec<Pdu>, SegmentationError> {
+        let mut sequence_number = sequence_number;
+        self.segment(template, message, self.mode_for(esme_id))?
+            .iter()
+            .map(|fields| {
+                let pdu = Pdu::new(
+                    0,
+                    sequence_number,
+                    fields.to_deliver_sm()?.into(),
+                );
+                sequence_number = sequence_number % 0x7fffffff + 1;
+                Ok(pdu?)
+            })
+            .collect()
+    }
+
+    fn new_reference(&self, source: ConcatSource) -> u16 {
+        let reference = self.next_reference.fetch_add(1, Ordering::Relaxed);
+        match source {
+            ConcatSource::Udh8Bit => reference & 0xff,
+            _ => reference,
+        }
+    }
+}
+
+/// The number of bytes of short_message that fit in one SMS with the given
+/// data_coding, after a UDH of header_length octets.
+pub fn max_user_data_length(data_coding: u8, header_length: usize) -> usize {
+    Coding::from_data_coding(data_coding).max_bytes(header_length)
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Coding {
+    /// One byte per septet, e.g. GSM 03.38 or IA5
+    Septets,
+    /// Two bytes per character: UCS2/UTF-16
+    Ucs2,
+    /// One byte per octet, e.g. Latin-1 or binary
+    Octets,
+}
+
+impl Coding {
+    /// https://smpp.org/SMPP_v3_4_Issue1_2.pdf section 5.2.19
+    fn from_data_coding(data_coding: u8) -> Self {
+        match data_coding {
+            0x00 | 0x01 => Coding::Septets,
+            0x08 => Coding::Ucs2,
+            _ => Coding::Octets,
+        }
+    }
+
+    /// The number of bytes of short_message we can fit in one SMS after
+    /// a UDH of header_length octets.
+    fn max_bytes(self, header_length: usize) -> usize {
+        let octets = MAX_OCTETS.saturating_sub(header_length);
+        match self {
+            // Septets after a UDH start on a septet boundary
+            Coding::Septets => octets * 8 / 7,
+            Coding::Ucs2 => octets & !1,
+            Coding::Octets => octets,
+        }
+    }
+
+    /// Split message into chunks of at most max_bytes, never splitting
+    /// a character.
+    fn split(self, message: &[u8], max_bytes: usize) -> Vec<&[u8]> {
+        let mut ret = Vec::new();
+        let mut rest = message;
+        while rest.len() > max_bytes {
+            let mut len = max_bytes;
+            match self {
+                Coding::Septets => {
+                    if rest[len - 1] == GSM_ESCAPE {
+                        len -= 1;
+                    }
+                }
+                Coding::Ucs2 => {
+                    // Don't split a UTF-16 surrogate pair
+                    if (0xd8..=0xdb).contains(&rest[len - 2]) {
+                        len -= 2;
+                    }
+                }
+                Coding::Octets => {}
+            }
+            let (chunk, remainder) = rest.split_at(len);
+            ret.push(chunk);
+            rest = remainder;
+        }
+        if !rest.is_empty() {
+            ret.push(rest);
+        }
+        ret
+    }
+}
@@ -0,0 +1,9 @@
+//! smpp-send: a command-line ESME that binds, submits some messages,
+//! prints the responses and, if asked, the DRs and MOs that follow.
+
+#[allow(clippy::module_inception)]
+pub mod send;
+pub mod send_config;
+
+pub use send::{run, send, SendError};
+pub use send_config::{SendConfig, TlvArg};
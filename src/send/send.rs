@@ -0,0 +1,266 @@
+use async_trait::async_trait;
+use log::*;
+use smpp_pdu::pdu::DeliverSmPdu;
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::error;
+use std::fmt::{Display, Formatter};
+use std::io::Write;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::{timeout_at, Instant};
+
+use crate::async_result::AsyncResult;
+use crate::esme::{DeliverSmError, EsmeClient, EsmeLogic};
+use crate::segmentation::{SegmentationError, Segmenter};
+use crate::send::send_config::{parse_hex, SendConfig};
+use crate::sm_fields::SmFields;
+
+/// The bits of esm_class that give the message type
+const ESM_CLASS_MESSAGE_TYPE: u8 = 0x3c;
+const ESM_CLASS_DELIVERY_RECEIPT: u8 = 0x04;
+
+#[derive(Debug)]
+pub enum SendError {
+    /// The text cannot be written in the chosen data_coding
+    CannotEncode {
+        message: String,
+        data_coding: u8,
+    },
+    /// --hex was given, but the message is not hex
+    NotHex(String),
+    Segmentation(SegmentationError),
+}
+
+impl Display for SendError {
+    fn fmt(
+        &self,
+        formatter: &mut Formatter,
+    ) -> std::result::Result<(), std::fmt::Error> {
+        let s = match self {
+            SendError::CannotEncode {
+                message,
+                data_coding,
+            } => format!(
+                "Cannot encode '{}' with data_coding {}: try \
+                --data-coding 8, or --hex",
+                message, data_coding
+            ),
+            SendError::NotHex(message) => {
+                format!("'{}' is not a string of hex bytes", message)
+            }
+            SendError::Segmentation(SegmentationError::TooLong) => {
+                String::from("A message is too long to send")
+            }
+            SendError::Segmentation(SegmentationError::PduError(e)) => {
+                format!("Unable to make submit_sm: {}", e)
+            }
+        };
+        formatter.write_str(&s)
+    }
+}
+
+impl error::Error for SendError {}
+
+/// Send the messages in config, printing the results to stdout.  Returns
+/// how many submit_sm failed.
+pub fn run(config: SendConfig) -> AsyncResult<usize> {
+    let rt = tokio::runtime::Runtime::new()?;
+    rt.block_on(send(&config, &mut std::io::stdout()))
+}
+
+/// Bind, send each message to each destination, write the SMSC's answers
+/// to output, then wait for DRs and MOs if config says to.  Returns how
+/// many submit_sm failed.
+pub async fn send<W: Write>(
+    config: &SendConfig,
+    output: &mut W,
+) -> AsyncResult<usize> {
+    let submits = build_submits(config)?;
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let client =
+        EsmeClient::connect(config.esme.clone(), ForwardingLogic { tx })
+            .await?;
+
+    let mut message_ids = HashSet::new();
+    let mut failures = 0;
+    for (what, fields) in submits {
+        match client.submit_sm(fields.to_submit_sm()?).await {
+            Ok(message_id) => {
+                writeln!(output, "{}: message_id={}", what, message_id)?;
+                message_ids.insert(message_id);
+            }
+            Err(e) => {
+                writeln!(output, "{}: {}", what, e)?;
+                failures += 1;
+            }
+        }
+    }
+
+    if config.wait_secs > 0 {
+        // Only registered_delivery 1 promises a DR for every message
+        let mut awaiting_drs = if config.registered_delivery & 0x03 == 1 {
+            Some(message_ids.clone())
+        } else {
+            None
+        };
+        let deadline = Instant::now() + Duration::from_secs(config.wait_secs);
+        while !awaiting_drs.as_ref().is_some_and(HashSet::is_empty) {
+            let delivered = match timeout_at(deadline, rx.recv()).await {
+                Ok(Some(delivered)) => delivered,
+                _ => break,
+            };
+            writeln!(output, "{}", describe(&delivered, &message_ids))?;
+            if let (Some(awaiting), Some(id)) =
+                (awaiting_drs.as_mut(), &delivered.receipted_message_id)
+            {
+                awaiting.remove(id);
+            }
+        }
+    }
+
+    if let Err(e) = client.unbind().await {
+        warn!("Failed to unbind: {}", e);
+    }
+    Ok(failures)
+}
+
+/// The submit_sm to send, each with a description for the output.  We
+/// build them all before binding, so a bad message sends nothing.
+fn build_submits(
+    config: &SendConfig,
+) -> Result<Vec<(String, SmFields)>, SendError> {
+    let messages = config
+        .messages
+        .iter()
+        .map(|message| encode(message, config))
+        .collect::<Result<Vec<_>, _>>()?;
+    let template = SmFields {
+        service_type: config.service_type.clone(),
+        source_addr_ton: config.source_addr_ton,
+        source_addr_npi: config.source_addr_npi,
+        source_addr: config.source_addr.clone(),
+        dest_addr_ton: config.dest_addr_ton,
+        dest_addr_npi: config.dest_addr_npi,
+        registered_delivery: config.registered_delivery,
+        data_coding: config.data_coding,
+        tlvs: config.tlvs.iter().map(|tlv| tlv.0.clone()).collect(),
+        ..Default::default()
+    };
+    let segmenter = Segmenter::new(config.split);
+
+    let mut submits = Vec::new();
+    for destination_addr in &config.destination_addrs {
+        let template = SmFields {
+            destination_addr: destination_addr.clone(),
+            ..template.clone()
+        };
+        for message in &messages {
+            let parts = segmenter
+                .segment(&template, message, config.split)
+                .map_err(SendError::Segmentation)?;
+            let total = parts.len();
+            for (i, fields) in parts.into_iter().enumerate() {
+                let mut what = format!("submit_sm to {}", destination_addr);
+                if total > 1 {
+                    what += &format!(" (part {}/{})", i + 1, total);
+                }
+                submits.push((what, fields));
+            }
+        }
+    }
+    Ok(submits)
+}
+
+/// message as the bytes of a short_message with config's data_coding
+fn encode(message: &str, config: &SendConfig) -> Result<Vec<u8>, SendError> {
+    if config.hex {
+        return parse_hex(message)
+            .ok_or_else(|| SendError::NotHex(String::from(message)));
+    }
+    let cannot_encode = || SendError::CannotEncode {
+        message: String::from(message),
+        data_coding: config.data_coding,
+    };
+    match config.data_coding {
+        0x00 | 0x01 if message.is_ascii() => Ok(message.as_bytes().to_vec()),
+        0x03 => message
+            .chars()
+            .map(|c| u8::try_from(u32::from(c)).map_err(|_| cannot_encode()))
+            .collect(),
+        0x08 => Ok(message
+            .encode_utf16()
+            .flat_map(|unit| unit.to_be_bytes())
+            .collect()),
+        _ => Err(cannot_encode()),
+    }
+}
+
+/// What we need from a deliver_sm to print it
+struct Delivered {
+    fields: SmFields,
+    receipted_message_id: Option<String>,
+}
+
+/// A deliver_sm as one line: a DR, saying whether it is for one of
+/// message_ids, or an MO
+fn describe(delivered: &Delivered, message_ids: &HashSet<String>) -> String {
+    let fields = &delivered.fields;
+    let text = fields.text();
+
+    if fields.esm_class & ESM_CLASS_MESSAGE_TYPE != ESM_CLASS_DELIVERY_RECEIPT {
+        return format!(
+            "MO from {} to {}: {}",
+            fields.source_addr, fields.destination_addr, text
+        );
+    }
+    let message_id = match &delivered.receipted_message_id {
+        Some(id) if message_ids.contains(id) => format!("message_id={}", id),
+        Some(id) => format!("message_id={} (not sent by us)", id),
+        None => String::from("unknown message_id"),
+    };
+    let status =
+        match (receipt_field(&text, "stat"), receipt_field(&text, "err")) {
+            (Some(stat), Some(err)) => format!("stat={} err={}", stat, err),
+            (Some(stat), None) => format!("stat={}", stat),
+            _ => text.clone(),
+        };
+    format!(
+        "DR for {} to {}: {}",
+        message_id, fields.source_addr, status
+    )
+}
+
+/// The value of a "name:value" field in the text of a DR (see Appendix B
+/// of https://smpp.org/SMPP_v3_4_Issue1_2.pdf)
+fn receipt_field<'a>(text: &'a str, name: &str) -> Option<&'a str> {
+    text.split_whitespace().find_map(|word| {
+        let (key, value) = word.split_once(':')?;
+        if key.eq_ignore_ascii_case(name) {
+            Some(value)
+        } else {
+            None
+        }
+    })
+}
+
+/// Passes each deliver_sm on to send, to print
+struct ForwardingLogic {
+    tx: mpsc::UnboundedSender<Delivered>,
+}
+
+#[async_trait]
+impl EsmeLogic for ForwardingLogic {
+    async fn deliver_sm(
+        &mut self,
+        pdu: &DeliverSmPdu,
+    ) -> Result<(), DeliverSmError> {
+        // send stops listening once it has finished waiting
+        let _ = self.tx.send(Delivered {
+            fields: SmFields::from_sm_data(&pdu.0),
+            receipted_message_id: pdu.extract_receipted_message_id(),
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,126 @@
+use clap::{ArgSettings, Clap};
+use smpp_pdu::pdu::tlvs::Tlv;
+use std::str::FromStr;
+
+use crate::esme::EsmeConfig;
+use crate::segmentation::SegmentationMode;
+
+/// Send SMS through an SMSC: bind, submit messages and print the
+/// responses, then optionally wait for DRs and MOs
+#[derive(Clap, Clone, Debug)]
+#[clap(name = "smpp-send")]
+pub struct SendConfig {
+    #[clap(flatten)]
+    pub esme: EsmeConfig,
+
+    /// The messages to send.  Each is sent to every --to address.
+    #[clap(required = true)]
+    pub messages: Vec<String>,
+
+    /// destination_addr to send to.  Repeat to send to several.
+    #[clap(long = "to", required = true, number_of_values = 1)]
+    pub destination_addrs: Vec<String>,
+
+    #[clap(long, default_value = "1")]
+    pub dest_addr_ton: u8,
+
+    #[clap(long, default_value = "1")]
+    pub dest_addr_npi: u8,
+
+    /// source_addr to send from
+    #[clap(
+        long = "from",
+        default_value = "",
+        setting = ArgSettings::AllowEmptyValues
+    )]
+    pub source_addr: String,
+
+    #[clap(long, default_value = "0")]
+    pub source_addr_ton: u8,
+
+    #[clap(long, default_value = "0")]
+    pub source_addr_npi: u8,
+
+    #[clap(
+        long,
+        default_value = "",
+        setting = ArgSettings::AllowEmptyValues
+    )]
+    pub service_type: String,
+
+    /// Messages are encoded as ASCII for data_coding 0 and 1, Latin-1 for
+    /// 3 and UCS2 for 8.  Use --hex for any other.
+    #[clap(long, default_value = "0")]
+    pub data_coding: u8,
+
+    /// The messages are hex, to be sent as the bytes they spell
+    #[clap(long)]
+    pub hex: bool,
+
+    /// 1 asks for a DR whatever happens, 2 only on failure
+    #[clap(long, default_value = "0")]
+    pub registered_delivery: u8,
+
+    /// A TLV to add to each submit_sm, as TAG=HEX_VALUE, e.g.
+    /// 0x1400=0102.  Repeat to add several.
+    #[clap(long = "tlv", number_of_values = 1)]
+    pub tlvs: Vec<TlvArg>,
+
+    /// How to split messages too long for one short_message: udh8,
+    /// udh16, sar or payload
+    #[clap(long, default_value = "udh8")]
+    pub split: SegmentationMode,
+
+    /// How long to wait for DRs and MOs after submitting, printing them
+    /// as they arrive.  With --registered-delivery 1, we stop early once
+    /// every message has its DR.
+    #[clap(long, default_value = "0")]
+    pub wait_secs: u64,
+
+    #[clap(long, default_value = "warn")]
+    pub log_level: String,
+}
+
+/// A TLV given on the command line
+#[derive(Clone, Debug, PartialEq)]
+pub struct TlvArg(pub Tlv);
+
+impl FromStr for TlvArg {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad = || {
+            format!(
+                "Invalid TLV '{}': expected TAG=HEX_VALUE, e.g. 0x1400=01",
+                s
+            )
+        };
+        let (tag, value) = s.split_once('=').ok_or_else(bad)?;
+        let raw_tag = parse_tag(tag).ok_or_else(bad)?;
+        let value = parse_hex(value).ok_or_else(bad)?;
+        Ok(TlvArg(Tlv { raw_tag, value }))
+    }
+}
+
+/// A TLV tag in hex with a 0x prefix, or in decimal
+pub fn parse_tag(s: &str) -> Option<u16> {
+    match s.strip_prefix("0x") {
+        Some(hex_tag) => u16::from_str_radix(hex_tag, 16),
+        None => s.parse(),
+    }
+    .ok()
+}
+
+/// The bytes spelt by s, which may contain spaces between them
+pub fn parse_hex(s: &str) -> Option<Vec<u8>> {
+    let digits: Vec<u8> = s.bytes().filter(|b| *b != b' ').collect();
+    if !digits.len().is_multiple_of(2) {
+        return None;
+    }
+    digits
+        .chunks(2)
+        .map(|pair| {
+            u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()
+        })
+        .collect()
+}
@@ -0,0 +1,48 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+
+const MAX_SEQUENCE_NUMBER: u32 = 0x7fffffff;
+
+/// Hands out sequence_numbers for the requests we send on one connection.
+/// They count up from 1 and wrap round after 0x7FFFFFFF, the largest
+/// value SMPP allows.
+pub struct SequenceNumberGenerator {
+    last: AtomicU32,
+}
+
+impl SequenceNumberGenerator {
+    pub fn new() -> Self {
+        Self::starting_after(0)
+    }
+
+    /// A generator whose first sequence_number follows `last`, wrapping
+    /// round to 1 if `last` is 0x7FFFFFFF or more.
+    pub fn starting_after(last: u32) -> Self {
+        Self {
+            last: AtomicU32::new(last),
+        }
+    }
+
+    pub fn next_sequence_number(&self) -> u32 {
+        let previous = self
+            .last
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |last| {
+                Some(following(last))
+            })
+            .expect("the update always returns Some");
+        following(previous)
+    }
+}
+
+impl Default for SequenceNumberGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn following(last: u32) -> u32 {
+    if last >= MAX_SEQUENCE_NUMBER {
+        1
+    } else {
+        last + 1
+    }
+}
@@ -0,0 +1,651 @@
+//! An SmscLogic for testing ESMEs against, which rejects a share of MTs
+//! and sends DRs with chosen final states after chosen delays, by rules
+//! for destination prefixes and accounts.
+
+use async_trait::async_trait;
+use clap::Clap;
+use log::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Deserialize;
+use smpp_pdu::pdu::tlvs::{KnownTlvTag, Tlv, Tlvs};
+use smpp_pdu::pdu::{
+    DeliverEsmClass, DeliverSmPdu, Pdu, SubmitSmPdu, SubmitSmRespPdu,
+};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::future::Future;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::{oneshot, Mutex};
+use tokio::time::sleep;
+
+use crate::async_result::AsyncResult;
+use crate::message_id_generator::MessageIdGenerator;
+use crate::message_unique_key::MessageUniqueKey;
+use crate::routing::RoutingDecision;
+use crate::smpp_connection::EsmeId;
+use crate::smsc::{
+    AccountStore, BindContext, BindData, BindError, Smsc, SmscConfig,
+    SmscLogic, SubmitSmError,
+};
+
+/// The namespace_id of the MessageUniqueKeys for the MTs we accept
+pub const SIMULATOR_NAMESPACE: &str = "simulator";
+
+/// The final state of a message, as given in its DR
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(try_from = "String")]
+pub enum DrState {
+    Delivered,
+    Undeliverable,
+    Expired,
+    Rejected,
+}
+
+impl DrState {
+    /// The stat: field of the DR text
+    pub fn stat(&self) -> &'static str {
+        match self {
+            DrState::Delivered => "DELIVRD",
+            DrState::Undeliverable => "UNDELIV",
+            DrState::Expired => "EXPIRED",
+            DrState::Rejected => "REJECTD",
+        }
+    }
+
+    /// The value of the message_state TLV
+    pub fn message_state(&self) -> u8 {
+        match self {
+            DrState::Delivered => 2,
+            DrState::Expired => 3,
+            DrState::Undeliverable => 5,
+            DrState::Rejected => 8,
+        }
+    }
+}
+
+impl FromStr for DrState {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "DELIVRD" => Ok(DrState::Delivered),
+            "UNDELIV" => Ok(DrState::Undeliverable),
+            "EXPIRED" => Ok(DrState::Expired),
+            "REJECTD" => Ok(DrState::Rejected),
+            _ => Err(format!(
+                "Unknown DR state '{}': expected DELIVRD, UNDELIV, EXPIRED \
+                or REJECTD",
+                s
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for DrState {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// One possible ending for a message, chosen in proportion to its weight
+/// among the others.  Written as STATE[:ERR][=WEIGHT], e.g. DELIVRD=90
+/// or UNDELIV:034=10.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(try_from = "String")]
+pub struct DrOutcome {
+    pub state: DrState,
+    /// The err: field of the DR text, written as 3 digits
+    pub err: u16,
+    pub weight: u32,
+}
+
+impl FromStr for DrOutcome {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (outcome, weight) = match s.split_once('=') {
+            Some((outcome, weight)) => (
+                outcome,
+                weight
+                    .parse()
+                    .map_err(|_| format!("Invalid weight in '{}'", s))?,
+            ),
+            None => (s, 1),
+        };
+        let (state, err) = match outcome.split_once(':') {
+            Some((state, err)) => (
+                state,
+                err.parse()
+                    .ok()
+                    .filter(|err| *err < 1000)
+                    .ok_or_else(|| format!("Invalid err in '{}'", s))?,
+            ),
+            None => (outcome, 0),
+        };
+        Ok(Self {
+            state: state.parse()?,
+            err,
+            weight,
+        })
+    }
+}
+
+impl TryFrom<String> for DrOutcome {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// How long after accepting an MT to send its DR.  Written as fixed:MS,
+/// uniform:MIN-MAX or exponential:MEAN, all in milliseconds.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(try_from = "String")]
+pub enum DrDelay {
+    Fixed { ms: u64 },
+    Uniform { min_ms: u64, max_ms: u64 },
+    Exponential { mean_ms: u64 },
+}
+
+impl DrDelay {
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> Duration {
+        let ms = match *self {
+            DrDelay::Fixed { ms } => ms,
+            DrDelay::Uniform { min_ms, max_ms } => {
+                rng.gen_range(min_ms..=max_ms.max(min_ms))
+            }
+            DrDelay::Exponential { mean_ms } => {
+                let uniform: f64 = rng.gen();
+                (-(mean_ms as f64) * (1.0 - uniform).ln()) as u64
+            }
+        };
+        Duration::from_millis(ms)
+    }
+}
+
+impl FromStr for DrDelay {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "Invalid DR delay '{}': expected fixed:MS, uniform:MIN-MAX \
+                or exponential:MEAN",
+                s
+            )
+        };
+        let ms = |value: &str| value.parse::<u64>().map_err(|_| invalid());
+        let (kind, value) = s.split_once(':').ok_or_else(invalid)?;
+        match kind {
+            "fixed" => Ok(DrDelay::Fixed { ms: ms(value)? }),
+            "uniform" => {
+                let (min, max) = value.split_once('-').ok_or_else(invalid)?;
+                let (min_ms, max_ms) = (ms(min)?, ms(max)?);
+                if min_ms > max_ms {
+                    return Err(invalid());
+                }
+                Ok(DrDelay::Uniform { min_ms, max_ms })
+            }
+            "exponential" => Ok(DrDelay::Exponential {
+                mean_ms: ms(value)?,
+            }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl TryFrom<String> for DrDelay {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// How to treat MTs to some destinations, or from some account.  Settings
+/// left out are taken from less specific rules, or the SimulatorConfig.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SimulatorRule {
+    /// Matches destination_addr values (digits only) starting with this.
+    /// Empty matches everything.
+    #[serde(default)]
+    pub prefix: String,
+    /// If set, only matches MTs from the ESME with this system_id
+    #[serde(default)]
+    pub account: Option<String>,
+    /// The share of submit_sm, from 0 to 1, to reject
+    #[serde(default)]
+    pub error_rate: Option<f64>,
+    /// The final states to choose between for DRs
+    #[serde(default)]
+    pub dr_outcomes: Option<Vec<DrOutcome>>,
+    #[serde(default)]
+    pub dr_delay: Option<DrDelay>,
+}
+
+impl SimulatorRule {
+    fn matches(&self, account: &str, destination: &str) -> bool {
+        destination.starts_with(&self.prefix)
+            && self.account.as_deref().is_none_or(|a| a == account)
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if !self.prefix.chars().all(|c| c.is_ascii_digit()) {
+            return Err(format!(
+                "simulator prefix '{}' must contain only digits",
+                self.prefix
+            ));
+        }
+        if let Some(error_rate) = self.error_rate {
+            validate_error_rate(error_rate)?;
+        }
+        if let Some(outcomes) = &self.dr_outcomes {
+            validate_outcomes(outcomes)?;
+        }
+        Ok(())
+    }
+}
+
+fn validate_error_rate(error_rate: f64) -> Result<(), String> {
+    if (0.0..=1.0).contains(&error_rate) {
+        Ok(())
+    } else {
+        Err(format!(
+            "simulator error_rate {} must be from 0 to 1",
+            error_rate
+        ))
+    }
+}
+
+fn validate_outcomes(outcomes: &[DrOutcome]) -> Result<(), String> {
+    if outcomes.iter().any(|o| o.weight > 0) {
+        Ok(())
+    } else {
+        Err(String::from(
+            "simulator DR outcomes need one with weight > 0",
+        ))
+    }
+}
+
+/// How the simulator treats MTs that no SimulatorRule covers
+#[derive(Clap, Clone, Debug)]
+pub struct SimulatorConfig {
+    /// The share of submit_sm, from 0 to 1, to reject with
+    /// ESME_RSUBMITFAIL
+    #[clap(long, default_value = "0", env = "SIM_ERROR_RATE")]
+    pub sim_error_rate: f64,
+
+    /// The final states to choose between for DRs, as STATE[:ERR][=WEIGHT]
+    /// separated by commas, e.g. DELIVRD=90,UNDELIV:034=5,EXPIRED=5
+    #[clap(
+        long,
+        default_value = "DELIVRD",
+        env = "SIM_DR_OUTCOMES",
+        use_delimiter = true
+    )]
+    pub sim_dr_outcomes: Vec<DrOutcome>,
+
+    /// How long to wait before sending each DR: fixed:MS, uniform:MIN-MAX
+    /// or exponential:MEAN, in milliseconds
+    #[clap(long, default_value = "fixed:1000", env = "SIM_DR_DELAY")]
+    pub sim_dr_delay: DrDelay,
+
+    /// Rules for some destination prefixes or accounts, which override
+    /// the flags.  Each setting comes from the matching rules that set it:
+    /// rules for the ESME's account win over rules for all accounts, then
+    /// the longest prefix wins.
+    #[clap(skip)]
+    pub rules: Vec<SimulatorRule>,
+}
+
+impl Default for SimulatorConfig {
+    /// The same as the flags' defaults: accept every MT, and send DRs
+    /// saying DELIVRD after 1 second
+    fn default() -> Self {
+        Self {
+            sim_error_rate: 0.0,
+            sim_dr_outcomes: vec![DrOutcome {
+                state: DrState::Delivered,
+                err: 0,
+                weight: 1,
+            }],
+            sim_dr_delay: DrDelay::Fixed { ms: 1000 },
+            rules: Vec::new(),
+        }
+    }
+}
+
+impl SimulatorConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        validate_error_rate(self.sim_error_rate)?;
+        validate_outcomes(&self.sim_dr_outcomes)?;
+        self.rules.iter().try_for_each(SimulatorRule::validate)
+    }
+
+    /// What to do with an MT from account to destination
+    fn behaviour(&self, account: &str, destination: &str) -> Behaviour<'_> {
+        let mut rules: Vec<&SimulatorRule> = self
+            .rules
+            .iter()
+            .filter(|r| r.matches(account, destination))
+            .collect();
+        rules.sort_by_key(|r| {
+            (Reverse(r.account.is_some()), Reverse(r.prefix.len()))
+        });
+        Behaviour {
+            error_rate: rules
+                .iter()
+                .find_map(|r| r.error_rate)
+                .unwrap_or(self.sim_error_rate),
+            dr_outcomes: rules
+                .iter()
+                .find_map(|r| r.dr_outcomes.as_deref())
+                .unwrap_or(&self.sim_dr_outcomes),
+            dr_delay: rules
+                .iter()
+                .find_map(|r| r.dr_delay)
+                .unwrap_or(self.sim_dr_delay),
+        }
+    }
+}
+
+struct Behaviour<'a> {
+    error_rate: f64,
+    dr_outcomes: &'a [DrOutcome],
+    dr_delay: DrDelay,
+}
+
+/// DRs and MOs waiting until the Smsc has recorded the MT they follow,
+/// and responded to it, so they can be routed to its ESME
+#[derive(Default)]
+pub(crate) struct PendingSends {
+    waiting: HashMap<MessageUniqueKey, Vec<oneshot::Sender<()>>>,
+}
+
+impl PendingSends {
+    /// Run send once the MT with message_unique_key has been responded to
+    pub(crate) fn add<F>(
+        &mut self,
+        message_unique_key: MessageUniqueKey,
+        send: F,
+    ) where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        self.waiting.entry(message_unique_key).or_default().push(tx);
+        tokio::spawn(async move {
+            if rx.await.is_ok() {
+                send.await;
+            }
+        });
+    }
+
+    /// Start the sends waiting for any of message_unique_keys
+    pub(crate) fn release(&mut self, message_unique_keys: &[MessageUniqueKey]) {
+        for key in message_unique_keys {
+            for tx in self.waiting.remove(key).into_iter().flatten() {
+                let _ = tx.send(());
+            }
+        }
+    }
+}
+
+/// Accepts binds like DrsAfter1Sec: if system_id==password, or if the ESME
+/// is in its AccountStore when it has one.  Rejects a share of MTs, and
+/// sends a DR for each accepted one that asks for it, by the rules in its
+/// SimulatorConfig.  On reload, both are read again.
+pub struct Simulator {
+    config: SimulatorConfig,
+    accounts: Option<AccountStore>,
+    message_ids: MessageIdGenerator,
+    rng: StdRng,
+    pending: PendingSends,
+}
+
+impl Simulator {
+    /// Fails if config.simulator is invalid, or accounts_file cannot be
+    /// loaded
+    pub fn new(config: &SmscConfig) -> AsyncResult<Self> {
+        let mut simulator = Self {
+            config: config.simulator.clone(),
+            accounts: None,
+            message_ids: MessageIdGenerator::new(),
+            rng: StdRng::from_entropy(),
+            pending: PendingSends::default(),
+        };
+        simulator.configure(config)?;
+        Ok(simulator)
+    }
+
+    fn configure(&mut self, config: &SmscConfig) -> AsyncResult<()> {
+        config.simulator.validate()?;
+        self.accounts = match &config.accounts_file {
+            Some(path) => Some(AccountStore::load(Path::new(path))?),
+            None => None,
+        };
+        self.config = config.simulator.clone();
+        Ok(())
+    }
+
+    async fn simulate(
+        &mut self,
+        smsc: Arc<Mutex<Smsc>>,
+        pdu: &SubmitSmPdu,
+        account: &str,
+    ) -> Result<(SubmitSmRespPdu, MessageUniqueKey), SubmitSmError> {
+        let destination: String = pdu
+            .destination_addr()
+            .chars()
+            .filter(char::is_ascii_digit)
+            .collect();
+        let behaviour = self.config.behaviour(account, &destination);
+        if self.rng.gen_bool(behaviour.error_rate) {
+            return Err(SubmitSmError::SubmitFailed);
+        }
+
+        let message_id = self.message_ids.next_message_id();
+        let message_unique_key = MessageUniqueKey::from_submit_sm(
+            String::from(SIMULATOR_NAMESPACE),
+            message_id.clone(),
+            pdu,
+        );
+        let outcome = choose(behaviour.dr_outcomes, &mut self.rng);
+        let wants_dr = match pdu.0.registered_delivery.value & 0x03 {
+            1 => true,
+            2 => outcome.state != DrState::Delivered,
+            _ => false,
+        };
+        if wants_dr {
+            let delay = behaviour.dr_delay.sample(&mut self.rng);
+            let submitted = SystemTime::now();
+            let deliver_sm = create_dr(&message_id, pdu, &outcome, submitted)
+                .map_err(|_| SubmitSmError::InternalError)?;
+            let send = async move {
+                sleep(delay).await;
+                let result = smsc
+                    .lock()
+                    .await
+                    .receive_pdu(SIMULATOR_NAMESPACE, deliver_sm)
+                    .await;
+                if let Err(e) = result {
+                    warn!("Unable to send simulated DR: {}", e);
+                }
+            };
+            self.pending.add(message_unique_key.clone(), send);
+        }
+
+        Ok((
+            SubmitSmRespPdu::new(&message_id)
+                .map_err(|_| SubmitSmError::InternalError)?,
+            message_unique_key,
+        ))
+    }
+}
+
+/// One of outcomes, in proportion to their weights
+fn choose<R: Rng>(outcomes: &[DrOutcome], rng: &mut R) -> DrOutcome {
+    let total: u64 = outcomes.iter().map(|o| u64::from(o.weight)).sum();
+    let mut pick = rng.gen_range(0..total.max(1));
+    for outcome in outcomes {
+        if pick < u64::from(outcome.weight) {
+            return outcome.clone();
+        }
+        pick -= u64::from(outcome.weight);
+    }
+    // Only reached if no outcome has a weight, which validate prevents
+    outcomes[0].clone()
+}
+
+#[async_trait]
+impl SmscLogic for Simulator {
+    async fn bind(&mut self, bind_data: &BindData) -> Result<(), BindError> {
+        if bind_data.system_id == bind_data.password {
+            Ok(())
+        } else {
+            Err(BindError::IncorrectPassword)
+        }
+    }
+
+    async fn bind_with_context(
+        &mut self,
+        bind_data: &BindData,
+        context: &BindContext,
+    ) -> Result<(), BindError> {
+        match &self.accounts {
+            Some(accounts) => accounts.authenticate(bind_data, context).await,
+            None => self.bind(bind_data).await,
+        }
+    }
+
+    async fn reload(&mut self, config: &SmscConfig) -> AsyncResult<()> {
+        self.configure(config)
+    }
+
+    async fn may_stay_bound(&mut self, esme_id: &EsmeId) -> bool {
+        self.accounts
+            .as_ref()
+            .is_none_or(|accounts| accounts.allows(esme_id.system_id.as_str()))
+    }
+
+    async fn submit_sm(
+        &mut self,
+        smsc: Arc<Mutex<Smsc>>,
+        pdu: &SubmitSmPdu,
+        _sequence_number: u32,
+    ) -> Result<(SubmitSmRespPdu, MessageUniqueKey), SubmitSmError> {
+        self.simulate(smsc, pdu, "").await
+    }
+
+    async fn submit_sm_from_esme(
+        &mut self,
+        smsc: Arc<Mutex<Smsc>>,
+        pdu: &SubmitSmPdu,
+        _sequence_number: u32,
+        esme_id: &EsmeId,
+        _route: Option<&RoutingDecision>,
+    ) -> Result<(SubmitSmRespPdu, MessageUniqueKey), SubmitSmError> {
+        self.simulate(smsc, pdu, esme_id.system_id.as_str()).await
+    }
+
+    async fn submit_sm_responded(
+        &mut self,
+        _smsc: Arc<Mutex<Smsc>>,
+        message_unique_keys: &[MessageUniqueKey],
+    ) {
+        self.pending.release(message_unique_keys);
+    }
+}
+
+/// A DR for the MT submit_sm, in the format of Appendix B of
+/// https://smpp.org/SMPP_v3_4_Issue1_2.pdf, with the receipted_message_id
+/// and message_state TLVs too.  The done date is when we make it.
+pub(crate) fn create_dr(
+    message_id: &str,
+    submit_sm: &SubmitSmPdu,
+    outcome: &DrOutcome,
+    submitted: SystemTime,
+) -> AsyncResult<Pdu> {
+    let delivered = outcome.state == DrState::Delivered;
+    let text = format!(
+        "id:{} sub:001 dlvrd:{} submit date:{} done date:{} stat:{} \
+        err:{:03} text:",
+        message_id,
+        if delivered { "001" } else { "000" },
+        dr_date(submitted),
+        dr_date(SystemTime::now()),
+        outcome.state.stat(),
+        outcome.err,
+    );
+    Ok(Pdu::new(
+        0,
+        // Smsc replaces this with the next sequence_number for the ESME
+        1,
+        DeliverSmPdu::new(
+            "",
+            submit_sm.dest_addr_ton(),
+            submit_sm.dest_addr_npi(),
+            &submit_sm.destination_addr(),
+            submit_sm.source_addr_ton(),
+            submit_sm.source_addr_npi(),
+            &submit_sm.source_addr(),
+            DeliverEsmClass::SmscDeliveryReceipt as u8,
+            0x34,
+            0,
+            "",
+            "",
+            0,
+            0,
+            0,
+            0,
+            text.as_bytes(),
+            Tlvs::from(&[
+                Tlv::new(
+                    KnownTlvTag::receipted_message_id,
+                    message_id.as_bytes(),
+                ),
+                Tlv::new(
+                    KnownTlvTag::message_state,
+                    &[outcome.state.message_state()],
+                ),
+            ]),
+        )?
+        .into(),
+    )?)
+}
+
+/// time as YYMMDDhhmm in UTC, for the dates in a DR
+fn dr_date(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, secs_of_day) = (secs / 86_400, secs % 86_400);
+    // Days since 1970-01-01 to a civil date, by Howard Hinnant's algorithm
+    // (https://howardhinnant.github.io/date_algorithms.html#civil_from_days)
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524
+        - day_of_era / 146_096)
+        / 365;
+    let day_of_year =
+        day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    format!(
+        "{:02}{:02}{:02}{:02}{:02}",
+        year % 100,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60
+    )
+}
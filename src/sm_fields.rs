@@ -0,0 +1,169 @@
+use smpp_pdu::pdu::data::sm_data::SmData;
+use smpp_pdu::pdu::tlvs::{KnownTlvTag, Tlv, Tlvs};
+use smpp_pdu::pdu::{DeliverSmPdu, PduParseError, SubmitSmPdu};
+use std::io::Cursor;
+
+use crate::concatenation::ESM_CLASS_UDHI;
+
+/// An owned, editable copy of the body of a submit_sm or deliver_sm.
+/// The types in smpp_pdu can't be cloned or modified, so we use this when
+/// we need to build a PDU that is based on another one.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SmFields {
+    pub service_type: String,
+    pub source_addr_ton: u8,
+    pub source_addr_npi: u8,
+    pub source_addr: String,
+    pub dest_addr_ton: u8,
+    pub dest_addr_npi: u8,
+    pub destination_addr: String,
+    pub esm_class: u8,
+    pub protocol_id: u8,
+    pub priority_flag: u8,
+    pub schedule_delivery_time: String,
+    pub validity_period: String,
+    pub registered_delivery: u8,
+    pub replace_if_present_flag: u8,
+    pub data_coding: u8,
+    pub sm_default_msg_id: u8,
+    pub short_message: Vec<u8>,
+    pub tlvs: Vec<Tlv>,
+}
+
+impl SmFields {
+    pub fn from_sm_data(sm_data: &SmData) -> Self {
+        Self {
+            service_type: sm_data.service_type.value.to_string(),
+            source_addr_ton: sm_data.source_addr_ton.value,
+            source_addr_npi: sm_data.source_addr_npi.value,
+            source_addr: sm_data.source_addr.value.to_string(),
+            dest_addr_ton: sm_data.dest_addr_ton.value,
+            dest_addr_npi: sm_data.dest_addr_npi.value,
+            destination_addr: sm_data.destination_addr.value.to_string(),
+            esm_class: sm_data.esm_class.value,
+            protocol_id: sm_data.protocol_id.value,
+            priority_flag: sm_data.priority_flag.value,
+            schedule_delivery_time: sm_data
+                .schedule_delivery_time
+                .value
+                .to_string(),
+            validity_period: sm_data.validity_period.value.to_string(),
+            registered_delivery: sm_data.registered_delivery.value,
+            replace_if_present_flag: sm_data.replace_if_present_flag.value,
+            data_coding: sm_data.data_coding.value,
+            sm_default_msg_id: sm_data.sm_default_msg_id.value,
+            short_message: sm_data.short_message.value.clone(),
+            tlvs: tlvs_to_vec(&sm_data.tlvs),
+        }
+    }
+
+    pub fn to_submit_sm(&self) -> Result<SubmitSmPdu, PduParseError> {
+        SubmitSmPdu::new(
+            &self.service_type,
+            self.source_addr_ton,
+            self.source_addr_npi,
+            &self.source_addr,
+            self.dest_addr_ton,
+            self.dest_addr_npi,
+            &self.destination_addr,
+            self.esm_class,
+            self.protocol_id,
+            self.priority_flag,
+            &self.schedule_delivery_time,
+            &self.validity_period,
+            self.registered_delivery,
+            self.replace_if_present_flag,
+            self.data_coding,
+            self.sm_default_msg_id,
+            &self.short_message,
+            Tlvs::from(&self.tlvs),
+        )
+    }
+
+    pub fn to_deliver_sm(&self) -> Result<DeliverSmPdu, PduParseError> {
+        DeliverSmPdu::new(
+            &self.service_type,
+            self.source_addr_ton,
+            self.source_addr_npi,
+            &self.source_addr,
+            self.dest_addr_ton,
+            self.dest_addr_npi,
+            &self.destination_addr,
+            self.esm_class,
+            self.protocol_id,
+            self.priority_flag,
+            &self.schedule_delivery_time,
+            &self.validity_period,
+            self.registered_delivery,
+            self.replace_if_present_flag,
+            self.data_coding,
+            self.sm_default_msg_id,
+            &self.short_message,
+            Tlvs::from(&self.tlvs),
+        )
+    }
+
+    pub fn tlv(&self, tag: KnownTlvTag) -> Option<&Tlv> {
+        let raw_tag = Tlv::new(tag, &[]).raw_tag;
+        self.tlvs.iter().find(|tlv| tlv.raw_tag == raw_tag)
+    }
+
+    /// Replace any existing TLV with the same tag, or add it at the end
+    pub fn set_tlv(&mut self, tlv: Tlv) {
+        if let Some(existing) =
+            self.tlvs.iter_mut().find(|t| t.raw_tag == tlv.raw_tag)
+        {
+            *existing = tlv;
+        } else {
+            self.tlvs.push(tlv);
+        }
+    }
+
+    pub fn remove_tlv(&mut self, tag: KnownTlvTag) {
+        let raw_tag = Tlv::new(tag, &[]).raw_tag;
+        self.tlvs.retain(|tlv| tlv.raw_tag != raw_tag);
+    }
+
+    /// The text of the short_message, or the message_payload if there is
+    /// no short_message, without any UDH.  Decoded as Latin-1 for
+    /// data_coding 3, UCS2 for 8, and otherwise as UTF-8, which covers
+    /// ASCII.
+    pub fn text(&self) -> String {
+        let mut user_data = match self.tlv(KnownTlvTag::message_payload) {
+            Some(tlv) if self.short_message.is_empty() => &tlv.value[..],
+            _ => &self.short_message[..],
+        };
+        if self.esm_class & ESM_CLASS_UDHI != 0 {
+            let udh_length =
+                user_data.first().map_or(0, |l| usize::from(*l) + 1);
+            user_data = user_data.get(udh_length..).unwrap_or(&[]);
+        }
+        match self.data_coding {
+            0x03 => user_data.iter().map(|b| char::from(*b)).collect(),
+            0x08 => String::from_utf16_lossy(
+                &user_data
+                    .chunks_exact(2)
+                    .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                    .collect::<Vec<_>>(),
+            ),
+            _ => String::from_utf8_lossy(user_data).into_owned(),
+        }
+    }
+}
+
+/// List the TLVs inside a Tlvs.  Tlvs does not let us iterate over its
+/// contents, so we write it out and read it back one Tlv at a time.
+pub fn tlvs_to_vec(tlvs: &Tlvs) -> Vec<Tlv> {
+    let mut bytes: Vec<u8> = Vec::new();
+    // Writing to a Vec never waits, so it is safe to block here.
+    if futures::executor::block_on(tlvs.write(&mut bytes)).is_err() {
+        return Vec::new();
+    }
+
+    let mut cursor = Cursor::new(bytes);
+    let mut ret = Vec::new();
+    while let Ok(Some(tlv)) = Tlv::read(&mut cursor) {
+        ret.push(tlv);
+    }
+    ret
+}
@@ -1,32 +1,83 @@
 use ascii::AsciiString;
-use bytes::{Buf, BytesMut};
+use bytes::BytesMut;
 use log::*;
 use smpp_pdu::pdu::{CheckOutcome, Pdu, PduParseError, PduParseErrorBody};
 use std::io;
 use std::io::Cursor;
 use std::net::SocketAddr;
-use tokio::io::{split, AsyncReadExt, ReadHalf, WriteHalf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::io::{split, AsyncReadExt, AsyncWriteExt, ReadHalf, WriteHalf};
 use tokio::net::TcpStream;
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, Notify};
+use tokio::time::sleep;
+use tracing::field::{display, Empty};
+use tracing::{info_span, Span};
 
-#[derive(Clone, Eq, Hash, PartialEq)]
+use crate::capture::{
+    unknown_local_addr, CaptureFile, CapturedSession, Capturer,
+};
+use crate::esme::BindType;
+use crate::extra_pdu::{read_header, AnyPdu, ExtraPdu};
+use crate::faults::{FaultInjector, FaultPlan};
+use crate::logging::LoggedPdu;
+use crate::sequence_number_generator::SequenceNumberGenerator;
+use crate::smsc::metrics::Direction;
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub struct EsmeId {
     pub system_id: AsciiString,
     pub system_type: AsciiString,
 }
 
+/// How a connection is bound, and since when
+#[derive(Clone, Debug)]
+pub struct Binding {
+    pub esme_id: EsmeId,
+    pub bind_type: BindType,
+    pub since: Instant,
+}
+
 pub struct SmppConnection {
     pub socket_addr: SocketAddr,
     read: Mutex<Option<SmppRead>>,
     write: Mutex<Option<SmppWrite>>,
-    bound_esme_id: std::sync::Mutex<Option<EsmeId>>,
+    binding: std::sync::Mutex<Option<Binding>>,
+    /// For requests we originate on this connection, kept separate from
+    /// the sequence_numbers the peer uses for its own requests
+    sequence_numbers: SequenceNumberGenerator,
+    pdus_received: AtomicU64,
+    pdus_sent: AtomicU64,
+    /// Wakes a reader blocked in read_any_pdu, so kill can end it
+    killed: Notify,
+    /// We have sent unbind, and are waiting for unbind_resp
+    unbinding: AtomicBool,
+    id: u64,
+    span: Span,
+    local_addr: SocketAddr,
+    opened: SystemTime,
+    capturer: Option<Arc<Capturer>>,
+    capture_file: std::sync::Mutex<Option<CaptureFile>>,
+    faults: Option<Arc<FaultInjector>>,
+    /// A response the FaultInjector is holding back, to send after the
+    /// next one
+    held_response: std::sync::Mutex<Option<Vec<u8>>>,
+    /// Set by into_arc, so another task can finish a write later
+    shared: Weak<SmppConnection>,
 }
 
+static NEXT_SESSION_ID: AtomicU64 = AtomicU64::new(1);
+
 impl SmppConnection {
     pub fn new(
         tcp_stream: TcpStream,
         socket_addr: SocketAddr,
     ) -> SmppConnection {
+        let id = NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed);
+        let local_addr = tcp_stream
+            .local_addr()
+            .unwrap_or_else(|_| unknown_local_addr());
         let (read_stream, write_stream) = split(tcp_stream);
         let buffer = BytesMut::with_capacity(4096);
         let read = SmppRead {
@@ -40,30 +91,159 @@ impl SmppConnection {
             read: Mutex::new(Some(read)),
             write: Mutex::new(Some(write)),
             socket_addr,
-            bound_esme_id: std::sync::Mutex::new(None),
+            binding: std::sync::Mutex::new(None),
+            sequence_numbers: SequenceNumberGenerator::new(),
+            pdus_received: AtomicU64::new(0),
+            pdus_sent: AtomicU64::new(0),
+            killed: Notify::new(),
+            unbinding: AtomicBool::new(false),
+            id,
+            span: info_span!(
+                "session",
+                id,
+                peer = %socket_addr,
+                system_id = Empty,
+                system_type = Empty,
+            ),
+            local_addr,
+            opened: SystemTime::now(),
+            capturer: None,
+            capture_file: std::sync::Mutex::new(None),
+            faults: None,
+            held_response: std::sync::Mutex::new(None),
+            shared: Weak::new(),
         }
     }
 
+    /// Wrap this connection in an Arc, which lets responses the
+    /// FaultInjector delays or holds back be written by a task of their
+    /// own, without holding up the caller
+    pub fn into_arc(mut self) -> Arc<Self> {
+        Arc::new_cyclic(|shared| {
+            self.shared = shared.clone();
+            self
+        })
+    }
+
+    /// Record this connection's PDUs whenever capturer says to
+    pub fn with_capturer(mut self, capturer: Arc<Capturer>) -> Self {
+        self.capturer = Some(capturer);
+        self
+    }
+
+    /// Inject faults into the PDUs written, whenever faults says to
+    pub fn with_faults(mut self, faults: Arc<FaultInjector>) -> Self {
+        self.faults = Some(faults);
+        self
+    }
+
+    /// Unique within this process
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// The span for everything done on this connection.  It has the
+    /// session id and peer address, and the EsmeId once bound.
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
     pub fn bound_esme_id(&self) -> Option<EsmeId> {
-        self.bound_esme_id.lock().unwrap().clone()
+        self.binding().map(|binding| binding.esme_id)
+    }
+
+    pub fn binding(&self) -> Option<Binding> {
+        self.binding.lock().unwrap().clone()
     }
 
-    pub async fn bind(&self, system_id: AsciiString, system_type: AsciiString) {
-        self.bound_esme_id.lock().unwrap().replace(EsmeId {
-            system_id,
-            system_type,
+    pub async fn bind(
+        &self,
+        system_id: AsciiString,
+        system_type: AsciiString,
+        bind_type: BindType,
+    ) {
+        self.span.record("system_id", &display(&system_id));
+        self.span.record("system_type", &display(&system_type));
+        self.binding.lock().unwrap().replace(Binding {
+            esme_id: EsmeId {
+                system_id,
+                system_type,
+            },
+            bind_type,
+            since: Instant::now(),
         });
     }
 
+    /// How many PDUs we have read from this connection
+    pub fn pdus_received(&self) -> u64 {
+        self.pdus_received.load(Ordering::Relaxed)
+    }
+
+    /// How many PDUs we have written to this connection
+    pub fn pdus_sent(&self) -> u64 {
+        self.pdus_sent.load(Ordering::Relaxed)
+    }
+
+    /// Record that we have asked the peer to unbind, so nothing more is
+    /// sent on this connection.  Returns false if we already had.
+    pub fn start_unbinding(&self) -> bool {
+        !self.unbinding.swap(true, Ordering::Relaxed)
+    }
+
+    /// Whether we have asked the peer to unbind
+    pub fn is_unbinding(&self) -> bool {
+        self.unbinding.load(Ordering::Relaxed)
+    }
+
+    /// Make the current (or next) read_any_pdu return Ok(None) as if the
+    /// peer had closed the connection, so whoever is reading drops it
+    pub fn kill(&self) {
+        self.killed.notify_one();
+    }
+
+    /// Read a PDU that smpp_pdu understands.  Any other PDU is reported
+    /// as an UnknownCommandId error - use read_any_pdu to receive those.
     pub async fn read_pdu(&self) -> Result<Option<Pdu>, PduParseError> {
+        match self.read_any_pdu().await? {
+            Some(AnyPdu::Pdu(pdu)) => Ok(Some(pdu)),
+            Some(AnyPdu::Extra(pdu)) => {
+                Err(PduParseError::new(PduParseErrorBody::UnknownCommandId)
+                    .into_with_header(
+                        Some(pdu.command_id()),
+                        Some(pdu.command_status),
+                        Some(pdu.sequence_number),
+                    ))
+            }
+            None => Ok(None),
+        }
+    }
+
+    pub async fn read_any_pdu(&self) -> Result<Option<AnyPdu>, PduParseError> {
         loop {
             let mut read = self.read.lock().await;
             if let Some(read) = &mut *read {
-                if let Some(pdu) = read.parse_pdu()? {
-                    return Ok(Some(pdu));
+                match read.parse_pdu() {
+                    Ok(Some((pdu, bytes))) => {
+                        self.capture(Direction::In, &bytes);
+                        self.pdus_received.fetch_add(1, Ordering::Relaxed);
+                        return Ok(Some(pdu));
+                    }
+                    Ok(None) => {}
+                    Err(e) => {
+                        // Whatever we could not parse
+                        self.capture(Direction::In, &read.buffer);
+                        return Err(e);
+                    }
                 }
 
-                if 0 == read.read_own_buf().await? {
+                let bytes_read = tokio::select! {
+                    bytes_read = read.read_own_buf() => bytes_read?,
+                    _ = self.killed.notified() => {
+                        info!("Connection {} - killed", self.socket_addr);
+                        return Ok(None);
+                    }
+                };
+                if 0 == bytes_read {
                     if read.buffer.is_empty() {
                         return Ok(None);
                     } else {
@@ -75,19 +255,159 @@ impl SmppConnection {
             } else {
                 error!("Attempting to read from a closed connection!");
                 return Err(PduParseError::new(
-                    PduParseErrorBody::NotEnoughBytes.into(),
+                    PduParseErrorBody::NotEnoughBytes,
                 ));
             }
         }
     }
 
     pub async fn write_pdu(&self, pdu: &Pdu) -> io::Result<()> {
-        info!("=> {} {:?}", self.socket_addr, pdu);
-        if let Some(write) = &mut *self.write.lock().await {
-            pdu.write(&mut write.stream).await
+        self.span.in_scope(|| {
+            info!("=> {} {}", self.socket_addr, LoggedPdu::from(pdu))
+        });
+        let mut bytes = Vec::new();
+        pdu.write(&mut bytes).await?;
+        self.write_bytes(bytes).await
+    }
+
+    pub async fn write_extra_pdu(&self, pdu: &ExtraPdu) -> io::Result<()> {
+        self.span.in_scope(|| {
+            info!("=> {} {}", self.socket_addr, LoggedPdu::from(pdu))
+        });
+        self.write_bytes(pdu.to_bytes()).await
+    }
+
+    /// Write a whole PDU, unless the FaultInjector says otherwise
+    async fn write_bytes(&self, bytes: Vec<u8>) -> io::Result<()> {
+        let plan = match &self.faults {
+            Some(faults) => {
+                faults.plan(bytes, &mut self.held_response.lock().unwrap())
+            }
+            None => FaultPlan {
+                writes: vec![bytes],
+                ..Default::default()
+            },
+        };
+        if let Some(timeout) = plan.flush_held_after {
+            self.flush_held_later(timeout);
+        }
+        match (plan.delay, self.shared.upgrade()) {
+            (Some(delay), Some(connection)) => {
+                // Write later, so the caller can carry on reading PDUs
+                tokio::spawn(async move {
+                    sleep(delay).await;
+                    if let Err(e) = connection.write_plan(&plan).await {
+                        warn!("Failed to write delayed response: {}", e);
+                    }
+                });
+                Ok(())
+            }
+            (delay, _) => {
+                if let Some(delay) = delay {
+                    sleep(delay).await;
+                }
+                self.write_plan(&plan).await
+            }
+        }
+    }
+
+    /// If the response held back now is still held after timeout, because
+    /// no other response came along, write it on its own
+    fn flush_held_later(&self, timeout: Duration) {
+        let held = self.held_response.lock().unwrap().clone();
+        if let (Some(held), Some(connection)) = (held, self.shared.upgrade()) {
+            tokio::spawn(async move {
+                sleep(timeout).await;
+                let still_held = {
+                    let mut current = connection.held_response.lock().unwrap();
+                    if current.as_ref() == Some(&held) {
+                        current.take()
+                    } else {
+                        None
+                    }
+                };
+                if let Some(bytes) = still_held {
+                    info!("Fault: sending held back response on its own");
+                    let plan = FaultPlan {
+                        writes: vec![bytes],
+                        ..Default::default()
+                    };
+                    if let Err(e) = connection.write_plan(&plan).await {
+                        warn!("Failed to write held back response: {}", e);
+                    }
+                }
+            });
+        }
+    }
+
+    /// Do the writes in plan now
+    async fn write_plan(&self, plan: &FaultPlan) -> io::Result<()> {
+        let mut write = self.write.lock().await;
+        if let Some(write) = &mut *write {
+            for bytes in &plan.writes {
+                self.capture(Direction::Out, bytes);
+                write.stream.write_all(bytes).await?;
+                self.pdus_sent.fetch_add(1, Ordering::Relaxed);
+            }
         } else {
             error!("Attempting to write to a closed connection!");
-            Err(io::ErrorKind::BrokenPipe.into())
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        if plan.close {
+            if let Some(mut write) = write.take() {
+                write.stream.shutdown().await?;
+            }
+            self.kill();
+        }
+        Ok(())
+    }
+
+    pub async fn write_any_pdu(&self, pdu: &AnyPdu) -> io::Result<()> {
+        match pdu {
+            AnyPdu::Pdu(pdu) => self.write_pdu(pdu).await,
+            AnyPdu::Extra(pdu) => self.write_extra_pdu(pdu).await,
+        }
+    }
+
+    /// The next sequence_number for a request we originate
+    pub fn next_sequence_number(&self) -> u32 {
+        self.sequence_numbers.next_sequence_number()
+    }
+
+    /// Write a request we originate, such as deliver_sm or enquire_link,
+    /// replacing its sequence_number with the next one for this
+    /// connection.  Returns the sequence_number used.
+    pub async fn write_request(&self, pdu: AnyPdu) -> io::Result<u32> {
+        let sequence_number = self.next_sequence_number();
+        let mut pdu = with_sequence_number(pdu, sequence_number);
+        let duplicate = self
+            .faults
+            .as_ref()
+            .is_some_and(|faults| faults.duplicate_dr(&pdu));
+        self.write_any_pdu(&pdu).await?;
+        if duplicate {
+            info!("Fault: sending DR again");
+            pdu = with_sequence_number(pdu, self.next_sequence_number());
+            self.write_any_pdu(&pdu).await?;
+        }
+        Ok(sequence_number)
+    }
+
+    fn capture(&self, direction: Direction, bytes: &[u8]) {
+        if let Some(capturer) = &self.capturer {
+            let session = CapturedSession {
+                id: self.id,
+                started: self.opened,
+                local: self.local_addr,
+                peer: self.socket_addr,
+                esme_id: self.bound_esme_id(),
+            };
+            capturer.record(
+                &mut self.capture_file.lock().unwrap(),
+                &session,
+                direction,
+                bytes,
+            );
         }
     }
 
@@ -97,6 +417,19 @@ impl SmppConnection {
     }
 }
 
+fn with_sequence_number(pdu: AnyPdu, sequence_number: u32) -> AnyPdu {
+    match pdu {
+        AnyPdu::Pdu(mut pdu) => {
+            pdu.sequence_number.value = sequence_number;
+            AnyPdu::Pdu(pdu)
+        }
+        AnyPdu::Extra(mut pdu) => {
+            pdu.sequence_number = sequence_number;
+            AnyPdu::Extra(pdu)
+        }
+    }
+}
+
 struct SmppRead {
     stream: ReadHalf<TcpStream>,
     buffer: BytesMut,
@@ -107,20 +440,30 @@ impl SmppRead {
         self.stream.read_buf(&mut self.buffer).await
     }
 
-    fn parse_pdu(&mut self) -> Result<Option<Pdu>, PduParseError> {
+    /// The next PDU in the buffer, if it is all there, and its bytes
+    fn parse_pdu(
+        &mut self,
+    ) -> Result<Option<(AnyPdu, BytesMut)>, PduParseError> {
         let mut buf = Cursor::new(&self.buffer[..]);
         match Pdu::check(&mut buf) {
             Ok(CheckOutcome::Ready) => {
                 // Pdu::check moved us to the end, so position is length
                 let len = buf.position() as usize;
 
-                // Rewind and parse
+                // Rewind and parse, using our own parser for PDUs that
+                // smpp_pdu does not know about
                 buf.set_position(0);
-                let pdu = Pdu::parse(&mut buf)?;
+                let pdu = match read_header(&self.buffer[..len]) {
+                    Some((command_id, _, _))
+                        if ExtraPdu::parses_command_id(command_id) =>
+                    {
+                        AnyPdu::Extra(ExtraPdu::parse(&self.buffer[..len])?)
+                    }
+                    _ => AnyPdu::Pdu(Pdu::parse(&mut buf)?),
+                };
 
                 // Parsing succeeded, so consume bytes from buffer and return
-                self.buffer.advance(len);
-                Ok(Some(pdu))
+                Ok(Some((pdu, self.buffer.split_to(len))))
             }
             // Try again when we have more
             Ok(CheckOutcome::Incomplete) => Ok(None),
@@ -0,0 +1,232 @@
+//! Accounts that may bind, read from a file, for use in SmscLogic::bind.
+
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, SaltString};
+use argon2::{Argon2, PasswordHasher, PasswordVerifier};
+use ipnet::IpNet;
+use log::*;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::path::Path;
+use tokio::task;
+
+use crate::esme::BindType;
+use crate::smsc::config_file::parse_file;
+use crate::smsc::{BindContext, BindData, BindError, ConfigError};
+
+/// Checked against when the system_id is unknown.  It has the same
+/// parameters as the hashes from AccountStore::hash_password.
+const UNKNOWN_ACCOUNT_HASH: &str = "$argon2id$v=19$m=19456,t=2,p=1$\
+    VLp8XXUNT+twVq0w5vgf0Q$8H+Bi2mq5fhXbTBntdYrHtxsiHnmBRDpdcQ5++9xZcM";
+
+/// An ESME allowed to bind, as written in an accounts file
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Account {
+    pub system_id: String,
+    /// An argon2 hash in PHC format ("$argon2id$...") or a bcrypt hash
+    /// ("$2b$..."), e.g. from AccountStore::hash_password
+    pub password_hash: String,
+    /// Which ways this account may bind.  If empty, any.
+    #[serde(default)]
+    pub bind_types: Vec<BindType>,
+    /// IP addresses or CIDR ranges this account may bind from.  If empty,
+    /// any.
+    #[serde(default)]
+    pub allowed_ips: Vec<String>,
+    /// How many sessions this account may have bound at once
+    #[serde(default)]
+    pub max_sessions: Option<usize>,
+    /// Disabled accounts may not bind
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AccountsFile {
+    accounts: Vec<Account>,
+}
+
+struct StoredAccount {
+    account: Account,
+    allowed_ips: Vec<IpNet>,
+}
+
+/// Checks binds against a list of accounts.  Call authenticate from your
+/// SmscLogic::bind_with_context.
+pub struct AccountStore {
+    accounts: HashMap<String, StoredAccount>,
+}
+
+impl AccountStore {
+    pub fn new(accounts: Vec<Account>) -> Result<Self, String> {
+        let mut system_ids = HashSet::new();
+        let mut stored = HashMap::new();
+        for account in accounts {
+            if !system_ids.insert(account.system_id.clone()) {
+                return Err(format!(
+                    "account '{}' is repeated",
+                    account.system_id
+                ));
+            }
+            check_hash_format(&account)?;
+            let allowed_ips = account
+                .allowed_ips
+                .iter()
+                .map(|ip| parse_ip_range(ip))
+                .collect::<Result<Vec<IpNet>, String>>()?;
+            stored.insert(
+                account.system_id.clone(),
+                StoredAccount {
+                    account,
+                    allowed_ips,
+                },
+            );
+        }
+        Ok(Self { accounts: stored })
+    }
+
+    /// Read a TOML or YAML file containing a list of accounts
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let file: AccountsFile = parse_file(path)?;
+        Self::new(file.accounts).map_err(|message| {
+            ConfigError::Invalid(path.to_path_buf(), message)
+        })
+    }
+
+    /// A new argon2id hash of password, with a random salt, suitable for
+    /// Account::password_hash
+    pub fn hash_password(password: &str) -> String {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .expect("Failed to hash password")
+            .to_string()
+    }
+
+    /// Whether system_id is an enabled account, e.g. to decide whether a
+    /// bound session may stay bound after the accounts change
+    pub fn allows(&self, system_id: &str) -> bool {
+        self.accounts
+            .get(system_id)
+            .is_some_and(|stored| stored.account.enabled)
+    }
+
+    /// Decide whether this bind is allowed.  An unknown system_id gives
+    /// InvalidSystemId, a wrong password IncorrectPassword, and any other
+    /// reason BindFailed.  The password is checked on a blocking thread,
+    /// since argon2 and bcrypt are slow on purpose.
+    pub async fn authenticate(
+        &self,
+        bind_data: &BindData,
+        context: &BindContext,
+    ) -> Result<(), BindError> {
+        let system_id = bind_data.system_id.value.as_str();
+        let password = bind_data.password.value.as_str();
+        let stored = match self.accounts.get(system_id) {
+            Some(stored) => stored,
+            None => {
+                // Take as long as for a known system_id, so the time
+                // taken doesn't tell which system_ids exist
+                verify_in_background(password, UNKNOWN_ACCOUNT_HASH).await;
+                info!("Bind from unknown system_id '{}'", system_id);
+                return Err(BindError::InvalidSystemId);
+            }
+        };
+        let account = &stored.account;
+
+        if !verify_in_background(password, &account.password_hash).await {
+            info!("Bind with incorrect password for '{}'", system_id);
+            return Err(BindError::IncorrectPassword);
+        }
+
+        let refusal = if !account.enabled {
+            Some(String::from("account is disabled"))
+        } else if !account.bind_types.is_empty()
+            && !account.bind_types.contains(&context.bind_type)
+        {
+            Some(format!("{:?} binds are not allowed", context.bind_type))
+        } else if !stored.allowed_ips.is_empty()
+            && !stored
+                .allowed_ips
+                .iter()
+                .any(|range| range.contains(&context.peer.ip()))
+        {
+            Some(format!("{} is not allowed", context.peer.ip()))
+        } else if account
+            .max_sessions
+            .is_some_and(|max| context.sessions >= max)
+        {
+            Some(format!("already has {} sessions", context.sessions))
+        } else {
+            None
+        };
+
+        match refusal {
+            Some(reason) => {
+                info!("Refusing bind for '{}': {}", system_id, reason);
+                Err(BindError::BindFailed)
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+fn check_hash_format(account: &Account) -> Result<(), String> {
+    let hash = &account.password_hash;
+    let valid = if is_bcrypt(hash) {
+        hash.parse::<bcrypt::HashParts>().is_ok()
+    } else {
+        PasswordHash::new(hash).is_ok()
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "password_hash for account '{}' is not an argon2 or bcrypt hash",
+            account.system_id
+        ))
+    }
+}
+
+fn is_bcrypt(hash: &str) -> bool {
+    ["$2a$", "$2b$", "$2x$", "$2y$"]
+        .iter()
+        .any(|prefix| hash.starts_with(prefix))
+}
+
+async fn verify_in_background(password: &str, hash: &str) -> bool {
+    let password = String::from(password);
+    let hash = String::from(hash);
+    task::spawn_blocking(move || verify_password(&password, &hash))
+        .await
+        .unwrap_or(false)
+}
+
+fn verify_password(password: &str, hash: &str) -> bool {
+    if is_bcrypt(hash) {
+        bcrypt::verify(password, hash).unwrap_or(false)
+    } else {
+        PasswordHash::new(hash)
+            .map(|parsed| {
+                Argon2::default()
+                    .verify_password(password.as_bytes(), &parsed)
+                    .is_ok()
+            })
+            .unwrap_or(false)
+    }
+}
+
+/// Parse "10.0.0.0/8" or a single address like "10.1.2.3"
+fn parse_ip_range(value: &str) -> Result<IpNet, String> {
+    value
+        .parse::<IpNet>()
+        .or_else(|_| value.parse::<IpAddr>().map(IpNet::from))
+        .map_err(|_| format!("'{}' is not an IP address or CIDR range", value))
+}
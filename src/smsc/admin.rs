@@ -0,0 +1,410 @@
+//! An HTTP API for operators, bound to localhost, for seeing and acting on
+//! a running SMSC without reading its logs.  Responses are JSON.
+//!
+//! - GET /sessions lists bound sessions
+//! - POST /sessions/{system_id}/unbind sends unbind to its sessions
+//! - POST /sessions/{system_id}/kill closes its sessions
+//! - GET /messages?namespace_id=&message_id=&destination_addr= finds who
+//!   sent an MT and whether its DR has been passed on
+//! - POST /deliver_sm/dr injects a DR, as if a supplier had sent it
+//! - POST /deliver_sm/mo sends an MO to a bound ESME
+//! - POST /reload reloads the configuration, like SIGHUP
+//! - GET /captures lists what is being captured
+//! - POST /captures/start and /captures/stop start and stop capturing the
+//!   PDUs of sessions matching a CaptureTarget, e.g. {"system_id": "acme"}
+//!   or {"peer": "10.0.0.1"}
+//! - GET /metrics gives Prometheus metrics, as with --metrics-address
+//!
+//! The session endpoints take an optional system_type query parameter.
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use log::*;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use smpp_pdu::pdu::tlvs::{KnownTlvTag, Tlv, Tlvs};
+use smpp_pdu::pdu::{DeliverEsmClass, DeliverSmPdu, Pdu};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+use crate::async_result::AsyncResult;
+use crate::capture::CaptureTarget;
+use crate::message_unique_key::MessageUniqueKey;
+use crate::smpp_connection::EsmeId;
+use crate::smsc::metrics::metrics_response;
+use crate::smsc::{ConfigError, SessionInfo, Smsc, SmscConfig};
+
+type ConfigLoader =
+    Box<dyn Fn() -> Result<SmscConfig, ConfigError> + Send + Sync>;
+
+struct Admin {
+    smsc: Arc<Mutex<Smsc>>,
+    load_config: ConfigLoader,
+}
+
+/// A DR to inject, as if the supplier namespace_id had sent it
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct InjectDr {
+    namespace_id: String,
+    /// The supplier's message ID
+    message_id: String,
+    /// The destination_addr of the MT
+    destination_addr: String,
+    #[serde(default)]
+    source_addr: String,
+    #[serde(default = "delivered")]
+    stat: String,
+}
+
+fn delivered() -> String {
+    String::from("DELIVRD")
+}
+
+/// An MO to send to a bound ESME
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct InjectMo {
+    system_id: String,
+    source_addr: String,
+    destination_addr: String,
+    short_message: String,
+}
+
+struct AdminError {
+    status: StatusCode,
+    message: String,
+}
+
+impl AdminError {
+    fn new(status: StatusCode, message: impl ToString) -> Self {
+        Self {
+            status,
+            message: message.to_string(),
+        }
+    }
+}
+
+type AdminResult = Result<Response<Body>, AdminError>;
+
+/// Serve the admin API on address, which must be a loopback address.
+/// POST /reload uses load_config to read the new configuration.  Returns
+/// the address actually bound, which differs from address if it used
+/// port 0.
+pub async fn start_admin<F>(
+    smsc: Arc<Mutex<Smsc>>,
+    address: &str,
+    load_config: F,
+) -> AsyncResult<SocketAddr>
+where
+    F: Fn() -> Result<SmscConfig, ConfigError> + Send + Sync + 'static,
+{
+    let socket_addr = address.to_socket_addrs()?.next().ok_or_else(|| {
+        format!("admin address '{}' did not resolve", address)
+    })?;
+    if !socket_addr.ip().is_loopback() {
+        return Err(format!(
+            "admin address '{}' must be a loopback address",
+            address
+        )
+        .into());
+    }
+
+    let admin = Arc::new(Admin {
+        smsc,
+        load_config: Box::new(load_config),
+    });
+    let make_service = make_service_fn(move |_| {
+        let admin = Arc::clone(&admin);
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                handle(Arc::clone(&admin), request)
+            }))
+        }
+    });
+    let server = Server::try_bind(&socket_addr)?.serve(make_service);
+    let local_addr = server.local_addr();
+    info!("Admin API on http://{}", local_addr);
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            error!("Admin API failed: {}", e);
+        }
+    });
+    Ok(local_addr)
+}
+
+async fn handle(
+    admin: Arc<Admin>,
+    request: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    // Not the query, which may hold a destination_addr
+    info!("Admin API: {} {}", request.method(), request.uri().path());
+    Ok(route(&admin, request).await.unwrap_or_else(|e| {
+        info!("Admin API: {} {}", e.status, e.message);
+        json_response(e.status, &ErrorBody { error: e.message })
+    }))
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+}
+
+async fn route(admin: &Admin, request: Request<Body>) -> AdminResult {
+    let path: Vec<String> = request
+        .uri()
+        .path()
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(decode)
+        .collect();
+    let path: Vec<&str> = path.iter().map(String::as_str).collect();
+    let query = query_params(&request);
+
+    match (request.method(), path.as_slice()) {
+        (&Method::GET, ["metrics"]) => {
+            Ok(metrics_response(admin.smsc.lock().await.metrics_text()))
+        }
+        (&Method::GET, ["sessions"]) => ok(&admin.smsc.lock().await.sessions()),
+        (&Method::POST, ["sessions", system_id, "unbind"]) => {
+            let mut smsc = admin.smsc.lock().await;
+            let sessions = matching_sessions(&smsc, system_id, &query)?;
+            for session in &sessions {
+                smsc.unbind(&esme_id(session))
+                    .await
+                    .map_err(internal_error)?;
+            }
+            ok(&sessions)
+        }
+        (&Method::POST, ["sessions", system_id, "kill"]) => {
+            let mut smsc = admin.smsc.lock().await;
+            let sessions = matching_sessions(&smsc, system_id, &query)?;
+            for session in &sessions {
+                smsc.kill(&esme_id(session));
+            }
+            ok(&sessions)
+        }
+        (&Method::GET, ["messages"]) => {
+            let param = |name: &str| {
+                query.get(name).cloned().ok_or_else(|| {
+                    AdminError::new(
+                        StatusCode::BAD_REQUEST,
+                        format!("{} is required", name),
+                    )
+                })
+            };
+            let key = MessageUniqueKey::new(
+                param("namespace_id")?,
+                param("message_id")?,
+                param("destination_addr")?,
+            );
+            match admin.smsc.lock().await.message_info(&key) {
+                Some(info) => ok(&info),
+                None => Err(AdminError::new(
+                    StatusCode::NOT_FOUND,
+                    "No such message",
+                )),
+            }
+        }
+        (&Method::POST, ["deliver_sm", "dr"]) => {
+            let dr: InjectDr = read_json(request).await?;
+            let pdu = dr_pdu(&dr).map_err(bad_request)?;
+            admin
+                .smsc
+                .lock()
+                .await
+                .receive_pdu(&dr.namespace_id, pdu)
+                .await
+                .map_err(|e| AdminError::new(StatusCode::NOT_FOUND, e))?;
+            ok(&"DR injected")
+        }
+        (&Method::POST, ["deliver_sm", "mo"]) => {
+            let mo: InjectMo = read_json(request).await?;
+            let pdu = mo_pdu(&mo).map_err(bad_request)?;
+            admin
+                .smsc
+                .lock()
+                .await
+                .deliver_mo(&mo.system_id, pdu)
+                .await
+                .map_err(|e| AdminError::new(StatusCode::NOT_FOUND, e))?;
+            ok(&"MO sent")
+        }
+        (&Method::POST, ["reload"]) => {
+            let config = (admin.load_config)().map_err(bad_request)?;
+            Smsc::reload(&admin.smsc, config)
+                .await
+                .map_err(internal_error)?;
+            ok(&"Reloaded")
+        }
+        (&Method::GET, ["captures"]) => {
+            ok(&admin.smsc.lock().await.capturer().targets())
+        }
+        (&Method::POST, ["captures", "start"]) => {
+            let target: CaptureTarget = read_json(request).await?;
+            let capturer = admin.smsc.lock().await.capturer();
+            capturer.start(target).map_err(bad_request)?;
+            ok(&capturer.targets())
+        }
+        (&Method::POST, ["captures", "stop"]) => {
+            let target: CaptureTarget = read_json(request).await?;
+            let capturer = admin.smsc.lock().await.capturer();
+            if capturer.stop(&target) {
+                ok(&capturer.targets())
+            } else {
+                Err(AdminError::new(
+                    StatusCode::NOT_FOUND,
+                    "Not capturing that",
+                ))
+            }
+        }
+        _ => Err(AdminError::new(StatusCode::NOT_FOUND, "Unknown endpoint")),
+    }
+}
+
+fn matching_sessions(
+    smsc: &Smsc,
+    system_id: &str,
+    query: &HashMap<String, String>,
+) -> Result<Vec<SessionInfo>, AdminError> {
+    let system_type = query.get("system_type");
+    let sessions: Vec<SessionInfo> = smsc
+        .sessions()
+        .into_iter()
+        .filter(|s| {
+            s.system_id == system_id
+                && system_type.is_none_or(|t| &s.system_type == t)
+        })
+        .collect();
+    if sessions.is_empty() {
+        Err(AdminError::new(
+            StatusCode::NOT_FOUND,
+            format!("No session bound as '{}'", system_id),
+        ))
+    } else {
+        Ok(sessions)
+    }
+}
+
+fn esme_id(session: &SessionInfo) -> EsmeId {
+    // These came from AsciiStrings, so they convert back
+    EsmeId {
+        system_id: session.system_id.parse().unwrap(),
+        system_type: session.system_type.parse().unwrap(),
+    }
+}
+
+fn dr_pdu(dr: &InjectDr) -> AsyncResult<Pdu> {
+    let text = format!(
+        "id:{} sub:001 dlvrd:{} submit date:0000000000 \
+        done date:0000000000 stat:{} err:000 text:",
+        dr.message_id,
+        if dr.stat == "DELIVRD" { "001" } else { "000" },
+        dr.stat
+    );
+    Ok(Pdu::new(
+        0,
+        // Smsc replaces this with the next sequence_number for the ESME
+        1,
+        DeliverSmPdu::new(
+            "",
+            0,
+            0,
+            &dr.destination_addr,
+            0,
+            0,
+            &dr.source_addr,
+            DeliverEsmClass::SmscDeliveryReceipt as u8,
+            0x34,
+            0,
+            "",
+            "",
+            0,
+            0,
+            0,
+            0,
+            text.as_bytes(),
+            Tlvs::from(&[Tlv::new(
+                KnownTlvTag::receipted_message_id,
+                dr.message_id.as_bytes(),
+            )]),
+        )?
+        .into(),
+    )?)
+}
+
+fn mo_pdu(mo: &InjectMo) -> AsyncResult<Pdu> {
+    Ok(Pdu::new(
+        0,
+        1,
+        DeliverSmPdu::new(
+            "",
+            0,
+            0,
+            &mo.source_addr,
+            0,
+            0,
+            &mo.destination_addr,
+            0,
+            0x34,
+            0,
+            "",
+            "",
+            0,
+            0,
+            0,
+            0,
+            mo.short_message.as_bytes(),
+            Tlvs::new(),
+        )?
+        .into(),
+    )?)
+}
+
+async fn read_json<T: DeserializeOwned>(
+    request: Request<Body>,
+) -> Result<T, AdminError> {
+    let body = hyper::body::to_bytes(request.into_body())
+        .await
+        .map_err(bad_request)?;
+    serde_json::from_slice(&body).map_err(bad_request)
+}
+
+fn query_params(request: &Request<Body>) -> HashMap<String, String> {
+    form_urlencoded::parse(request.uri().query().unwrap_or("").as_bytes())
+        .into_owned()
+        .collect()
+}
+
+/// Percent-decode a path segment.  Unlike in a query, "+" is just "+".
+fn decode(segment: &str) -> String {
+    let segment = segment.replace('+', "%2B");
+    form_urlencoded::parse(format!("s={}", segment).as_bytes())
+        .next()
+        .map(|(_, value)| value.into_owned())
+        .unwrap_or_default()
+}
+
+fn ok<T: Serialize>(body: &T) -> AdminResult {
+    Ok(json_response(StatusCode::OK, body))
+}
+
+fn json_response<T: Serialize>(status: StatusCode, body: &T) -> Response<Body> {
+    let body = serde_json::to_vec(body).expect("Failed to serialize JSON");
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(body))
+        .expect("Failed to build response")
+}
+
+fn bad_request(e: impl ToString) -> AdminError {
+    AdminError::new(StatusCode::BAD_REQUEST, e)
+}
+
+fn internal_error(e: impl ToString) -> AdminError {
+    AdminError::new(StatusCode::INTERNAL_SERVER_ERROR, e)
+}
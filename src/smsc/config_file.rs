@@ -0,0 +1,433 @@
+//! Loading SmscConfig from a TOML or YAML file, with environment variables
+//! and command-line flags overriding what the file says.
+
+use clap::{ArgMatches, Clap};
+use log::LevelFilter;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::error;
+use std::ffi::OsString;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::capture::CaptureFormat;
+use crate::esme::BindType;
+use crate::faults::FaultRate;
+use crate::logging::{LogFormat, Redaction};
+use crate::routing::Route;
+use crate::simulator::SimulatorRule;
+use crate::smsc::{Account, AccountStore};
+
+/// The contents of a config file.  Every section is optional.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigFile {
+    /// system_id used as an identifier of the SMSC
+    pub system_id: Option<String>,
+    /// Maximum number of sockets that can be open
+    pub max_open_sockets: Option<usize>,
+    /// Addresses to accept ESME connections on
+    pub listeners: Vec<ListenerConfig>,
+    /// ESMEs allowed to bind.  If empty, the SmscLogic decides alone.
+    pub accounts: Vec<AccountConfig>,
+    /// File of accounts with hashed passwords, for an AccountStore
+    pub accounts_file: Option<String>,
+    /// File of rules for answering MTs, for a Scripted logic
+    pub script: Option<String>,
+    pub timers: TimersConfig,
+    pub routes: Vec<Route>,
+    pub logging: LoggingConfig,
+    pub normalization: NormalizationConfig,
+    pub admin: AdminConfig,
+    pub metrics: MetricsConfig,
+    pub capture: CaptureFileConfig,
+    /// Rules for the Simulator logic, by destination prefix and account
+    pub simulator: Vec<SimulatorRule>,
+    pub faults: FaultsConfig,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ListenerConfig {
+    pub bind_address: String,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct AccountConfig {
+    pub system_id: String,
+    /// An argon2 or bcrypt hash, e.g. from AccountStore::hash_password
+    pub password_hash: String,
+    /// Reject submit_sm with ESME_RTHROTTLED above this rate
+    #[serde(default)]
+    pub max_submits_per_sec: Option<u32>,
+    /// Which ways this account may bind.  If empty, any.
+    #[serde(default)]
+    pub bind_types: Vec<BindType>,
+    /// IP addresses or CIDR ranges this account may bind from.  If empty,
+    /// any.
+    #[serde(default)]
+    pub allowed_ips: Vec<String>,
+    /// How many sessions this account may have bound at once
+    #[serde(default)]
+    pub max_sessions: Option<usize>,
+}
+
+impl AccountConfig {
+    /// The account for an AccountStore, which checks the password
+    pub(crate) fn to_account(&self) -> Account {
+        Account {
+            system_id: self.system_id.clone(),
+            password_hash: self.password_hash.clone(),
+            bind_types: self.bind_types.clone(),
+            allowed_ips: self.allowed_ips.clone(),
+            max_sessions: self.max_sessions,
+            enabled: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimersConfig {
+    /// How long to wait for all the parts of a concatenated MT
+    pub concat_timeout_secs: Option<u64>,
+    /// How long to wait for unbind_resp after we send unbind
+    pub unbind_timeout_secs: Option<u64>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    /// e.g. "info" or "debug".  RUST_LOG still overrides this.
+    pub level: Option<String>,
+    /// "text" or "json"
+    pub format: Option<String>,
+    /// "none", "mask" or "hide"
+    pub redaction: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct NormalizationConfig {
+    pub country_code: Option<String>,
+    pub national_prefix: Option<String>,
+    pub international_prefix: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminConfig {
+    /// Where to serve the admin HTTP API.  Must be a loopback address.
+    pub bind_address: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    /// Where to serve Prometheus metrics, at /metrics
+    pub bind_address: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct CaptureFileConfig {
+    /// Where to write PDU captures, once switched on with the admin API
+    pub directory: Option<String>,
+    /// "pcap" or "hex"
+    pub format: Option<String>,
+    pub max_file_bytes: Option<u64>,
+    pub max_files: Option<usize>,
+}
+
+/// Faults to inject into the PDUs the SMSC writes.  Each rate is a
+/// probability from 0 to 1, or "every:N".
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct FaultsConfig {
+    pub delay_response: Option<String>,
+    pub delay_ms: Option<u64>,
+    pub drop_response: Option<String>,
+    pub reorder_response: Option<String>,
+    pub duplicate_dr: Option<String>,
+    pub malformed_pdu: Option<String>,
+    pub oversized_pdu: Option<String>,
+    pub close_mid_pdu: Option<String>,
+    pub generic_nack: Option<String>,
+}
+
+impl FaultsConfig {
+    /// The rates, by the name of their flag
+    fn rates(&self) -> Vec<(&'static str, Option<String>)> {
+        vec![
+            ("fault-delay-response", self.delay_response.clone()),
+            ("fault-drop-response", self.drop_response.clone()),
+            ("fault-reorder-response", self.reorder_response.clone()),
+            ("fault-duplicate-dr", self.duplicate_dr.clone()),
+            ("fault-malformed-pdu", self.malformed_pdu.clone()),
+            ("fault-oversized-pdu", self.oversized_pdu.clone()),
+            ("fault-close-mid-pdu", self.close_mid_pdu.clone()),
+            ("fault-generic-nack", self.generic_nack.clone()),
+        ]
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, io::Error),
+    /// The file is not valid TOML or YAML, or has unexpected fields
+    Parse(PathBuf, String),
+    /// The file name does not end in .toml, .yaml or .yml
+    UnknownFormat(PathBuf),
+    /// The file parsed, but a value in it is not allowed
+    Invalid(PathBuf, String),
+    /// The command line or environment is not valid, or asked for --help
+    /// or --version, which clap::Error::exit prints
+    Args(clap::Error),
+}
+
+impl Display for ConfigError {
+    fn fmt(
+        &self,
+        formatter: &mut Formatter,
+    ) -> std::result::Result<(), std::fmt::Error> {
+        let s = match self {
+            ConfigError::Io(path, e) => {
+                format!("Unable to read {}: {}", path.display(), e)
+            }
+            ConfigError::Parse(path, message) => {
+                format!("Unable to parse {}: {}", path.display(), message)
+            }
+            ConfigError::UnknownFormat(path) => format!(
+                "Unknown config file format for {} \
+                (expected .toml, .yaml or .yml)",
+                path.display()
+            ),
+            ConfigError::Invalid(path, message) => {
+                format!("Invalid config in {}: {}", path.display(), message)
+            }
+            ConfigError::Args(e) => e.to_string(),
+        };
+        formatter.write_str(&s)
+    }
+}
+
+impl error::Error for ConfigError {}
+
+impl ConfigFile {
+    /// Read and validate a config file, choosing TOML or YAML by extension
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let config: ConfigFile = parse_file(path)?;
+        config.validate().map_err(|message| {
+            ConfigError::Invalid(path.to_path_buf(), message)
+        })?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.max_open_sockets == Some(0) {
+            return Err(String::from("max_open_sockets must be at least 1"));
+        }
+
+        let mut bind_addresses = HashSet::new();
+        for listener in &self.listeners {
+            let address = &listener.bind_address;
+            let port = address.rsplit_once(':').map(|(_, port)| port);
+            if port.and_then(|p| p.parse::<u16>().ok()).is_none() {
+                return Err(format!(
+                    "listener bind_address '{}' must be host:port",
+                    address
+                ));
+            }
+            if !bind_addresses.insert(address) {
+                return Err(format!("listener '{}' is repeated", address));
+            }
+        }
+
+        let mut system_ids = HashSet::new();
+        for account in &self.accounts {
+            let system_id = &account.system_id;
+            // Both are C-Octet Strings including the NUL terminator
+            if system_id.is_empty() || system_id.len() > 15 {
+                return Err(format!(
+                    "account system_id '{}' must be 1-15 characters",
+                    system_id
+                ));
+            }
+            if account.max_submits_per_sec == Some(0) {
+                return Err(format!(
+                    "max_submits_per_sec for account '{}' must be at least 1",
+                    system_id
+                ));
+            }
+            if !system_ids.insert(system_id) {
+                return Err(format!("account '{}' is repeated", system_id));
+            }
+        }
+        AccountStore::new(
+            self.accounts
+                .iter()
+                .map(AccountConfig::to_account)
+                .collect(),
+        )?;
+
+        for route in &self.routes {
+            if !route.prefix.chars().all(|c| c.is_ascii_digit()) {
+                return Err(format!(
+                    "route prefix '{}' must contain only digits",
+                    route.prefix
+                ));
+            }
+            if route.targets.iter().all(|t| t.weight == 0) {
+                return Err(format!(
+                    "route for prefix '{}' needs a target with weight > 0",
+                    route.prefix
+                ));
+            }
+            if !route.cost.is_finite() || route.cost < 0.0 {
+                return Err(format!(
+                    "route for prefix '{}' has invalid cost {}",
+                    route.prefix, route.cost
+                ));
+            }
+        }
+
+        for rule in &self.simulator {
+            rule.validate()?;
+        }
+
+        if let Some(level) = &self.logging.level {
+            if level.parse::<LevelFilter>().is_err() {
+                return Err(format!("unknown logging level '{}'", level));
+            }
+        }
+        if let Some(format) = &self.logging.format {
+            format.parse::<LogFormat>()?;
+        }
+        if let Some(redaction) = &self.logging.redaction {
+            redaction.parse::<Redaction>()?;
+        }
+        if let Some(format) = &self.capture.format {
+            format.parse::<CaptureFormat>()?;
+        }
+        for (_, rate) in self.faults.rates() {
+            rate.map(|r| r.parse::<FaultRate>()).transpose()?;
+        }
+
+        Ok(())
+    }
+
+    /// Values from this file to use in place of the built-in defaults of
+    /// SmscConfig's flags, by flag id (clap uses the kebab-case name).
+    fn arg_defaults(&self) -> Vec<(&'static str, String)> {
+        let normalization = &self.normalization;
+        vec![
+            (
+                "bind-address",
+                self.listeners.first().map(|l| l.bind_address.clone()),
+            ),
+            (
+                "max-open-sockets",
+                self.max_open_sockets.map(|n| n.to_string()),
+            ),
+            ("system-id", self.system_id.clone()),
+            ("accounts-file", self.accounts_file.clone()),
+            ("script", self.script.clone()),
+            (
+                "concat-timeout-secs",
+                self.timers.concat_timeout_secs.map(|n| n.to_string()),
+            ),
+            (
+                "unbind-timeout-secs",
+                self.timers.unbind_timeout_secs.map(|n| n.to_string()),
+            ),
+            ("log-level", self.logging.level.clone()),
+            ("log-format", self.logging.format.clone()),
+            ("log-redaction", self.logging.redaction.clone()),
+            ("country-code", normalization.country_code.clone()),
+            ("national-prefix", normalization.national_prefix.clone()),
+            (
+                "international-prefix",
+                normalization.international_prefix.clone(),
+            ),
+            ("admin-address", self.admin.bind_address.clone()),
+            ("metrics-address", self.metrics.bind_address.clone()),
+            ("capture-dir", self.capture.directory.clone()),
+            ("capture-format", self.capture.format.clone()),
+            (
+                "capture-max-file-bytes",
+                self.capture.max_file_bytes.map(|n| n.to_string()),
+            ),
+            (
+                "capture-max-files",
+                self.capture.max_files.map(|n| n.to_string()),
+            ),
+            (
+                "fault-delay-ms",
+                self.faults.delay_ms.map(|n| n.to_string()),
+            ),
+        ]
+        .into_iter()
+        .chain(self.faults.rates())
+        .filter_map(|(id, value)| value.map(|v| (id, v)))
+        .collect()
+    }
+}
+
+/// Read a TOML or YAML file, choosing which by its extension
+pub(crate) fn parse_file<T: DeserializeOwned>(
+    path: &Path,
+) -> Result<T, ConfigError> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| ConfigError::Io(path.to_path_buf(), e))?;
+    let parse_error =
+        |message: String| ConfigError::Parse(path.to_path_buf(), message);
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("toml") => {
+            toml::from_str(&contents).map_err(|e| parse_error(e.to_string()))
+        }
+        Some("yaml") | Some("yml") => serde_yaml::from_str(&contents)
+            .map_err(|e| parse_error(e.to_string())),
+        _ => Err(ConfigError::UnknownFormat(path.to_path_buf())),
+    }
+}
+
+/// Parse C from args and the environment.  If a config file is named with
+/// --config, its values replace the built-in defaults, so environment
+/// variables and flags still override it.  The file is returned so the
+/// caller can use the sections that have no flags.
+pub(crate) fn parse_with_config_file<C, I, T>(
+    args: I,
+) -> Result<(C, Option<ConfigFile>), ConfigError>
+where
+    C: Clap,
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
+    let args: Vec<OsString> = args.into_iter().map(Into::into).collect();
+    let matches = C::into_app()
+        .try_get_matches_from(args.clone())
+        .map_err(ConfigError::Args)?;
+    let path = match config_path(&matches) {
+        Some(path) => path,
+        None => return Ok((C::from_arg_matches(&matches), None)),
+    };
+
+    let config_file = ConfigFile::load(&path)?;
+    let arg_defaults = config_file.arg_defaults();
+    let mut app = C::into_app();
+    for (id, value) in &arg_defaults {
+        app = app.mut_arg(*id, |arg| arg.default_value(value));
+    }
+    // The file's values were validated, so clap only rejects the command
+    // line and environment here
+    let matches = app.try_get_matches_from(args).map_err(ConfigError::Args)?;
+    Ok((C::from_arg_matches(&matches), Some(config_file)))
+}
+
+fn config_path(matches: &ArgMatches) -> Option<PathBuf> {
+    matches.value_of("config").map(PathBuf::from)
+}
@@ -0,0 +1,83 @@
+use smpp_pdu::pdu::tlvs::{KnownTlvTag, Tlv};
+use smpp_pdu::pdu::{DeliverSmPdu, PduParseError};
+use std::collections::HashMap;
+
+use crate::message_unique_key::MessageUniqueKey;
+use crate::sm_fields::SmFields;
+
+/// Links the message IDs a supplier gave to the MTs we forwarded to the
+/// message IDs we gave our ESMEs, so that the supplier's IDs can be
+/// replaced with ours in DRs.
+#[derive(Default)]
+pub struct MessageIdMap {
+    message_ids: HashMap<MessageUniqueKey, String>,
+}
+
+impl MessageIdMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(
+        &mut self,
+        supplier_key: MessageUniqueKey,
+        message_id: String,
+    ) {
+        // Later: Issue#14: delete old entries in this map to keep size bounded
+        self.message_ids.insert(supplier_key, message_id);
+    }
+
+    /// Our message_id for the MT the supplier knows by supplier_key
+    pub fn get(&self, supplier_key: &MessageUniqueKey) -> Option<&String> {
+        self.message_ids.get(supplier_key)
+    }
+}
+
+/// A copy of the DR with its message ID replaced by message_id, both in
+/// the receipted_message_id TLV (if present) and in the "id:" field of the
+/// receipt text (see Appendix B of https://smpp.org/SMPP_v3_4_Issue1_2.pdf).
+pub fn rewrite_dr_message_id(
+    dr: &DeliverSmPdu,
+    message_id: &str,
+) -> Result<DeliverSmPdu, PduParseError> {
+    let mut fields = SmFields::from_sm_data(&dr.0);
+
+    if let Some(tlv) = fields.tlv(KnownTlvTag::receipted_message_id) {
+        // Keep the terminating NULL if the supplier sent one
+        let mut value = message_id.as_bytes().to_vec();
+        if tlv.value.ends_with(&[0]) {
+            value.push(0);
+        }
+        fields.set_tlv(Tlv::new(KnownTlvTag::receipted_message_id, &value));
+    }
+
+    if let Some(short_message) =
+        replace_id_field(&fields.short_message, message_id)
+    {
+        fields.short_message = short_message;
+    }
+
+    fields.to_deliver_sm()
+}
+
+/// Replace the value of the first "id:" field in receipt text, or None if
+/// there is no such field.  The field name is case-insensitive, and must
+/// start the text or follow whitespace.
+fn replace_id_field(text: &[u8], message_id: &str) -> Option<Vec<u8>> {
+    let start = (0..text.len()).find(|&i| {
+        text[i..].len() >= 3
+            && text[i..i + 3].eq_ignore_ascii_case(b"id:")
+            && (i == 0 || text[i - 1].is_ascii_whitespace())
+    })? + 3;
+    let end = text[start..]
+        .iter()
+        .position(|b| b.is_ascii_whitespace())
+        .map(|len| start + len)
+        .unwrap_or(text.len());
+
+    let mut ret = Vec::with_capacity(text.len() + message_id.len());
+    ret.extend_from_slice(&text[..start]);
+    ret.extend_from_slice(message_id.as_bytes());
+    ret.extend_from_slice(&text[end..]);
+    Some(ret)
+}
@@ -0,0 +1,265 @@
+//! Prometheus metrics for an Smsc, served in the text format on /metrics.
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use log::*;
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge,
+    Opts, Registry, TextEncoder,
+};
+use std::convert::Infallible;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+use crate::async_result::AsyncResult;
+use crate::smpp_connection::SmppConnection;
+use crate::smsc::Smsc;
+
+/// Whether a PDU came from an ESME or went to one
+#[derive(Clone, Copy, Debug)]
+pub enum Direction {
+    In,
+    Out,
+}
+
+/// The metrics for one Smsc.  Each Smsc has its own registry, so several
+/// can run in one process.
+pub struct SmscMetrics {
+    registry: Registry,
+    pdus: IntCounterVec,
+    submit_sm_seconds: Histogram,
+    submit_to_dr_seconds: Histogram,
+    open_sockets: IntGauge,
+    refused_connections: IntCounter,
+    bound_sessions: IntGauge,
+    stored_messages: IntGauge,
+}
+
+impl SmscMetrics {
+    pub fn new(max_open_sockets: usize) -> Self {
+        let registry = Registry::new();
+        let pdus = IntCounterVec::new(
+            Opts::new(
+                "smpp_pdus_total",
+                "PDUs received from and sent to ESMEs",
+            ),
+            &["direction", "system_id", "command", "command_status"],
+        )
+        .unwrap();
+        let submit_sm_seconds = Histogram::with_opts(HistogramOpts::new(
+            "smpp_submit_sm_logic_seconds",
+            "Time SmscLogic::submit_sm took to accept or reject an MT",
+        ))
+        .unwrap();
+        let submit_to_dr_seconds = Histogram::with_opts(
+            HistogramOpts::new(
+                "smpp_submit_to_dr_seconds",
+                "Time from accepting an MT to forwarding its first DR",
+            )
+            .buckets(vec![
+                0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 300.0, 900.0, 3600.0,
+            ]),
+        )
+        .unwrap();
+        let open_sockets =
+            IntGauge::new("smpp_open_sockets", "ESME sockets open now")
+                .unwrap();
+        let max_open_sockets_gauge = IntGauge::new(
+            "smpp_max_open_sockets",
+            "How many ESME sockets may be open at once",
+        )
+        .unwrap();
+        max_open_sockets_gauge.set(max_open_sockets as i64);
+        let refused_connections = IntCounter::new(
+            "smpp_refused_connections_total",
+            "Connections refused because max_open_sockets were open",
+        )
+        .unwrap();
+        let bound_sessions =
+            IntGauge::new("smpp_bound_sessions", "ESME sessions bound now")
+                .unwrap();
+        let stored_messages = IntGauge::new(
+            "smpp_stored_messages",
+            "MTs remembered so their DRs can be routed back",
+        )
+        .unwrap();
+
+        for collector in [
+            Box::new(pdus.clone()) as Box<dyn prometheus::core::Collector>,
+            Box::new(submit_sm_seconds.clone()),
+            Box::new(submit_to_dr_seconds.clone()),
+            Box::new(open_sockets.clone()),
+            Box::new(max_open_sockets_gauge),
+            Box::new(refused_connections.clone()),
+            Box::new(bound_sessions.clone()),
+            Box::new(stored_messages.clone()),
+        ] {
+            registry.register(collector).unwrap();
+        }
+
+        Self {
+            registry,
+            pdus,
+            submit_sm_seconds,
+            submit_to_dr_seconds,
+            open_sockets,
+            refused_connections,
+            bound_sessions,
+            stored_messages,
+        }
+    }
+
+    /// Count a PDU on connection, labelled with the ESME it is bound as
+    /// (or "" if it is not bound yet)
+    pub fn count_pdu(
+        &self,
+        direction: Direction,
+        connection: &SmppConnection,
+        command_id: u32,
+        command_status: u32,
+    ) {
+        let system_id = connection
+            .bound_esme_id()
+            .map(|esme_id| esme_id.system_id.to_string())
+            .unwrap_or_default();
+        let direction = match direction {
+            Direction::In => "in",
+            Direction::Out => "out",
+        };
+        self.pdus
+            .with_label_values(&[
+                direction,
+                &system_id,
+                &command_name(command_id),
+                &format!("0x{:08x}", command_status),
+            ])
+            .inc();
+    }
+
+    pub fn observe_submit_sm(&self, duration: Duration) {
+        self.submit_sm_seconds.observe(duration.as_secs_f64());
+    }
+
+    pub fn observe_submit_to_dr(&self, duration: Duration) {
+        self.submit_to_dr_seconds.observe(duration.as_secs_f64());
+    }
+
+    pub fn socket_opened(&self) {
+        self.open_sockets.inc();
+    }
+
+    pub fn socket_closed(&self) {
+        self.open_sockets.dec();
+    }
+
+    pub fn connection_refused(&self) {
+        self.refused_connections.inc();
+    }
+
+    /// Everything in the Prometheus text format, after setting the gauges
+    /// that are read from the Smsc when scraped
+    pub fn encode(
+        &self,
+        bound_sessions: usize,
+        stored_messages: usize,
+    ) -> String {
+        self.bound_sessions.set(bound_sessions as i64);
+        self.stored_messages.set(stored_messages as i64);
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("Failed to encode metrics");
+        String::from_utf8(buffer).expect("Metrics were not UTF-8")
+    }
+}
+
+/// The SMPP v3.4 name of a command, or its ID in hex if we don't know it
+pub fn command_name(command_id: u32) -> String {
+    let name = match command_id {
+        0x80000000 => "generic_nack",
+        0x00000001 => "bind_receiver",
+        0x80000001 => "bind_receiver_resp",
+        0x00000002 => "bind_transmitter",
+        0x80000002 => "bind_transmitter_resp",
+        0x00000003 => "query_sm",
+        0x80000003 => "query_sm_resp",
+        0x00000004 => "submit_sm",
+        0x80000004 => "submit_sm_resp",
+        0x00000005 => "deliver_sm",
+        0x80000005 => "deliver_sm_resp",
+        0x00000006 => "unbind",
+        0x80000006 => "unbind_resp",
+        0x00000007 => "replace_sm",
+        0x80000007 => "replace_sm_resp",
+        0x00000008 => "cancel_sm",
+        0x80000008 => "cancel_sm_resp",
+        0x00000009 => "bind_transceiver",
+        0x80000009 => "bind_transceiver_resp",
+        0x0000000B => "outbind",
+        0x00000015 => "enquire_link",
+        0x80000015 => "enquire_link_resp",
+        0x00000021 => "submit_multi",
+        0x80000021 => "submit_multi_resp",
+        0x00000102 => "alert_notification",
+        0x00000103 => "data_sm",
+        0x80000103 => "data_sm_resp",
+        _ => return format!("0x{:08x}", command_id),
+    };
+    String::from(name)
+}
+
+/// Serve GET /metrics for smsc on address.  Unlike the admin API, this
+/// may listen on any interface, so Prometheus can scrape it.  Returns the
+/// address actually bound.
+pub async fn start_metrics(
+    smsc: Arc<Mutex<Smsc>>,
+    address: &str,
+) -> AsyncResult<SocketAddr> {
+    let socket_addr = address.to_socket_addrs()?.next().ok_or_else(|| {
+        format!("metrics address '{}' did not resolve", address)
+    })?;
+    let make_service = make_service_fn(move |_| {
+        let smsc = Arc::clone(&smsc);
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                serve_metrics(Arc::clone(&smsc), request)
+            }))
+        }
+    });
+    let server = Server::try_bind(&socket_addr)?.serve(make_service);
+    let local_addr = server.local_addr();
+    info!("Metrics on http://{}/metrics", local_addr);
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            error!("Metrics server failed: {}", e);
+        }
+    });
+    Ok(local_addr)
+}
+
+async fn serve_metrics(
+    smsc: Arc<Mutex<Smsc>>,
+    request: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    let response = if request.method() == Method::GET
+        && request.uri().path() == "/metrics"
+    {
+        metrics_response(smsc.lock().await.metrics_text())
+    } else {
+        Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::from("Not found\n"))
+            .expect("Failed to build response")
+    };
+    Ok(response)
+}
+
+/// A response carrying text from SmscMetrics::encode
+pub(crate) fn metrics_response(text: String) -> Response<Body> {
+    Response::builder()
+        .header("Content-Type", TextEncoder::new().format_type())
+        .body(Body::from(text))
+        .expect("Failed to build response")
+}
@@ -1,9 +1,28 @@
+pub mod account_store;
+pub mod admin;
+pub mod config_file;
+pub mod message_id_map;
+pub mod metrics;
+pub mod reassembly;
+#[allow(clippy::module_inception)]
 pub mod smsc;
 pub mod smsc_config;
 pub mod smsc_logic;
+pub mod submit_sm_validation;
 
+pub use account_store::{Account, AccountStore};
+pub use admin::start_admin;
+pub use config_file::{AccountConfig, ConfigError, ConfigFile};
+pub use message_id_map::MessageIdMap;
+pub use metrics::{start_metrics, SmscMetrics};
+pub use reassembly::{ConcatenatedSm, ConcatenatedSmPart, Reassembler};
 pub use smpp_pdu::pdu::data::bind_data::BindData;
 pub use smpp_pdu::pdu::data::bind_resp_data::BindRespData;
-pub use smsc::{run, Smsc};
+#[cfg(unix)]
+pub use smsc::reload_on_hangup;
+pub use smsc::{run, MessageInfo, MessageState, SessionInfo, Smsc};
 pub use smsc_config::SmscConfig;
-pub use smsc_logic::{BindError, SmscLogic, SubmitSmError};
+pub use smsc_logic::{
+    BindContext, BindError, SmscLogic, SubmitSmError, SubmitSmFuture,
+};
+pub use submit_sm_validation::SubmitSmChecks;
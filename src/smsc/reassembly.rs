@@ -0,0 +1,327 @@
+//! Buffering the parts of concatenated MTs until we have the whole message.
+
+use log::*;
+use smpp_pdu::pdu::tlvs::{KnownTlvTag, Tlv, Tlvs};
+use smpp_pdu::pdu::{PduParseError, SubmitSmPdu};
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::concatenation::{
+    concat_udh, ConcatInfo, ConcatSource, ESM_CLASS_UDHI,
+};
+use crate::logging;
+use crate::sm_fields::SmFields;
+use crate::smpp_connection::EsmeId;
+
+/// Parts belong to the same message if they came from the same ESME, have
+/// the same addresses and the same concatenation reference.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct PartsKey {
+    pub esme_id: EsmeId,
+    pub source_addr: String,
+    pub destination_addr: String,
+    pub reference: u16,
+}
+
+/// For logs, with the addresses redacted by --log-redaction
+impl fmt::Display for PartsKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let redaction = logging::redaction();
+        write!(
+            f,
+            "from {} ({}) to {} with reference {}",
+            logging::redact(&self.source_addr, redaction),
+            self.esme_id.system_id,
+            logging::redact(&self.destination_addr, redaction),
+            self.reference
+        )
+    }
+}
+
+/// One part of a ConcatenatedSm
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConcatenatedSmPart {
+    /// 1-based position of this part within the message
+    pub part_number: u8,
+    /// The message_id we gave the ESME in the submit_sm_resp for this part.
+    /// DRs sent to the ESME should refer to this.
+    pub message_id: String,
+    /// The user data of this part, with any UDH removed
+    pub user_data: Vec<u8>,
+}
+
+/// A logical message made by joining together all the parts of a
+/// concatenated MT.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConcatenatedSm {
+    pub esme_id: EsmeId,
+    /// How the ESME marked the parts as belonging together
+    pub concat_source: ConcatSource,
+    pub reference: u16,
+    /// As the parts said, even if some never arrived
+    pub total_parts: u8,
+    pub service_type: String,
+    pub source_addr_ton: u8,
+    pub source_addr_npi: u8,
+    pub source_addr: String,
+    pub dest_addr_ton: u8,
+    pub dest_addr_npi: u8,
+    pub destination_addr: String,
+    /// esm_class of the first part, with the UDHI bit cleared
+    pub esm_class: u8,
+    pub protocol_id: u8,
+    pub priority_flag: u8,
+    pub registered_delivery: u8,
+    pub data_coding: u8,
+    /// In order of part_number
+    pub parts: Vec<ConcatenatedSmPart>,
+}
+
+impl ConcatenatedSm {
+    /// The full text of the message, as bytes in its data_coding
+    pub fn user_data(&self) -> Vec<u8> {
+        self.parts
+            .iter()
+            .flat_map(|p| p.user_data.iter().copied())
+            .collect()
+    }
+
+    pub fn message_ids(&self) -> Vec<String> {
+        self.parts.iter().map(|p| p.message_id.clone()).collect()
+    }
+
+    /// A submit_sm for the whole message, with its user data in
+    /// message_payload, e.g. to match against rules written for MTs
+    pub fn joined_pdu(&self) -> Result<SubmitSmPdu, PduParseError> {
+        let mut fields = SmFields {
+            service_type: self.service_type.clone(),
+            source_addr_ton: self.source_addr_ton,
+            source_addr_npi: self.source_addr_npi,
+            source_addr: self.source_addr.clone(),
+            dest_addr_ton: self.dest_addr_ton,
+            dest_addr_npi: self.dest_addr_npi,
+            destination_addr: self.destination_addr.clone(),
+            esm_class: self.esm_class,
+            protocol_id: self.protocol_id,
+            priority_flag: self.priority_flag,
+            registered_delivery: self.registered_delivery,
+            data_coding: self.data_coding,
+            ..Default::default()
+        };
+        fields
+            .set_tlv(Tlv::new(KnownTlvTag::message_payload, &self.user_data()));
+        fields.to_submit_sm()
+    }
+
+    /// A submit_sm for one part, e.g. to pass to SmscLogic::submit_sm,
+    /// with the concatenation UDH or SAR TLVs the ESME marked it with so
+    /// the handset can still join it to the others.  Any other UDH
+    /// Information Elements are not kept.
+    pub fn part_pdu(
+        &self,
+        part: &ConcatenatedSmPart,
+    ) -> Result<SubmitSmPdu, PduParseError> {
+        let mut short_message = concat_udh(
+            self.concat_source,
+            self.reference,
+            self.total_parts,
+            part.part_number,
+        );
+        short_message.extend_from_slice(&part.user_data);
+        let (esm_class, tlvs) = match self.concat_source {
+            ConcatSource::Sar => (
+                self.esm_class,
+                Tlvs::from(&[
+                    Tlv::new(
+                        KnownTlvTag::sar_msg_ref_num,
+                        &self.reference.to_be_bytes(),
+                    ),
+                    Tlv::new(
+                        KnownTlvTag::sar_total_segments,
+                        &[self.total_parts],
+                    ),
+                    Tlv::new(
+                        KnownTlvTag::sar_segment_seqnum,
+                        &[part.part_number],
+                    ),
+                ]),
+            ),
+            _ => (self.esm_class | ESM_CLASS_UDHI, Tlvs::new()),
+        };
+        SubmitSmPdu::new(
+            &self.service_type,
+            self.source_addr_ton,
+            self.source_addr_npi,
+            &self.source_addr,
+            self.dest_addr_ton,
+            self.dest_addr_npi,
+            &self.destination_addr,
+            esm_class,
+            self.protocol_id,
+            self.priority_flag,
+            "",
+            "",
+            self.registered_delivery,
+            0,
+            self.data_coding,
+            0,
+            &short_message,
+            tlvs,
+        )
+    }
+}
+
+struct PendingParts {
+    first_received: Instant,
+    total_parts: u8,
+    first: Option<ConcatenatedSm>,
+    parts: HashMap<u8, ConcatenatedSmPart>,
+}
+
+/// Collects parts of concatenated MTs, handing back the whole message
+/// once every part has arrived.  Call remove_expired regularly to discard
+/// incomplete messages that are older than the timeout.
+pub struct Reassembler {
+    timeout: Duration,
+    pending: HashMap<PartsKey, PendingParts>,
+}
+
+impl Reassembler {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Store one part, which has been given message_id.  If this was the
+    /// last missing part, returns the complete message.
+    pub fn add_part(
+        &mut self,
+        esme_id: &EsmeId,
+        pdu: &SubmitSmPdu,
+        concat_info: &ConcatInfo,
+        message_id: String,
+    ) -> Option<ConcatenatedSm> {
+        let key = PartsKey {
+            esme_id: esme_id.clone(),
+            source_addr: pdu.source_addr(),
+            destination_addr: pdu.destination_addr(),
+            reference: concat_info.reference,
+        };
+
+        let pending =
+            self.pending
+                .entry(key.clone())
+                .or_insert_with(|| PendingParts {
+                    first_received: Instant::now(),
+                    total_parts: concat_info.total_parts,
+                    first: None,
+                    parts: HashMap::new(),
+                });
+
+        if pending.total_parts != concat_info.total_parts {
+            warn!(
+                "Concatenated message {} changed total parts from {} to {}.",
+                key, pending.total_parts, concat_info.total_parts
+            );
+            pending.total_parts = concat_info.total_parts;
+        }
+
+        if pending.first.is_none() || concat_info.part_number == 1 {
+            pending.first = Some(message_header(esme_id, pdu, concat_info));
+        }
+
+        let part = ConcatenatedSmPart {
+            part_number: concat_info.part_number,
+            message_id,
+            user_data: concat_info
+                .user_data(&pdu.0.short_message.value)
+                .to_vec(),
+        };
+        if pending.parts.insert(part.part_number, part).is_some() {
+            warn!(
+                "Received part {} of concatenated message {} twice. \
+                Using the latest one.",
+                concat_info.part_number, key
+            );
+        }
+
+        if pending.parts.len() < usize::from(pending.total_parts) {
+            return None;
+        }
+
+        join(self.pending.remove(&key)?)
+    }
+
+    /// Discard any incomplete messages that have been waiting longer than
+    /// our timeout, returning them with the parts that did arrive.
+    pub fn remove_expired(&mut self, now: Instant) -> Vec<ConcatenatedSm> {
+        let timeout = self.timeout;
+        let expired: Vec<PartsKey> = self
+            .pending
+            .iter()
+            .filter(|(_, p)| now.duration_since(p.first_received) > timeout)
+            .map(|(k, _)| k.clone())
+            .collect();
+
+        let mut messages = Vec::new();
+        for key in &expired {
+            if let Some(p) = self.pending.remove(key) {
+                warn!(
+                    "Gave up waiting for concatenated message {}: \
+                    received {} of {} parts.",
+                    key,
+                    p.parts.len(),
+                    p.total_parts
+                );
+                messages.extend(join(p));
+            }
+        }
+        messages
+    }
+
+    /// The number of messages we are still waiting for parts of
+    pub fn num_pending(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+/// The message with the parts we have, in order
+fn join(pending: PendingParts) -> Option<ConcatenatedSm> {
+    let mut message = pending.first?;
+    message.total_parts = pending.total_parts;
+    let mut parts: Vec<ConcatenatedSmPart> =
+        pending.parts.into_values().collect();
+    parts.sort_by_key(|p| p.part_number);
+    message.parts = parts;
+    Some(message)
+}
+
+fn message_header(
+    esme_id: &EsmeId,
+    pdu: &SubmitSmPdu,
+    concat_info: &ConcatInfo,
+) -> ConcatenatedSm {
+    let d = &pdu.0;
+    ConcatenatedSm {
+        esme_id: esme_id.clone(),
+        concat_source: concat_info.source,
+        reference: concat_info.reference,
+        total_parts: concat_info.total_parts,
+        service_type: d.service_type.value.to_string(),
+        source_addr_ton: d.source_addr_ton.value,
+        source_addr_npi: d.source_addr_npi.value,
+        source_addr: d.source_addr.value.to_string(),
+        dest_addr_ton: d.dest_addr_ton.value,
+        dest_addr_npi: d.dest_addr_npi.value,
+        destination_addr: d.destination_addr.value.to_string(),
+        esm_class: d.esm_class.value & !ESM_CLASS_UDHI,
+        protocol_id: d.protocol_id.value,
+        priority_flag: d.priority_flag.value,
+        registered_delivery: d.registered_delivery.value,
+        data_coding: d.data_coding.value,
+        parts: Vec::new(),
+    }
+}
@@ -1,4 +1,7 @@
+use async_trait::async_trait;
+use futures::future::{self, FutureExt};
 use log::*;
+use serde::Serialize;
 use smpp_pdu::pdu::{
     BindReceiverRespPdu, BindTransceiverRespPdu, BindTransmitterRespPdu,
     EnquireLinkRespPdu, GenericNackPdu, Pdu, PduBody, PduParseError, PduStatus,
@@ -9,37 +12,191 @@ use std::error;
 use std::fmt::{Display, Formatter};
 use std::io;
 use std::net::SocketAddr;
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
 use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::{Mutex, Semaphore, TryAcquireError};
-use tokio::time::sleep;
+use tokio::task::JoinHandle;
+use tokio::time::{self, sleep};
+use tracing::Instrument;
 
+use crate::address::{Address, NormalizationRules};
 use crate::async_result::AsyncResult;
+use crate::capture::Capturer;
+use crate::concatenation::ConcatInfo;
+use crate::esme::BindType;
+use crate::extra_pdu::{AnyPdu, ExtraPdu, ExtraPduBody};
+use crate::faults::FaultInjector;
+use crate::logging;
+use crate::logging::{pdu_span, LoggedPdu};
+use crate::message_id_generator::MessageIdGenerator;
 use crate::message_unique_key::MessageUniqueKey;
+use crate::rate_limiter::RateLimiter;
+use crate::routing::{RoutingDecision, RoutingTable};
 use crate::smpp_connection::{EsmeId, SmppConnection};
-use crate::smsc::{SmscConfig, SmscLogic};
+use crate::smsc::message_id_map::rewrite_dr_message_id;
+use crate::smsc::metrics::{Direction, SmscMetrics};
+use crate::smsc::{
+    start_admin, start_metrics, AccountConfig, AccountStore, BindContext,
+    BindData, BindError, ConfigError, MessageIdMap, Reassembler, SmscConfig,
+    SmscLogic, SubmitSmChecks,
+};
 
+/// Run an SMSC until it stops, with the admin API and metrics if their
+/// addresses are set.  On SIGHUP or POST /reload, the configuration is
+/// read again from the command line, environment and config file, and
+/// applied with Smsc::reload.
 pub fn run<L: SmscLogic + Send + Sync + 'static>(
     config: SmscConfig,
     smsc_logic: L,
 ) -> AsyncResult<()> {
     let rt = tokio::runtime::Runtime::new()?;
     rt.block_on(async move {
+        let admin_address = config.admin_address.clone();
+        let metrics_address = config.metrics_address.clone();
         let smsc = Smsc::start(config, smsc_logic).await?;
+        if let Some(admin_address) = admin_address {
+            start_admin(Arc::clone(&smsc), &admin_address, SmscConfig::load)
+                .await?;
+        }
+        if let Some(metrics_address) = metrics_address {
+            start_metrics(Arc::clone(&smsc), &metrics_address).await?;
+        }
+        #[cfg(unix)]
+        tokio::spawn(reload_on_hangup(Arc::clone(&smsc), SmscConfig::load));
         loop {
-            if let Err(e) = smsc.lock().await.stopped().await {
-                return Err(e);
-            }
+            smsc.lock().await.stopped().await?;
             sleep(Duration::from_millis(100)).await;
             // TODO: notify instead of poll?
         }
     })
 }
 
+/// Reload the configuration using load each time we get SIGHUP
+#[cfg(unix)]
+pub async fn reload_on_hangup<F>(
+    smsc: Arc<Mutex<Smsc>>,
+    load: F,
+) -> AsyncResult<()>
+where
+    F: Fn() -> Result<SmscConfig, ConfigError>,
+{
+    let mut hangups = signal(SignalKind::hangup())?;
+    while hangups.recv().await.is_some() {
+        info!("Received SIGHUP");
+        let result = match load() {
+            Ok(config) => Smsc::reload(&smsc, config).await,
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = result {
+            error!("Failed to reload configuration: {}", e);
+        }
+    }
+    Ok(())
+}
+
+/// Starts a task accepting connections on a listener
+type ListenerSpawner = Box<
+    dyn Fn(TcpListener, Arc<Mutex<Smsc>>, SmscConfig) -> JoinHandle<()>
+        + Send
+        + Sync,
+>;
+
+/// The parts of SmscLogic that Smsc::reload needs, without knowing the
+/// type of the logic
+#[async_trait]
+trait ReloadHooks: Send + Sync {
+    async fn reload(&self, config: &SmscConfig) -> AsyncResult<()>;
+    async fn may_stay_bound(&self, esme_id: &EsmeId) -> bool;
+}
+
+#[async_trait]
+impl<L: SmscLogic + Send + Sync> ReloadHooks for Mutex<L> {
+    async fn reload(&self, config: &SmscConfig) -> AsyncResult<()> {
+        self.lock().await.reload(config).await
+    }
+
+    async fn may_stay_bound(&self, esme_id: &EsmeId) -> bool {
+        self.lock().await.may_stay_bound(esme_id).await
+    }
+}
+
+/// A bound session, as listed by the admin API
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SessionInfo {
+    pub system_id: String,
+    pub system_type: String,
+    pub peer: SocketAddr,
+    pub bind_type: BindType,
+    pub uptime_secs: u64,
+    pub pdus_received: u64,
+    pub pdus_sent: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageState {
+    AwaitingDr,
+    DrForwarded,
+}
+
+/// What we know about an MT, found by its supplier's MessageUniqueKey
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct MessageInfo {
+    /// The ESME that sent it, which its DRs go to
+    pub system_id: String,
+    pub system_type: String,
+    /// Whether that ESME is bound now, so a DR could reach it
+    pub bound: bool,
+    /// The message_id we gave the ESME, if not the supplier's
+    pub our_message_id: Option<String>,
+    pub state: MessageState,
+    pub drs_forwarded: u32,
+}
+
+struct MessageRecord {
+    esme_id: EsmeId,
+    drs_forwarded: u32,
+    submitted_at: Instant,
+}
+
 pub struct Smsc {
-    connections: HashMap<EsmeId, Arc<SmppConnection>>,
-    messages: HashMap<MessageUniqueKey, EsmeId>,
+    /// Bound sessions, by connection id.  An ESME may have several.
+    connections: HashMap<u64, Arc<SmppConnection>>,
+    messages: HashMap<MessageUniqueKey, MessageRecord>,
+    reassembler: Option<Reassembler>,
+    /// How long a session has to answer our unbind
+    unbind_timeout: Duration,
+    message_id_generator: MessageIdGenerator,
+    message_id_map: MessageIdMap,
+    submit_sm_checks: SubmitSmChecks,
+    normalization_rules: NormalizationRules,
+    routing_table: Option<RoutingTable>,
+    /// Checks binds, if accounts are configured
+    account_store: Option<Arc<AccountStore>>,
+    accounts: HashMap<String, Account>,
+    /// Accept-loop tasks, by bind address
+    listeners: HashMap<String, JoinHandle<()>>,
+    spawn_listener: ListenerSpawner,
+    logic: Arc<dyn ReloadHooks>,
+    metrics: Arc<SmscMetrics>,
+    capturer: Arc<Capturer>,
+    faults: Arc<FaultInjector>,
+}
+
+/// An ESME allowed to bind, from SmscConfig::accounts
+struct Account {
+    rate_limiter: Option<RateLimiter>,
+}
+
+impl From<&AccountConfig> for Account {
+    fn from(config: &AccountConfig) -> Self {
+        Self {
+            rate_limiter: config.max_submits_per_sec.map(RateLimiter::new),
+        }
+    }
 }
 
 impl Smsc {
@@ -53,26 +210,319 @@ impl Smsc {
     ) -> AsyncResult<Arc<Mutex<Self>>> {
         info!("Starting SMSC");
 
-        let smsc = Smsc {
+        // All listeners share one limit on open sockets, and one logic
+        let sem = Arc::new(Semaphore::new(smsc_config.max_open_sockets));
+        let smsc_logic = Arc::new(Mutex::new(smsc_logic));
+        let expiry_logic = Arc::clone(&smsc_logic);
+        let capturer = Arc::new(Capturer::new(smsc_config.capture.clone()));
+        let faults = Arc::new(FaultInjector::new(smsc_config.faults.clone()));
+        let spawn_listener: ListenerSpawner = {
+            let smsc_logic = Arc::clone(&smsc_logic);
+            let capturer = Arc::clone(&capturer);
+            let faults = Arc::clone(&faults);
+            Box::new(move |listener, smsc, config| {
+                tokio::spawn(listen_loop(
+                    listener,
+                    smsc,
+                    Arc::clone(&sem),
+                    Arc::clone(&capturer),
+                    Arc::clone(&faults),
+                    config,
+                    Arc::clone(&smsc_logic),
+                ))
+            })
+        };
+
+        let mut smsc = Smsc {
             connections: HashMap::new(),
             messages: HashMap::new(),
+            reassembler: smsc_config
+                .concat_timeout_secs
+                .map(|secs| Reassembler::new(Duration::from_secs(secs))),
+            unbind_timeout: Duration::ZERO,
+            message_id_generator: MessageIdGenerator::new(),
+            message_id_map: MessageIdMap::new(),
+            submit_sm_checks: SubmitSmChecks::default(),
+            normalization_rules: NormalizationRules::default(),
+            routing_table: None,
+            account_store: None,
+            accounts: HashMap::new(),
+            listeners: HashMap::new(),
+            spawn_listener,
+            logic: smsc_logic,
+            metrics: Arc::new(SmscMetrics::new(smsc_config.max_open_sockets)),
+            capturer,
+            faults,
         };
+        smsc.apply_config(&smsc_config, account_store(&smsc_config)?);
         let smsc = Arc::new(Mutex::new(smsc));
+        if smsc_config.concat_timeout_secs.is_some() {
+            tokio::spawn(expire_concatenated_sms(
+                Arc::downgrade(&smsc),
+                expiry_logic,
+            ));
+        }
 
-        let listener = TcpListener::bind(&smsc_config.bind_address).await?;
-        info!("Bound on {}", &smsc_config.bind_address);
-
-        // Spawn off a task that deals with incoming connections
-        tokio::spawn(listen_loop(
-            listener,
-            Arc::clone(&smsc),
-            smsc_config,
-            smsc_logic,
-        ));
+        // Spawn off a task for each listener that deals with incoming
+        // connections
+        let mut listeners = Vec::new();
+        for bind_address in bind_addresses(&smsc_config) {
+            listeners.push((
+                bind_address.clone(),
+                TcpListener::bind(bind_address).await?,
+            ));
+            info!("Bound on {}", bind_address);
+        }
+        smsc.lock()
+            .await
+            .add_listeners(listeners, &smsc, &smsc_config);
 
         Ok(smsc)
     }
 
+    /// Apply a new configuration without dropping sessions.  Accounts,
+    /// rate limits, routes, submit_sm checks, number normalization and the
+    /// log level apply from the next PDU, listeners are added and removed,
+    /// and sessions whose accounts are gone (or that the logic says may
+    /// not stay bound) are unbound.  max_open_sockets, concat_timeout_secs
+    /// and system_id need a restart.  If a new listener can't bind,
+    /// nothing changes.
+    pub async fn reload(
+        smsc: &Arc<Mutex<Smsc>>,
+        config: SmscConfig,
+    ) -> AsyncResult<()> {
+        info!("Reloading configuration");
+        let account_store = account_store(&config)?;
+        let (logic, new_addresses) = {
+            let smsc = smsc.lock().await;
+            let new_addresses: Vec<String> = bind_addresses(&config)
+                .filter(|a| !smsc.listeners.contains_key(*a))
+                .cloned()
+                .collect();
+            (Arc::clone(&smsc.logic), new_addresses)
+        };
+
+        let mut new_listeners = Vec::new();
+        for bind_address in new_addresses {
+            let listener = TcpListener::bind(&bind_address).await?;
+            info!("Bound on {}", bind_address);
+            new_listeners.push((bind_address, listener));
+        }
+        logic.reload(&config).await?;
+
+        let esme_ids: Vec<EsmeId> = {
+            let mut locked = smsc.lock().await;
+            locked.add_listeners(new_listeners, smsc, &config);
+            let wanted: Vec<&String> = bind_addresses(&config).collect();
+            locked.listeners.retain(|bind_address, task| {
+                let keep = wanted.contains(&bind_address);
+                if !keep {
+                    info!("Stopped listening on {}", bind_address);
+                    task.abort();
+                }
+                keep
+            });
+            locked.apply_config(&config, account_store);
+            let mut esme_ids: Vec<EsmeId> = locked
+                .connections
+                .values()
+                .filter_map(|connection| connection.bound_esme_id())
+                .collect();
+            esme_ids.sort_by(|a, b| {
+                (&a.system_id, &a.system_type)
+                    .cmp(&(&b.system_id, &b.system_type))
+            });
+            esme_ids.dedup();
+            esme_ids
+        };
+        logging::set_level(&config.log_level);
+        logging::set_redaction(config.log_redaction);
+
+        for esme_id in esme_ids {
+            let has_account = smsc.lock().await.has_account(&esme_id);
+            if !has_account || !logic.may_stay_bound(&esme_id).await {
+                if let Err(e) = smsc.lock().await.unbind(&esme_id).await {
+                    warn!("Failed to unbind {}: {}", esme_id.system_id, e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The parts of config that reload can change in place
+    fn apply_config(
+        &mut self,
+        config: &SmscConfig,
+        account_store: Option<Arc<AccountStore>>,
+    ) {
+        self.account_store = account_store;
+        self.unbind_timeout = Duration::from_secs(config.unbind_timeout_secs);
+        self.capturer.configure(config.capture.clone());
+        self.faults.configure(config.faults.clone());
+        self.submit_sm_checks = config.submit_sm_checks.clone();
+        self.normalization_rules = config.normalization_rules.clone();
+        self.accounts = config
+            .accounts
+            .iter()
+            .map(|a| (a.system_id.clone(), Account::from(a)))
+            .collect();
+        self.routing_table = if config.routes.is_empty() {
+            None
+        } else {
+            Some(RoutingTable::new(config.routes.clone()))
+        };
+    }
+
+    fn add_listeners(
+        &mut self,
+        listeners: Vec<(String, TcpListener)>,
+        smsc: &Arc<Mutex<Smsc>>,
+        config: &SmscConfig,
+    ) {
+        for (bind_address, listener) in listeners {
+            let task = (self.spawn_listener)(
+                listener,
+                Arc::clone(smsc),
+                config.clone(),
+            );
+            self.listeners.insert(bind_address, task);
+        }
+    }
+
+    /// Ask each of a bound ESME's sessions to unbind, and stop sending
+    /// them PDUs.  Their connections close when they respond, or after
+    /// unbind_timeout_secs if they don't.
+    pub async fn unbind(&mut self, esme_id: &EsmeId) -> AsyncResult<()> {
+        info!(
+            "Unbinding system_id='{}' system_type='{}'",
+            esme_id.system_id, esme_id.system_type
+        );
+        let ids = self.session_ids(esme_id);
+        if ids.is_empty() {
+            return Err(no_connection(esme_id).into());
+        }
+        for id in ids {
+            let connection = match self.connections.get(&id) {
+                Some(connection) if connection.start_unbinding() => {
+                    Arc::clone(connection)
+                }
+                _ => continue,
+            };
+            tokio::spawn(kill_if_still_open(
+                Arc::downgrade(&connection),
+                self.unbind_timeout,
+            ));
+            let unbind = ExtraPdu::new(0, 1, ExtraPduBody::Unbind)?;
+            self.write_request(&connection, unbind.into()).await?;
+        }
+        Ok(())
+    }
+
+    pub fn metrics(&self) -> Arc<SmscMetrics> {
+        Arc::clone(&self.metrics)
+    }
+
+    /// What decides which sessions' PDUs are captured
+    pub fn capturer(&self) -> Arc<Capturer> {
+        Arc::clone(&self.capturer)
+    }
+
+    /// All metrics, in the Prometheus text format
+    pub fn metrics_text(&self) -> String {
+        self.metrics
+            .encode(self.connections.len(), self.messages.len())
+    }
+
+    /// Every bound session
+    pub fn sessions(&self) -> Vec<SessionInfo> {
+        self.connections
+            .values()
+            .filter_map(|connection| {
+                let binding = connection.binding()?;
+                Some(SessionInfo {
+                    system_id: binding.esme_id.system_id.to_string(),
+                    system_type: binding.esme_id.system_type.to_string(),
+                    peer: connection.socket_addr,
+                    bind_type: binding.bind_type,
+                    uptime_secs: binding.since.elapsed().as_secs(),
+                    pdus_received: connection.pdus_received(),
+                    pdus_sent: connection.pdus_sent(),
+                })
+            })
+            .collect()
+    }
+
+    /// Close each of a bound ESME's connections without unbinding.
+    /// Returns false if it is not bound.
+    pub fn kill(&mut self, esme_id: &EsmeId) -> bool {
+        let ids = self.session_ids(esme_id);
+        if ids.is_empty() {
+            return false;
+        }
+        info!(
+            "Killing system_id='{}' system_type='{}'",
+            esme_id.system_id, esme_id.system_type
+        );
+        for id in ids {
+            if let Some(connection) = self.connections.remove(&id) {
+                connection.kill();
+            }
+        }
+        true
+    }
+
+    /// Who sent the MT a supplier knows by message_unique_key, and whether
+    /// its DRs have been passed on
+    pub fn message_info(
+        &self,
+        message_unique_key: &MessageUniqueKey,
+    ) -> Option<MessageInfo> {
+        let key = message_unique_key.normalize(&self.normalization_rules);
+        let record = self.messages.get(&key)?;
+        Some(MessageInfo {
+            system_id: record.esme_id.system_id.to_string(),
+            system_type: record.esme_id.system_type.to_string(),
+            bound: connection_for(&self.connections, &record.esme_id).is_some(),
+            our_message_id: self.message_id_map.get(&key).cloned(),
+            state: if record.drs_forwarded == 0 {
+                MessageState::AwaitingDr
+            } else {
+                MessageState::DrForwarded
+            },
+            drs_forwarded: record.drs_forwarded,
+        })
+    }
+
+    /// Send an MO deliver_sm to a session of system_id that can receive
+    pub async fn deliver_mo(
+        &self,
+        system_id: &str,
+        pdu: Pdu,
+    ) -> AsyncResult<()> {
+        let connection = self
+            .connections
+            .values()
+            .find(|connection| {
+                connection.binding().is_some_and(|binding| {
+                    binding.esme_id.system_id.as_str() == system_id
+                        && binding.bind_type != BindType::Transmitter
+                })
+            })
+            .ok_or_else(|| {
+                format!("No receiver or transceiver bound as '{}'", system_id)
+            })?;
+        let (command_id, command_status) =
+            (pdu.command_id().value, pdu.command_status.value);
+        connection.write_request(pdu.into()).await?;
+        self.metrics.count_pdu(
+            Direction::Out,
+            connection,
+            command_id,
+            command_status,
+        );
+        Ok(())
+    }
+
     async fn stopped(&self) -> AsyncResult<()> {
         // TODO: check whether we are stopped and return an error if so
         Ok(())
@@ -85,7 +535,7 @@ impl Smsc {
     ) -> AsyncResult<()> {
         // Later: Issue#5: consider retrying after a delay if unable to match DR
         // Later: Issue#12: handle MOs
-        info!("<= receive_pdu() {:?}", pdu);
+        info!("<= receive_pdu() {}", LoggedPdu::from(&pdu));
         match pdu.body() {
             PduBody::DeliverSm(body) => {
                 let k =
@@ -112,23 +562,179 @@ impl Smsc {
         pdu: Pdu,
         message_unique_key: MessageUniqueKey,
     ) -> AsyncResult<()> {
+        let pdu = self.with_our_message_id(pdu, &message_unique_key)?;
         let conn = self.connection_for_message(message_unique_key).await?;
+        let metrics = self.metrics();
         // Later: Issue#3: in order to support a window size to the client, we
         // will need to put this PDU into a queue rather than writing
         // it immediately here.
         tokio::spawn(async move {
             // We schedule the write here, as a sort-of 1-message queue,
-            // so we return immediately, and the IO is done later.
-            conn.write_pdu(&pdu).await.map_err(
-                |e| error!("Failed to send PDU to client: {}", e), // TODO: give information about the client here
-            )
+            // so we return immediately, and the IO is done later.  The
+            // logic's sequence_number may clash with the ESME's own, so
+            // we use the next one from this connection instead.
+            let (command_id, command_status) =
+                (pdu.command_id().value, pdu.command_status.value);
+            conn.write_request(pdu.into())
+                .await
+                .map(|_| {
+                    metrics.count_pdu(
+                        Direction::Out,
+                        &conn,
+                        command_id,
+                        command_status,
+                    )
+                })
+                .map_err(
+                    |e| error!("Failed to send PDU to client: {}", e), // TODO: give information about the client here
+                )
         });
         Ok(())
     }
 
+    /// A new message_id to give an ESME, unique within this process
+    pub fn next_message_id(&self) -> String {
+        self.message_id_generator.next_message_id()
+    }
+
+    /// Record that we gave an ESME message_id for the MT that a supplier
+    /// knows by supplier_key.  DRs from the supplier will be rewritten to
+    /// use message_id before we deliver them.
+    pub fn map_message_id(
+        &mut self,
+        supplier_key: MessageUniqueKey,
+        message_id: String,
+    ) {
+        let supplier_key = supplier_key.normalize(&self.normalization_rules);
+        self.message_id_map.insert(supplier_key, message_id);
+    }
+
+    /// If we gave the ESME our own message_id for this message, replace
+    /// the supplier's message ID in the DR with ours.
+    fn with_our_message_id(
+        &self,
+        pdu: Pdu,
+        message_unique_key: &MessageUniqueKey,
+    ) -> AsyncResult<Pdu> {
+        let key = message_unique_key.normalize(&self.normalization_rules);
+        match (self.message_id_map.get(&key), pdu.body()) {
+            (Some(message_id), PduBody::DeliverSm(body)) => Ok(Pdu::new(
+                pdu.command_status.value,
+                pdu.sequence_number.value,
+                rewrite_dr_message_id(body, message_id)?.into(),
+            )?),
+            _ => Ok(pdu),
+        }
+    }
+
+    /// Use routing_table to choose a supplier for each MT.  The decision
+    /// is passed to SmscLogic::submit_sm_with_route.
+    pub fn set_routing_table(&mut self, routing_table: RoutingTable) {
+        self.routing_table = Some(routing_table);
+    }
+
+    /// Choose a supplier for an MT from esme_id, using the routing table.
+    /// Returns None if there is no routing table, or no route matches.
+    pub fn route(
+        &self,
+        esme_id: &EsmeId,
+        pdu: &SubmitSmPdu,
+    ) -> Option<RoutingDecision> {
+        let routing_table = self.routing_table.as_ref()?;
+        let destination = Address::new(
+            pdu.dest_addr_ton(),
+            pdu.dest_addr_npi(),
+            &pdu.destination_addr(),
+        )
+        .normalize(&self.normalization_rules);
+        let decision = routing_table.route(
+            esme_id.system_id.as_str(),
+            &pdu.source_addr(),
+            &destination,
+        );
+        if decision.is_none() {
+            info!(
+                "No route for MT from {} to {}",
+                esme_id.system_id,
+                destination.logged()
+            );
+        }
+        decision
+    }
+
+    /// False if accounts are configured and esme_id is not one of them
+    fn has_account(&self, esme_id: &EsmeId) -> bool {
+        self.account_store
+            .as_ref()
+            .is_none_or(|store| store.allows(esme_id.system_id.as_str()))
+    }
+
+    /// How many sessions are bound with this system_id
+    fn sessions_for(&self, system_id: &str) -> usize {
+        self.connections
+            .values()
+            .filter_map(|connection| connection.bound_esme_id())
+            .filter(|esme_id| esme_id.system_id.as_str() == system_id)
+            .count()
+    }
+
+    /// The ids of the sessions bound as esme_id
+    fn session_ids(&self, esme_id: &EsmeId) -> Vec<u64> {
+        self.connections
+            .iter()
+            .filter(|(_, connection)| {
+                connection.bound_esme_id().as_ref() == Some(esme_id)
+            })
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    /// False if esme_id has sent more submit_sm than its account's
+    /// max_submits_per_sec allows
+    async fn may_submit(&self, esme_id: &EsmeId) -> bool {
+        match self
+            .accounts
+            .get(esme_id.system_id.as_str())
+            .and_then(|a| a.rate_limiter.as_ref())
+        {
+            Some(rate_limiter) => rate_limiter.try_acquire().await,
+            None => true,
+        }
+    }
+
+    /// Send a request we originate, such as enquire_link or unbind, to a
+    /// bound ESME.  Its sequence_number is replaced by the next one for
+    /// that ESME's connection, which is returned.
+    pub async fn send_request(
+        &self,
+        esme_id: &EsmeId,
+        pdu: AnyPdu,
+    ) -> AsyncResult<u32> {
+        let connection = connection_for(&self.connections, esme_id)
+            .ok_or_else(|| no_connection(esme_id))?;
+        self.write_request(connection, pdu).await
+    }
+
+    async fn write_request(
+        &self,
+        connection: &SmppConnection,
+        pdu: AnyPdu,
+    ) -> AsyncResult<u32> {
+        let (command_id, command_status) =
+            (pdu.command_id(), pdu.command_status());
+        let sequence_number = connection.write_request(pdu).await?;
+        self.metrics.count_pdu(
+            Direction::Out,
+            connection,
+            command_id,
+            command_status,
+        );
+        Ok(sequence_number)
+    }
+
     pub fn add_connection(&mut self, connection: Arc<SmppConnection>) {
-        if let Some(esme_id) = connection.bound_esme_id() {
-            self.connections.insert(esme_id, connection);
+        if connection.bound_esme_id().is_some() {
+            self.connections.insert(connection.id(), connection);
         } else {
             error!(
                 "Failed to add connection {} because it is not bound!",
@@ -142,70 +748,185 @@ impl Smsc {
         connection: &Arc<SmppConnection>,
     ) {
         connection.disconnect().await;
-        if let Some(esme_id) = connection.bound_esme_id() {
-            self.connections.remove(&esme_id);
+        // Only if it is still this connection's entry
+        let id = connection.id();
+        if self
+            .connections
+            .get(&id)
+            .is_some_and(|entry| Arc::ptr_eq(entry, connection))
+        {
+            self.connections.remove(&id);
         }
     }
 
-    fn add_message(
+    pub(crate) fn add_message(
         &mut self,
         message_unique_key: MessageUniqueKey,
         esme_id: EsmeId,
     ) {
         // Later: Issue#14: delete old entries in this map to keep size bounded
-        self.messages.insert(message_unique_key, esme_id);
+        let message_unique_key =
+            message_unique_key.normalize(&self.normalization_rules);
+        self.messages.insert(
+            message_unique_key,
+            MessageRecord {
+                esme_id,
+                drs_forwarded: 0,
+                submitted_at: Instant::now(),
+            },
+        );
     }
 
     async fn connection_for_message(
         &mut self,
         message_unique_key: MessageUniqueKey,
     ) -> AsyncResult<Arc<SmppConnection>> {
-        if let Some(esme_id) = self.messages.get(&message_unique_key) {
-            if let Some(connection) = self.connections.get(esme_id) {
+        let message_unique_key =
+            message_unique_key.normalize(&self.normalization_rules);
+        if let Some(record) = self.messages.get_mut(&message_unique_key) {
+            let esme_id = &record.esme_id;
+            if let Some(connection) = connection_for(&self.connections, esme_id)
+            {
+                if record.drs_forwarded == 0 {
+                    self.metrics
+                        .observe_submit_to_dr(record.submitted_at.elapsed());
+                }
+                record.drs_forwarded += 1;
                 Ok(Arc::clone(connection))
             } else {
-                Err(format!(
-                    "No client connection found with \
-                system_id='{}' system_type='{}'.",
-                    esme_id.system_id, esme_id.system_type
-                )
-                .into())
+                Err(no_connection(esme_id).into())
             }
         } else {
             Err(format!(
                 "No record found of message with \
-                namespaceId='{}', message_id='{}', destination_addr='{}'",
+                namespaceId='{}', message_id='{}', destination_addr={}",
                 message_unique_key.namespace_id,
                 message_unique_key.message_id,
-                message_unique_key.destination_addr
+                message_unique_key.destination_addr.logged()
             )
             .into())
         }
     }
 }
 
+/// An AccountStore holding config's accounts, if there are any
+fn account_store(
+    config: &SmscConfig,
+) -> Result<Option<Arc<AccountStore>>, String> {
+    if config.accounts.is_empty() {
+        return Ok(None);
+    }
+    let accounts = config.accounts.iter().map(AccountConfig::to_account);
+    Ok(Some(Arc::new(AccountStore::new(accounts.collect())?)))
+}
+
+/// The session to send esme_id's DRs and requests on, preferring one
+/// that can receive
+fn connection_for<'a>(
+    connections: &'a HashMap<u64, Arc<SmppConnection>>,
+    esme_id: &EsmeId,
+) -> Option<&'a Arc<SmppConnection>> {
+    let sessions = || {
+        connections.values().filter(move |connection| {
+            connection.bound_esme_id().as_ref() == Some(esme_id)
+                && !connection.is_unbinding()
+        })
+    };
+    sessions()
+        .find(|connection| {
+            connection.binding().is_some_and(|binding| {
+                binding.bind_type != BindType::Transmitter
+            })
+        })
+        .or_else(|| sessions().next())
+}
+
+/// Close connection if the peer has not answered our unbind by the time
+/// timeout is up
+async fn kill_if_still_open(
+    connection: Weak<SmppConnection>,
+    timeout: Duration,
+) {
+    sleep(timeout).await;
+    if let Some(connection) = connection.upgrade() {
+        warn!(
+            "No unbind_resp from {} after {:?}.  Closing the connection.",
+            connection.socket_addr, timeout
+        );
+        connection.kill();
+    }
+}
+
+fn no_connection(esme_id: &EsmeId) -> String {
+    format!(
+        "No client connection found with \
+        system_id='{}' system_type='{}'.",
+        esme_id.system_id, esme_id.system_type
+    )
+}
+
+fn bind_addresses(config: &SmscConfig) -> impl Iterator<Item = &String> {
+    std::iter::once(&config.bind_address)
+        .chain(config.additional_bind_addresses.iter())
+}
+
+/// Every second, hand the logic any concatenated MTs that have waited too
+/// long for their missing parts.  Stops once the Smsc is dropped.
+async fn expire_concatenated_sms<L: SmscLogic>(
+    smsc: Weak<Mutex<Smsc>>,
+    smsc_logic: Arc<Mutex<L>>,
+) {
+    let mut interval = time::interval(Duration::from_secs(1));
+    loop {
+        interval.tick().await;
+        let smsc = match smsc.upgrade() {
+            Some(smsc) => smsc,
+            None => return,
+        };
+        let expired = match smsc.lock().await.reassembler.as_mut() {
+            Some(reassembler) => reassembler.remove_expired(Instant::now()),
+            None => Vec::new(),
+        };
+        for message in expired {
+            smsc_logic
+                .lock()
+                .await
+                .concatenated_sm_expired(Arc::clone(&smsc), &message)
+                .await;
+        }
+    }
+}
+
 /// Listen for clients connecting, and spawn a new task every time one does
 async fn listen_loop<L: SmscLogic + Send + Sync + 'static>(
     listener: TcpListener,
     smsc: Arc<Mutex<Smsc>>,
+    sem: Arc<Semaphore>,
+    capturer: Arc<Capturer>,
+    faults: Arc<FaultInjector>,
     config: SmscConfig,
-    logic: L,
+    logic: Arc<Mutex<L>>,
 ) {
-    let sem = Arc::new(Semaphore::new(config.max_open_sockets));
-    let logic = Arc::new(Mutex::new(logic));
     loop {
         match listener.accept().await {
             Err(e) => {
                 error!("Client connection failed: {}", e);
             }
             Ok((tcp_stream, socket_addr)) => {
-                tokio::spawn(process_stream(
-                    Arc::clone(&sem),
-                    SmppConnection::new(tcp_stream, socket_addr),
-                    config.clone(),
-                    Arc::clone(&logic),
-                    Arc::clone(&smsc),
-                ));
+                let connection = SmppConnection::new(tcp_stream, socket_addr)
+                    .with_capturer(Arc::clone(&capturer))
+                    .with_faults(Arc::clone(&faults));
+                let span = connection.span().clone();
+                tokio::spawn(
+                    process_stream(
+                        Arc::clone(&sem),
+                        connection,
+                        config.clone(),
+                        Arc::clone(&logic),
+                        Arc::clone(&smsc),
+                    )
+                    .instrument(span),
+                );
             }
         }
     }
@@ -218,15 +939,20 @@ async fn process_stream<L: SmscLogic + Send + Sync + 'static>(
     logic: Arc<Mutex<L>>,
     smsc: Arc<Mutex<Smsc>>,
 ) {
-    let socket_addr = connection.socket_addr.clone();
+    let socket_addr = connection.socket_addr;
+    let metrics = smsc.lock().await.metrics();
     let aqu = sem.try_acquire();
     match aqu {
         Ok(_guard) => {
             info!("Connection {} - opened", socket_addr);
-            let result = process(connection, config, logic, smsc).await;
+            metrics.socket_opened();
+            let result =
+                process(connection, config, logic, smsc, &metrics).await;
+            metrics.socket_closed();
             log_result(result, socket_addr);
         }
         Err(TryAcquireError::NoPermits) => {
+            metrics.connection_refused();
             error!(
                 "Refused connection {} - too many open sockets",
                 connection.socket_addr
@@ -329,6 +1055,7 @@ async fn process<L: SmscLogic>(
     config: SmscConfig,
     smsc_logic: Arc<Mutex<L>>,
     smsc: Arc<Mutex<Smsc>>,
+    metrics: &SmscMetrics,
 ) -> Result<bool, ProcessError> {
     struct DisconnectGuard {
         smsc: Arc<Mutex<Smsc>>,
@@ -352,7 +1079,7 @@ async fn process<L: SmscLogic>(
     // from elsewhere.
     let disconnect_guard = DisconnectGuard {
         smsc: Arc::clone(&smsc),
-        connection: Arc::new(connection),
+        connection: connection.into_arc(),
     };
 
     process_loop(
@@ -360,6 +1087,7 @@ async fn process<L: SmscLogic>(
         config,
         smsc_logic,
         smsc,
+        metrics,
     )
     .await
 }
@@ -369,48 +1097,38 @@ async fn process_loop<L: SmscLogic>(
     config: SmscConfig,
     smsc_logic: Arc<Mutex<L>>,
     smsc: Arc<Mutex<Smsc>>,
+    metrics: &SmscMetrics,
 ) -> Result<bool, ProcessError> {
     loop {
-        let pdu = connection.read_pdu().await;
-        match pdu {
-            Ok(pdu) => {
-                if let Some(pdu) = pdu {
-                    let sequence_number = pdu.sequence_number.value;
-                    match handle_pdu(
-                        pdu,
-                        Arc::clone(&connection),
-                        &config,
-                        Arc::clone(&smsc_logic),
-                        Arc::clone(&smsc),
-                    )
-                    .await
-                    {
-                        Ok(response) => connection.write_pdu(&response).await?,
-                        Err(e) => {
-                            // Couldn't handle this PDU type.  Send a nack...
-                            connection
-                                .write_pdu(
-                                    &Pdu::new(
-                                        PduStatus::ESME_RINVCMDID as u32,
-                                        sequence_number,
-                                        GenericNackPdu::new_error().into(),
-                                    )
-                                    .unwrap(),
-                                )
-                                .await?;
-                            // ...and Drop the connection.
-                            return Err(e);
-                        }
-                    }
-                } else {
-                    // Client closed the connection
-                    return Ok(false);
+        match connection.read_any_pdu().await {
+            Ok(Some(pdu)) => {
+                metrics.count_pdu(
+                    Direction::In,
+                    &connection,
+                    pdu.command_id(),
+                    pdu.command_status(),
+                );
+                let span = pdu_span(&pdu);
+                let closed_by_us = handle_any_pdu(
+                    pdu,
+                    &connection,
+                    &config,
+                    &smsc_logic,
+                    &smsc,
+                    metrics,
+                )
+                .instrument(span)
+                .await?;
+                if let Some(closed_by_us) = closed_by_us {
+                    return Ok(closed_by_us);
                 }
             }
+            // Client closed the connection
+            Ok(None) => return Ok(false),
             Err(pdu_parse_error) => {
                 // Respond with an error
                 let response = handle_pdu_parse_error(&pdu_parse_error);
-                connection.write_pdu(&response).await?;
+                write_response(&connection, response, metrics).await?;
 
                 // Then return the error, so we drop the connection
                 return Err(pdu_parse_error.into());
@@ -419,6 +1137,138 @@ async fn process_loop<L: SmscLogic>(
     }
 }
 
+/// Handle one PDU read from connection.  Returns Some(closed_by_us) if we
+/// should stop reading, or None to carry on.
+async fn handle_any_pdu<L: SmscLogic>(
+    pdu: AnyPdu,
+    connection: &Arc<SmppConnection>,
+    config: &SmscConfig,
+    smsc_logic: &Arc<Mutex<L>>,
+    smsc: &Arc<Mutex<Smsc>>,
+    metrics: &SmscMetrics,
+) -> Result<Option<bool>, ProcessError> {
+    let pdu = match pdu {
+        AnyPdu::Extra(pdu) => {
+            return if handle_extra_pdu(pdu, connection, metrics).await? {
+                Ok(None)
+            } else {
+                Ok(Some(true))
+            };
+        }
+        AnyPdu::Pdu(pdu) => pdu,
+    };
+    if let PduBody::EnquireLinkResp(_) = pdu.body() {
+        // The response to an enquire_link we sent
+        info!("<= {} {}", connection.socket_addr, LoggedPdu::from(&pdu));
+        return Ok(None);
+    }
+    let sequence_number = pdu.sequence_number.value;
+    match handle_pdu(
+        pdu,
+        Arc::clone(connection),
+        config,
+        Arc::clone(smsc_logic),
+        Arc::clone(smsc),
+        metrics,
+    )
+    .await
+    {
+        Ok((response, message_unique_keys)) => {
+            let written = write_response(connection, response, metrics).await;
+            if !message_unique_keys.is_empty() {
+                smsc_logic
+                    .lock()
+                    .await
+                    .submit_sm_responded(Arc::clone(smsc), &message_unique_keys)
+                    .await;
+            }
+            written?;
+            Ok(None)
+        }
+        Err(e) => {
+            // Couldn't handle this PDU type.  Send a nack...
+            write_response(
+                connection,
+                Pdu::new(
+                    PduStatus::ESME_RINVCMDID as u32,
+                    sequence_number,
+                    GenericNackPdu::new_error().into(),
+                )
+                .unwrap(),
+                metrics,
+            )
+            .await?;
+            // ...and Drop the connection.
+            Err(e)
+        }
+    }
+}
+
+async fn write_response(
+    connection: &SmppConnection,
+    pdu: Pdu,
+    metrics: &SmscMetrics,
+) -> io::Result<()> {
+    connection.write_pdu(&pdu).await?;
+    metrics.count_pdu(
+        Direction::Out,
+        connection,
+        pdu.command_id().value,
+        pdu.command_status.value,
+    );
+    Ok(())
+}
+
+/// Handle a PDU that smpp_pdu doesn't understand.  Returns false if we
+/// should close the connection.
+async fn handle_extra_pdu(
+    pdu: ExtraPdu,
+    connection: &SmppConnection,
+    metrics: &SmscMetrics,
+) -> Result<bool, ProcessError> {
+    info!("<= {} {}", connection.socket_addr, LoggedPdu::from(&pdu));
+    match pdu.body {
+        ExtraPduBody::Unbind => {
+            let unbind_resp = ExtraPdu::new(
+                PduStatus::ESME_ROK as u32,
+                pdu.sequence_number,
+                ExtraPduBody::UnbindResp,
+            )?;
+            connection.write_extra_pdu(&unbind_resp).await?;
+            metrics.count_pdu(
+                Direction::Out,
+                connection,
+                unbind_resp.command_id(),
+                unbind_resp.command_status,
+            );
+            Ok(false)
+        }
+        // Later: Issue#5: retry deliver_sm that get an error response
+        ExtraPduBody::DeliverSmResp | ExtraPduBody::GenericNack => Ok(true),
+        // The ESME agreed to the unbind we sent, so the session is over
+        ExtraPduBody::UnbindResp => Ok(false),
+        _ => {
+            let nack = Pdu::new(
+                PduStatus::ESME_RINVCMDID as u32,
+                pdu.sequence_number,
+                GenericNackPdu::new_error().into(),
+            )
+            .unwrap();
+            connection.write_pdu(&nack).await?;
+            metrics.count_pdu(
+                Direction::Out,
+                connection,
+                nack.command_id().value,
+                nack.command_status.value,
+            );
+            Err(ProcessError::new_unexpected_pdu_type(
+                pdu.command_id(),
+                pdu.sequence_number,
+            ))
+        }
+    }
+}
+
 fn handle_pdu_parse_error(error: &PduParseError) -> Pdu {
     let sequence_number = error.sequence_number.unwrap_or(1);
     match error.command_id {
@@ -445,6 +1295,33 @@ fn handle_pdu_parse_error(error: &PduParseError) -> Pdu {
     }
 }
 
+/// Check the account, if accounts are configured, and then ask the logic
+async fn bind<L: SmscLogic>(
+    smsc_logic: &Arc<Mutex<L>>,
+    smsc: &Arc<Mutex<Smsc>>,
+    connection: &SmppConnection,
+    bind_type: BindType,
+    bind_data: &BindData,
+) -> Result<(), BindError> {
+    let (context, account_store) = {
+        let smsc = smsc.lock().await;
+        let context = BindContext {
+            bind_type,
+            peer: connection.socket_addr,
+            sessions: smsc.sessions_for(bind_data.system_id.value.as_str()),
+        };
+        (context, smsc.account_store.clone())
+    };
+    if let Some(account_store) = account_store {
+        account_store.authenticate(bind_data, &context).await?;
+    }
+    smsc_logic
+        .lock()
+        .await
+        .bind_with_context(bind_data, &context)
+        .await
+}
+
 async fn handle_bind_pdu<L: SmscLogic>(
     pdu: Pdu,
     connection: Arc<SmppConnection>,
@@ -454,52 +1331,70 @@ async fn handle_bind_pdu<L: SmscLogic>(
 ) -> Result<Pdu, ProcessError> {
     let mut command_status = PduStatus::ESME_ROK;
 
-    let (bind_data, ret_body) = match pdu.body() {
-        PduBody::BindReceiver(body) => {
-            let mut logic = smsc_logic.lock().await;
-            Ok((
+    let (bind_data, bind_type, ret_body) = match pdu.body() {
+        PduBody::BindReceiver(body) => Ok((
+            body.bind_data(),
+            BindType::Receiver,
+            match bind(
+                &smsc_logic,
+                &smsc,
+                &connection,
+                BindType::Receiver,
                 body.bind_data(),
-                match logic.bind(body.bind_data()).await {
-                    Ok(()) => BindReceiverRespPdu::new(&config.system_id)
-                        .unwrap()
-                        .into(),
-                    Err(e) => {
-                        command_status = e.into();
-                        BindReceiverRespPdu::new_error().into()
-                    }
-                },
-            ))
-        }
-        PduBody::BindTransceiver(body) => {
-            let mut logic = smsc_logic.lock().await;
-            Ok((
+            )
+            .await
+            {
+                Ok(()) => {
+                    BindReceiverRespPdu::new(&config.system_id).unwrap().into()
+                }
+                Err(e) => {
+                    command_status = e.into();
+                    BindReceiverRespPdu::new_error().into()
+                }
+            },
+        )),
+        PduBody::BindTransceiver(body) => Ok((
+            body.bind_data(),
+            BindType::Transceiver,
+            match bind(
+                &smsc_logic,
+                &smsc,
+                &connection,
+                BindType::Transceiver,
                 body.bind_data(),
-                match logic.bind(body.bind_data()).await {
-                    Ok(()) => BindTransceiverRespPdu::new(&config.system_id)
-                        .unwrap()
-                        .into(),
-                    Err(e) => {
-                        command_status = e.into();
-                        BindTransceiverRespPdu::new_error().into()
-                    }
-                },
-            ))
-        }
-        PduBody::BindTransmitter(body) => {
-            let mut logic = smsc_logic.lock().await;
-            Ok((
+            )
+            .await
+            {
+                Ok(()) => BindTransceiverRespPdu::new(&config.system_id)
+                    .unwrap()
+                    .into(),
+                Err(e) => {
+                    command_status = e.into();
+                    BindTransceiverRespPdu::new_error().into()
+                }
+            },
+        )),
+        PduBody::BindTransmitter(body) => Ok((
+            body.bind_data(),
+            BindType::Transmitter,
+            match bind(
+                &smsc_logic,
+                &smsc,
+                &connection,
+                BindType::Transmitter,
                 body.bind_data(),
-                match logic.bind(body.bind_data()).await {
-                    Ok(()) => BindTransmitterRespPdu::new(&config.system_id)
-                        .unwrap()
-                        .into(),
-                    Err(e) => {
-                        command_status = e.into();
-                        BindTransmitterRespPdu::new_error().into()
-                    }
-                },
-            ))
-        }
+            )
+            .await
+            {
+                Ok(()) => BindTransmitterRespPdu::new(&config.system_id)
+                    .unwrap()
+                    .into(),
+                Err(e) => {
+                    command_status = e.into();
+                    BindTransmitterRespPdu::new_error().into()
+                }
+            },
+        )),
         // This function should only be called with a Bind PDU
         _ => Err(ProcessError::new_internal_error(
             "handle_bind_pdu called with non-bind PDU!",
@@ -513,6 +1408,7 @@ async fn handle_bind_pdu<L: SmscLogic>(
             .bind(
                 bind_data.system_id.value.clone(),
                 bind_data.system_type.value.clone(),
+                bind_type,
             )
             .await;
         // TODO: we only need to know about this connection if it can transmit,
@@ -530,59 +1426,181 @@ async fn handle_submit_sm_pdu<L: SmscLogic>(
     connection: Arc<SmppConnection>,
     smsc_logic: Arc<Mutex<L>>,
     smsc: Arc<Mutex<Smsc>>,
-) -> Result<Pdu, ProcessError> {
+    metrics: &SmscMetrics,
+) -> Result<(Pdu, Vec<MessageUniqueKey>), ProcessError> {
     // Later: Issue#15: only do this if bound as a receiver or transceiver -
     // find out using connection.bound_esme_id
 
     if let Some(esme_id) = connection.bound_esme_id() {
+        let checks = smsc.lock().await.submit_sm_checks.clone();
+        if let Err(e) = checks.validate(body) {
+            info!("Rejecting invalid submit_sm from {:?}: {:?}", esme_id, e);
+            return error_response(PduStatus::from(e), sequence_number);
+        }
+
+        if !smsc.lock().await.may_submit(&esme_id).await {
+            info!("Throttling submit_sm from {:?}", esme_id);
+            return error_response(PduStatus::ESME_RTHROTTLED, sequence_number);
+        }
+
+        if let Some(concat_info) = ConcatInfo::from_sm_data(&body.0) {
+            if smsc.lock().await.reassembler.is_some() {
+                return handle_submit_sm_part(
+                    body,
+                    &concat_info,
+                    sequence_number,
+                    esme_id,
+                    smsc_logic,
+                    smsc,
+                )
+                .await;
+            }
+        }
+
+        let route = smsc.lock().await.route(&esme_id, body);
+
         let mut command_status = PduStatus::ESME_ROK;
-        let resp = match smsc_logic
-            .lock()
-            .await
-            .submit_sm(smsc.clone(), body, sequence_number)
-            .await
-        {
+        let mut message_unique_keys = Vec::new();
+        let start = Instant::now();
+        let submit = {
+            let mut smsc_logic = smsc_logic.lock().await;
+            match smsc_logic.submit_sm_unlocked(
+                smsc.clone(),
+                body,
+                &esme_id,
+                route.as_ref(),
+            ) {
+                Some(submit) => submit,
+                None => future::ready(
+                    smsc_logic
+                        .submit_sm_from_esme(
+                            smsc.clone(),
+                            body,
+                            sequence_number,
+                            &esme_id,
+                            route.as_ref(),
+                        )
+                        .await,
+                )
+                .boxed(),
+            }
+        };
+        // The logic is unlocked now, for anything slow it left to do
+        let result = submit.await;
+        metrics.observe_submit_sm(start.elapsed());
+        let resp = match result {
             Ok((resp, message_unique_key)) => {
-                smsc.lock().await.add_message(message_unique_key, esme_id);
+                smsc.lock()
+                    .await
+                    .add_message(message_unique_key.clone(), esme_id);
+                message_unique_keys.push(message_unique_key);
                 resp
             }
             Err(e) => {
                 command_status = e.into();
-                SubmitSmRespPdu::new_error().into()
+                SubmitSmRespPdu::new_error()
             }
         };
-        Pdu::new(command_status as u32, sequence_number, resp.into())
-            .map_err(|e| e.into())
+        let pdu =
+            Pdu::new(command_status as u32, sequence_number, resp.into())?;
+        Ok((pdu, message_unique_keys))
     } else {
         // Later: Issue#15: check this is not a receiver
         Err(ProcessError::new_connection_not_bound_as_transmitter())
     }
 }
 
+/// Acknowledge one part of a concatenated MT with a message_id of our own,
+/// and pass the whole message to the logic once all the parts are here.
+async fn handle_submit_sm_part<L: SmscLogic>(
+    body: &SubmitSmPdu,
+    concat_info: &ConcatInfo,
+    sequence_number: u32,
+    esme_id: EsmeId,
+    smsc_logic: Arc<Mutex<L>>,
+    smsc: Arc<Mutex<Smsc>>,
+) -> Result<(Pdu, Vec<MessageUniqueKey>), ProcessError> {
+    let (message_id, complete_message) = {
+        let mut smsc = smsc.lock().await;
+        let message_id = smsc.message_id_generator.next_message_id();
+        let complete_message =
+            smsc.reassembler.as_mut().and_then(|reassembler| {
+                reassembler.add_part(
+                    &esme_id,
+                    body,
+                    concat_info,
+                    message_id.clone(),
+                )
+            });
+        (message_id, complete_message)
+    };
+
+    let mut command_status = PduStatus::ESME_ROK;
+    let mut message_unique_keys = Vec::new();
+    if let Some(message) = complete_message {
+        match smsc_logic
+            .lock()
+            .await
+            .submit_concatenated_sm(smsc.clone(), &message)
+            .await
+        {
+            Ok(keys) => {
+                let mut smsc = smsc.lock().await;
+                for message_unique_key in &keys {
+                    smsc.add_message(
+                        message_unique_key.clone(),
+                        esme_id.clone(),
+                    );
+                }
+                message_unique_keys = keys;
+            }
+            Err(e) => command_status = e.into(),
+        }
+    }
+
+    let resp = if command_status == PduStatus::ESME_ROK {
+        SubmitSmRespPdu::new(&message_id)?
+    } else {
+        SubmitSmRespPdu::new_error()
+    };
+    let pdu = Pdu::new(command_status as u32, sequence_number, resp.into())?;
+    Ok((pdu, message_unique_keys))
+}
+
+/// A submit_sm_resp with command_status, and no MTs recorded
+fn error_response(
+    command_status: PduStatus,
+    sequence_number: u32,
+) -> Result<(Pdu, Vec<MessageUniqueKey>), ProcessError> {
+    let pdu = Pdu::new(
+        command_status as u32,
+        sequence_number,
+        SubmitSmRespPdu::new_error().into(),
+    )?;
+    Ok((pdu, Vec::new()))
+}
+
+/// The response to pdu, and the keys of the MTs it recorded, so the logic
+/// can be told once the response is written
 async fn handle_pdu<L: SmscLogic>(
     pdu: Pdu,
     connection: Arc<SmppConnection>,
     config: &SmscConfig,
     smsc_logic: Arc<Mutex<L>>,
     smsc: Arc<Mutex<Smsc>>,
-) -> Result<Pdu, ProcessError> {
-    info!("<= {} {:?}", connection.socket_addr, pdu);
+    metrics: &SmscMetrics,
+) -> Result<(Pdu, Vec<MessageUniqueKey>), ProcessError> {
+    info!("<= {} {}", connection.socket_addr, LoggedPdu::from(&pdu));
     let sequence_number = pdu.sequence_number.value;
-    match pdu.body() {
+    let response = match pdu.body() {
         PduBody::BindReceiver(_body) => {
-            handle_bind_pdu(pdu, connection, config, smsc_logic, smsc)
-                .await
-                .map_err(|e| e.into())
+            handle_bind_pdu(pdu, connection, config, smsc_logic, smsc).await
         }
         PduBody::BindTransmitter(_body) => {
-            handle_bind_pdu(pdu, connection, config, smsc_logic, smsc)
-                .await
-                .map_err(|e| e.into())
+            handle_bind_pdu(pdu, connection, config, smsc_logic, smsc).await
         }
         PduBody::BindTransceiver(_body) => {
-            handle_bind_pdu(pdu, connection, config, smsc_logic, smsc)
-                .await
-                .map_err(|e| e.into())
+            handle_bind_pdu(pdu, connection, config, smsc_logic, smsc).await
         }
 
         PduBody::EnquireLink(_body) => Pdu::new(
@@ -593,18 +1611,20 @@ async fn handle_pdu<L: SmscLogic>(
         .map_err(|e| e.into()),
 
         PduBody::SubmitSm(body) => {
-            handle_submit_sm_pdu(
+            return handle_submit_sm_pdu(
                 body,
                 sequence_number,
                 connection,
                 smsc_logic,
                 smsc,
+                metrics,
             )
-            .await
+            .await;
         }
         _ => Err(ProcessError::new_unexpected_pdu_type(
             pdu.command_id().value,
             pdu.sequence_number.value,
         )),
-    }
+    };
+    response.map(|pdu| (pdu, Vec::new()))
 }
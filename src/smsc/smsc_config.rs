@@ -1,4 +1,14 @@
 use clap::Clap;
+use std::ffi::OsString;
+
+use crate::address::NormalizationRules;
+use crate::capture::CaptureConfig;
+use crate::faults::FaultConfig;
+use crate::logging::{LogFormat, Redaction};
+use crate::routing::Route;
+use crate::simulator::SimulatorConfig;
+use crate::smsc::config_file::{parse_with_config_file, ConfigError};
+use crate::smsc::{AccountConfig, ConfigFile, SubmitSmChecks};
 
 /// Short Message Service Center (SMSC) in Rust
 #[derive(Clap, Clone, Debug)]
@@ -15,4 +25,117 @@ pub struct SmscConfig {
     /// system_id used as an identifier of the SMSC
     #[clap(short, long, default_value = "rust_smpp", env = "SYSTEM_ID")]
     pub system_id: String,
+
+    /// Reassemble concatenated MTs before passing them to the SMSC logic,
+    /// waiting up to this many seconds for all the parts to arrive
+    #[clap(long, env = "CONCAT_TIMEOUT_SECS")]
+    pub concat_timeout_secs: Option<u64>,
+
+    /// How long a session we asked to unbind has to send unbind_resp
+    /// before we close its connection
+    #[clap(long, default_value = "10", env = "UNBIND_TIMEOUT_SECS")]
+    pub unbind_timeout_secs: u64,
+
+    #[clap(flatten)]
+    pub submit_sm_checks: SubmitSmChecks,
+
+    #[clap(flatten)]
+    pub normalization_rules: NormalizationRules,
+
+    #[clap(flatten)]
+    pub capture: CaptureConfig,
+
+    #[clap(flatten)]
+    pub simulator: SimulatorConfig,
+
+    #[clap(flatten)]
+    pub faults: FaultConfig,
+
+    /// TOML or YAML file to read settings from.  Flags and environment
+    /// variables override values in the file.
+    #[clap(long, env = "SMSC_CONFIG")]
+    pub config: Option<String>,
+
+    /// TOML or YAML file listing the accounts that may bind, with hashed
+    /// passwords, for SmscLogic implementations that use an AccountStore
+    #[clap(long, env = "ACCOUNTS_FILE")]
+    pub accounts_file: Option<String>,
+
+    /// TOML or YAML file of rules saying how to answer the MTs that match
+    /// them, for testing ESMEs.  Read again on reload.
+    #[clap(long, env = "SMSC_SCRIPT")]
+    pub script: Option<String>,
+
+    /// Log level used if RUST_LOG is not set
+    #[clap(long, default_value = "info", env = "LOG_LEVEL")]
+    pub log_level: String,
+
+    /// How log lines are written: "text", or "json" for one JSON object
+    /// per line
+    #[clap(long, default_value = "text", env = "LOG_FORMAT")]
+    pub log_format: LogFormat,
+
+    /// How much of the addresses, message content and passwords in PDUs
+    /// to log: "none" redacts nothing, "mask" shows the last 4 characters
+    /// of addresses, and "hide" shows none.  "mask" and "hide" both show
+    /// only the length of message content, and never show passwords.
+    #[clap(long, default_value = "none", env = "LOG_REDACTION")]
+    pub log_redaction: Redaction,
+
+    /// Address for the admin HTTP API, e.g. 127.0.0.1:8081.  It must be a
+    /// loopback address.  If not set, there is no admin API.
+    #[clap(long, env = "ADMIN_ADDRESS")]
+    pub admin_address: Option<String>,
+
+    /// Address to serve Prometheus metrics on, at /metrics, e.g.
+    /// 0.0.0.0:9100.  If not set, metrics are only on the admin API.
+    #[clap(long, env = "METRICS_ADDRESS")]
+    pub metrics_address: Option<String>,
+
+    /// Addresses to listen on as well as bind_address
+    #[clap(skip)]
+    pub additional_bind_addresses: Vec<String>,
+
+    /// ESMEs allowed to bind, checked before the SmscLogic is asked.  If
+    /// empty, the SmscLogic decides alone.
+    #[clap(skip)]
+    pub accounts: Vec<AccountConfig>,
+
+    /// If not empty, used to choose a supplier for each MT
+    #[clap(skip)]
+    pub routes: Vec<Route>,
+}
+
+impl SmscConfig {
+    /// Parse the command line and environment, reading the file named by
+    /// --config (if any) for everything they don't set.
+    pub fn load() -> Result<Self, ConfigError> {
+        Self::load_from(std::env::args_os())
+    }
+
+    pub fn load_from<I, T>(args: I) -> Result<Self, ConfigError>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let (mut config, config_file) =
+            parse_with_config_file::<Self, _, _>(args)?;
+        if let Some(config_file) = config_file {
+            config.apply_config_file(config_file);
+        }
+        Ok(config)
+    }
+
+    /// Take the settings that have no flags from config_file
+    pub fn apply_config_file(&mut self, config_file: ConfigFile) {
+        self.additional_bind_addresses = config_file
+            .listeners
+            .into_iter()
+            .skip(1)
+            .map(|l| l.bind_address)
+            .collect();
+        self.accounts = config_file.accounts;
+        self.routes = config_file.routes;
+        self.simulator.rules = config_file.simulator;
+    }
 }
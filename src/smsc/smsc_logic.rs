@@ -1,15 +1,28 @@
 use async_trait::async_trait;
+use futures::future::BoxFuture;
+use serde::Deserialize;
 use smpp_pdu::pdu::data::bind_data::BindData;
 use smpp_pdu::pdu::PduStatus;
 use smpp_pdu::pdu::{SubmitSmPdu, SubmitSmRespPdu};
+use std::convert::TryFrom;
+use std::net::SocketAddr;
+use std::str::FromStr;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
+use crate::async_result::AsyncResult;
+use crate::esme::BindType;
 use crate::message_unique_key::MessageUniqueKey;
-use crate::smsc::Smsc;
+use crate::routing::RoutingDecision;
+use crate::smpp_connection::EsmeId;
+use crate::smsc::{ConcatenatedSm, Smsc, SmscConfig};
 
+#[derive(Debug, PartialEq)]
 pub enum BindError {
     IncorrectPassword,
+    InvalidSystemId,
+    /// Refused for any other reason, e.g. the account is disabled
+    BindFailed,
     InternalError,
 }
 
@@ -17,30 +30,244 @@ impl From<BindError> for PduStatus {
     fn from(e: BindError) -> PduStatus {
         match e {
             BindError::IncorrectPassword => PduStatus::ESME_RINVPASWD,
+            BindError::InvalidSystemId => PduStatus::ESME_RINVSYSID,
+            BindError::BindFailed => PduStatus::ESME_RBINDFAIL,
             BindError::InternalError => PduStatus::ESME_RSYSERR,
         }
     }
 }
 
+/// What the Smsc knows about a bind, besides what is in the bind PDU
+#[derive(Clone, Debug, PartialEq)]
+pub struct BindContext {
+    pub bind_type: BindType,
+    /// Where the ESME is connecting from
+    pub peer: SocketAddr,
+    /// How many sessions with this system_id are already bound
+    pub sessions: usize,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(try_from = "String")]
 pub enum SubmitSmError {
     InternalError,
+    InvalidSourceTon,
+    InvalidSourceNpi,
+    InvalidSourceAddress,
+    InvalidDestinationTon,
+    InvalidDestinationNpi,
+    InvalidDestinationAddress,
+    InvalidMessageLength,
+    InvalidEsmClass,
+    InvalidPriorityFlag,
+    InvalidRegisteredDelivery,
+    MessageQueueFull,
+    SubmitFailed,
+    Throttled,
 }
 
 impl From<SubmitSmError> for PduStatus {
     fn from(e: SubmitSmError) -> PduStatus {
         match e {
             SubmitSmError::InternalError => PduStatus::ESME_RSYSERR,
+            SubmitSmError::InvalidSourceTon => PduStatus::ESME_RINVSRCTON,
+            SubmitSmError::InvalidSourceNpi => PduStatus::ESME_RINVSRCNPI,
+            SubmitSmError::InvalidSourceAddress => PduStatus::ESME_RINVSRCADR,
+            SubmitSmError::InvalidDestinationTon => PduStatus::ESME_RINVDSTTON,
+            SubmitSmError::InvalidDestinationNpi => PduStatus::ESME_RINVDSTNPI,
+            SubmitSmError::InvalidDestinationAddress => {
+                PduStatus::ESME_RINVDSTADR
+            }
+            SubmitSmError::InvalidMessageLength => PduStatus::ESME_RINVMSGLEN,
+            SubmitSmError::InvalidEsmClass => PduStatus::ESME_RINVESMCLASS,
+            SubmitSmError::InvalidPriorityFlag => PduStatus::ESME_RINVPRTFLG,
+            SubmitSmError::InvalidRegisteredDelivery => {
+                PduStatus::ESME_RINVREGDLVFLG
+            }
+            SubmitSmError::MessageQueueFull => PduStatus::ESME_RMSGQFUL,
+            SubmitSmError::SubmitFailed => PduStatus::ESME_RSUBMITFAIL,
+            SubmitSmError::Throttled => PduStatus::ESME_RTHROTTLED,
+        }
+    }
+}
+
+impl FromStr for SubmitSmError {
+    type Err = String;
+
+    /// The name of the command_status, e.g. ESME_RINVDSTADR
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ESME_RSYSERR" => Ok(SubmitSmError::InternalError),
+            "ESME_RINVSRCTON" => Ok(SubmitSmError::InvalidSourceTon),
+            "ESME_RINVSRCNPI" => Ok(SubmitSmError::InvalidSourceNpi),
+            "ESME_RINVSRCADR" => Ok(SubmitSmError::InvalidSourceAddress),
+            "ESME_RINVDSTTON" => Ok(SubmitSmError::InvalidDestinationTon),
+            "ESME_RINVDSTNPI" => Ok(SubmitSmError::InvalidDestinationNpi),
+            "ESME_RINVDSTADR" => Ok(SubmitSmError::InvalidDestinationAddress),
+            "ESME_RINVMSGLEN" => Ok(SubmitSmError::InvalidMessageLength),
+            "ESME_RINVESMCLASS" => Ok(SubmitSmError::InvalidEsmClass),
+            "ESME_RINVPRTFLG" => Ok(SubmitSmError::InvalidPriorityFlag),
+            "ESME_RINVREGDLVFLG" => {
+                Ok(SubmitSmError::InvalidRegisteredDelivery)
+            }
+            "ESME_RMSGQFUL" => Ok(SubmitSmError::MessageQueueFull),
+            "ESME_RSUBMITFAIL" => Ok(SubmitSmError::SubmitFailed),
+            "ESME_RTHROTTLED" => Ok(SubmitSmError::Throttled),
+            _ => Err(format!(
+                "Unknown or unsupported submit_sm command_status '{}'",
+                s
+            )),
         }
     }
 }
 
+impl TryFrom<String> for SubmitSmError {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// The rest of a submit_sm, for the Smsc to await with the logic unlocked
+pub type SubmitSmFuture = BoxFuture<
+    'static,
+    Result<(SubmitSmRespPdu, MessageUniqueKey), SubmitSmError>,
+>;
+
 #[async_trait]
-pub trait SmscLogic {
+pub trait SmscLogic: Send {
     async fn bind(&mut self, bind_data: &BindData) -> Result<(), BindError>;
+
+    /// Called for each bind instead of bind, with more details about it,
+    /// e.g. to check them using an AccountStore.  By default, ignores the
+    /// context and calls bind.
+    async fn bind_with_context(
+        &mut self,
+        bind_data: &BindData,
+        _context: &BindContext,
+    ) -> Result<(), BindError> {
+        self.bind(bind_data).await
+    }
     async fn submit_sm(
         &mut self,
         smsc: Arc<Mutex<Smsc>>,
         pdu: &SubmitSmPdu,
         sequence_number: u32,
     ) -> Result<(SubmitSmRespPdu, MessageUniqueKey), SubmitSmError>;
+
+    /// Called for each MT instead of submit_sm, with the supplier chosen by
+    /// the Smsc's routing table, or None if there is no table or no route
+    /// matched.  By default, ignores the route and calls submit_sm.
+    async fn submit_sm_with_route(
+        &mut self,
+        smsc: Arc<Mutex<Smsc>>,
+        pdu: &SubmitSmPdu,
+        sequence_number: u32,
+        _route: Option<&RoutingDecision>,
+    ) -> Result<(SubmitSmRespPdu, MessageUniqueKey), SubmitSmError> {
+        self.submit_sm(smsc, pdu, sequence_number).await
+    }
+
+    /// Called for each MT instead of submit_sm_with_route, with the ESME
+    /// that sent it, e.g. to treat accounts differently.  By default,
+    /// ignores the ESME and calls submit_sm_with_route.
+    async fn submit_sm_from_esme(
+        &mut self,
+        smsc: Arc<Mutex<Smsc>>,
+        pdu: &SubmitSmPdu,
+        sequence_number: u32,
+        _esme_id: &EsmeId,
+        route: Option<&RoutingDecision>,
+    ) -> Result<(SubmitSmRespPdu, MessageUniqueKey), SubmitSmError> {
+        self.submit_sm_with_route(smsc, pdu, sequence_number, route)
+            .await
+    }
+
+    /// Called for each MT before submit_sm_from_esme, with the logic
+    /// locked.  A logic that waits on something slow, such as an upstream
+    /// SMSC, can return a future that does the waiting: the Smsc awaits it
+    /// with the logic unlocked, so MTs from other sessions are not held
+    /// up, and does not call submit_sm_from_esme.  By default, returns
+    /// None.
+    fn submit_sm_unlocked(
+        &mut self,
+        _smsc: Arc<Mutex<Smsc>>,
+        _pdu: &SubmitSmPdu,
+        _esme_id: &EsmeId,
+        _route: Option<&RoutingDecision>,
+    ) -> Option<SubmitSmFuture> {
+        None
+    }
+
+    /// Called once the Smsc has recorded the MTs with these keys and tried
+    /// to write the submit_sm_resp for them, so a DR or MO sent from now on
+    /// can be routed, and follows the response.  By default, does nothing.
+    async fn submit_sm_responded(
+        &mut self,
+        _smsc: Arc<Mutex<Smsc>>,
+        _message_unique_keys: &[MessageUniqueKey],
+    ) {
+    }
+
+    /// Called by Smsc::reload, e.g. on SIGHUP, so the logic can pick up
+    /// changes such as a new accounts file.  If this fails, the rest of
+    /// the new configuration is not applied.
+    async fn reload(&mut self, _config: &SmscConfig) -> AsyncResult<()> {
+        Ok(())
+    }
+
+    /// Called by Smsc::reload for each bound session.  Return false to
+    /// unbind it, e.g. because its account has been disabled.
+    async fn may_stay_bound(&mut self, _esme_id: &EsmeId) -> bool {
+        true
+    }
+
+    /// Called instead of submit_sm when concat_timeout_secs is set in the
+    /// SmscConfig and all the parts of a concatenated MT have arrived.
+    /// Each part has already been acknowledged with its own message_id,
+    /// which can be found in message.parts.  Return the keys that DRs for
+    /// this message will arrive with, so we can route them to the ESME.
+    /// By default, passes each part to submit_sm_from_esme with its
+    /// concatenation UDH or SAR TLVs, and maps the message ID it returns
+    /// to the one the ESME was given.
+    async fn submit_concatenated_sm(
+        &mut self,
+        smsc: Arc<Mutex<Smsc>>,
+        message: &ConcatenatedSm,
+    ) -> Result<Vec<MessageUniqueKey>, SubmitSmError> {
+        let mut message_unique_keys = Vec::new();
+        for part in &message.parts {
+            let pdu = message
+                .part_pdu(part)
+                .map_err(|_| SubmitSmError::InternalError)?;
+            let route = smsc.lock().await.route(&message.esme_id, &pdu);
+            // The part was answered already, so there is no sequence_number
+            let (_, message_unique_key) = self
+                .submit_sm_from_esme(
+                    smsc.clone(),
+                    &pdu,
+                    0,
+                    &message.esme_id,
+                    route.as_ref(),
+                )
+                .await?;
+            smsc.lock().await.map_message_id(
+                message_unique_key.clone(),
+                part.message_id.clone(),
+            );
+            message_unique_keys.push(message_unique_key);
+        }
+        Ok(message_unique_keys)
+    }
+
+    /// Called when concat_timeout_secs passes before all the parts of a
+    /// concatenated MT arrive.  message.parts holds the parts that did,
+    /// which were acknowledged already.  By default, does nothing.
+    async fn concatenated_sm_expired(
+        &mut self,
+        _smsc: Arc<Mutex<Smsc>>,
+        _message: &ConcatenatedSm,
+    ) {
+    }
 }
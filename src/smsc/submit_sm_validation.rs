@@ -0,0 +1,242 @@
+//! Checks on the fields of an incoming submit_sm that the SMSC can make
+//! before passing it to the SmscLogic.  Each check is switched on
+//! separately, so that an SMSC can be as strict or as lenient as the
+//! ESMEs connecting to it need.
+//! See https://smpp.org/SMPP_v3_4_Issue1_2.pdf section 5.2.
+
+use clap::Clap;
+use smpp_pdu::pdu::data::sm_data::SmData;
+use smpp_pdu::pdu::tlvs::KnownTlvTag;
+use smpp_pdu::pdu::SubmitSmPdu;
+
+use crate::concatenation::ESM_CLASS_UDHI;
+use crate::segmentation::max_user_data_length;
+use crate::smsc::SubmitSmError;
+
+/// The largest message_payload allowed by the spec
+const MAX_PAYLOAD_OCTETS: usize = 64 * 1024 - 1;
+
+const TON_INTERNATIONAL: u8 = 1;
+const TON_ALPHANUMERIC: u8 = 5;
+const MAX_TON: u8 = 6;
+
+const NPI_ISDN: u8 = 1;
+const VALID_NPIS: [u8; 10] = [0, 1, 3, 4, 6, 8, 9, 10, 14, 18];
+
+/// E.164 numbers have at most 15 digits
+const MAX_E164_DIGITS: usize = 15;
+
+/// GSM 03.38 alphanumeric addresses have at most 11 characters
+const MAX_ALPHANUMERIC_LENGTH: usize = 11;
+
+/// esm_class bits 2-5 give the message type.  An ESME may only submit
+/// a normal message or an acknowledgement.
+const ESM_CLASS_MESSAGE_TYPE_MASK: u8 = 0b0011_1100;
+const ESM_CLASS_VALID_SUBMIT_TYPES: [u8; 3] = [0x00, 0x08, 0x10];
+
+/// registered_delivery bits 0-1 request an SMSC delivery receipt, and 3
+/// (both) is reserved.  Bits 5-7 are reserved.
+const REGISTERED_DELIVERY_RECEIPT_MASK: u8 = 0b0000_0011;
+const REGISTERED_DELIVERY_RESERVED_MASK: u8 = 0b1110_0000;
+
+const MAX_PRIORITY_FLAG: u8 = 3;
+
+/// Which checks to make on every submit_sm before it reaches the
+/// SmscLogic.  All checks are off by default.
+#[derive(Clap, Clone, Debug, Default)]
+pub struct SubmitSmChecks {
+    /// Reject submit_sm with an unknown TON or NPI, or an address that
+    /// does not match its TON/NPI
+    #[clap(long, env = "CHECK_ADDRESSES")]
+    pub check_addresses: bool,
+
+    /// Reject submit_sm whose short_message does not fit in one SMS with
+    /// its data_coding
+    #[clap(long, env = "CHECK_MESSAGE_LENGTH")]
+    pub check_message_length: bool,
+
+    /// Reject submit_sm with both a short_message and a message_payload
+    #[clap(long, env = "CHECK_MESSAGE_PAYLOAD")]
+    pub check_message_payload: bool,
+
+    /// Reject submit_sm with an esm_class an ESME may not send, or with
+    /// UDHI set but no valid UDH
+    #[clap(long, env = "CHECK_ESM_CLASS")]
+    pub check_esm_class: bool,
+
+    /// Reject submit_sm with a priority_flag above 3
+    #[clap(long, env = "CHECK_PRIORITY_FLAG")]
+    pub check_priority_flag: bool,
+
+    /// Reject submit_sm with reserved registered_delivery bits set
+    #[clap(long, env = "CHECK_REGISTERED_DELIVERY")]
+    pub check_registered_delivery: bool,
+}
+
+impl SubmitSmChecks {
+    /// Every check switched on
+    pub fn all() -> Self {
+        Self {
+            check_addresses: true,
+            check_message_length: true,
+            check_message_payload: true,
+            check_esm_class: true,
+            check_priority_flag: true,
+            check_registered_delivery: true,
+        }
+    }
+
+    /// Make the checks that are switched on, returning the first problem
+    /// found.
+    pub fn validate(&self, pdu: &SubmitSmPdu) -> Result<(), SubmitSmError> {
+        let sm_data = &pdu.0;
+        if self.check_addresses {
+            check_addresses(sm_data)?;
+        }
+        if self.check_esm_class {
+            check_esm_class(sm_data)?;
+        }
+        if self.check_message_payload {
+            check_message_payload(sm_data)?;
+        }
+        if self.check_message_length {
+            check_message_length(sm_data)?;
+        }
+        if self.check_priority_flag
+            && sm_data.priority_flag.value > MAX_PRIORITY_FLAG
+        {
+            return Err(SubmitSmError::InvalidPriorityFlag);
+        }
+        if self.check_registered_delivery {
+            check_registered_delivery(sm_data.registered_delivery.value)?;
+        }
+        Ok(())
+    }
+}
+
+fn check_addresses(sm_data: &SmData) -> Result<(), SubmitSmError> {
+    let source_ton = sm_data.source_addr_ton.value;
+    let source_npi = sm_data.source_addr_npi.value;
+    let source_addr = sm_data.source_addr.value.as_str();
+    if source_ton > MAX_TON {
+        return Err(SubmitSmError::InvalidSourceTon);
+    }
+    if !VALID_NPIS.contains(&source_npi) {
+        return Err(SubmitSmError::InvalidSourceNpi);
+    }
+    // An empty source_addr means the SMSC should fill in a default
+    if !source_addr.is_empty()
+        && !address_is_valid(source_ton, source_npi, source_addr)
+    {
+        return Err(SubmitSmError::InvalidSourceAddress);
+    }
+
+    let dest_ton = sm_data.dest_addr_ton.value;
+    let dest_npi = sm_data.dest_addr_npi.value;
+    let destination_addr = sm_data.destination_addr.value.as_str();
+    if dest_ton > MAX_TON {
+        return Err(SubmitSmError::InvalidDestinationTon);
+    }
+    if !VALID_NPIS.contains(&dest_npi) {
+        return Err(SubmitSmError::InvalidDestinationNpi);
+    }
+    // Nobody can reply to an alphanumeric address
+    if destination_addr.is_empty()
+        || dest_ton == TON_ALPHANUMERIC
+        || !address_is_valid(dest_ton, dest_npi, destination_addr)
+    {
+        return Err(SubmitSmError::InvalidDestinationAddress);
+    }
+    Ok(())
+}
+
+fn address_is_valid(ton: u8, npi: u8, addr: &str) -> bool {
+    if ton == TON_ALPHANUMERIC {
+        return addr.chars().count() <= MAX_ALPHANUMERIC_LENGTH;
+    }
+
+    let digits = if ton == TON_INTERNATIONAL {
+        addr.strip_prefix('+').unwrap_or(addr)
+    } else {
+        addr
+    };
+    let all_digits =
+        !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit());
+    if ton == TON_INTERNATIONAL || npi == NPI_ISDN {
+        all_digits && digits.len() <= MAX_E164_DIGITS
+    } else {
+        true
+    }
+}
+
+fn check_esm_class(sm_data: &SmData) -> Result<(), SubmitSmError> {
+    let esm_class = sm_data.esm_class.value;
+    if !ESM_CLASS_VALID_SUBMIT_TYPES
+        .contains(&(esm_class & ESM_CLASS_MESSAGE_TYPE_MASK))
+    {
+        return Err(SubmitSmError::InvalidEsmClass);
+    }
+    if esm_class & ESM_CLASS_UDHI != 0
+        && udh_length(&sm_data.short_message.value).is_none()
+    {
+        return Err(SubmitSmError::InvalidEsmClass);
+    }
+    Ok(())
+}
+
+/// The length of the UDH (including the UDHL byte) at the start of
+/// short_message, or None if it is not a well-formed UDH.
+fn udh_length(short_message: &[u8]) -> Option<usize> {
+    let udhl = usize::from(*short_message.first()?);
+    let udh = short_message.get(1..udhl + 1)?;
+    let mut i = 0;
+    while i < udh.len() {
+        let iedl = usize::from(*udh.get(i + 1)?);
+        i += 2 + iedl;
+    }
+    if i == udh.len() && udhl > 0 {
+        Some(udhl + 1)
+    } else {
+        None
+    }
+}
+
+fn check_message_payload(sm_data: &SmData) -> Result<(), SubmitSmError> {
+    if let Some(payload) = sm_data.tlvs.get(KnownTlvTag::message_payload) {
+        if !sm_data.short_message.value.is_empty()
+            || payload.value.len() > MAX_PAYLOAD_OCTETS
+        {
+            return Err(SubmitSmError::InvalidMessageLength);
+        }
+    }
+    Ok(())
+}
+
+fn check_message_length(sm_data: &SmData) -> Result<(), SubmitSmError> {
+    let short_message = &sm_data.short_message.value;
+    let data_coding = sm_data.data_coding.value;
+    let header_length = if sm_data.esm_class.value & ESM_CLASS_UDHI != 0 {
+        udh_length(short_message).unwrap_or(0)
+    } else {
+        0
+    };
+    let user_data_length = short_message.len() - header_length;
+
+    if user_data_length > max_user_data_length(data_coding, header_length)
+        || (data_coding == 0x08 && !user_data_length.is_multiple_of(2))
+    {
+        return Err(SubmitSmError::InvalidMessageLength);
+    }
+    Ok(())
+}
+
+fn check_registered_delivery(
+    registered_delivery: u8,
+) -> Result<(), SubmitSmError> {
+    if registered_delivery & REGISTERED_DELIVERY_RECEIPT_MASK == 0b11
+        || registered_delivery & REGISTERED_DELIVERY_RESERVED_MASK != 0
+    {
+        return Err(SubmitSmError::InvalidRegisteredDelivery);
+    }
+    Ok(())
+}
@@ -0,0 +1,265 @@
+use smpp::esme::BindType;
+use smpp::examples::smsc_drs_after_1_sec::DrsAfter1Sec;
+use smpp::smsc::{
+    Account, AccountStore, BindContext, BindData, BindError, ConfigError,
+};
+use std::net::SocketAddr;
+use std::time::Instant;
+
+mod test_utils;
+
+use test_utils::{TempFile, TestClient, TestServer};
+
+#[tokio::test]
+async fn argon2_hashes_from_hash_password_are_accepted() {
+    let store = store(vec![Account {
+        password_hash: AccountStore::hash_password("secret"),
+        ..account("acme")
+    }]);
+
+    assert_eq!(
+        store
+            .authenticate(&bind_data("acme", "secret"), &ctx())
+            .await,
+        Ok(())
+    );
+    assert_eq!(
+        store
+            .authenticate(&bind_data("acme", "wrong"), &ctx())
+            .await,
+        Err(BindError::IncorrectPassword)
+    );
+}
+
+#[tokio::test]
+async fn bcrypt_hashes_are_accepted() {
+    let store = store(vec![account("acme")]);
+
+    assert_eq!(
+        store
+            .authenticate(&bind_data("acme", "secret"), &ctx())
+            .await,
+        Ok(())
+    );
+    assert_eq!(
+        store
+            .authenticate(&bind_data("acme", "wrong"), &ctx())
+            .await,
+        Err(BindError::IncorrectPassword)
+    );
+}
+
+#[tokio::test]
+async fn unknown_system_ids_are_rejected() {
+    let store = store(vec![account("acme")]);
+
+    assert_eq!(
+        store
+            .authenticate(&bind_data("other", "secret"), &ctx())
+            .await,
+        Err(BindError::InvalidSystemId)
+    );
+}
+
+#[tokio::test]
+async fn unknown_system_ids_take_about_as_long_as_wrong_passwords() {
+    let store = store(vec![Account {
+        password_hash: AccountStore::hash_password("secret"),
+        ..account("acme")
+    }]);
+
+    let started = Instant::now();
+    let result = store
+        .authenticate(&bind_data("acme", "wrong"), &ctx())
+        .await;
+    assert_eq!(result, Err(BindError::IncorrectPassword));
+    let wrong_password = started.elapsed();
+
+    let started = Instant::now();
+    let result = store
+        .authenticate(&bind_data("other", "wrong"), &ctx())
+        .await;
+    assert_eq!(result, Err(BindError::InvalidSystemId));
+    let unknown_system_id = started.elapsed();
+
+    assert!(
+        unknown_system_id > wrong_password / 4,
+        "{:?} for an unknown system_id but {:?} for a wrong password",
+        unknown_system_id,
+        wrong_password
+    );
+}
+
+#[tokio::test]
+async fn disabled_accounts_may_not_bind() {
+    let store = store(vec![Account {
+        enabled: false,
+        ..account("acme")
+    }]);
+
+    assert_eq!(
+        store
+            .authenticate(&bind_data("acme", "secret"), &ctx())
+            .await,
+        Err(BindError::BindFailed)
+    );
+}
+
+#[tokio::test]
+async fn only_allowed_bind_types_may_bind() {
+    let store = store(vec![Account {
+        bind_types: vec![BindType::Transmitter],
+        ..account("acme")
+    }]);
+
+    let receiver = BindContext {
+        bind_type: BindType::Receiver,
+        ..ctx()
+    };
+    assert_eq!(
+        store
+            .authenticate(&bind_data("acme", "secret"), &receiver)
+            .await,
+        Err(BindError::BindFailed)
+    );
+}
+
+#[tokio::test]
+async fn only_allowed_addresses_may_bind() {
+    let store = store(vec![Account {
+        allowed_ips: vec![String::from("10.0.0.0/8"), String::from("::1")],
+        ..account("acme")
+    }]);
+
+    let from = |peer: &str| BindContext {
+        peer: peer.parse().unwrap(),
+        ..ctx()
+    };
+    let bind_data = bind_data("acme", "secret");
+    assert_eq!(
+        store.authenticate(&bind_data, &from("10.1.2.3:5000")).await,
+        Ok(())
+    );
+    assert_eq!(
+        store.authenticate(&bind_data, &from("[::1]:5000")).await,
+        Ok(())
+    );
+    assert_eq!(
+        store
+            .authenticate(&bind_data, &from("192.168.0.1:5000"))
+            .await,
+        Err(BindError::BindFailed)
+    );
+}
+
+#[tokio::test]
+async fn accounts_may_not_exceed_max_sessions() {
+    let store = store(vec![Account {
+        max_sessions: Some(2),
+        ..account("acme")
+    }]);
+
+    let with_sessions = |sessions| BindContext { sessions, ..ctx() };
+    let bind_data = bind_data("acme", "secret");
+    assert_eq!(
+        store.authenticate(&bind_data, &with_sessions(1)).await,
+        Ok(())
+    );
+    assert_eq!(
+        store.authenticate(&bind_data, &with_sessions(2)).await,
+        Err(BindError::BindFailed)
+    );
+}
+
+#[tokio::test]
+async fn accounts_can_be_loaded_from_a_file() {
+    let file = TempFile::new(
+        "accounts.yaml",
+        &format!(
+            "accounts:\n  - system_id: acme\n    password_hash: '{}'\n    \
+            bind_types: [transceiver]\n    allowed_ips: ['127.0.0.1']\n",
+            bcrypt_hash("secret")
+        ),
+    );
+
+    let store = AccountStore::load(file.path()).unwrap();
+
+    assert_eq!(
+        store
+            .authenticate(&bind_data("acme", "secret"), &ctx())
+            .await,
+        Ok(())
+    );
+}
+
+#[test]
+fn files_with_invalid_hashes_are_rejected() {
+    let file = TempFile::new(
+        "bad_accounts.toml",
+        "[[accounts]]\nsystem_id = \"acme\"\npassword_hash = \"secret\"\n",
+    );
+
+    match AccountStore::load(file.path()) {
+        Err(ConfigError::Invalid(_, message)) => assert_eq!(
+            message,
+            "password_hash for account 'acme' is not an argon2 or bcrypt hash"
+        ),
+        other => panic!("Expected Invalid but got {:?}", other.err()),
+    }
+}
+
+#[tokio::test]
+async fn the_smsc_tells_the_logic_how_many_sessions_are_bound() {
+    let logic = DrsAfter1Sec::with_accounts(store(vec![Account {
+        password_hash: bcrypt_hash("password"),
+        max_sessions: Some(1),
+        ..account("esmeid")
+    }]));
+    let server = TestServer::start_with_logic(logic).await.unwrap();
+
+    let mut client1 = TestClient::connect_to(&server).await.unwrap();
+    client1.bind_transceiver().await;
+
+    // max_sessions is 1, so a second session is refused
+    let mut client2 = TestClient::connect_to(&server).await.unwrap();
+    client2
+        .send_and_expect_response(
+            b"\x00\x00\x00\x29\x00\x00\x00\x09\x00\x00\x00\x00\x00\x00\x00\x08\
+            esmeid\0password\0type\0\x34\x00\x00\0",
+            // command_status=ESME_RBINDFAIL
+            b"\x00\x00\x00\x10\x80\x00\x00\x09\x00\x00\x00\x0d\x00\x00\x00\x08",
+        )
+        .await;
+}
+
+fn store(accounts: Vec<Account>) -> AccountStore {
+    AccountStore::new(accounts).unwrap()
+}
+
+fn account(system_id: &str) -> Account {
+    Account {
+        system_id: String::from(system_id),
+        password_hash: bcrypt_hash("secret"),
+        bind_types: Vec::new(),
+        allowed_ips: Vec::new(),
+        max_sessions: None,
+        enabled: true,
+    }
+}
+
+/// A quick-to-check bcrypt hash, using the lowest cost
+fn bcrypt_hash(password: &str) -> String {
+    bcrypt::hash(password, 4).unwrap()
+}
+
+fn bind_data(system_id: &str, password: &str) -> BindData {
+    BindData::new(system_id, password, "", 0x34, 0, 0, "").unwrap()
+}
+
+fn ctx() -> BindContext {
+    BindContext {
+        bind_type: BindType::Transceiver,
+        peer: "127.0.0.1:5000".parse::<SocketAddr>().unwrap(),
+        sessions: 0,
+    }
+}
@@ -0,0 +1,88 @@
+use smpp::address::{Address, NormalizationRules};
+use smpp::message_unique_key::MessageUniqueKey;
+
+#[test]
+fn international_numbers_are_written_without_plus() {
+    let rules = NormalizationRules::default();
+    let expected = Address::new(1, 1, "447700900123");
+
+    assert_eq!(
+        Address::new(1, 1, "+447700900123").normalize(&rules),
+        expected
+    );
+    assert_eq!(
+        Address::new(1, 1, "447700900123").normalize(&rules),
+        expected
+    );
+    assert_eq!(
+        Address::new(1, 0, "00447700900123").normalize(&rules),
+        expected
+    );
+    assert_eq!(
+        Address::new(0, 1, "+44 7700 900123").normalize(&rules),
+        expected
+    );
+    assert_eq!(
+        Address::new(0, 1, "447700900123").normalize(&rules),
+        expected
+    );
+}
+
+#[test]
+fn national_numbers_get_the_country_code() {
+    let rules = uk_rules();
+    let expected = Address::new(1, 1, "447700900123");
+
+    assert_eq!(
+        Address::new(0, 1, "07700900123").normalize(&rules),
+        expected
+    );
+    assert_eq!(
+        Address::new(2, 1, "07700900123").normalize(&rules),
+        expected
+    );
+    assert_eq!(Address::new(2, 1, "7700900123").normalize(&rules), expected);
+}
+
+#[test]
+fn national_numbers_are_unchanged_without_a_country_code() {
+    let rules = NormalizationRules::default();
+    let address = Address::new(0, 1, "07700900123");
+
+    assert_eq!(address.normalize(&rules), address);
+}
+
+#[test]
+fn non_numeric_addresses_are_unchanged() {
+    let rules = uk_rules();
+    let alphanumeric = Address::new(5, 0, "MyCompany");
+    let unknown_digits = Address::new(0, 0, "Help");
+
+    assert_eq!(alphanumeric.normalize(&rules), alphanumeric);
+    assert_eq!(unknown_digits.normalize(&rules), unknown_digits);
+}
+
+#[test]
+fn keys_with_differently_formatted_addresses_match_once_normalized() {
+    let rules = uk_rules();
+    let from_mt = MessageUniqueKey::with_address(
+        String::from("supplier"),
+        String::from("id1"),
+        Address::new(1, 1, "+447700900123"),
+    );
+    let from_dr = MessageUniqueKey::with_address(
+        String::from("supplier"),
+        String::from("id1"),
+        Address::new(0, 1, "07700900123"),
+    );
+
+    assert_ne!(from_mt, from_dr);
+    assert_eq!(from_mt.normalize(&rules), from_dr.normalize(&rules));
+}
+
+fn uk_rules() -> NormalizationRules {
+    NormalizationRules {
+        country_code: Some(String::from("44")),
+        ..NormalizationRules::default()
+    }
+}
@@ -0,0 +1,255 @@
+use async_trait::async_trait;
+use serde_json::json;
+use smpp::message_unique_key::MessageUniqueKey;
+use smpp::sm_fields::SmFields;
+use smpp::smsc::{
+    start_admin, BindData, BindError, Smsc, SmscLogic, SubmitSmError,
+};
+use smpp_pdu::pdu::tlvs::Tlvs;
+use smpp_pdu::pdu::{Pdu, PduBody, SubmitSmPdu, SubmitSmRespPdu};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+mod test_utils;
+
+use test_utils::{http, TestClient, TestServer};
+
+#[tokio::test]
+async fn bound_sessions_are_listed() {
+    let (server, admin) = start().await;
+    let mut client = TestClient::connect_to(&server).await.unwrap();
+    client.bind_transceiver().await;
+
+    let (status, sessions) = http(admin, "GET", "/sessions", None).await;
+
+    assert_eq!(status, 200);
+    let session = &sessions[0];
+    assert_eq!(session["system_id"], "esmeid");
+    assert_eq!(session["system_type"], "type");
+    assert_eq!(session["bind_type"], "transceiver");
+    assert_eq!(session["pdus_received"], 1);
+    assert_eq!(session["pdus_sent"], 1);
+}
+
+#[tokio::test]
+async fn killed_sessions_are_closed_without_an_unbind() {
+    let (server, admin) = start().await;
+    let mut client = TestClient::connect_to(&server).await.unwrap();
+    client.bind_transceiver().await;
+
+    let (status, _) = http(admin, "POST", "/sessions/esmeid/kill", None).await;
+
+    assert_eq!(status, 200);
+    assert!(client.read_n_maybe(1).await.is_err());
+    assert_eq!(http(admin, "GET", "/sessions", None).await.1, json!([]));
+}
+
+#[tokio::test]
+async fn unbound_sessions_are_sent_unbind() {
+    let (server, admin) = start().await;
+    let mut client = TestClient::connect_to(&server).await.unwrap();
+    client.bind_transceiver().await;
+
+    let (status, _) = http(
+        admin,
+        "POST",
+        "/sessions/esmeid/unbind?system_type=type",
+        None,
+    )
+    .await;
+
+    assert_eq!(status, 200);
+    let unbind = client.read_n(16).await;
+    assert_eq!(&unbind[4..8], b"\x00\x00\x00\x06");
+}
+
+#[tokio::test]
+async fn sessions_that_do_not_answer_unbind_are_closed_after_a_timeout() {
+    let server = TestServer::start_with_logic_and_smsc_config(Logic, |c| {
+        c.unbind_timeout_secs = 1
+    })
+    .await
+    .unwrap();
+    let admin =
+        start_admin(server.smsc.clone(), "127.0.0.1:0", || unreachable!())
+            .await
+            .unwrap();
+    let mut client = TestClient::connect_to(&server).await.unwrap();
+    client.bind_transceiver().await;
+
+    http(admin, "POST", "/sessions/esmeid/unbind", None).await;
+    let unbind = client.read_n(16).await;
+    assert_eq!(&unbind[4..8], b"\x00\x00\x00\x06");
+
+    // The session stays until its connection closes
+    let (_, sessions) = http(admin, "GET", "/sessions", None).await;
+    assert_eq!(sessions.as_array().unwrap().len(), 1);
+
+    assert!(client.read_n_maybe(1).await.is_err());
+    assert_eq!(http(admin, "GET", "/sessions", None).await.1, json!([]));
+}
+
+#[tokio::test]
+async fn acting_on_an_unknown_session_is_not_found() {
+    let (_server, admin) = start().await;
+
+    let (status, body) =
+        http(admin, "POST", "/sessions/nobody/unbind", None).await;
+
+    assert_eq!(status, 404);
+    assert_eq!(body["error"], "No session bound as 'nobody'");
+}
+
+#[tokio::test]
+async fn messages_can_be_looked_up_and_given_an_injected_dr() {
+    let (server, admin) = start().await;
+    let mut client = TestClient::connect_to(&server).await.unwrap();
+    client.bind_transceiver().await;
+    submit_sm(&mut client).await;
+
+    let lookup = "/messages?namespace_id=supplier&message_id=mymsgid\
+        &destination_addr=%2B447777222222";
+    let (status, message) = http(admin, "GET", lookup, None).await;
+    assert_eq!(status, 200);
+    assert_eq!(message["system_id"], "esmeid");
+    assert_eq!(message["bound"], true);
+    assert_eq!(message["state"], "awaiting_dr");
+
+    let (status, _) = http(
+        admin,
+        "POST",
+        "/deliver_sm/dr",
+        Some(json!({
+            "namespace_id": "supplier",
+            "message_id": "mymsgid",
+            "destination_addr": "447777222222",
+        })),
+    )
+    .await;
+    assert_eq!(status, 200);
+
+    let dr = client.read_pdu().await;
+    match dr.body() {
+        PduBody::DeliverSm(body) => {
+            assert!(String::from_utf8_lossy(
+                &SmFields::from_sm_data(&body.0).short_message
+            )
+            .starts_with("id:mymsgid "))
+        }
+        _ => panic!("Expected deliver_sm but got {:?}", dr),
+    }
+    let (_, message) = http(admin, "GET", lookup, None).await;
+    assert_eq!(message["state"], "dr_forwarded");
+    assert_eq!(message["drs_forwarded"], 1);
+}
+
+#[tokio::test]
+async fn injected_mos_reach_the_bound_esme() {
+    let (server, admin) = start().await;
+    let mut client = TestClient::connect_to(&server).await.unwrap();
+    client.bind_transceiver().await;
+
+    let (status, _) = http(
+        admin,
+        "POST",
+        "/deliver_sm/mo",
+        Some(json!({
+            "system_id": "esmeid",
+            "source_addr": "447777222222",
+            "destination_addr": "MyCompany",
+            "short_message": "hello",
+        })),
+    )
+    .await;
+
+    assert_eq!(status, 200);
+    let mo = client.read_pdu().await;
+    match mo.body() {
+        PduBody::DeliverSm(body) => {
+            assert_eq!(SmFields::from_sm_data(&body.0).short_message, b"hello")
+        }
+        _ => panic!("Expected deliver_sm but got {:?}", mo),
+    }
+}
+
+#[tokio::test]
+async fn the_admin_api_refuses_non_loopback_addresses() {
+    let server = TestServer::start_with_logic(Logic).await.unwrap();
+
+    let result = start_admin(server.smsc, "0.0.0.0:0", || {
+        panic!("Should not load config")
+    })
+    .await;
+
+    assert!(result.is_err());
+}
+
+struct Logic;
+
+#[async_trait]
+impl SmscLogic for Logic {
+    async fn bind(&mut self, _bind_data: &BindData) -> Result<(), BindError> {
+        Ok(())
+    }
+
+    async fn submit_sm(
+        &mut self,
+        _smsc: Arc<Mutex<Smsc>>,
+        pdu: &SubmitSmPdu,
+        _sequence_number: u32,
+    ) -> Result<(SubmitSmRespPdu, MessageUniqueKey), SubmitSmError> {
+        Ok((
+            SubmitSmRespPdu::new("mymsgid").unwrap(),
+            MessageUniqueKey::from_submit_sm(
+                String::from("supplier"),
+                String::from("mymsgid"),
+                pdu,
+            ),
+        ))
+    }
+}
+
+async fn start() -> (TestServer, SocketAddr) {
+    let server = TestServer::start_with_logic(Logic).await.unwrap();
+    let admin =
+        start_admin(server.smsc.clone(), "127.0.0.1:0", || unreachable!())
+            .await
+            .unwrap();
+    (server, admin)
+}
+
+async fn submit_sm(client: &mut TestClient) {
+    let pdu = Pdu::new(
+        0,
+        2,
+        SubmitSmPdu::new(
+            "",
+            0,
+            0,
+            "MyCompany",
+            1,
+            1,
+            "447777222222",
+            0,
+            0,
+            1,
+            "",
+            "",
+            1,
+            0,
+            3,
+            0,
+            b"hi",
+            Tlvs::new(),
+        )
+        .unwrap()
+        .into(),
+    )
+    .unwrap();
+    let mut bytes = Vec::new();
+    pdu.write(&mut bytes).await.unwrap();
+    client.stream.write_all(&bytes).await.unwrap();
+    assert_eq!(client.read_pdu().await.command_status.value, 0);
+}
@@ -0,0 +1,302 @@
+use serde_json::json;
+use smpp::capture::{CaptureFormat, CaptureTarget};
+use smpp::smsc::start_admin;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::time::sleep;
+
+mod test_utils;
+
+use test_utils::{http, DefaultLogic, TestClient, TestServer};
+
+const BIND_RESP: &[u8; 27] =
+    b"\x00\x00\x00\x1b\x80\x00\x00\x09\x00\x00\x00\x00\x00\x00\x00\x07\
+    TestServer\0";
+const ENQUIRE_LINK: &[u8; 16] =
+    b"\x00\x00\x00\x10\x00\x00\x00\x15\x00\x00\x00\x00\x00\x00\x00\x02";
+const ENQUIRE_LINK_RESP: &[u8; 16] =
+    b"\x00\x00\x00\x10\x80\x00\x00\x15\x00\x00\x00\x00\x00\x00\x00\x02";
+
+#[tokio::test]
+async fn bound_esmes_are_captured_as_pcap() {
+    let dir = capture_dir("pcap");
+    let server = start(&dir, CaptureFormat::Pcap, 1_000_000, 10).await;
+    server
+        .smsc
+        .lock()
+        .await
+        .capturer()
+        .start(CaptureTarget::esme("esmeid"))
+        .unwrap();
+
+    let mut client = TestClient::connect_to(&server).await.unwrap();
+    client.bind_transceiver().await;
+    client
+        .send_and_expect_response(ENQUIRE_LINK, ENQUIRE_LINK_RESP)
+        .await;
+
+    let files = captured_files(&server, &dir).await;
+    assert_eq!(files.len(), 1);
+    assert!(
+        files[0].to_str().unwrap().ends_with("-001.pcap"),
+        "{:?}",
+        files
+    );
+    let packets = pcap_packets(&fs::read(&files[0]).unwrap());
+    // The bind itself arrives before we know who it is from
+    let payloads: Vec<&[u8]> = packets.iter().map(|p| &p[40..]).collect();
+    assert_eq!(
+        payloads,
+        vec![&BIND_RESP[..], &ENQUIRE_LINK[..], &ENQUIRE_LINK_RESP[..]]
+    );
+    for packet in &packets {
+        assert_eq!(packet[0], 0x45);
+        assert_eq!(ones_complement_sum(&packet[..20]), 0xffff);
+    }
+    // The SMSC's sequence continues from its bind_resp
+    let seq = |p: &[u8]| u32::from_be_bytes([p[24], p[25], p[26], p[27]]);
+    assert_eq!(seq(&packets[0]), 1);
+    assert_eq!(seq(&packets[1]), 1);
+    assert_eq!(seq(&packets[2]), 1 + BIND_RESP.len() as u32);
+}
+
+#[tokio::test]
+async fn peers_are_captured_as_hex_from_their_first_pdu() {
+    let dir = capture_dir("hex");
+    let server = start(&dir, CaptureFormat::Hex, 1_000_000, 10).await;
+    server
+        .smsc
+        .lock()
+        .await
+        .capturer()
+        .start(CaptureTarget::peer("127.0.0.1"))
+        .unwrap();
+
+    let mut client = TestClient::connect_to(&server).await.unwrap();
+    client.bind_transceiver().await;
+
+    let files = captured_files(&server, &dir).await;
+    assert_eq!(files.len(), 1);
+    let text = fs::read_to_string(&files[0]).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert!(
+        lines[0].contains(" <= 127.0.0.1:")
+            && lines[0].ends_with(
+                " bind_transceiver command_status=0x00000000 \
+                sequence_number=7 (41 bytes)"
+            ),
+        "{}",
+        text
+    );
+    assert_eq!(
+        lines[1],
+        "  0000  00 00 00 29 00 00 00 09 00 00 00 00 00 00 00 07  \
+        |...)............|"
+    );
+    assert!(text.contains(" => "), "{}", text);
+    assert!(text.contains("bind_transceiver_resp"), "{}", text);
+}
+
+#[tokio::test]
+async fn capture_files_are_rotated_and_capped() {
+    let dir = capture_dir("rotate");
+    let server = start(&dir, CaptureFormat::Hex, 100, 2).await;
+    server
+        .smsc
+        .lock()
+        .await
+        .capturer()
+        .start(CaptureTarget::esme("esmeid"))
+        .unwrap();
+
+    let mut client = TestClient::connect_to(&server).await.unwrap();
+    client.bind_transceiver().await;
+    for _ in 0..4 {
+        client
+            .send_and_expect_response(ENQUIRE_LINK, ENQUIRE_LINK_RESP)
+            .await;
+    }
+
+    // 9 PDUs, each file full after one, so parts 1-9 with 8 and 9 kept
+    let files = captured_files(&server, &dir).await;
+    assert_eq!(files.len(), 2, "{:?}", files);
+    assert!(
+        files[0].to_str().unwrap().ends_with("-008.txt"),
+        "{:?}",
+        files
+    );
+    assert!(
+        files[1].to_str().unwrap().ends_with("-009.txt"),
+        "{:?}",
+        files
+    );
+}
+
+#[tokio::test]
+async fn files_still_being_written_are_not_deleted() {
+    let dir = capture_dir("open");
+    let server = start(&dir, CaptureFormat::Hex, 1_000_000, 1).await;
+    server
+        .smsc
+        .lock()
+        .await
+        .capturer()
+        .start(CaptureTarget::esme("esmeid"))
+        .unwrap();
+
+    let mut client1 = TestClient::connect_to(&server).await.unwrap();
+    client1.bind_transceiver().await;
+    let first = captured_files(&server, &dir).await;
+    let mut client2 = TestClient::connect_to(&server).await.unwrap();
+    client2.bind_transceiver().await;
+
+    // Over the limit while both sessions are live
+    assert_eq!(captured_files(&server, &dir).await.len(), 2);
+
+    client1.stream.shutdown().await.unwrap();
+    drop(client1);
+
+    // The first is deleted once its session closes
+    let mut files = captured_files(&server, &dir).await;
+    for _ in 0..100 {
+        if files.len() == 1 {
+            break;
+        }
+        sleep(Duration::from_millis(10)).await;
+        files = captured_files(&server, &dir).await;
+    }
+    assert_eq!(files.len(), 1, "{:?}", files);
+    assert_ne!(files, first);
+}
+
+#[tokio::test]
+async fn captures_are_switched_with_the_admin_api() {
+    let dir = capture_dir("admin");
+    let server = start(&dir, CaptureFormat::Pcap, 1_000_000, 10).await;
+    let admin =
+        start_admin(server.smsc.clone(), "127.0.0.1:0", || unreachable!())
+            .await
+            .unwrap();
+
+    let (status, targets) = http(
+        admin,
+        "POST",
+        "/captures/start",
+        Some(json!({"system_id": "esmeid", "system_type": "type"})),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(
+        targets,
+        json!([{"system_id": "esmeid", "system_type": "type"}])
+    );
+
+    let (status, body) =
+        http(admin, "POST", "/captures/start", Some(json!({"peer": "x"})))
+            .await;
+    assert_eq!(status, 400);
+    assert_eq!(body["error"], "peer 'x' is not an IP address");
+
+    let (status, _) = http(
+        admin,
+        "POST",
+        "/captures/stop",
+        Some(json!({"system_id": "esmeid", "system_type": "type"})),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(http(admin, "GET", "/captures", None).await.1, json!([]));
+
+    let mut client = TestClient::connect_to(&server).await.unwrap();
+    client.bind_transceiver().await;
+    assert!(captured_files(&server, &dir).await.is_empty());
+}
+
+#[tokio::test]
+async fn nothing_is_captured_without_a_directory() {
+    let server = TestServer::start().await.unwrap();
+
+    let result = server
+        .smsc
+        .lock()
+        .await
+        .capturer()
+        .start(CaptureTarget::esme("esmeid"));
+
+    assert_eq!(
+        result,
+        Err(String::from("capture_dir must be set to capture sessions"))
+    );
+}
+
+async fn start(
+    dir: &Path,
+    format: CaptureFormat,
+    max_file_bytes: u64,
+    max_files: usize,
+) -> TestServer {
+    let dir = dir.to_str().unwrap().to_string();
+    TestServer::start_with_logic_and_smsc_config(DefaultLogic {}, |config| {
+        config.capture.capture_dir = Some(dir);
+        config.capture.capture_format = format;
+        config.capture.capture_max_file_bytes = max_file_bytes;
+        config.capture.capture_max_files = max_files;
+    })
+    .await
+    .unwrap()
+}
+
+fn capture_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "smpp_capture_test_{}_{}",
+        std::process::id(),
+        name
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+/// The files in dir once everything captured so far is written
+async fn captured_files(server: &TestServer, dir: &Path) -> Vec<PathBuf> {
+    server.smsc.lock().await.capturer().flush();
+    files_in(dir)
+}
+
+fn files_in(dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries.map(|e| e.unwrap().path()).collect(),
+        Err(_) => Vec::new(),
+    };
+    files.sort();
+    files
+}
+
+/// The packets in a little-endian, LINKTYPE_RAW pcap file
+fn pcap_packets(bytes: &[u8]) -> Vec<Vec<u8>> {
+    let u32_at = |i: usize| {
+        u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]])
+    };
+    assert_eq!(u32_at(0), 0xa1b2c3d4);
+    assert_eq!(u32_at(20), 101);
+    let mut packets = Vec::new();
+    let mut i = 24;
+    while i < bytes.len() {
+        let len = u32_at(i + 8) as usize;
+        packets.push(bytes[i + 16..i + 16 + len].to_vec());
+        i += 16 + len;
+    }
+    packets
+}
+
+fn ones_complement_sum(bytes: &[u8]) -> u16 {
+    let mut sum: u32 = bytes
+        .chunks(2)
+        .map(|pair| u32::from(u16::from_be_bytes([pair[0], pair[1]])))
+        .sum();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    sum as u16
+}
@@ -0,0 +1,413 @@
+use smpp::capture::CaptureFormat;
+use smpp::esme::BindType;
+use smpp::logging::{LogFormat, Redaction};
+use smpp::routing::{Route, RouteTarget};
+use smpp::smsc::{AccountConfig, ConfigError, ConfigFile, SmscConfig};
+use smpp_pdu::pdu::tlvs::Tlvs;
+use smpp_pdu::pdu::{Pdu, PduStatus, SubmitSmPdu};
+use tokio::io::AsyncWriteExt;
+
+mod test_utils;
+
+use test_utils::{test_bind_address, TempFile, TestClient, TestServer};
+
+/// A bcrypt hash of "secret", as in TOML and YAML
+const SECRET_HASH: &str =
+    "$2b$04$u5O/Qin5KNbO5blI.UeLwetSFpEh7CyGaqPDZudvI6/bSExJZbW7K";
+
+const TOML: &str = r#"
+system_id = "from_file"
+max_open_sockets = 7
+
+[[listeners]]
+bind_address = "127.0.0.1:2775"
+
+[[listeners]]
+bind_address = "127.0.0.1:2776"
+
+[[accounts]]
+system_id = "acme"
+password_hash = "$2b$04$u5O/Qin5KNbO5blI.UeLwetSFpEh7CyGaqPDZudvI6/bSExJZbW7K"
+max_submits_per_sec = 5
+bind_types = ["transmitter"]
+allowed_ips = ["127.0.0.0/8"]
+max_sessions = 2
+
+[timers]
+concat_timeout_secs = 30
+
+[[routes]]
+prefix = "44"
+cost = 1.5
+failover = ["backup"]
+targets = [{ supplier = "uk", weight = 3 }, { supplier = "uk2" }]
+
+[logging]
+level = "debug"
+redaction = "mask"
+
+[capture]
+directory = "/var/tmp/smpp"
+format = "hex"
+
+[normalization]
+country_code = "44"
+"#;
+
+const YAML: &str = r#"
+system_id: from_file
+max_open_sockets: 7
+listeners:
+  - bind_address: "127.0.0.1:2775"
+  - bind_address: "127.0.0.1:2776"
+accounts:
+  - system_id: acme
+    password_hash: "$2b$04$u5O/Qin5KNbO5blI.UeLwetSFpEh7CyGaqPDZudvI6/bSExJZbW7K"
+    max_submits_per_sec: 5
+    bind_types: [transmitter]
+    allowed_ips: ["127.0.0.0/8"]
+    max_sessions: 2
+timers:
+  concat_timeout_secs: 30
+routes:
+  - prefix: "44"
+    cost: 1.5
+    failover: [backup]
+    targets:
+      - supplier: uk
+        weight: 3
+      - supplier: uk2
+logging:
+  level: debug
+  redaction: mask
+capture:
+  directory: /var/tmp/smpp
+  format: hex
+normalization:
+  country_code: "44"
+"#;
+
+#[test]
+fn toml_and_yaml_files_give_the_same_config() {
+    let from_toml =
+        ConfigFile::load(TempFile::new("same.toml", TOML).path()).unwrap();
+    let from_yaml =
+        ConfigFile::load(TempFile::new("same.yaml", YAML).path()).unwrap();
+
+    assert_eq!(from_toml, from_yaml);
+    assert_eq!(
+        from_toml.accounts,
+        vec![AccountConfig {
+            system_id: String::from("acme"),
+            password_hash: String::from(SECRET_HASH),
+            max_submits_per_sec: Some(5),
+            bind_types: vec![BindType::Transmitter],
+            allowed_ips: vec![String::from("127.0.0.0/8")],
+            max_sessions: Some(2),
+        }]
+    );
+    assert_eq!(
+        from_toml.routes,
+        vec![Route {
+            targets: vec![
+                RouteTarget::new("uk", 3),
+                RouteTarget::new("uk2", 1)
+            ],
+            failover: vec![String::from("backup")],
+            ..Route::new("44", "uk", 1.5)
+        }]
+    );
+}
+
+#[test]
+fn file_values_are_used_for_flags_not_given() {
+    let file = TempFile::new("values.toml", TOML);
+
+    let config = load(&["--config", file.path_str()]);
+
+    assert_eq!(config.system_id, "from_file");
+    assert_eq!(config.bind_address, "127.0.0.1:2775");
+    assert_eq!(config.additional_bind_addresses, vec!["127.0.0.1:2776"]);
+    assert_eq!(config.concat_timeout_secs, Some(30));
+    assert_eq!(config.log_level, "debug");
+    assert_eq!(config.log_redaction, Redaction::Mask);
+    assert_eq!(config.log_format, LogFormat::Text);
+    assert_eq!(
+        config.capture.capture_dir,
+        Some(String::from("/var/tmp/smpp"))
+    );
+    assert_eq!(config.capture.capture_format, CaptureFormat::Hex);
+    assert_eq!(
+        config.normalization_rules.country_code,
+        Some(String::from("44"))
+    );
+    assert_eq!(config.accounts.len(), 1);
+    assert_eq!(config.routes.len(), 1);
+}
+
+#[test]
+fn flags_override_file_values() {
+    let file = TempFile::new("flags.toml", TOML);
+
+    let config = load(&[
+        "--config",
+        file.path_str(),
+        "--system-id",
+        "from_flag",
+        "--bind-address",
+        "127.0.0.1:9999",
+    ]);
+
+    assert_eq!(config.system_id, "from_flag");
+    assert_eq!(config.bind_address, "127.0.0.1:9999");
+}
+
+#[test]
+fn environment_variables_override_file_values() {
+    let file = TempFile::new("env.toml", TOML);
+
+    // No other test looks at max_open_sockets, so this is safe to set
+    // while they run.
+    std::env::set_var("MAX_OPEN_SOCKETS", "12");
+    let config = load(&["--config", file.path_str()]);
+    std::env::remove_var("MAX_OPEN_SOCKETS");
+
+    assert_eq!(config.max_open_sockets, 12);
+}
+
+#[test]
+fn unknown_fields_are_reported() {
+    let file = TempFile::new("unknown.toml", "bind_adress = \"0.0.0.0:1\"\n");
+
+    match ConfigFile::load(file.path()) {
+        Err(ConfigError::Parse(_, message)) => {
+            assert!(message.contains("bind_adress"), "{}", message)
+        }
+        other => panic!("Expected Parse error but got {:?}", other),
+    }
+}
+
+#[test]
+fn invalid_values_are_reported() {
+    let file = TempFile::new(
+        "invalid.yaml",
+        "routes:\n  - prefix: \"+44\"\n    targets: [{supplier: uk}]\n",
+    );
+
+    let e = ConfigFile::load(file.path()).unwrap_err();
+
+    assert_eq!(
+        e.to_string(),
+        format!(
+            "Invalid config in {}: route prefix '+44' must contain only digits",
+            file.path().display()
+        )
+    );
+}
+
+#[test]
+fn accounts_need_a_password_hash() {
+    let file = TempFile::new(
+        "plaintext.toml",
+        "[[accounts]]\nsystem_id = \"acme\"\npassword_hash = \"secret\"\n",
+    );
+
+    let e = ConfigFile::load(file.path()).unwrap_err();
+
+    assert_eq!(
+        e.to_string(),
+        format!(
+            "Invalid config in {}: password_hash for account 'acme' is not \
+            an argon2 or bcrypt hash",
+            file.path().display()
+        )
+    );
+}
+
+#[test]
+fn invalid_flags_are_returned_as_errors() {
+    let file = TempFile::new("flags_error.toml", TOML);
+
+    for args in [
+        vec!["smsc", "--log-format", "xml"],
+        vec!["smsc", "--config", file.path_str(), "--no-such-flag"],
+    ] {
+        match SmscConfig::load_from(args) {
+            Err(ConfigError::Args(_)) => {}
+            other => panic!("Expected Args error but got {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn files_with_unknown_extensions_are_rejected() {
+    let file = TempFile::new("config.ini", "");
+
+    match ConfigFile::load(file.path()) {
+        Err(ConfigError::UnknownFormat(_)) => {}
+        other => panic!("Expected UnknownFormat but got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn binds_are_checked_against_configured_accounts() {
+    let server = start_with_account(None).await;
+
+    let mut client = TestClient::connect_to(&server).await.unwrap();
+    assert_eq!(bind(&mut client, "esmeid", "wrong").await, 0x0000000E);
+
+    let mut client = TestClient::connect_to(&server).await.unwrap();
+    assert_eq!(bind(&mut client, "stranger", "password").await, 0x0000000F);
+
+    let mut client = TestClient::connect_to(&server).await.unwrap();
+    assert_eq!(bind(&mut client, "esmeid", "password").await, 0);
+}
+
+#[tokio::test]
+async fn configured_accounts_are_limited_to_their_bind_types_and_ips() {
+    let server = start_with_account_config(|account| {
+        account.bind_types = vec![BindType::Transmitter];
+    })
+    .await;
+    let mut client = TestClient::connect_to(&server).await.unwrap();
+    assert_eq!(bind(&mut client, "esmeid", "password").await, 0x0000000D);
+
+    let server = start_with_account_config(|account| {
+        account.allowed_ips = vec![String::from("192.0.2.0/24")];
+    })
+    .await;
+    let mut client = TestClient::connect_to(&server).await.unwrap();
+    assert_eq!(bind(&mut client, "esmeid", "password").await, 0x0000000D);
+
+    let server = start_with_account_config(|account| {
+        account.max_sessions = Some(1);
+    })
+    .await;
+    let mut client = TestClient::connect_to(&server).await.unwrap();
+    assert_eq!(bind(&mut client, "esmeid", "password").await, 0);
+    let mut client = TestClient::connect_to(&server).await.unwrap();
+    assert_eq!(bind(&mut client, "esmeid", "password").await, 0x0000000D);
+}
+
+#[tokio::test]
+async fn submits_above_the_account_limit_are_throttled() {
+    let server = start_with_account(Some(1)).await;
+    let mut client = TestClient::connect_to(&server).await.unwrap();
+    client.bind_transceiver().await;
+
+    // DefaultLogic fails every submit_sm, but only after the throttle
+    assert_eq!(
+        submit_sm(&mut client, 2).await,
+        PduStatus::ESME_RSYSERR as u32
+    );
+    assert_eq!(
+        submit_sm(&mut client, 3).await,
+        PduStatus::ESME_RTHROTTLED as u32
+    );
+}
+
+#[tokio::test]
+async fn the_smsc_accepts_binds_on_every_listener() {
+    let additional = test_bind_address();
+    let server = TestServer::start_with_logic_and_smsc_config(
+        test_utils::DefaultLogic {},
+        |config| config.additional_bind_addresses = vec![additional.clone()],
+    )
+    .await
+    .unwrap();
+
+    let mut client = TestClient::connect_to(&server).await.unwrap();
+    client.bind_transceiver().await;
+
+    let server = TestServer {
+        smsc: server.smsc,
+        bind_address: additional,
+    };
+    let mut client = TestClient::connect_to(&server).await.unwrap();
+    client.bind_transmitter().await;
+}
+
+fn load(args: &[&str]) -> SmscConfig {
+    SmscConfig::load_from(std::iter::once("smsc").chain(args.iter().cloned()))
+        .unwrap()
+}
+
+async fn start_with_account(max_submits_per_sec: Option<u32>) -> TestServer {
+    start_with_account_config(|account| {
+        account.max_submits_per_sec = max_submits_per_sec
+    })
+    .await
+}
+
+async fn start_with_account_config(
+    change: impl FnOnce(&mut AccountConfig),
+) -> TestServer {
+    let mut account = AccountConfig {
+        system_id: String::from("esmeid"),
+        password_hash: bcrypt::hash("password", 4).unwrap(),
+        max_submits_per_sec: None,
+        bind_types: Vec::new(),
+        allowed_ips: Vec::new(),
+        max_sessions: None,
+    };
+    change(&mut account);
+    TestServer::start_with_logic_and_smsc_config(
+        test_utils::DefaultLogic {},
+        |config| {
+            config.max_open_sockets = 5;
+            config.accounts = vec![account];
+        },
+    )
+    .await
+    .unwrap()
+}
+
+async fn bind(client: &mut TestClient, system_id: &str, password: &str) -> u32 {
+    let mut body = Vec::new();
+    body.extend(system_id.as_bytes());
+    body.push(0);
+    body.extend(password.as_bytes());
+    body.extend(b"\0type\0\x34\x00\x00\0");
+    let mut bind_pdu = Vec::new();
+    bind_pdu.extend(&(body.len() as u32 + 16).to_be_bytes());
+    bind_pdu.extend(b"\x00\x00\x00\x09\x00\x00\x00\x00\x00\x00\x00\x01");
+    bind_pdu.extend(body);
+
+    client.stream.write_all(&bind_pdu).await.unwrap();
+    client.read_pdu().await.command_status.value
+}
+
+async fn submit_sm(client: &mut TestClient, sequence_number: u32) -> u32 {
+    let pdu = Pdu::new(
+        0,
+        sequence_number,
+        SubmitSmPdu::new(
+            "",
+            0,
+            0,
+            "MyCompany",
+            0,
+            0,
+            "447777222222",
+            0,
+            0,
+            1,
+            "",
+            "",
+            1,
+            0,
+            3,
+            0,
+            b"hi",
+            Tlvs::new(),
+        )
+        .unwrap()
+        .into(),
+    )
+    .unwrap();
+    let mut bytes = Vec::new();
+    pdu.write(&mut bytes).await.unwrap();
+
+    client.stream.write_all(&bytes).await.unwrap();
+    client.read_pdu().await.command_status.value
+}
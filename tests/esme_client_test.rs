@@ -0,0 +1,294 @@
+use async_trait::async_trait;
+use smpp::esme::{
+    BindType, DeliverSmError, EsmeClient, EsmeConfig, EsmeError, EsmeLogic,
+    UnacknowledgedPolicy,
+};
+use smpp::extra_pdu::{ExtraPdu, ExtraPduBody};
+use smpp::message_unique_key::MessageUniqueKey;
+use smpp::smsc::{BindData, BindError, Smsc, SmscLogic, SubmitSmError};
+use smpp_pdu::pdu::tlvs::{KnownTlvTag, Tlv, Tlvs};
+use smpp_pdu::pdu::{
+    DeliverEsmClass, DeliverSmPdu, Pdu, SubmitSmPdu, SubmitSmRespPdu,
+};
+use std::sync::Arc;
+use tokio::sync::{mpsc, Mutex};
+
+mod test_utils;
+
+use test_utils::{DefaultLogic, TestServer};
+
+#[tokio::test]
+async fn submit_sm_returns_the_message_id_from_the_smsc() {
+    let server = TestServer::start_with_logic(Logic {}).await.unwrap();
+    let (client, _received) = connect(&server, BindType::Transmitter).await;
+
+    assert_eq!(client.submit_sm(submit_sm()).await.unwrap(), "msg1");
+    assert_eq!(client.submit_sm(submit_sm()).await.unwrap(), "msg1");
+}
+
+#[tokio::test]
+async fn deliver_sm_is_passed_to_the_logic_and_acknowledged() {
+    let server = TestServer::start_with_logic(Logic {}).await.unwrap();
+    let (client, mut received) = connect(&server, BindType::Transceiver).await;
+
+    client.submit_sm(submit_sm()).await.unwrap();
+    server
+        .receive_pdu("supplier", dr_pdu("msg1"))
+        .await
+        .unwrap();
+
+    assert_eq!(received.recv().await.unwrap(), "447777222222");
+    // The SMSC accepted our deliver_sm_resp and is still talking to us
+    client.enquire_link().await.unwrap();
+}
+
+#[tokio::test]
+async fn error_responses_are_returned_as_errors() {
+    let server = TestServer::start_with_logic(DefaultLogic {}).await.unwrap();
+    let (client, _received) = connect(&server, BindType::Transceiver).await;
+
+    match client.submit_sm(submit_sm()).await {
+        Err(EsmeError::ErrorResponse(0x00000008)) => {}
+        other => panic!("Expected ESME_RSYSERR but got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn refused_bind_is_an_error() {
+    let server = TestServer::start_with_logic(RefuseBindLogic {})
+        .await
+        .unwrap();
+
+    let result = EsmeClient::connect(
+        config(&server, BindType::Receiver),
+        ChannelLogic {
+            received: mpsc::unbounded_channel().0,
+        },
+    )
+    .await;
+
+    match result {
+        Err(EsmeError::BindFailed(0x0000000E)) => {}
+        Err(e) => panic!("Expected ESME_RINVPASWD but got {:?}", e),
+        Ok(_) => panic!("Expected bind to fail"),
+    }
+}
+
+#[tokio::test]
+async fn after_unbind_requests_fail() {
+    let server = TestServer::start_with_logic(Logic {}).await.unwrap();
+    let (client, _received) = connect(&server, BindType::Transceiver).await;
+
+    client.enquire_link().await.unwrap();
+    client.unbind().await.unwrap();
+
+    assert!(client.submit_sm(submit_sm()).await.is_err());
+}
+
+#[test]
+fn extra_pdus_can_be_written_and_parsed() {
+    let bytes = ExtraPdu::new(0, 0x12, ExtraPduBody::DeliverSmResp)
+        .unwrap()
+        .to_bytes();
+    assert_eq!(
+        bytes,
+        b"\x00\x00\x00\x11\x80\x00\x00\x05\x00\x00\x00\x00\x00\x00\x00\x12\0"
+    );
+    let pdu = ExtraPdu::parse(&bytes).unwrap();
+    assert!(matches!(pdu.body, ExtraPduBody::DeliverSmResp));
+    assert_eq!(pdu.sequence_number, 0x12);
+
+    let bytes = ExtraPdu::new(
+        0,
+        3,
+        ExtraPduBody::Outbind {
+            system_id: String::from("smsc"),
+            password: String::from("pass"),
+        },
+    )
+    .unwrap()
+    .to_bytes();
+    match ExtraPdu::parse(&bytes).unwrap().body {
+        ExtraPduBody::Outbind {
+            system_id,
+            password,
+        } => {
+            assert_eq!(system_id, "smsc");
+            assert_eq!(password, "pass");
+        }
+        other => panic!("Expected outbind but got {:?}", other),
+    }
+
+    let bytes = ExtraPdu::new(0x00000003, 0, ExtraPduBody::GenericNack)
+        .unwrap()
+        .to_bytes();
+    let pdu = ExtraPdu::parse(&bytes).unwrap();
+    assert!(matches!(pdu.body, ExtraPduBody::GenericNack));
+    assert_eq!(pdu.sequence_number, 0);
+    assert!(ExtraPdu::new(0, 0, ExtraPduBody::UnbindResp).is_err());
+
+    let bind = ExtraPdu::new(
+        0,
+        7,
+        ExtraPduBody::BindTransmitter(
+            BindData::new("esmeid", "password", "type", 0x34, 0, 0, "")
+                .unwrap(),
+        ),
+    )
+    .unwrap();
+    assert_eq!(
+        bind.to_bytes(),
+        b"\x00\x00\x00\x29\x00\x00\x00\x02\x00\x00\x00\x00\x00\x00\x00\x07\
+        esmeid\0password\0type\0\x34\x00\x00\0"
+            .to_vec()
+    );
+}
+
+async fn connect(
+    server: &TestServer,
+    bind_type: BindType,
+) -> (EsmeClient, mpsc::UnboundedReceiver<String>) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let client = EsmeClient::connect(
+        config(server, bind_type),
+        ChannelLogic { received: tx },
+    )
+    .await
+    .unwrap();
+    (client, rx)
+}
+
+fn config(server: &TestServer, bind_type: BindType) -> EsmeConfig {
+    EsmeConfig {
+        smsc_address: server.bind_address.clone(),
+        system_id: String::from("esmeid"),
+        password: String::from("password"),
+        system_type: String::new(),
+        bind_type,
+        enquire_link_interval_secs: 30,
+        enquire_link_timeout_secs: 10,
+        reconnect_initial_delay_ms: 1000,
+        reconnect_max_delay_ms: 60000,
+        unacknowledged_policy: UnacknowledgedPolicy::Fail,
+        window_size: 10,
+        response_timeout_secs: 30,
+        connect_timeout_secs: 10,
+    }
+}
+
+/// Passes the source_addr of each deliver_sm to a channel
+struct ChannelLogic {
+    received: mpsc::UnboundedSender<String>,
+}
+
+#[async_trait]
+impl EsmeLogic for ChannelLogic {
+    async fn deliver_sm(
+        &mut self,
+        pdu: &DeliverSmPdu,
+    ) -> Result<(), DeliverSmError> {
+        self.received
+            .send(pdu.source_addr())
+            .map_err(|_| DeliverSmError::InternalError)
+    }
+}
+
+struct Logic {}
+
+#[async_trait]
+impl SmscLogic for Logic {
+    async fn bind(&mut self, _bind_data: &BindData) -> Result<(), BindError> {
+        Ok(())
+    }
+
+    async fn submit_sm(
+        &mut self,
+        _smsc: Arc<Mutex<Smsc>>,
+        pdu: &SubmitSmPdu,
+        _sequence_number: u32,
+    ) -> Result<(SubmitSmRespPdu, MessageUniqueKey), SubmitSmError> {
+        Ok((
+            SubmitSmRespPdu::new("msg1").unwrap(),
+            MessageUniqueKey::from_submit_sm(
+                String::from("supplier"),
+                String::from("msg1"),
+                pdu,
+            ),
+        ))
+    }
+}
+
+struct RefuseBindLogic {}
+
+#[async_trait]
+impl SmscLogic for RefuseBindLogic {
+    async fn bind(&mut self, _bind_data: &BindData) -> Result<(), BindError> {
+        Err(BindError::IncorrectPassword)
+    }
+
+    async fn submit_sm(
+        &mut self,
+        _smsc: Arc<Mutex<Smsc>>,
+        _pdu: &SubmitSmPdu,
+        _sequence_number: u32,
+    ) -> Result<(SubmitSmRespPdu, MessageUniqueKey), SubmitSmError> {
+        Err(SubmitSmError::InternalError)
+    }
+}
+
+fn submit_sm() -> SubmitSmPdu {
+    SubmitSmPdu::new(
+        "",
+        0,
+        0,
+        "MyCompany",
+        1,
+        1,
+        "447777222222",
+        0,
+        0,
+        1,
+        "",
+        "",
+        1,
+        0,
+        0,
+        0,
+        b"hello",
+        Tlvs::new(),
+    )
+    .unwrap()
+}
+
+fn dr_pdu(message_id: &str) -> Pdu {
+    Pdu::new(
+        0,
+        0x6d,
+        DeliverSmPdu::new(
+            "",
+            1,
+            1,
+            "447777222222",
+            0,
+            0,
+            "MyCompany",
+            DeliverEsmClass::SmscDeliveryReceipt as u8,
+            0,
+            1,
+            "",
+            "",
+            0,
+            0,
+            0,
+            0,
+            b"",
+            Tlvs::from(&[Tlv::new(
+                KnownTlvTag::receipted_message_id,
+                message_id.as_bytes(),
+            )]),
+        )
+        .unwrap()
+        .into(),
+    )
+    .unwrap()
+}
@@ -0,0 +1,208 @@
+use smpp::esme::{Balancing, EsmePool, EsmePoolConfig};
+use smpp::extra_pdu::AnyPdu;
+use smpp::smpp_connection::SmppConnection;
+use smpp_pdu::pdu::tlvs::Tlvs;
+use smpp_pdu::pdu::{PduBody, SubmitSmPdu};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::time::{sleep, timeout};
+
+mod test_utils;
+
+use test_utils::{esme_config, write_submit_sm_resp, FakeSmsc, NullEsmeLogic};
+
+#[tokio::test]
+async fn round_robin_spreads_submits_over_all_binds() {
+    let smsc = FakeSmsc::start().await;
+    let (pool, connections) = connect(&smsc, 3, Balancing::RoundRobin).await;
+    respond_on_all(connections, None);
+
+    let mut results = Vec::new();
+    for _ in 0..6 {
+        results.push(pool.submit_sm(submit_sm()).await.unwrap());
+    }
+
+    results.sort();
+    assert_eq!(
+        results,
+        vec!["bind0", "bind0", "bind1", "bind1", "bind2", "bind2"]
+    );
+}
+
+#[tokio::test]
+async fn least_outstanding_avoids_busy_binds() {
+    let smsc = FakeSmsc::start().await;
+    let (pool, connections) =
+        connect(&smsc, 2, Balancing::LeastOutstanding).await;
+    // Whichever bind gets the first submit_sm never responds to it
+    respond_on_all(connections, Some(Arc::new(AtomicBool::new(false))));
+    let pool = Arc::new(pool);
+
+    let stuck = {
+        let pool = Arc::clone(&pool);
+        tokio::spawn(async move { pool.submit_sm(submit_sm()).await })
+    };
+    sleep(Duration::from_millis(100)).await;
+
+    let first = pool.submit_sm(submit_sm()).await.unwrap();
+    for _ in 0..3 {
+        assert_eq!(pool.submit_sm(submit_sm()).await.unwrap(), first);
+    }
+    stuck.abort();
+}
+
+#[tokio::test]
+async fn cancelled_submits_no_longer_count_as_outstanding() {
+    let smsc = FakeSmsc::start().await;
+    let (pool, connections) =
+        connect(&smsc, 2, Balancing::LeastOutstanding).await;
+    respond_on_all(connections, Some(Arc::new(AtomicBool::new(false))));
+    let pool = Arc::new(pool);
+
+    let stuck = {
+        let pool = Arc::clone(&pool);
+        tokio::spawn(async move { pool.submit_sm(submit_sm()).await })
+    };
+    sleep(Duration::from_millis(100)).await;
+    let other = pool.submit_sm(submit_sm()).await.unwrap();
+
+    // Once nobody is waiting for it, the stuck bind is chosen again
+    stuck.abort();
+    let _ = stuck.await;
+    assert_ne!(pool.submit_sm(submit_sm()).await.unwrap(), other);
+}
+
+#[tokio::test]
+async fn disconnected_binds_are_taken_out_of_rotation() {
+    let smsc = FakeSmsc::start().await;
+    let (pool, mut connections) =
+        connect(&smsc, 2, Balancing::RoundRobin).await;
+    // The client will try to reconnect, but we never accept its bind
+    let lost = connections.remove(0);
+    lost.disconnect().await;
+    // The remaining bind responds with bind0
+    respond_on_all(connections, None);
+
+    timeout(Duration::from_secs(5), async {
+        while pool.bound_count() != 1 {
+            sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+
+    for _ in 0..4 {
+        assert_eq!(pool.submit_sm(submit_sm()).await.unwrap(), "bind0");
+    }
+}
+
+#[tokio::test]
+async fn submits_respect_the_rate_limit_across_binds() {
+    let smsc = FakeSmsc::start().await;
+    let mut config = pool_config(&smsc, 2, Balancing::RoundRobin);
+    config.max_submits_per_sec = Some(20);
+    let (pool, connections) = connect_with_config(&smsc, config).await;
+    respond_on_all(connections, None);
+
+    let start = Instant::now();
+    for _ in 0..6 {
+        pool.submit_sm(submit_sm()).await.unwrap();
+    }
+
+    // The first goes at once, then one every 50ms
+    assert!(start.elapsed() >= Duration::from_millis(250));
+}
+
+async fn connect(
+    smsc: &FakeSmsc,
+    binds: usize,
+    balancing: Balancing,
+) -> (EsmePool, Vec<SmppConnection>) {
+    connect_with_config(smsc, pool_config(smsc, binds, balancing)).await
+}
+
+async fn connect_with_config(
+    smsc: &FakeSmsc,
+    config: EsmePoolConfig,
+) -> (EsmePool, Vec<SmppConnection>) {
+    let binds = config.binds;
+    let accept_all = async {
+        let mut connections = Vec::new();
+        for _ in 0..binds {
+            connections.push(smsc.accept_and_bind().await);
+        }
+        connections
+    };
+    let (pool, connections) =
+        tokio::join!(EsmePool::connect(config, NullEsmeLogic {}), accept_all);
+    (pool.unwrap(), connections)
+}
+
+fn pool_config(
+    smsc: &FakeSmsc,
+    binds: usize,
+    balancing: Balancing,
+) -> EsmePoolConfig {
+    EsmePoolConfig {
+        esme: esme_config(smsc),
+        binds,
+        balancing,
+        max_submits_per_sec: None,
+    }
+}
+
+/// Respond to every submit_sm with message_id "bind<n>", where n is the
+/// index of the connection.  If hold_first is given, the first submit_sm
+/// on any connection gets no response.
+fn respond_on_all(
+    connections: Vec<SmppConnection>,
+    hold_first: Option<Arc<AtomicBool>>,
+) {
+    for (i, connection) in connections.into_iter().enumerate() {
+        let hold_first = hold_first.clone();
+        tokio::spawn(async move {
+            let message_id = format!("bind{}", i);
+            while let Ok(Some(pdu)) = connection.read_any_pdu().await {
+                let sequence_number = match pdu {
+                    AnyPdu::Pdu(pdu) => match pdu.body() {
+                        PduBody::SubmitSm(_) => pdu.sequence_number.value,
+                        _ => continue,
+                    },
+                    AnyPdu::Extra(_) => continue,
+                };
+                if let Some(held) = &hold_first {
+                    if !held.swap(true, Ordering::Relaxed) {
+                        continue;
+                    }
+                }
+                write_submit_sm_resp(&connection, sequence_number, &message_id)
+                    .await;
+            }
+        });
+    }
+}
+
+fn submit_sm() -> SubmitSmPdu {
+    SubmitSmPdu::new(
+        "",
+        0,
+        0,
+        "MyCompany",
+        1,
+        1,
+        "447777222222",
+        0,
+        0,
+        1,
+        "",
+        "",
+        1,
+        0,
+        0,
+        0,
+        b"hello",
+        Tlvs::new(),
+    )
+    .unwrap()
+}
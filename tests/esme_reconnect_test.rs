@@ -0,0 +1,235 @@
+use smpp::esme::{
+    Backoff, ConnectionState, EsmeClient, EsmeError, UnacknowledgedPolicy,
+};
+use smpp::extra_pdu::{ExtraPdu, ExtraPduBody};
+use smpp_pdu::pdu::tlvs::Tlvs;
+use smpp_pdu::pdu::SubmitSmPdu;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::timeout;
+
+mod test_utils;
+
+use test_utils::{
+    esme_config, read_submit_sm, respond_to_submit_sm, FakeSmsc, NullEsmeLogic,
+};
+
+#[test]
+fn backoff_delays_grow_up_to_the_maximum_and_reset() {
+    let mut backoff =
+        Backoff::new(Duration::from_millis(100), Duration::from_millis(1000));
+
+    let delays: Vec<Duration> = (0..6).map(|_| backoff.next_delay()).collect();
+    let ceilings = [100, 200, 400, 800, 1000, 1000];
+    for (delay, ceiling) in delays.iter().zip(ceilings.iter()) {
+        let ceiling = Duration::from_millis(*ceiling);
+        assert!(*delay >= ceiling / 2, "{:?} < {:?} / 2", delay, ceiling);
+        assert!(*delay <= ceiling, "{:?} > {:?}", delay, ceiling);
+    }
+
+    backoff.reset();
+    assert!(backoff.next_delay() <= Duration::from_millis(100));
+}
+
+#[tokio::test]
+async fn client_reconnects_after_the_smsc_closes_the_connection() {
+    let smsc = FakeSmsc::start().await;
+    let (client, connection) = smsc.connect(esme_config(&smsc)).await;
+    let mut states = client.state_changes();
+
+    connection.disconnect().await;
+
+    wait_for_state(&mut states, ConnectionState::Disconnected).await;
+    let connection = smsc.accept_and_bind().await;
+    wait_for_state(&mut states, ConnectionState::Bound).await;
+
+    let (result, ()) = tokio::join!(
+        client.submit_sm(submit_sm()),
+        respond_to_submit_sm(&connection, "msg2"),
+    );
+    assert_eq!(result.unwrap(), "msg2");
+}
+
+#[tokio::test]
+async fn unacknowledged_submit_sm_is_resent_after_reconnect_if_requeuing() {
+    let smsc = FakeSmsc::start().await;
+    let mut config = esme_config(&smsc);
+    config.unacknowledged_policy = UnacknowledgedPolicy::Requeue;
+    let (client, connection) = smsc.connect(config).await;
+
+    let smsc_side = async {
+        // Read the submit_sm but close the connection without responding
+        read_submit_sm(&connection).await;
+        connection.disconnect().await;
+
+        let connection = smsc.accept_and_bind().await;
+        respond_to_submit_sm(&connection, "msg2").await;
+    };
+    let (result, ()) = tokio::join!(client.submit_sm(submit_sm()), smsc_side);
+
+    assert_eq!(result.unwrap(), "msg2");
+}
+
+#[tokio::test]
+async fn unacknowledged_submit_sm_fails_if_not_requeuing() {
+    let smsc = FakeSmsc::start().await;
+    let (client, connection) = smsc.connect(esme_config(&smsc)).await;
+
+    let smsc_side = async {
+        read_submit_sm(&connection).await;
+        connection.disconnect().await;
+    };
+    let (result, ()) = tokio::join!(client.submit_sm(submit_sm()), smsc_side);
+
+    match result {
+        Err(EsmeError::ConnectionClosed) => {}
+        other => panic!("Expected ConnectionClosed but got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn client_reconnects_if_enquire_link_is_not_answered() {
+    let smsc = FakeSmsc::start().await;
+    let mut config = esme_config(&smsc);
+    config.enquire_link_interval_secs = 1;
+    config.enquire_link_timeout_secs = 1;
+    let (client, connection) = smsc.connect(config).await;
+    let mut states = client.state_changes();
+
+    // Read the enquire_link, but never respond
+    let pdu = connection.read_any_pdu().await.unwrap().unwrap();
+    assert_eq!(pdu.command_id(), 0x00000015);
+
+    wait_for_state(&mut states, ConnectionState::Disconnected).await;
+    let _connection = smsc.accept_and_bind().await;
+    wait_for_state(&mut states, ConnectionState::Bound).await;
+}
+
+#[tokio::test]
+async fn client_does_not_reconnect_after_unbind() {
+    let smsc = FakeSmsc::start().await;
+    let (client, connection) = smsc.connect(esme_config(&smsc)).await;
+
+    let smsc_side = async {
+        let unbind = connection.read_any_pdu().await.unwrap().unwrap();
+        assert_eq!(unbind.command_id(), 0x00000006);
+        connection
+            .write_extra_pdu(
+                &ExtraPdu::new(
+                    0,
+                    unbind.sequence_number(),
+                    ExtraPduBody::UnbindResp,
+                )
+                .unwrap(),
+            )
+            .await
+            .unwrap();
+        connection.disconnect().await;
+    };
+    let (result, ()) = tokio::join!(client.unbind(), smsc_side);
+    result.unwrap();
+
+    assert_eq!(client.state(), ConnectionState::Unbound);
+    let accepted =
+        timeout(Duration::from_millis(200), smsc.listener.accept()).await;
+    assert!(accepted.is_err(), "Client reconnected after unbind");
+}
+
+#[tokio::test]
+async fn a_reconnect_that_binds_after_unbind_is_unbound() {
+    let smsc = FakeSmsc::start().await;
+    let (client, connection) = smsc.connect(esme_config(&smsc)).await;
+    let mut states = client.state_changes();
+
+    connection.disconnect().await;
+    wait_for_state(&mut states, ConnectionState::Connecting).await;
+    // The reconnect is waiting for its bind_resp
+    client.unbind().await.unwrap();
+    let connection = smsc.accept_and_bind().await;
+
+    let unbind = connection.read_any_pdu().await.unwrap().unwrap();
+    assert_eq!(unbind.command_id(), 0x00000006);
+    connection
+        .write_extra_pdu(
+            &ExtraPdu::new(
+                0,
+                unbind.sequence_number(),
+                ExtraPduBody::UnbindResp,
+            )
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert!(connection.read_any_pdu().await.unwrap().is_none());
+    assert_eq!(client.state(), ConnectionState::Unbound);
+}
+
+#[tokio::test]
+async fn connect_fails_if_the_smsc_does_not_answer_the_bind() {
+    let smsc = FakeSmsc::start().await;
+    let mut config = esme_config(&smsc);
+    config.connect_timeout_secs = 1;
+
+    // Accept the connection, but never respond to the bind
+    let (result, _accepted) = tokio::join!(
+        EsmeClient::connect(config, NullEsmeLogic {}),
+        smsc.listener.accept(),
+    );
+    assert!(matches!(result, Err(EsmeError::Timeout)));
+}
+
+#[tokio::test]
+async fn client_tries_again_if_a_reconnect_gets_no_bind_resp() {
+    let smsc = FakeSmsc::start().await;
+    let mut config = esme_config(&smsc);
+    config.connect_timeout_secs = 1;
+    let (client, connection) = smsc.connect(config).await;
+    let mut states = client.state_changes();
+
+    connection.disconnect().await;
+
+    // The first reconnect gets no bind_resp, so it times out and the
+    // client tries again
+    wait_for_state(&mut states, ConnectionState::Disconnected).await;
+    let _unanswered = smsc.listener.accept().await.unwrap();
+    wait_for_state(&mut states, ConnectionState::Disconnected).await;
+    let _connection = smsc.accept_and_bind().await;
+    wait_for_state(&mut states, ConnectionState::Bound).await;
+}
+
+async fn wait_for_state(
+    states: &mut watch::Receiver<ConnectionState>,
+    expected: ConnectionState,
+) {
+    timeout(Duration::from_secs(5), async {
+        while *states.borrow_and_update() != expected {
+            states.changed().await.unwrap();
+        }
+    })
+    .await
+    .unwrap_or_else(|_| panic!("Timed out waiting for {:?}", expected));
+}
+
+fn submit_sm() -> SubmitSmPdu {
+    SubmitSmPdu::new(
+        "",
+        0,
+        0,
+        "MyCompany",
+        1,
+        1,
+        "447777222222",
+        0,
+        0,
+        1,
+        "",
+        "",
+        1,
+        0,
+        0,
+        0,
+        b"hello",
+        Tlvs::new(),
+    )
+    .unwrap()
+}
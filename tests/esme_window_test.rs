@@ -0,0 +1,165 @@
+use smpp::esme::EsmeError;
+use smpp_pdu::pdu::tlvs::Tlvs;
+use smpp_pdu::pdu::{EnquireLinkRespPdu, Pdu, SubmitSmPdu};
+use std::time::Duration;
+use tokio::time::timeout;
+
+mod test_utils;
+
+use test_utils::{
+    esme_config, read_submit_sm, respond_to_submit_sm, write_submit_sm_resp,
+    FakeSmsc,
+};
+
+#[tokio::test]
+async fn responses_are_matched_by_sequence_number_in_any_order() {
+    let smsc = FakeSmsc::start().await;
+    let (client, connection) = smsc.connect(esme_config(&smsc)).await;
+
+    let smsc_side = async {
+        let mut received = Vec::new();
+        for _ in 0..3 {
+            received.push(read_submit_sm(&connection).await);
+        }
+        // Respond last-first, with the destination as the message_id
+        for (sequence_number, destination_addr) in received.iter().rev() {
+            write_submit_sm_resp(
+                &connection,
+                *sequence_number,
+                destination_addr,
+            )
+            .await;
+        }
+    };
+    let (r1, r2, r3, ()) = tokio::join!(
+        client.submit_sm(submit_sm("447777000001")),
+        client.submit_sm(submit_sm("447777000002")),
+        client.submit_sm(submit_sm("447777000003")),
+        smsc_side,
+    );
+
+    assert_eq!(r1.unwrap(), "447777000001");
+    assert_eq!(r2.unwrap(), "447777000002");
+    assert_eq!(r3.unwrap(), "447777000003");
+}
+
+#[tokio::test]
+async fn submits_beyond_the_window_wait_for_a_response() {
+    let smsc = FakeSmsc::start().await;
+    let mut config = esme_config(&smsc);
+    config.window_size = 2;
+    let (client, connection) = smsc.connect(config).await;
+
+    let smsc_side = async {
+        let (first, _) = read_submit_sm(&connection).await;
+        let (second, _) = read_submit_sm(&connection).await;
+
+        // The window is full, so the third submit_sm is not sent yet
+        let third =
+            timeout(Duration::from_millis(200), read_submit_sm(&connection));
+        assert!(third.await.is_err(), "Window was exceeded");
+
+        write_submit_sm_resp(&connection, first, "msg1").await;
+        respond_to_submit_sm(&connection, "msg3").await;
+        write_submit_sm_resp(&connection, second, "msg2").await;
+    };
+    let (r1, r2, r3, ()) = tokio::join!(
+        client.submit_sm(submit_sm("447777000001")),
+        client.submit_sm(submit_sm("447777000002")),
+        client.submit_sm(submit_sm("447777000003")),
+        smsc_side,
+    );
+
+    let mut results = vec![r1.unwrap(), r2.unwrap(), r3.unwrap()];
+    results.sort();
+    assert_eq!(results, vec!["msg1", "msg2", "msg3"]);
+}
+
+#[tokio::test]
+async fn enquire_link_does_not_use_a_window_slot() {
+    let smsc = FakeSmsc::start().await;
+    let mut config = esme_config(&smsc);
+    config.window_size = 1;
+    config.response_timeout_secs = 5;
+    let (client, connection) = smsc.connect(config).await;
+
+    let smsc_side = async {
+        // Leave the submit_sm unanswered, so the window is full
+        read_submit_sm(&connection).await;
+        let enquire_link = connection.read_pdu().await.unwrap().unwrap();
+        assert_eq!(enquire_link.command_id().value, 0x00000015);
+        connection
+            .write_pdu(
+                &Pdu::new(
+                    0,
+                    enquire_link.sequence_number.value,
+                    EnquireLinkRespPdu::new().into(),
+                )
+                .unwrap(),
+            )
+            .await
+            .unwrap();
+    };
+    let enquire_link = async {
+        // Give the submit_sm a head start
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        client.enquire_link().await
+    };
+    let submit = timeout(
+        Duration::from_millis(500),
+        client.submit_sm(submit_sm("447777000001")),
+    );
+    let (_, enquire_link_result, ()) =
+        tokio::join!(submit, enquire_link, smsc_side);
+
+    enquire_link_result.unwrap();
+}
+
+#[tokio::test]
+async fn requests_fail_if_there_is_no_response_in_time() {
+    let smsc = FakeSmsc::start().await;
+    let mut config = esme_config(&smsc);
+    config.window_size = 1;
+    config.response_timeout_secs = 1;
+    let (client, connection) = smsc.connect(config).await;
+
+    let (result, _) = tokio::join!(
+        client.submit_sm(submit_sm("447777000001")),
+        read_submit_sm(&connection),
+    );
+    match result {
+        Err(EsmeError::Timeout) => {}
+        other => panic!("Expected Timeout but got {:?}", other),
+    }
+
+    // The timed-out request no longer takes up the window
+    let (result, ()) = tokio::join!(
+        client.submit_sm(submit_sm("447777000002")),
+        respond_to_submit_sm(&connection, "msg2"),
+    );
+    assert_eq!(result.unwrap(), "msg2");
+}
+
+fn submit_sm(destination_addr: &str) -> SubmitSmPdu {
+    SubmitSmPdu::new(
+        "",
+        0,
+        0,
+        "MyCompany",
+        1,
+        1,
+        destination_addr,
+        0,
+        0,
+        1,
+        "",
+        "",
+        1,
+        0,
+        0,
+        0,
+        b"hello",
+        Tlvs::new(),
+    )
+    .unwrap()
+}
@@ -0,0 +1,269 @@
+use async_trait::async_trait;
+use smpp::esme::{DeliverSmError, EsmeClient, EsmeConfig, EsmeLogic};
+use smpp::faults::{FaultConfig, FaultRate};
+use smpp::simulator::Simulator;
+use smpp::sm_fields::SmFields;
+use smpp::smsc::SmscConfig;
+use smpp_pdu::pdu::{DeliverSmPdu, SubmitSmPdu};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::mpsc;
+
+mod test_utils;
+
+use test_utils::{DefaultLogic, TempFile, TestClient, TestServer};
+
+const BIND_TRANSMITTER: &[u8; 0x29] =
+    b"\x00\x00\x00\x29\x00\x00\x00\x02\x00\x00\x00\x00\x00\x00\x00\x01\
+    esmeid\0password\0type\0\x34\x00\x00\0";
+const BIND_TRANSMITTER_RESP: &[u8; 0x1b] =
+    b"\x00\x00\x00\x1b\x80\x00\x00\x02\x00\x00\x00\x00\x00\x00\x00\x01\
+    TestServer\0";
+
+#[tokio::test]
+async fn responses_are_dropped_reordered_and_replaced_with_generic_nack() {
+    let mut client = connect(|faults| {
+        faults.fault_drop_response = FaultRate::Every(2);
+        faults.fault_reorder_response = FaultRate::Every(2);
+        faults.fault_generic_nack = FaultRate::Every(3);
+    })
+    .await;
+    client
+        .send_and_expect_response(BIND_TRANSMITTER, BIND_TRANSMITTER_RESP)
+        .await;
+
+    // The responses to 2 and 4 are dropped.  The response to 3 is held
+    // back until after the one to 5, which is a generic_nack.
+    for sequence_number in 2..=5 {
+        client
+            .stream
+            .write_all(&enquire_link(sequence_number))
+            .await
+            .unwrap();
+    }
+    client.expect_to_receive(&generic_nack(5)).await;
+    client.expect_to_receive(&enquire_link_resp(3)).await;
+}
+
+#[tokio::test]
+async fn responses_are_delayed() {
+    let mut client = connect(|faults| {
+        faults.fault_delay_response = FaultRate::Probability(1.0);
+        faults.fault_delay_ms = 200;
+    })
+    .await;
+
+    let start = Instant::now();
+    client
+        .send_and_expect_response(BIND_TRANSMITTER, BIND_TRANSMITTER_RESP)
+        .await;
+    assert!(start.elapsed() >= Duration::from_millis(200));
+}
+
+#[tokio::test]
+async fn a_delayed_response_does_not_hold_up_later_ones() {
+    let mut client = connect(|faults| {
+        faults.fault_delay_response = FaultRate::Every(2);
+        faults.fault_delay_ms = 300;
+    })
+    .await;
+    client
+        .send_and_expect_response(BIND_TRANSMITTER, BIND_TRANSMITTER_RESP)
+        .await;
+
+    // The response to 2 is delayed, but 3 is answered straight away
+    let start = Instant::now();
+    for sequence_number in 2..=3 {
+        client
+            .stream
+            .write_all(&enquire_link(sequence_number))
+            .await
+            .unwrap();
+    }
+    client.expect_to_receive(&enquire_link_resp(3)).await;
+    assert!(start.elapsed() < Duration::from_millis(300));
+    client.expect_to_receive(&enquire_link_resp(2)).await;
+    assert!(start.elapsed() >= Duration::from_millis(300));
+}
+
+#[tokio::test]
+async fn a_held_back_response_is_sent_if_no_other_comes() {
+    let mut client = connect(|faults| {
+        faults.fault_reorder_response = FaultRate::Every(1);
+        faults.fault_delay_ms = 200;
+    })
+    .await;
+
+    let start = Instant::now();
+    client
+        .send_and_expect_response(BIND_TRANSMITTER, BIND_TRANSMITTER_RESP)
+        .await;
+    assert!(start.elapsed() >= Duration::from_millis(200));
+}
+
+#[tokio::test]
+async fn malformed_and_oversized_pdus_come_before_the_response() {
+    let mut client = connect(|faults| {
+        faults.fault_malformed_pdu = FaultRate::Every(1);
+        faults.fault_oversized_pdu = FaultRate::Every(1);
+    })
+    .await;
+    client.stream.write_all(BIND_TRANSMITTER).await.unwrap();
+
+    client
+        .expect_to_receive(
+            b"\x00\x00\x00\x18\x00\x00\x00\x05\x00\x00\x00\x00\x7f\xff\xff\xff\
+            \xff\xff\xff\xff\xff\xff\xff\xff",
+        )
+        .await;
+    let oversized = client.read_n(100_000).await;
+    assert_eq!(&oversized[..8], b"\x00\x01\x86\xa0\x00\x00\x00\x05");
+    client.expect_to_receive(BIND_TRANSMITTER_RESP).await;
+}
+
+#[tokio::test]
+async fn connection_is_closed_mid_pdu() {
+    let mut client = connect(|faults| {
+        faults.fault_close_mid_pdu = FaultRate::Every(2);
+    })
+    .await;
+    client
+        .send_and_expect_response(BIND_TRANSMITTER, BIND_TRANSMITTER_RESP)
+        .await;
+
+    client.stream.write_all(&enquire_link(2)).await.unwrap();
+    client.expect_to_receive(&enquire_link_resp(2)[..8]).await;
+    assert_eq!(client.stream.read(&mut [0; 16]).await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn drs_are_duplicated() {
+    let config = SmscConfig::load_from(vec![
+        "smsc",
+        "--sim-dr-delay",
+        "fixed:0",
+        "--fault-duplicate-dr",
+        "1",
+    ])
+    .unwrap();
+    let simulator = Simulator::new(&config).unwrap();
+    let server = TestServer::start_with_logic_and_smsc_config(simulator, |c| {
+        c.faults = config.faults
+    })
+    .await
+    .unwrap();
+    let (tx, mut drs) = mpsc::unbounded_channel();
+    let client = EsmeClient::connect(
+        EsmeConfig::new(&server.bind_address, "acme", "acme"),
+        ChannelLogic { tx },
+    )
+    .await
+    .unwrap();
+
+    let message_id = client.submit_sm(submit_sm()).await.unwrap();
+    assert_eq!(drs.recv().await.unwrap(), Some(message_id.clone()));
+    assert_eq!(drs.recv().await.unwrap(), Some(message_id));
+}
+
+#[test]
+fn rates_are_parsed_and_read_from_the_config_file() {
+    assert_eq!("0".parse::<FaultRate>(), Ok(FaultRate::Never));
+    assert_eq!(
+        "0.25".parse::<FaultRate>(),
+        Ok(FaultRate::Probability(0.25))
+    );
+    assert_eq!("every:10".parse::<FaultRate>(), Ok(FaultRate::Every(10)));
+    assert!("1.5".parse::<FaultRate>().is_err());
+    assert!("every:0".parse::<FaultRate>().is_err());
+
+    let file = TempFile::new(
+        "faults.toml",
+        "[faults]\ndrop_response = \"0.1\"\ndelay_ms = 250\n\
+        generic_nack = \"every:5\"\n",
+    );
+    let config = SmscConfig::load_from(vec![
+        "smsc",
+        "--config",
+        file.path_str(),
+        "--fault-generic-nack",
+        "every:7",
+    ])
+    .unwrap();
+    assert_eq!(
+        config.faults.fault_drop_response,
+        FaultRate::Probability(0.1)
+    );
+    assert_eq!(config.faults.fault_delay_ms, 250);
+    assert_eq!(config.faults.fault_generic_nack, FaultRate::Every(7));
+    assert_eq!(config.faults.fault_close_mid_pdu, FaultRate::Never);
+
+    file.write("[faults]\nduplicate_dr = \"often\"\n");
+    let error =
+        SmscConfig::load_from(vec!["smsc", "--config", file.path_str()])
+            .unwrap_err();
+    assert!(error.to_string().contains("Invalid fault rate 'often'"));
+}
+
+async fn connect<F: FnOnce(&mut FaultConfig)>(configure: F) -> TestClient {
+    let server =
+        TestServer::start_with_logic_and_smsc_config(DefaultLogic {}, |c| {
+            configure(&mut c.faults)
+        })
+        .await
+        .unwrap();
+    TestClient::connect_to(&server).await.unwrap()
+}
+
+fn header(
+    command_id: u32,
+    command_status: u32,
+    sequence_number: u32,
+) -> Vec<u8> {
+    [16, command_id, command_status, sequence_number]
+        .iter()
+        .flat_map(|word| word.to_be_bytes().to_vec())
+        .collect()
+}
+
+fn enquire_link(sequence_number: u32) -> Vec<u8> {
+    header(0x00000015, 0, sequence_number)
+}
+
+fn enquire_link_resp(sequence_number: u32) -> Vec<u8> {
+    header(0x80000015, 0, sequence_number)
+}
+
+fn generic_nack(sequence_number: u32) -> Vec<u8> {
+    header(0x80000000, 0x08, sequence_number)
+}
+
+fn submit_sm() -> SubmitSmPdu {
+    SmFields {
+        source_addr: String::from("MyCompany"),
+        dest_addr_ton: 1,
+        dest_addr_npi: 1,
+        destination_addr: String::from("447700900123"),
+        registered_delivery: 1,
+        short_message: b"hello".to_vec(),
+        ..Default::default()
+    }
+    .to_submit_sm()
+    .unwrap()
+}
+
+/// Passes the receipted_message_id of each deliver_sm to a channel
+struct ChannelLogic {
+    tx: mpsc::UnboundedSender<Option<String>>,
+}
+
+#[async_trait]
+impl EsmeLogic for ChannelLogic {
+    async fn deliver_sm(
+        &mut self,
+        pdu: &DeliverSmPdu,
+    ) -> Result<(), DeliverSmError> {
+        self.tx
+            .send(pdu.extract_receipted_message_id())
+            .map_err(|_| DeliverSmError::InternalError)
+    }
+}
@@ -0,0 +1,238 @@
+use async_trait::async_trait;
+use clap::Clap;
+use smpp::load::{load, LoadConfig, LoadReport, Percentiles};
+use smpp::message_unique_key::MessageUniqueKey;
+use smpp::smsc::{BindData, BindError, Smsc, SmscLogic, SubmitSmError};
+use smpp_pdu::pdu::tlvs::{KnownTlvTag, Tlv, Tlvs};
+use smpp_pdu::pdu::{
+    DeliverEsmClass, DeliverSmPdu, Pdu, SubmitSmPdu, SubmitSmRespPdu,
+};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+mod test_utils;
+
+use test_utils::TestServer;
+
+#[tokio::test]
+async fn submits_over_several_sessions_and_reports_errors_and_drs() {
+    let server = start_server(Logic::new(4, true)).await;
+    let config = config(
+        &server,
+        &["--sessions", "2", "--count", "20", "--rate", "1000"],
+    );
+
+    let report = load(&config).await.unwrap();
+
+    assert_eq!(report.sessions, 2);
+    assert_eq!(report.submitted, 20);
+    assert_eq!(report.accepted, 15);
+    assert_eq!(report.failed, 5);
+    assert_eq!(
+        report.errors,
+        vec![(String::from("0x00000058"), 5)]
+            .into_iter()
+            .collect::<BTreeMap<_, _>>()
+    );
+    assert_eq!(report.submit_sm_resp_ms.unwrap().count, 20);
+    assert_eq!(report.drs_received, 15);
+    assert_eq!(report.drs_missing, 0);
+    assert_eq!(report.drs_unmatched, 0);
+    assert_eq!(report.dr_ms.unwrap().count, 15);
+}
+
+#[tokio::test]
+async fn drs_that_do_not_arrive_are_reported_missing() {
+    let server = start_server(Logic::new(0, false)).await;
+    let config = config(
+        &server,
+        &["--count", "3", "--rate", "1000", "--dr-wait-secs", "0"],
+    );
+
+    let report = load(&config).await.unwrap();
+
+    assert_eq!(report.accepted, 3);
+    assert_eq!(report.drs_received, 0);
+    assert_eq!(report.drs_missing, 3);
+    assert_eq!(report.dr_ms, None);
+}
+
+#[tokio::test]
+async fn without_registered_delivery_no_drs_are_expected() {
+    let server = start_server(Logic::new(0, false)).await;
+    let config = config(
+        &server,
+        &[
+            "--count",
+            "3",
+            "--rate",
+            "1000",
+            "--registered-delivery",
+            "0",
+        ],
+    );
+
+    let report = load(&config).await.unwrap();
+
+    assert_eq!(report.accepted, 3);
+    assert_eq!(report.drs_missing, 0);
+}
+
+#[test]
+fn the_report_shows_percentiles_and_errors() {
+    let durations = (1..=100).map(Duration::from_millis).collect::<Vec<_>>();
+    let percentiles = Percentiles::from_durations(&durations).unwrap();
+    assert_eq!(
+        percentiles,
+        Percentiles {
+            count: 100,
+            min: 1.0,
+            p50: 50.0,
+            p90: 90.0,
+            p99: 99.0,
+            max: 100.0,
+        }
+    );
+    assert_eq!(Percentiles::from_durations(&[]), None);
+
+    let report = LoadReport {
+        sessions: 2,
+        submitted: 100,
+        accepted: 98,
+        failed: 2,
+        errors: vec![(String::from("0x00000058"), 2)].into_iter().collect(),
+        elapsed_secs: 2.0,
+        throughput: 50.0,
+        submit_sm_resp_ms: Some(percentiles),
+        drs_received: 97,
+        drs_missing: 1,
+        drs_unmatched: 0,
+        dr_ms: None,
+    };
+    assert_eq!(
+        report.to_string(),
+        "Sessions:        2\n\
+        Submitted:       100 in 2.00s (50.0/s)\n\
+        Accepted:        98\n\
+        Failed:          2\n  \
+          0x00000058: 2\n\
+        submit_sm_resp:  min 1.0ms  p50 50.0ms  p90 90.0ms  p99 99.0ms  \
+        max 100.0ms\n\
+        DRs:             97 received, 1 missing, 0 unmatched\n\
+        DR arrival:      none\n"
+    );
+}
+
+async fn start_server(logic: Logic) -> TestServer {
+    TestServer::start_with_logic_and_smsc_config(logic, |config| {
+        config.max_open_sockets = 10
+    })
+    .await
+    .unwrap()
+}
+
+fn config(server: &TestServer, args: &[&str]) -> LoadConfig {
+    let mut all_args = vec![
+        "smpp-load",
+        "--smsc-address",
+        &server.bind_address,
+        "--system-id",
+        "esmeid",
+        "--password",
+        "password",
+    ];
+    all_args.extend_from_slice(args);
+    LoadConfig::parse_from(all_args)
+}
+
+/// Accepts submit_sm, except every reject_every'th, which is throttled,
+/// and sends a DR straight away for each one accepted if send_drs
+struct Logic {
+    reject_every: u32,
+    send_drs: bool,
+    submitted: u32,
+}
+
+impl Logic {
+    fn new(reject_every: u32, send_drs: bool) -> Self {
+        Self {
+            reject_every,
+            send_drs,
+            submitted: 0,
+        }
+    }
+}
+
+#[async_trait]
+impl SmscLogic for Logic {
+    async fn bind(&mut self, _bind_data: &BindData) -> Result<(), BindError> {
+        Ok(())
+    }
+
+    async fn submit_sm(
+        &mut self,
+        smsc: Arc<Mutex<Smsc>>,
+        pdu: &SubmitSmPdu,
+        _sequence_number: u32,
+    ) -> Result<(SubmitSmRespPdu, MessageUniqueKey), SubmitSmError> {
+        self.submitted += 1;
+        if self.reject_every > 0
+            && self.submitted.is_multiple_of(self.reject_every)
+        {
+            return Err(SubmitSmError::Throttled);
+        }
+        let message_id = format!("m{}", self.submitted);
+        if self.send_drs {
+            let deliver_sm = dr(&message_id, pdu);
+            tokio::spawn(async move {
+                smsc.lock()
+                    .await
+                    .receive_pdu("MySupplier", deliver_sm)
+                    .await
+            });
+        }
+        Ok((
+            SubmitSmRespPdu::new(&message_id).unwrap(),
+            MessageUniqueKey::from_submit_sm(
+                String::from("MySupplier"),
+                message_id,
+                pdu,
+            ),
+        ))
+    }
+}
+
+fn dr(message_id: &str, submit_sm: &SubmitSmPdu) -> Pdu {
+    Pdu::new(
+        0x00,
+        1,
+        DeliverSmPdu::new(
+            "",
+            submit_sm.dest_addr_ton(),
+            submit_sm.dest_addr_npi(),
+            &submit_sm.destination_addr(),
+            submit_sm.source_addr_ton(),
+            submit_sm.source_addr_npi(),
+            &submit_sm.source_addr(),
+            DeliverEsmClass::SmscDeliveryReceipt as u8,
+            0x34,
+            1,
+            "",
+            "",
+            1,
+            0,
+            3,
+            0,
+            b"",
+            Tlvs::from(&[Tlv::new(
+                KnownTlvTag::receipted_message_id,
+                message_id.as_bytes(),
+            )]),
+        )
+        .unwrap()
+        .into(),
+    )
+    .unwrap()
+}
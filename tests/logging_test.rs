@@ -0,0 +1,162 @@
+use serde_json::Value;
+use smpp::extra_pdu::{ExtraPdu, ExtraPduBody};
+use smpp::logging::{redact, set_redaction, LoggedPdu, Redaction};
+use smpp_pdu::pdu::data::bind_data::BindData;
+use smpp_pdu::pdu::tlvs::{KnownTlvTag, Tlv, Tlvs};
+use smpp_pdu::pdu::{Pdu, SubmitSmPdu};
+use std::io;
+use std::sync::{Arc, Mutex};
+use tokio::io::AsyncWriteExt;
+
+mod test_utils;
+
+use test_utils::{TestClient, TestServer};
+
+#[test]
+fn unredacted_pdus_are_logged_whole() {
+    let pdu = submit_sm();
+
+    let logged = LoggedPdu::from(&pdu).with_redaction(Redaction::None);
+
+    assert_eq!(logged, format!("{:?}", pdu));
+}
+
+#[test]
+fn masked_pdus_show_the_end_of_addresses_and_no_content() {
+    let pdu = submit_sm();
+
+    let logged = LoggedPdu::from(&pdu).with_redaction(Redaction::Mask);
+
+    assert!(logged.starts_with("SubmitSm {"), "{}", logged);
+    assert!(logged.contains("source_addr: \"*****pany\""), "{}", logged);
+    assert!(
+        logged.contains("destination_addr: \"********2222\""),
+        "{}",
+        logged
+    );
+    assert!(logged.contains("short_message: <11 bytes>"), "{}", logged);
+    assert!(logged.contains("tlvs: [0x0424 <7 bytes>]"), "{}", logged);
+    assert!(!logged.contains("hello"), "{}", logged);
+    assert!(!logged.contains("payload"), "{}", logged);
+}
+
+#[test]
+fn hidden_pdus_show_no_addresses() {
+    let pdu = submit_sm();
+
+    let logged = LoggedPdu::from(&pdu).with_redaction(Redaction::Hide);
+
+    assert!(logged.contains("source_addr: \"<hidden>\""), "{}", logged);
+    assert!(!logged.contains("2222"), "{}", logged);
+}
+
+#[test]
+fn passwords_are_hidden_when_redacting() {
+    let pdu = ExtraPdu::new(
+        0,
+        1,
+        ExtraPduBody::BindTransceiver(
+            BindData::new("esmeid", "secret", "type", 0x34, 0, 0, "").unwrap(),
+        ),
+    )
+    .unwrap();
+
+    let logged = LoggedPdu::from(&pdu).with_redaction(Redaction::Mask);
+
+    assert!(logged.contains("system_id: \"esmeid\""), "{}", logged);
+    assert!(logged.contains("password: <hidden>"), "{}", logged);
+    assert!(!logged.contains("secret"), "{}", logged);
+}
+
+#[test]
+fn short_addresses_are_mostly_masked() {
+    assert_eq!(redact("447777222222", Redaction::Mask), "********2222");
+    assert_eq!(redact("12345", Redaction::Mask), "***45");
+    assert_eq!(redact("", Redaction::Mask), "");
+    assert_eq!(redact("12345", Redaction::None), "12345");
+}
+
+#[tokio::test]
+async fn lines_are_logged_as_json_inside_session_and_pdu_spans() {
+    let output = Arc::new(Mutex::new(Vec::new()));
+    let writer_output = Arc::clone(&output);
+    tracing_subscriber::fmt()
+        .json()
+        .with_current_span(false)
+        .with_span_list(true)
+        .with_writer(move || SharedWriter(Arc::clone(&writer_output)))
+        .try_init()
+        .expect("Another subscriber was already set");
+    set_redaction(Redaction::Mask);
+
+    let server = TestServer::start().await.unwrap();
+    let mut client = TestClient::connect_to(&server).await.unwrap();
+    client.bind_transceiver().await;
+    let mut bytes = Vec::new();
+    submit_sm().write(&mut bytes).await.unwrap();
+    client.stream.write_all(&bytes).await.unwrap();
+    client.read_pdu().await;
+
+    let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+    assert!(!output.contains("447777222222"), "{}", output);
+    assert!(!output.contains("password\""), "{}", output);
+    let line: Value = output
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap())
+        .find(|line| {
+            line["fields"]["message"]
+                .as_str()
+                .is_some_and(|m| m.starts_with("<= ") && m.contains("SubmitSm"))
+        })
+        .expect("No line for the submit_sm");
+    let spans = &line["spans"];
+    assert_eq!(spans[0]["name"], "session");
+    assert_eq!(spans[0]["system_id"], "esmeid");
+    assert_eq!(spans[0]["system_type"], "type");
+    assert_eq!(spans[1]["name"], "pdu");
+    assert_eq!(spans[1]["command"], "submit_sm");
+    assert_eq!(spans[1]["sequence_number"], 2);
+}
+
+struct SharedWriter(Arc<Mutex<Vec<u8>>>);
+
+impl io::Write for SharedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn submit_sm() -> Pdu {
+    Pdu::new(
+        0,
+        2,
+        SubmitSmPdu::new(
+            "",
+            0,
+            0,
+            "MyCompany",
+            1,
+            1,
+            "447777222222",
+            0,
+            0,
+            1,
+            "",
+            "",
+            1,
+            0,
+            3,
+            0,
+            b"hello world",
+            Tlvs::from(&[Tlv::new(KnownTlvTag::message_payload, b"payload")]),
+        )
+        .unwrap()
+        .into(),
+    )
+    .unwrap()
+}
@@ -0,0 +1,78 @@
+use smpp::sm_fields::SmFields;
+use smpp::smsc::message_id_map::rewrite_dr_message_id;
+use smpp_pdu::pdu::tlvs::{KnownTlvTag, Tlv};
+use smpp_pdu::pdu::DeliverSmPdu;
+
+#[test]
+fn receipted_message_id_and_text_are_both_rewritten() {
+    let dr = dr(
+        b"id:SUP123 sub:001 dlvrd:001 stat:DELIVRD",
+        Some(b"SUP123\0"),
+    );
+
+    let fields = fields(&rewrite_dr_message_id(&dr, "OUR9").unwrap());
+
+    assert_eq!(
+        fields.tlv(KnownTlvTag::receipted_message_id).unwrap().value,
+        b"OUR9\0"
+    );
+    assert_eq!(
+        fields.short_message,
+        b"id:OUR9 sub:001 dlvrd:001 stat:DELIVRD"
+    );
+}
+
+#[test]
+fn text_only_receipts_are_rewritten() {
+    let dr = dr(b"ID:SUP123", None);
+
+    let fields = fields(&rewrite_dr_message_id(&dr, "OUR9").unwrap());
+
+    assert!(fields.tlv(KnownTlvTag::receipted_message_id).is_none());
+    assert_eq!(fields.short_message, b"ID:OUR9");
+}
+
+#[test]
+fn only_a_whole_id_field_is_replaced() {
+    let dr = dr(b"msgid:X id:SUP123 err:000", None);
+
+    let fields = fields(&rewrite_dr_message_id(&dr, "OUR9").unwrap());
+
+    assert_eq!(fields.short_message, b"msgid:X id:OUR9 err:000");
+}
+
+#[test]
+fn text_without_an_id_field_is_unchanged() {
+    let dr = dr(b"hello", Some(b"SUP123"));
+
+    let fields = fields(&rewrite_dr_message_id(&dr, "OUR9").unwrap());
+
+    assert_eq!(
+        fields.tlv(KnownTlvTag::receipted_message_id).unwrap().value,
+        b"OUR9"
+    );
+    assert_eq!(fields.short_message, b"hello");
+}
+
+fn fields(pdu: &DeliverSmPdu) -> SmFields {
+    SmFields::from_sm_data(&pdu.0)
+}
+
+fn dr(
+    short_message: &[u8],
+    receipted_message_id: Option<&[u8]>,
+) -> DeliverSmPdu {
+    let mut fields = SmFields {
+        source_addr_ton: 1,
+        source_addr_npi: 1,
+        source_addr: String::from("447777222222"),
+        destination_addr: String::from("MyCompany"),
+        esm_class: 0x04,
+        short_message: short_message.to_vec(),
+        ..SmFields::default()
+    };
+    if let Some(id) = receipted_message_id {
+        fields.set_tlv(Tlv::new(KnownTlvTag::receipted_message_id, id));
+    }
+    fields.to_deliver_sm().unwrap()
+}
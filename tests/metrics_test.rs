@@ -0,0 +1,207 @@
+use async_trait::async_trait;
+use smpp::message_unique_key::MessageUniqueKey;
+use smpp::smsc::metrics::command_name;
+use smpp::smsc::{
+    start_metrics, BindData, BindError, Smsc, SmscLogic, SubmitSmError,
+};
+use smpp_pdu::pdu::tlvs::{KnownTlvTag, Tlv, Tlvs};
+use smpp_pdu::pdu::{
+    DeliverEsmClass, DeliverSmPdu, Pdu, SubmitSmPdu, SubmitSmRespPdu,
+};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+mod test_utils;
+
+use test_utils::{http_text, TestClient, TestServer};
+
+#[tokio::test]
+async fn pdus_are_counted_by_esme_command_and_status() {
+    let (server, metrics) = start().await;
+    let mut client = TestClient::connect_to(&server).await.unwrap();
+    client.bind_transceiver().await;
+    submit_sm(&mut client).await;
+
+    let text = scrape(metrics).await;
+
+    assert_has(
+        &text,
+        "smpp_pdus_total{command=\"bind_transceiver\",\
+        command_status=\"0x00000000\",direction=\"in\",system_id=\"\"} 1",
+    );
+    assert_has(
+        &text,
+        "smpp_pdus_total{command=\"submit_sm\",\
+        command_status=\"0x00000000\",direction=\"in\",\
+        system_id=\"esmeid\"} 1",
+    );
+    assert_has(
+        &text,
+        "smpp_pdus_total{command=\"submit_sm_resp\",\
+        command_status=\"0x00000000\",direction=\"out\",\
+        system_id=\"esmeid\"} 1",
+    );
+    assert_has(&text, "smpp_submit_sm_logic_seconds_count 1");
+}
+
+#[tokio::test]
+async fn dr_latency_and_gauges_are_reported() {
+    let (server, metrics) = start().await;
+    let mut client = TestClient::connect_to(&server).await.unwrap();
+    client.bind_transceiver().await;
+    submit_sm(&mut client).await;
+
+    server.receive_pdu("supplier", dr()).await.unwrap();
+    client.read_pdu().await;
+
+    let text = scrape(metrics).await;
+    assert_has(&text, "smpp_submit_to_dr_seconds_count 1");
+    assert_has(
+        &text,
+        "smpp_pdus_total{command=\"deliver_sm\",\
+        command_status=\"0x00000000\",direction=\"out\",\
+        system_id=\"esmeid\"} 1",
+    );
+    assert_has(&text, "smpp_open_sockets 1");
+    assert_has(&text, "smpp_max_open_sockets 2");
+    assert_has(&text, "smpp_bound_sessions 1");
+    assert_has(&text, "smpp_stored_messages 1");
+}
+
+#[tokio::test]
+async fn connections_over_the_limit_are_counted() {
+    let (server, metrics) = start().await;
+    let _client1 = TestClient::connect_to(&server).await.unwrap();
+    let _client2 = TestClient::connect_to(&server).await.unwrap();
+    let mut client3 = TestClient::connect_to(&server).await.unwrap();
+    // The SMSC closes the third connection straight away
+    assert!(client3.read_n_maybe(1).await.is_err());
+
+    let text = scrape(metrics).await;
+    assert_has(&text, "smpp_open_sockets 2");
+    assert_has(&text, "smpp_refused_connections_total 1");
+}
+
+#[test]
+fn commands_are_named_as_in_the_spec() {
+    assert_eq!(command_name(0x00000004), "submit_sm");
+    assert_eq!(command_name(0x80000015), "enquire_link_resp");
+    assert_eq!(command_name(0x00001234), "0x00001234");
+}
+
+struct Logic;
+
+#[async_trait]
+impl SmscLogic for Logic {
+    async fn bind(&mut self, _bind_data: &BindData) -> Result<(), BindError> {
+        Ok(())
+    }
+
+    async fn submit_sm(
+        &mut self,
+        _smsc: Arc<Mutex<Smsc>>,
+        pdu: &SubmitSmPdu,
+        _sequence_number: u32,
+    ) -> Result<(SubmitSmRespPdu, MessageUniqueKey), SubmitSmError> {
+        Ok((
+            SubmitSmRespPdu::new("mymsgid").unwrap(),
+            MessageUniqueKey::from_submit_sm(
+                String::from("supplier"),
+                String::from("mymsgid"),
+                pdu,
+            ),
+        ))
+    }
+}
+
+async fn start() -> (TestServer, SocketAddr) {
+    let server = TestServer::start_with_logic(Logic).await.unwrap();
+    let metrics = start_metrics(server.smsc.clone(), "127.0.0.1:0")
+        .await
+        .unwrap();
+    (server, metrics)
+}
+
+async fn scrape(metrics: SocketAddr) -> String {
+    let (status, text) = http_text(metrics, "GET", "/metrics", "").await;
+    assert_eq!(status, 200);
+    text
+}
+
+fn assert_has(text: &str, line: &str) {
+    assert!(
+        text.lines().any(|l| l == line),
+        "Expected '{}' in:\n{}",
+        line,
+        text
+    );
+}
+
+async fn submit_sm(client: &mut TestClient) {
+    let pdu = Pdu::new(
+        0,
+        2,
+        SubmitSmPdu::new(
+            "",
+            0,
+            0,
+            "MyCompany",
+            1,
+            1,
+            "447777222222",
+            0,
+            0,
+            1,
+            "",
+            "",
+            1,
+            0,
+            3,
+            0,
+            b"hi",
+            Tlvs::new(),
+        )
+        .unwrap()
+        .into(),
+    )
+    .unwrap();
+    let mut bytes = Vec::new();
+    pdu.write(&mut bytes).await.unwrap();
+    client.stream.write_all(&bytes).await.unwrap();
+    assert_eq!(client.read_pdu().await.command_status.value, 0);
+}
+
+fn dr() -> Pdu {
+    Pdu::new(
+        0,
+        1,
+        DeliverSmPdu::new(
+            "",
+            1,
+            1,
+            "447777222222",
+            0,
+            0,
+            "MyCompany",
+            DeliverEsmClass::SmscDeliveryReceipt as u8,
+            0x34,
+            0,
+            "",
+            "",
+            0,
+            0,
+            0,
+            0,
+            b"id:mymsgid stat:DELIVRD",
+            Tlvs::from(&[Tlv::new(
+                KnownTlvTag::receipted_message_id,
+                b"mymsgid",
+            )]),
+        )
+        .unwrap()
+        .into(),
+    )
+    .unwrap()
+}
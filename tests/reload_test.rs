@@ -0,0 +1,148 @@
+use smpp::examples::smsc_drs_after_1_sec::DrsAfter1Sec;
+use smpp::logging::{LogFormat, Redaction};
+use smpp::smsc::{AccountConfig, AccountStore, Smsc, SmscConfig};
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{sleep, Duration};
+
+mod test_utils;
+
+use test_utils::{test_bind_address, TempFile, TestClient, TestServer};
+
+#[tokio::test]
+async fn listeners_are_added_and_removed_on_reload() {
+    let server = TestServer::start().await.unwrap();
+    let additional = test_bind_address();
+
+    let mut config = base_config(&server);
+    config.additional_bind_addresses = vec![additional.clone()];
+    Smsc::reload(&server.smsc, config).await.unwrap();
+
+    let mut client = TestClient::connect_to(&TestServer {
+        smsc: server.smsc.clone(),
+        bind_address: additional.clone(),
+    })
+    .await
+    .unwrap();
+    client.bind_transceiver().await;
+
+    Smsc::reload(&server.smsc, base_config(&server))
+        .await
+        .unwrap();
+    sleep(Duration::from_millis(50)).await;
+
+    assert!(TcpStream::connect(&additional).await.is_err());
+    // The original listener is untouched
+    let mut client = TestClient::connect_to(&server).await.unwrap();
+    client.bind_transmitter().await;
+}
+
+#[tokio::test]
+async fn a_listener_that_cannot_bind_fails_the_whole_reload() {
+    let server = TestServer::start().await.unwrap();
+    let taken = test_bind_address();
+    let _other = TcpListener::bind(&taken).await.unwrap();
+
+    let mut config = base_config(&server);
+    config.additional_bind_addresses = vec![taken];
+    config.accounts = vec![account("esmeid", "password")];
+    assert!(Smsc::reload(&server.smsc, config).await.is_err());
+
+    // The accounts were not applied, so anyone may still bind
+    let mut client = TestClient::connect_to(&server).await.unwrap();
+    client.bind_transceiver_as("stranger").await;
+}
+
+#[tokio::test]
+async fn sessions_whose_accounts_are_removed_are_unbound() {
+    let server = TestServer::start_with_logic_and_smsc_config(
+        test_utils::DefaultLogic {},
+        |config| config.accounts = vec![account("esmeid", "password")],
+    )
+    .await
+    .unwrap();
+    let mut client = TestClient::connect_to(&server).await.unwrap();
+    client.bind_transceiver().await;
+
+    let mut config = base_config(&server);
+    config.accounts = vec![account("other", "password")];
+    Smsc::reload(&server.smsc, config).await.unwrap();
+
+    expect_unbind_and_close(&mut client).await;
+}
+
+#[tokio::test]
+async fn the_accounts_file_is_reread_and_disabled_accounts_unbound() {
+    let file = TempFile::new("accounts.toml", &accounts(true));
+    let logic =
+        DrsAfter1Sec::with_accounts(AccountStore::load(file.path()).unwrap());
+    let server = TestServer::start_with_logic(logic).await.unwrap();
+    let mut client = TestClient::connect_to(&server).await.unwrap();
+    client.bind_transceiver().await;
+
+    file.write(&accounts(false));
+    let mut config = base_config(&server);
+    config.accounts_file = Some(file.path_str().to_string());
+    Smsc::reload(&server.smsc, config).await.unwrap();
+
+    expect_unbind_and_close(&mut client).await;
+}
+
+/// Read an unbind, respond to it, and check the SMSC closes the connection
+async fn expect_unbind_and_close(client: &mut TestClient) {
+    let unbind = client.read_n(16).await;
+    assert_eq!(&unbind[0..8], b"\x00\x00\x00\x10\x00\x00\x00\x06");
+
+    let mut unbind_resp =
+        b"\x00\x00\x00\x10\x80\x00\x00\x06\x00\x00\x00\x00".to_vec();
+    unbind_resp.extend(&unbind[12..16]);
+    client.stream.write_all(&unbind_resp).await.unwrap();
+
+    assert!(client.read_n_maybe(1).await.is_err());
+}
+
+fn base_config(server: &TestServer) -> SmscConfig {
+    SmscConfig {
+        bind_address: server.bind_address.clone(),
+        max_open_sockets: 2,
+        system_id: String::from("TestServer"),
+        concat_timeout_secs: None,
+        unbind_timeout_secs: 10,
+        submit_sm_checks: Default::default(),
+        normalization_rules: Default::default(),
+        capture: Default::default(),
+        simulator: Default::default(),
+        faults: Default::default(),
+        config: None,
+        accounts_file: None,
+        script: None,
+        admin_address: None,
+        metrics_address: None,
+        log_level: String::from("trace"),
+        log_format: LogFormat::Text,
+        log_redaction: Redaction::None,
+        additional_bind_addresses: Vec::new(),
+        accounts: Vec::new(),
+        routes: Vec::new(),
+    }
+}
+
+fn account(system_id: &str, password: &str) -> AccountConfig {
+    AccountConfig {
+        system_id: String::from(system_id),
+        password_hash: bcrypt::hash(password, 4).unwrap(),
+        max_submits_per_sec: None,
+        bind_types: Vec::new(),
+        allowed_ips: Vec::new(),
+        max_sessions: None,
+    }
+}
+
+fn accounts(enabled: bool) -> String {
+    format!(
+        "[[accounts]]\nsystem_id = \"esmeid\"\npassword_hash = \"{}\"\n\
+        enabled = {}\n",
+        bcrypt::hash("password", 4).unwrap(),
+        enabled
+    )
+}
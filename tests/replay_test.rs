@@ -0,0 +1,384 @@
+use async_trait::async_trait;
+use clap::Clap;
+use smpp::capture::{CaptureFormat, CaptureTarget};
+use smpp::message_unique_key::MessageUniqueKey;
+use smpp::replay::{
+    compare, parse_trace, replay, Difference, ReplayConfig, Side, TracedPdu,
+};
+use smpp::smsc::{BindData, BindError, Smsc, SmscLogic, SubmitSmError};
+use smpp_pdu::pdu::tlvs::{KnownTlvTag, Tlv, Tlvs};
+use smpp_pdu::pdu::{
+    DeliverEsmClass, DeliverSmPdu, Pdu, SubmitSmPdu, SubmitSmRespPdu,
+};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+mod test_utils;
+
+use test_utils::{DefaultLogic, TestClient, TestServer};
+
+const ENQUIRE_LINK: &[u8; 16] =
+    b"\x00\x00\x00\x10\x00\x00\x00\x15\x00\x00\x00\x00\x00\x00\x00\x02";
+const ENQUIRE_LINK_RESP: &[u8; 16] =
+    b"\x00\x00\x00\x10\x80\x00\x00\x15\x00\x00\x00\x00\x00\x00\x00\x02";
+
+#[tokio::test]
+async fn replaying_a_hex_capture_to_the_same_smsc_finds_no_differences() {
+    let dir = capture_dir("hex");
+    let server = start(&dir, CaptureFormat::Hex, DefaultLogic {}).await;
+    let trace = capture_session(&server, &dir, peer()).await;
+
+    let differences = replay(&config(&trace, &server, &[]), read(&trace))
+        .await
+        .unwrap();
+
+    assert_eq!(differences, vec![]);
+}
+
+#[tokio::test]
+async fn pcap_captures_are_read_as_the_same_pdus_as_hex() {
+    let hex_dir = capture_dir("same_hex");
+    let hex_server = start(&hex_dir, CaptureFormat::Hex, DefaultLogic {}).await;
+    let pcap_dir = capture_dir("same_pcap");
+    let pcap_server =
+        start(&pcap_dir, CaptureFormat::Pcap, DefaultLogic {}).await;
+
+    let hex = read(&capture_session(&hex_server, &hex_dir, peer()).await);
+    let pcap_trace = capture_session(&pcap_server, &pcap_dir, peer()).await;
+    let pcap = read(&pcap_trace);
+
+    let summary = |pdus: &[TracedPdu]| -> Vec<(Side, String, u32)> {
+        pdus.iter()
+            .map(|p| (p.side, p.command(), p.command_status()))
+            .collect()
+    };
+    assert_eq!(summary(&pcap), summary(&hex));
+    assert_eq!(
+        summary(&pcap)[..2],
+        [
+            (Side::Esme, String::from("bind_transceiver"), 0),
+            (Side::Smsc, String::from("bind_transceiver_resp"), 0)
+        ]
+    );
+    let differences =
+        replay(&config(&pcap_trace, &pcap_server, &["--speed", "10"]), pcap)
+            .await
+            .unwrap();
+    assert_eq!(differences, vec![]);
+}
+
+#[tokio::test]
+async fn different_command_statuses_are_reported() {
+    let dir = capture_dir("status");
+    let server = start(&dir, CaptureFormat::Hex, DefaultLogic {}).await;
+    let trace = capture_session(&server, &dir, peer()).await;
+    let other_server = start(&dir, CaptureFormat::Hex, QueueFullLogic {}).await;
+
+    let differences = replay(&config(&trace, &other_server, &[]), read(&trace))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        differences,
+        vec![Difference::CommandStatus {
+            pdu: String::from("submit_sm_resp sequence_number=3"),
+            expected: 0x08,
+            actual: 0x14,
+        }]
+    );
+    assert_eq!(
+        differences[0].to_string(),
+        "submit_sm_resp sequence_number=3: expected command_status \
+        0x00000008, got 0x00000014"
+    );
+}
+
+#[tokio::test]
+async fn captures_without_the_bind_need_credentials_to_replay() {
+    let dir = capture_dir("no_bind");
+    let server = start(&dir, CaptureFormat::Hex, DefaultLogic {}).await;
+    let trace =
+        capture_session(&server, &dir, CaptureTarget::esme("esmeid")).await;
+    assert_eq!(read(&trace)[0].command(), "bind_transceiver_resp");
+
+    let result = replay(&config(&trace, &server, &[]), read(&trace)).await;
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "The trace does not include the bind, so --system-id and \
+        --password must be given"
+    );
+
+    let differences = replay(
+        &config(
+            &trace,
+            &server,
+            &["--system-id", "esmeid", "--password", "password"],
+        ),
+        read(&trace),
+    )
+    .await
+    .unwrap();
+    assert_eq!(differences, vec![]);
+}
+
+#[test]
+fn missing_unexpected_and_reordered_pdus_are_reported() {
+    let resp = |command_id: u32, seq: u32, body: &[u8]| TracedPdu {
+        at: Duration::from_secs(0),
+        side: Side::Smsc,
+        bytes: pdu_bytes(command_id, seq, body),
+    };
+    let expected = vec![
+        resp(0x80000004, 2, b"id1\0"),
+        resp(0x80000015, 3, b""),
+        resp(0x80000004, 4, b"id2\0"),
+        resp(0x00000005, 1, b"dr"),
+    ];
+    let actual = vec![
+        resp(0x80000015, 3, b""),
+        // message_ids are not compared
+        resp(0x80000004, 2, b"other\0"),
+        resp(0x80000006, 5, b""),
+        resp(0x00000005, 9, b"DR"),
+    ];
+
+    let differences = compare(&expected, &actual, false);
+
+    assert_eq!(
+        differences,
+        vec![
+            Difference::Missing(String::from(
+                "submit_sm_resp sequence_number=4"
+            )),
+            Difference::Unexpected(String::from(
+                "unbind_resp sequence_number=5"
+            )),
+            Difference::Content {
+                pdu: String::from("deliver_sm sequence_number=1"),
+                expected: String::from("64 72"),
+                actual: String::from("44 52"),
+            },
+            Difference::Order {
+                expected: vec![
+                    String::from("submit_sm_resp sequence_number=2"),
+                    String::from("enquire_link_resp sequence_number=3"),
+                    String::from("deliver_sm sequence_number=1"),
+                ],
+                actual: vec![
+                    String::from("enquire_link_resp sequence_number=3"),
+                    String::from("submit_sm_resp sequence_number=2"),
+                    String::from("deliver_sm sequence_number=1"),
+                ],
+            },
+        ]
+    );
+    assert_eq!(compare(&expected, &actual, true).len(), 3);
+}
+
+#[test]
+fn dr_message_ids_and_dates_are_not_compared() {
+    let dr = |text: &str, message_id: &str| TracedPdu {
+        at: Duration::from_secs(0),
+        side: Side::Smsc,
+        bytes: dr_bytes(text, message_id),
+    };
+    let expected = vec![dr(
+        "id:abc123 sub:001 dlvrd:001 submit date:2107121200 \
+        done date:2107121201 stat:DELIVRD err:000 text:",
+        "abc123",
+    )];
+    let same = vec![dr(
+        "id:xyz78901 sub:001 dlvrd:001 submit date:2610181200 \
+        done date:2610181205 stat:DELIVRD err:000 text:",
+        "xyz78901",
+    )];
+    let different = vec![dr(
+        "id:xyz78901 sub:001 dlvrd:000 submit date:2610181200 \
+        done date:2610181205 stat:UNDELIV err:001 text:",
+        "xyz78901",
+    )];
+
+    assert_eq!(compare(&expected, &same, false), vec![]);
+    assert!(matches!(
+        compare(&expected, &different, false).as_slice(),
+        [Difference::Content { .. }]
+    ));
+}
+
+struct QueueFullLogic {}
+
+#[async_trait]
+impl SmscLogic for QueueFullLogic {
+    async fn bind(&mut self, _bind_data: &BindData) -> Result<(), BindError> {
+        Ok(())
+    }
+
+    async fn submit_sm(
+        &mut self,
+        _smsc: Arc<Mutex<Smsc>>,
+        _pdu: &SubmitSmPdu,
+        _sequence_number: u32,
+    ) -> Result<(SubmitSmRespPdu, MessageUniqueKey), SubmitSmError> {
+        Err(SubmitSmError::MessageQueueFull)
+    }
+}
+
+async fn start<L: SmscLogic + Send + Sync + 'static>(
+    dir: &Path,
+    format: CaptureFormat,
+    logic: L,
+) -> TestServer {
+    let dir = dir.to_str().unwrap().to_string();
+    TestServer::start_with_logic_and_smsc_config(logic, |config| {
+        config.max_open_sockets = 10;
+        config.capture.capture_dir = Some(dir);
+        config.capture.capture_format = format;
+    })
+    .await
+    .unwrap()
+}
+
+fn peer() -> CaptureTarget {
+    CaptureTarget::peer("127.0.0.1")
+}
+
+/// Capture a session that binds, sends enquire_link and then a submit_sm
+/// that the SMSC rejects, returning the capture file
+async fn capture_session(
+    server: &TestServer,
+    dir: &Path,
+    target: CaptureTarget,
+) -> PathBuf {
+    let capturer = server.smsc.lock().await.capturer();
+    capturer.start(target.clone()).unwrap();
+
+    let mut client = TestClient::connect_to(server).await.unwrap();
+    client.bind_transceiver().await;
+    client
+        .send_and_expect_response(ENQUIRE_LINK, ENQUIRE_LINK_RESP)
+        .await;
+    client.stream.write_all(&submit_sm()).await.unwrap();
+    client.read_pdu().await;
+
+    capturer.stop(&target);
+    capturer.flush();
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .collect();
+    assert_eq!(files.len(), 1, "{:?}", files);
+    files.pop().unwrap()
+}
+
+fn config(trace: &Path, server: &TestServer, args: &[&str]) -> ReplayConfig {
+    let mut all_args = vec![
+        "replay",
+        trace.to_str().unwrap(),
+        "--address",
+        &server.bind_address,
+        "--wait-secs",
+        "2",
+    ];
+    all_args.extend_from_slice(args);
+    if !args.contains(&"--speed") {
+        all_args.extend_from_slice(&["--speed", "0"]);
+    }
+    ReplayConfig::parse_from(all_args)
+}
+
+fn read(trace: &Path) -> Vec<TracedPdu> {
+    parse_trace(&fs::read(trace).unwrap()).unwrap()
+}
+
+fn capture_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "smpp_replay_test_{}_{}",
+        std::process::id(),
+        name
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+fn submit_sm() -> Vec<u8> {
+    let pdu = Pdu::new(
+        0,
+        3,
+        SubmitSmPdu::new(
+            "",
+            0,
+            0,
+            "MyCompany",
+            1,
+            1,
+            "447777222222",
+            0,
+            0,
+            1,
+            "",
+            "",
+            1,
+            0,
+            3,
+            0,
+            b"hello",
+            Tlvs::new(),
+        )
+        .unwrap()
+        .into(),
+    )
+    .unwrap();
+    let mut bytes = Vec::new();
+    futures::executor::block_on(pdu.write(&mut bytes)).unwrap();
+    bytes
+}
+
+fn dr_bytes(text: &str, message_id: &str) -> Vec<u8> {
+    let pdu = Pdu::new(
+        0,
+        1,
+        DeliverSmPdu::new(
+            "",
+            1,
+            1,
+            "447777222222",
+            0,
+            0,
+            "MyCompany",
+            DeliverEsmClass::SmscDeliveryReceipt as u8,
+            0,
+            1,
+            "",
+            "",
+            0,
+            0,
+            0,
+            0,
+            text.as_bytes(),
+            Tlvs::from(&[Tlv::new(
+                KnownTlvTag::receipted_message_id,
+                message_id.as_bytes(),
+            )]),
+        )
+        .unwrap()
+        .into(),
+    )
+    .unwrap();
+    let mut bytes = Vec::new();
+    futures::executor::block_on(pdu.write(&mut bytes)).unwrap();
+    bytes
+}
+
+fn pdu_bytes(command_id: u32, sequence_number: u32, body: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&(16 + body.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(&command_id.to_be_bytes());
+    bytes.extend_from_slice(&0u32.to_be_bytes());
+    bytes.extend_from_slice(&sequence_number.to_be_bytes());
+    bytes.extend_from_slice(body);
+    bytes
+}
@@ -0,0 +1,388 @@
+use async_trait::async_trait;
+use futures::FutureExt;
+use smpp::address::NormalizationRules;
+use smpp::capture::CaptureConfig;
+use smpp::concatenation::ESM_CLASS_UDHI;
+use smpp::esme::{
+    DeliverSmError, EsmeClient, EsmeConfig, EsmeError, EsmeLogic,
+};
+use smpp::faults::FaultConfig;
+use smpp::logging::{LogFormat, Redaction};
+use smpp::message_unique_key::MessageUniqueKey;
+use smpp::router::{Router, RouterConfig};
+use smpp::routing::RoutingDecision;
+use smpp::simulator::SimulatorConfig;
+use smpp::sm_fields::SmFields;
+use smpp::smpp_connection::EsmeId;
+use smpp::smsc::{
+    BindData, BindError, Smsc, SmscConfig, SmscLogic, SubmitSmChecks,
+    SubmitSmError, SubmitSmFuture,
+};
+use smpp_pdu::pdu::tlvs::{KnownTlvTag, Tlv, Tlvs};
+use smpp_pdu::pdu::{
+    DeliverEsmClass, DeliverSmPdu, Pdu, SubmitSmPdu, SubmitSmRespPdu,
+};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, Barrier, Mutex};
+use tokio::time::timeout;
+
+mod test_utils;
+
+use test_utils::{test_bind_address, DefaultLogic, TestServer};
+
+#[tokio::test]
+async fn mts_are_forwarded_upstream_and_drs_come_back() {
+    let upstream = TestServer::start_with_logic(UpstreamLogic {})
+        .await
+        .unwrap();
+    let (_router, esme, mut received) = start_router(&upstream).await;
+
+    // The ESME gets our message_id, not the upstream's
+    let message_id = esme.submit_sm(submit_sm()).await.unwrap();
+    assert_ne!(message_id, "up-1");
+
+    // The upstream's DR reaches the ESME through the router, with our
+    // message_id in place of the upstream's
+    upstream
+        .receive_pdu("supplier", dr_pdu("up-1"))
+        .await
+        .unwrap();
+    let dr = received.recv().await.unwrap();
+    assert_eq!(dr.source_addr, "447777222222");
+    assert_eq!(
+        dr.tlv(KnownTlvTag::receipted_message_id).unwrap().value,
+        message_id.as_bytes()
+    );
+    assert_eq!(
+        dr.short_message,
+        format!("id:{} sub:001 dlvrd:001 stat:DELIVRD", message_id)
+            .into_bytes()
+    );
+}
+
+#[tokio::test]
+async fn upstream_errors_are_returned_to_the_esme() {
+    let upstream = TestServer::start_with_logic(DefaultLogic {}).await.unwrap();
+    let (_router, esme, _received) = start_router(&upstream).await;
+
+    // The upstream responded ESME_RSYSERR, which the ESME sees as
+    // ESME_RSUBMITFAIL
+    match esme.submit_sm(submit_sm()).await {
+        Err(EsmeError::ErrorResponse(0x00000045)) => {}
+        other => panic!("Expected ESME_RSUBMITFAIL but got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn mts_from_different_sessions_are_forwarded_at_the_same_time() {
+    // Each submit_sm upstream waits until both have arrived, so this only
+    // finishes if the router forwards the second before the first is
+    // answered
+    let upstream = TestServer::start_with_logic(GatedUpstreamLogic {
+        barrier: Arc::new(Barrier::new(2)),
+    })
+    .await
+    .unwrap();
+    // Two binds upstream, since the upstream answers each session's
+    // PDUs in turn
+    let (_router, bind_address) = start_router_only(&upstream, 2, None).await;
+    let (esme, _received) = connect_esme(&bind_address).await;
+    let (other_esme, _other_received) = connect_esme(&bind_address).await;
+
+    let both = futures::future::join(
+        esme.submit_sm(submit_sm()),
+        other_esme.submit_sm(submit_sm()),
+    );
+    let (first, second) = timeout(Duration::from_secs(5), both)
+        .await
+        .expect("MTs were forwarded one at a time");
+    assert!(first.is_ok());
+    assert!(second.is_ok());
+}
+
+#[tokio::test]
+async fn reassembled_parts_are_forwarded_with_their_udh() {
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let upstream = TestServer::start_with_logic(RecordingUpstreamLogic {
+        received: Arc::clone(&received),
+    })
+    .await
+    .unwrap();
+    let (_router, bind_address) =
+        start_router_only(&upstream, 1, Some(60)).await;
+    let (esme, _received) = connect_esme(&bind_address).await;
+
+    for (part_number, text) in [(1, &b"hel"[..]), (2, &b"lo"[..])] {
+        let mut short_message = vec![0x05, 0x00, 0x03, 0x2a, 2, part_number];
+        short_message.extend(text);
+        let pdu = SmFields {
+            esm_class: ESM_CLASS_UDHI,
+            short_message,
+            ..SmFields::from_sm_data(&submit_sm().0)
+        }
+        .to_submit_sm()
+        .unwrap();
+        esme.submit_sm(pdu).await.unwrap();
+    }
+
+    let forwarded = timeout(Duration::from_secs(5), async {
+        loop {
+            let received = received.lock().await.clone();
+            if received.len() == 2 {
+                return received;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("The parts were not forwarded");
+    for (fields, (part_number, text)) in
+        forwarded.iter().zip([(1, &b"hel"[..]), (2, &b"lo"[..])])
+    {
+        assert_ne!(fields.esm_class & ESM_CLASS_UDHI, 0);
+        let mut expected = vec![0x05, 0x00, 0x03, 0x2a, 2, part_number];
+        expected.extend(text);
+        assert_eq!(fields.short_message, expected);
+    }
+}
+
+async fn start_router(
+    upstream: &TestServer,
+) -> (Router, EsmeClient, mpsc::UnboundedReceiver<SmFields>) {
+    let (router, bind_address) = start_router_only(upstream, 1, None).await;
+    let (esme, received) = connect_esme(&bind_address).await;
+    (router, esme, received)
+}
+
+async fn start_router_only(
+    upstream: &TestServer,
+    upstream_binds: usize,
+    concat_timeout_secs: Option<u64>,
+) -> (Router, String) {
+    let bind_address = test_bind_address();
+    let router = Router::start(RouterConfig {
+        smsc: SmscConfig {
+            bind_address: bind_address.clone(),
+            max_open_sockets: 2,
+            system_id: String::from("router"),
+            concat_timeout_secs,
+            unbind_timeout_secs: 10,
+            submit_sm_checks: SubmitSmChecks::default(),
+            normalization_rules: NormalizationRules::default(),
+            capture: CaptureConfig::default(),
+            simulator: SimulatorConfig::default(),
+            faults: FaultConfig::default(),
+            config: None,
+            accounts_file: None,
+            script: None,
+            admin_address: None,
+            metrics_address: None,
+            log_level: String::from("info"),
+            log_format: LogFormat::Text,
+            log_redaction: Redaction::None,
+            additional_bind_addresses: Vec::new(),
+            accounts: Vec::new(),
+            routes: Vec::new(),
+        },
+        upstream_address: upstream.bind_address.clone(),
+        upstream_system_id: String::from("router"),
+        upstream_password: String::from("password"),
+        upstream_system_type: String::new(),
+        upstream_binds,
+        upstream_namespace: String::from("upstream"),
+    })
+    .await
+    .unwrap();
+    (router, bind_address)
+}
+
+async fn connect_esme(
+    bind_address: &str,
+) -> (EsmeClient, mpsc::UnboundedReceiver<SmFields>) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let esme = EsmeClient::connect(
+        EsmeConfig::new(bind_address, "esmeid", "password"),
+        ChannelLogic { received: tx },
+    )
+    .await
+    .unwrap();
+    (esme, rx)
+}
+
+/// Passes each deliver_sm to a channel
+struct ChannelLogic {
+    received: mpsc::UnboundedSender<SmFields>,
+}
+
+#[async_trait]
+impl EsmeLogic for ChannelLogic {
+    async fn deliver_sm(
+        &mut self,
+        pdu: &DeliverSmPdu,
+    ) -> Result<(), DeliverSmError> {
+        self.received
+            .send(SmFields::from_sm_data(&pdu.0))
+            .map_err(|_| DeliverSmError::InternalError)
+    }
+}
+
+/// The upstream SMSC: accepts every MT, with message_id up-1
+struct UpstreamLogic {}
+
+#[async_trait]
+impl SmscLogic for UpstreamLogic {
+    async fn bind(&mut self, _bind_data: &BindData) -> Result<(), BindError> {
+        Ok(())
+    }
+
+    async fn submit_sm(
+        &mut self,
+        _smsc: Arc<Mutex<Smsc>>,
+        pdu: &SubmitSmPdu,
+        _sequence_number: u32,
+    ) -> Result<(SubmitSmRespPdu, MessageUniqueKey), SubmitSmError> {
+        Ok((
+            SubmitSmRespPdu::new("up-1").unwrap(),
+            MessageUniqueKey::from_submit_sm(
+                String::from("supplier"),
+                String::from("up-1"),
+                pdu,
+            ),
+        ))
+    }
+}
+
+/// An upstream SMSC that accepts every MT and records its fields
+struct RecordingUpstreamLogic {
+    received: Arc<Mutex<Vec<SmFields>>>,
+}
+
+#[async_trait]
+impl SmscLogic for RecordingUpstreamLogic {
+    async fn bind(&mut self, _bind_data: &BindData) -> Result<(), BindError> {
+        Ok(())
+    }
+
+    async fn submit_sm(
+        &mut self,
+        smsc: Arc<Mutex<Smsc>>,
+        pdu: &SubmitSmPdu,
+        _sequence_number: u32,
+    ) -> Result<(SubmitSmRespPdu, MessageUniqueKey), SubmitSmError> {
+        self.received
+            .lock()
+            .await
+            .push(SmFields::from_sm_data(&pdu.0));
+        let message_id = smsc.lock().await.next_message_id();
+        Ok((
+            SubmitSmRespPdu::new(&message_id).unwrap(),
+            MessageUniqueKey::from_submit_sm(
+                String::from("supplier"),
+                message_id,
+                pdu,
+            ),
+        ))
+    }
+}
+
+/// An upstream SMSC that holds each MT until another arrives
+struct GatedUpstreamLogic {
+    barrier: Arc<Barrier>,
+}
+
+#[async_trait]
+impl SmscLogic for GatedUpstreamLogic {
+    async fn bind(&mut self, _bind_data: &BindData) -> Result<(), BindError> {
+        Ok(())
+    }
+
+    async fn submit_sm(
+        &mut self,
+        _smsc: Arc<Mutex<Smsc>>,
+        _pdu: &SubmitSmPdu,
+        _sequence_number: u32,
+    ) -> Result<(SubmitSmRespPdu, MessageUniqueKey), SubmitSmError> {
+        Err(SubmitSmError::InternalError)
+    }
+
+    fn submit_sm_unlocked(
+        &mut self,
+        smsc: Arc<Mutex<Smsc>>,
+        pdu: &SubmitSmPdu,
+        _esme_id: &EsmeId,
+        _route: Option<&RoutingDecision>,
+    ) -> Option<SubmitSmFuture> {
+        let barrier = Arc::clone(&self.barrier);
+        let key = MessageUniqueKey::from_submit_sm(
+            String::from("supplier"),
+            String::from("up-1"),
+            pdu,
+        );
+        Some(
+            async move {
+                barrier.wait().await;
+                let message_id = smsc.lock().await.next_message_id();
+                Ok((SubmitSmRespPdu::new(&message_id).unwrap(), key))
+            }
+            .boxed(),
+        )
+    }
+}
+
+fn submit_sm() -> SubmitSmPdu {
+    SubmitSmPdu::new(
+        "",
+        0,
+        0,
+        "MyCompany",
+        1,
+        1,
+        "447777222222",
+        0,
+        0,
+        1,
+        "",
+        "",
+        1,
+        0,
+        0,
+        0,
+        b"hello",
+        Tlvs::new(),
+    )
+    .unwrap()
+}
+
+fn dr_pdu(message_id: &str) -> Pdu {
+    Pdu::new(
+        0,
+        0x6d,
+        DeliverSmPdu::new(
+            "",
+            1,
+            1,
+            "447777222222",
+            0,
+            0,
+            "MyCompany",
+            DeliverEsmClass::SmscDeliveryReceipt as u8,
+            0,
+            1,
+            "",
+            "",
+            0,
+            0,
+            0,
+            0,
+            b"id:up-1 sub:001 dlvrd:001 stat:DELIVRD",
+            Tlvs::from(&[Tlv::new(
+                KnownTlvTag::receipted_message_id,
+                message_id.as_bytes(),
+            )]),
+        )
+        .unwrap()
+        .into(),
+    )
+    .unwrap()
+}
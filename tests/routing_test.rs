@@ -0,0 +1,229 @@
+use async_trait::async_trait;
+use smpp::address::Address;
+use smpp::message_unique_key::MessageUniqueKey;
+use smpp::routing::{Route, RouteTarget, RoutingDecision, RoutingTable};
+use smpp::smsc::{BindData, BindError, Smsc, SmscLogic, SubmitSmError};
+use smpp_pdu::pdu::tlvs::Tlvs;
+use smpp_pdu::pdu::{Pdu, SubmitEsmClass, SubmitSmPdu, SubmitSmRespPdu};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+mod test_utils;
+
+use test_utils::TestSetup;
+
+#[test]
+fn the_longest_matching_prefix_wins() {
+    let table = RoutingTable::new(vec![
+        Route::new("44", "uk", 2.0),
+        Route::new("447", "uk_mobile", 3.0),
+        Route::new("", "default", 1.0),
+    ]);
+
+    assert_eq!(supplier(&table, "acc", "Me", "447700900123"), "uk_mobile");
+    assert_eq!(supplier(&table, "acc", "Me", "442079460000"), "uk");
+    assert_eq!(supplier(&table, "acc", "Me", "33612345678"), "default");
+}
+
+#[test]
+fn no_decision_if_no_route_matches() {
+    let table = RoutingTable::new(vec![Route::new("44", "uk", 1.0)]);
+
+    assert!(table
+        .route("acc", "Me", &Address::new(1, 1, "33612345678"))
+        .is_none());
+}
+
+#[test]
+fn the_cheapest_route_wins_among_equal_prefixes() {
+    let table = RoutingTable::new(vec![
+        Route::new("44", "expensive", 3.0),
+        Route::new("44", "cheap", 1.5),
+    ]);
+
+    assert_eq!(supplier(&table, "acc", "Me", "447700900123"), "cheap");
+}
+
+#[test]
+fn per_account_routes_override_general_ones() {
+    let table = RoutingTable::new(vec![
+        Route::new("447", "uk_mobile", 1.0),
+        Route {
+            account: Some(String::from("vip")),
+            ..Route::new("44", "premium", 5.0)
+        },
+    ]);
+
+    assert_eq!(supplier(&table, "vip", "Me", "447700900123"), "premium");
+    assert_eq!(supplier(&table, "other", "Me", "447700900123"), "uk_mobile");
+}
+
+#[test]
+fn sender_id_routes_win_over_routes_for_any_sender() {
+    let table = RoutingTable::new(vec![
+        Route::new("44", "uk", 1.0),
+        Route {
+            sender_id: Some(String::from("MyBank")),
+            ..Route::new("44", "secure", 4.0)
+        },
+    ]);
+
+    assert_eq!(supplier(&table, "acc", "MyBank", "447700900123"), "secure");
+    assert_eq!(supplier(&table, "acc", "Shop", "447700900123"), "uk");
+}
+
+#[test]
+fn traffic_is_split_between_targets_by_weight() {
+    let table = RoutingTable::new(vec![Route {
+        targets: vec![RouteTarget::new("a", 3), RouteTarget::new("b", 1)],
+        ..Route::new("44", "unused", 1.0)
+    }]);
+
+    let suppliers: Vec<String> = (0..8)
+        .map(|_| supplier(&table, "acc", "Me", "447700900123"))
+        .collect();
+
+    assert_eq!(suppliers.iter().filter(|s| *s == "a").count(), 6);
+    assert_eq!(suppliers.iter().filter(|s| *s == "b").count(), 2);
+}
+
+#[test]
+fn failover_list_excludes_the_chosen_supplier() {
+    let table = RoutingTable::new(vec![Route {
+        failover: vec![String::from("uk"), String::from("backup")],
+        ..Route::new("44", "uk", 1.0)
+    }]);
+
+    let decision = table
+        .route("acc", "Me", &Address::new(1, 1, "447700900123"))
+        .unwrap();
+
+    assert_eq!(decision.supplier, "uk");
+    assert_eq!(decision.failover, vec![String::from("backup")]);
+    assert_eq!(decision.route.cost, 1.0);
+}
+
+#[tokio::test]
+async fn logic_is_given_the_route_for_the_normalized_destination() {
+    let logic = Logic {};
+    let mut t = TestSetup::new_with_logic(logic).await;
+    t.server
+        .smsc
+        .lock()
+        .await
+        .set_routing_table(RoutingTable::new(vec![
+            Route::new("447", "uk_mobile", 1.0),
+            Route::new("", "default", 1.0),
+        ]));
+    t.client.bind_transceiver().await;
+
+    // Logic returns the chosen supplier as the message_id
+    t.client
+        .send_and_expect_response(
+            &submit_sm(0x2f, "00447700900123").await,
+            &submit_sm_resp(0x2f, "uk_mobile").await,
+        )
+        .await;
+    t.client
+        .send_and_expect_response(
+            &submit_sm(0x30, "0033612345678").await,
+            &submit_sm_resp(0x30, "default").await,
+        )
+        .await;
+}
+
+fn supplier(
+    table: &RoutingTable,
+    account: &str,
+    sender_id: &str,
+    destination: &str,
+) -> String {
+    table
+        .route(account, sender_id, &Address::new(1, 1, destination))
+        .unwrap()
+        .supplier
+}
+
+struct Logic {}
+
+#[async_trait]
+impl SmscLogic for Logic {
+    async fn bind(&mut self, _bind_data: &BindData) -> Result<(), BindError> {
+        Ok(())
+    }
+
+    async fn submit_sm(
+        &mut self,
+        _smsc: Arc<Mutex<Smsc>>,
+        _pdu: &SubmitSmPdu,
+        _sequence_number: u32,
+    ) -> Result<(SubmitSmRespPdu, MessageUniqueKey), SubmitSmError> {
+        Err(SubmitSmError::InternalError)
+    }
+
+    async fn submit_sm_with_route(
+        &mut self,
+        _smsc: Arc<Mutex<Smsc>>,
+        pdu: &SubmitSmPdu,
+        _sequence_number: u32,
+        route: Option<&RoutingDecision>,
+    ) -> Result<(SubmitSmRespPdu, MessageUniqueKey), SubmitSmError> {
+        let supplier =
+            route.ok_or(SubmitSmError::SubmitFailed)?.supplier.clone();
+        Ok((
+            SubmitSmRespPdu::new(&supplier).unwrap(),
+            MessageUniqueKey::from_submit_sm(
+                String::from("testsystem"),
+                supplier,
+                pdu,
+            ),
+        ))
+    }
+}
+
+async fn submit_sm(sequence_number: u32, destination_addr: &str) -> Vec<u8> {
+    let pdu: Pdu = Pdu::new(
+        0,
+        sequence_number,
+        SubmitSmPdu::new(
+            "",
+            0,
+            0,
+            "MyCompany",
+            0,
+            0,
+            destination_addr,
+            SubmitEsmClass::Default as u8,
+            0x34,
+            1,
+            "",
+            "",
+            1,
+            0,
+            3,
+            0,
+            b"hi",
+            Tlvs::new(),
+        )
+        .unwrap()
+        .into(),
+    )
+    .unwrap();
+
+    let mut ret: Vec<u8> = Vec::new();
+    pdu.write(&mut ret).await.unwrap();
+    ret
+}
+
+async fn submit_sm_resp(sequence_number: u32, msgid: &str) -> Vec<u8> {
+    let pdu: Pdu = Pdu::new(
+        0,
+        sequence_number,
+        SubmitSmRespPdu::new(msgid).unwrap().into(),
+    )
+    .unwrap();
+
+    let mut ret: Vec<u8> = Vec::new();
+    pdu.write(&mut ret).await.unwrap();
+    ret
+}
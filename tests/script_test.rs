@@ -0,0 +1,332 @@
+use async_trait::async_trait;
+use futures::FutureExt;
+use smpp::concatenation::ESM_CLASS_UDHI;
+use smpp::esme::{
+    DeliverSmError, EsmeClient, EsmeConfig, EsmeError, EsmeLogic,
+};
+use smpp::message_unique_key::MessageUniqueKey;
+use smpp::routing::RoutingDecision;
+use smpp::script::{Script, ScriptRule, Scripted, TlvPattern};
+use smpp::sm_fields::SmFields;
+use smpp::smpp_connection::EsmeId;
+use smpp::smsc::{
+    BindData, BindError, Smsc, SmscLogic, SubmitSmError, SubmitSmFuture,
+};
+use smpp_pdu::pdu::tlvs::{KnownTlvTag, Tlv};
+use smpp_pdu::pdu::{DeliverSmPdu, SubmitSmPdu, SubmitSmRespPdu};
+use std::sync::Arc;
+use tokio::sync::{mpsc, Mutex};
+
+mod test_utils;
+
+use test_utils::{DefaultLogic, TempFile, TestServer};
+
+const RULES: &str = r#"
+[[rules]]
+destination_addr = "+447700900001"
+command_status = "ESME_RINVDSTADR"
+
+[[rules]]
+data_coding = 8
+tlvs = ["0x1400=01"]
+command_status = "ESME_RTHROTTLED"
+
+[[rules]]
+text = "EXPIRE"
+dr = "EXPIRED:027"
+dr_delay = "fixed:10"
+
+[[rules]]
+account = "acme"
+text = "(?i)^stop"
+mo_reply = "You will get no more messages"
+"#;
+
+#[tokio::test]
+async fn matching_mts_get_scripted_responses_drs_and_mos() {
+    let file = TempFile::new("rules.toml", RULES);
+    let script = Script::load(file.path()).unwrap();
+    let server =
+        TestServer::start_with_logic(Scripted::new(script, DefaultLogic {}))
+            .await
+            .unwrap();
+    let (tx, mut received) = mpsc::unbounded_channel();
+    let client = EsmeClient::connect(
+        EsmeConfig::new(&server.bind_address, "acme", "password"),
+        ChannelLogic { tx },
+    )
+    .await
+    .unwrap();
+
+    let result = client.submit_sm(submit_sm("447700900001", "hi", 0)).await;
+    assert!(matches!(result, Err(EsmeError::ErrorResponse(0x0b))));
+
+    let mut fields = fields("447700900002", "hi", 0);
+    fields.data_coding = 8;
+    fields.tlvs = vec![Tlv {
+        raw_tag: 0x1400,
+        value: vec![1],
+    }];
+    let result = client.submit_sm(fields.to_submit_sm().unwrap()).await;
+    assert!(matches!(result, Err(EsmeError::ErrorResponse(0x58))));
+
+    // No rule matches, so DefaultLogic rejects it
+    let result = client.submit_sm(submit_sm("447700900002", "hi", 1)).await;
+    assert!(matches!(result, Err(EsmeError::ErrorResponse(0x08))));
+
+    let message_id = client
+        .submit_sm(submit_sm("447700900002", "Please EXPIRE", 1))
+        .await
+        .unwrap();
+    let (dr, receipted_message_id) = received.recv().await.unwrap();
+    assert_eq!(receipted_message_id, Some(message_id));
+    assert!(dr.text().ends_with("stat:EXPIRED err:027 text:"));
+    assert_eq!(dr.tlv(KnownTlvTag::message_state).unwrap().value, vec![3]);
+
+    client
+        .submit_sm(submit_sm("447700900003", "STOP", 0))
+        .await
+        .unwrap();
+    let (mo, receipted_message_id) = received.recv().await.unwrap();
+    assert_eq!(receipted_message_id, None);
+    assert_eq!(mo.source_addr, "447700900003");
+    assert_eq!(mo.destination_addr, "MyCompany");
+    assert_eq!(mo.text(), "You will get no more messages");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn drs_and_mos_without_a_delay_follow_the_response() {
+    let file =
+        TempFile::new("no_delay.toml", "[[rules]]\nmo_reply = \"Thanks\"\n");
+    let script = Script::load(file.path()).unwrap();
+    let server =
+        TestServer::start_with_logic(Scripted::new(script, DefaultLogic {}))
+            .await
+            .unwrap();
+    let (tx, mut received) = mpsc::unbounded_channel();
+    let client = EsmeClient::connect(
+        EsmeConfig::new(&server.bind_address, "acme", "password"),
+        ChannelLogic { tx },
+    )
+    .await
+    .unwrap();
+
+    // The DR and MO are only sent once the MT is recorded, so neither is
+    // lost however quickly they follow it
+    for _ in 0..20 {
+        let message_id = client
+            .submit_sm(submit_sm("447700900002", "hi", 1))
+            .await
+            .unwrap();
+        let mut receipted_message_ids = vec![
+            received.recv().await.unwrap().1,
+            received.recv().await.unwrap().1,
+        ];
+        receipted_message_ids.sort();
+        assert_eq!(receipted_message_ids, vec![None, Some(message_id)]);
+    }
+}
+
+#[test]
+fn rules_files_with_bad_values_are_rejected() {
+    for (rules, expected) in [
+        ("[[rules]]\ntext = \"(\"\n", "Invalid text pattern '('"),
+        (
+            "[[rules]]\ncommand_status = \"ESME_ROK\"\n",
+            "Unknown or unsupported submit_sm command_status 'ESME_ROK'",
+        ),
+        (
+            "[[rules]]\ntlvs = [\"0x1400=1\"]\n",
+            "Invalid TLV '0x1400=1'",
+        ),
+        ("[[rules]]\ncolour = \"red\"\n", "unknown field `colour`"),
+    ] {
+        let file = TempFile::new("bad_rules.toml", rules);
+        let error = Script::load(file.path()).unwrap_err().to_string();
+        assert!(error.contains(expected), "{}", error);
+    }
+}
+
+#[test]
+fn statuses_and_tlv_patterns_are_parsed() {
+    assert_eq!(
+        "ESME_RINVDSTADR".parse::<SubmitSmError>(),
+        Ok(SubmitSmError::InvalidDestinationAddress)
+    );
+    assert_eq!(
+        "0x0204".parse::<TlvPattern>(),
+        Ok(TlvPattern {
+            raw_tag: 0x0204,
+            value: None
+        })
+    );
+    assert_eq!(
+        "5120=0102".parse::<TlvPattern>(),
+        Ok(TlvPattern {
+            raw_tag: 0x1400,
+            value: Some(vec![1, 2])
+        })
+    );
+}
+
+fn fields(
+    destination_addr: &str,
+    text: &str,
+    registered_delivery: u8,
+) -> SmFields {
+    SmFields {
+        source_addr: String::from("MyCompany"),
+        dest_addr_ton: 1,
+        dest_addr_npi: 1,
+        destination_addr: String::from(destination_addr),
+        registered_delivery,
+        short_message: text.as_bytes().to_vec(),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn rules_match_concatenated_mts_as_a_whole() {
+    let file = TempFile::new(
+        "concatenated.toml",
+        "[[rules]]\ntext = \"^hello world$\"\n\
+        command_status = \"ESME_RINVDSTADR\"\n\n\
+        [[rules]]\ntext = \"^goodbye\"\n",
+    );
+    let script = Script::load(file.path()).unwrap();
+    let server = TestServer::start_with_logic_and_smsc_config(
+        Scripted::new(script, DefaultLogic {}),
+        |c| c.concat_timeout_secs = Some(60),
+    )
+    .await
+    .unwrap();
+    let (tx, mut received) = mpsc::unbounded_channel();
+    let client = EsmeClient::connect(
+        EsmeConfig::new(&server.bind_address, "acme", "password"),
+        ChannelLogic { tx },
+    )
+    .await
+    .unwrap();
+
+    client.submit_sm(part(1, 1, "hello ", 0)).await.unwrap();
+    let result = client.submit_sm(part(1, 2, "world", 0)).await;
+    assert!(matches!(result, Err(EsmeError::ErrorResponse(0x0b))));
+
+    let first = client.submit_sm(part(2, 1, "goodbye ", 1)).await.unwrap();
+    client.submit_sm(part(2, 2, "world", 1)).await.unwrap();
+    let (dr, receipted_message_id) = received.recv().await.unwrap();
+    assert_eq!(receipted_message_id, Some(first));
+    assert!(dr.text().ends_with("stat:DELIVRD err:000 text:"));
+}
+
+#[tokio::test]
+async fn mts_that_match_no_rule_may_go_to_submit_sm_unlocked() {
+    let script = Script {
+        rules: vec![ScriptRule {
+            destination_addr: Some(String::from("447700900001")),
+            command_status: Some(SubmitSmError::InvalidDestinationAddress),
+            ..Default::default()
+        }],
+    };
+    let server =
+        TestServer::start_with_logic(Scripted::new(script, UnlockedLogic {}))
+            .await
+            .unwrap();
+    let (tx, _received) = mpsc::unbounded_channel();
+    let client = EsmeClient::connect(
+        EsmeConfig::new(&server.bind_address, "acme", "password"),
+        ChannelLogic { tx },
+    )
+    .await
+    .unwrap();
+
+    let result = client.submit_sm(submit_sm("447700900001", "hi", 0)).await;
+    assert!(matches!(result, Err(EsmeError::ErrorResponse(0x0b))));
+    let message_id = client
+        .submit_sm(submit_sm("447700900002", "hi", 0))
+        .await
+        .unwrap();
+    assert_eq!(message_id, "unlocked");
+}
+
+/// One of two parts of a message with a UDH
+fn part(
+    reference: u8,
+    part_number: u8,
+    text: &str,
+    registered_delivery: u8,
+) -> SubmitSmPdu {
+    let mut fields = fields("447700900002", text, registered_delivery);
+    fields.esm_class = ESM_CLASS_UDHI;
+    fields.short_message = vec![0x05, 0x00, 0x03, reference, 2, part_number];
+    fields.short_message.extend(text.as_bytes());
+    fields.to_submit_sm().unwrap()
+}
+
+fn submit_sm(
+    destination_addr: &str,
+    text: &str,
+    registered_delivery: u8,
+) -> SubmitSmPdu {
+    fields(destination_addr, text, registered_delivery)
+        .to_submit_sm()
+        .unwrap()
+}
+
+/// Passes the fields and receipted_message_id of each deliver_sm to a
+/// channel
+struct ChannelLogic {
+    tx: mpsc::UnboundedSender<(SmFields, Option<String>)>,
+}
+
+#[async_trait]
+impl EsmeLogic for ChannelLogic {
+    async fn deliver_sm(
+        &mut self,
+        pdu: &DeliverSmPdu,
+    ) -> Result<(), DeliverSmError> {
+        self.tx
+            .send((
+                SmFields::from_sm_data(&pdu.0),
+                pdu.extract_receipted_message_id(),
+            ))
+            .map_err(|_| DeliverSmError::InternalError)
+    }
+}
+
+/// Answers every MT from submit_sm_unlocked, with message_id "unlocked"
+struct UnlockedLogic {}
+
+#[async_trait]
+impl SmscLogic for UnlockedLogic {
+    async fn bind(&mut self, _bind_data: &BindData) -> Result<(), BindError> {
+        Ok(())
+    }
+
+    async fn submit_sm(
+        &mut self,
+        _smsc: Arc<Mutex<Smsc>>,
+        _pdu: &SubmitSmPdu,
+        _sequence_number: u32,
+    ) -> Result<(SubmitSmRespPdu, MessageUniqueKey), SubmitSmError> {
+        Err(SubmitSmError::InternalError)
+    }
+
+    fn submit_sm_unlocked(
+        &mut self,
+        _smsc: Arc<Mutex<Smsc>>,
+        pdu: &SubmitSmPdu,
+        _esme_id: &EsmeId,
+        _route: Option<&RoutingDecision>,
+    ) -> Option<SubmitSmFuture> {
+        let key = MessageUniqueKey::from_submit_sm(
+            String::from("unlocked"),
+            String::from("unlocked"),
+            pdu,
+        );
+        Some(
+            async move { Ok((SubmitSmRespPdu::new("unlocked").unwrap(), key)) }
+                .boxed(),
+        )
+    }
+}
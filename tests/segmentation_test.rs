@@ -0,0 +1,214 @@
+use smpp::concatenation::{ConcatInfo, ConcatSource, ESM_CLASS_UDHI};
+use smpp::segmentation::{SegmentationError, SegmentationMode, Segmenter};
+use smpp::sm_fields::SmFields;
+use smpp::smpp_connection::EsmeId;
+use smpp::smsc::Reassembler;
+use smpp_pdu::pdu::tlvs::KnownTlvTag;
+use smpp_pdu::pdu::PduBody;
+use std::time::Duration;
+
+#[test]
+fn short_messages_are_sent_in_one_part() {
+    let segmenter = Segmenter::new(SegmentationMode::Udh8Bit);
+    let text = [b'a'; 160];
+
+    let parts = segmenter
+        .segment(&template(0), &text, SegmentationMode::Udh8Bit)
+        .unwrap();
+
+    assert_eq!(parts.len(), 1);
+    assert_eq!(parts[0].short_message, text.to_vec());
+    assert_eq!(parts[0].esm_class, 0);
+}
+
+#[test]
+fn long_gsm_messages_are_split_with_8_bit_udh() {
+    let segmenter = Segmenter::new(SegmentationMode::Udh8Bit);
+    let text = [b'a'; 300];
+
+    let parts = segmenter
+        .segment(&template(0), &text, SegmentationMode::Udh8Bit)
+        .unwrap();
+
+    assert_eq!(parts.len(), 2);
+    assert_eq!(parts[0].short_message.len(), 6 + 153);
+    assert_eq!(parts[1].short_message.len(), 6 + 147);
+    for (i, part) in parts.iter().enumerate() {
+        assert_eq!(part.esm_class & ESM_CLASS_UDHI, ESM_CLASS_UDHI);
+        let info = concat_info(part);
+        assert_eq!(info.source, ConcatSource::Udh8Bit);
+        assert_eq!(info.total_parts, 2);
+        assert_eq!(info.part_number as usize, i + 1);
+    }
+    assert_eq!(
+        concat_info(&parts[0]).reference,
+        concat_info(&parts[1]).reference
+    );
+}
+
+#[test]
+fn each_message_gets_a_new_reference() {
+    let segmenter = Segmenter::new(SegmentationMode::Udh16Bit);
+    let text = [b'a'; 300];
+
+    let first = segmenter
+        .segment(&template(0), &text, SegmentationMode::Udh16Bit)
+        .unwrap();
+    let second = segmenter
+        .segment(&template(0), &text, SegmentationMode::Udh16Bit)
+        .unwrap();
+
+    assert_eq!(first[0].short_message.len(), 7 + 152);
+    assert_ne!(
+        concat_info(&first[0]).reference,
+        concat_info(&second[0]).reference
+    );
+}
+
+#[test]
+fn gsm_escape_sequences_are_not_split() {
+    let segmenter = Segmenter::new(SegmentationMode::Udh8Bit);
+    let mut text = vec![b'a'; 152];
+    text.extend(b"\x1b\x65"); // Euro sign
+    text.extend(vec![b'b'; 10]);
+
+    let parts = segmenter
+        .segment(&template(0), &text, SegmentationMode::Udh8Bit)
+        .unwrap();
+
+    assert_eq!(parts[0].short_message.len(), 6 + 152);
+    assert_eq!(&parts[1].short_message[6..8], b"\x1b\x65");
+}
+
+#[test]
+fn ucs2_messages_do_not_split_surrogate_pairs() {
+    let segmenter = Segmenter::new(SegmentationMode::Sar);
+    let mut text = Vec::new();
+    for _ in 0..69 {
+        text.extend(b"\x00\x61");
+    }
+    text.extend("\u{1F600}".encode_utf16().flat_map(|u| u.to_be_bytes()));
+
+    let parts = segmenter
+        .segment(&template(8), &text, SegmentationMode::Sar)
+        .unwrap();
+
+    assert_eq!(parts.len(), 2);
+    assert_eq!(parts[0].short_message.len(), 138);
+    assert_eq!(parts[1].short_message.len(), 4);
+}
+
+#[test]
+fn sar_parts_have_tlvs_and_no_udh() {
+    let segmenter = Segmenter::new(SegmentationMode::Sar);
+    let text = [b'x'; 141];
+
+    let parts = segmenter
+        .segment(&template(4), &text, SegmentationMode::Sar)
+        .unwrap();
+
+    assert_eq!(parts.len(), 2);
+    assert_eq!(parts[0].short_message.len(), 140);
+    assert_eq!(parts[0].esm_class, 0);
+    assert_eq!(
+        parts[1].tlv(KnownTlvTag::sar_segment_seqnum).unwrap().value,
+        vec![2]
+    );
+    let info = concat_info(&parts[1]);
+    assert_eq!(info.source, ConcatSource::Sar);
+    assert_eq!(info.total_parts, 2);
+}
+
+#[test]
+fn message_payload_puts_everything_in_one_pdu() {
+    let segmenter = Segmenter::new(SegmentationMode::Udh8Bit);
+    let text = [b'x'; 1000];
+
+    let parts = segmenter
+        .segment(&template(0), &text, SegmentationMode::MessagePayload)
+        .unwrap();
+
+    assert_eq!(parts.len(), 1);
+    assert!(parts[0].short_message.is_empty());
+    assert_eq!(
+        parts[0].tlv(KnownTlvTag::message_payload).unwrap().value,
+        text.to_vec()
+    );
+}
+
+#[test]
+fn messages_needing_too_many_parts_are_rejected() {
+    let segmenter = Segmenter::new(SegmentationMode::Udh8Bit);
+    let text = vec![b'x'; 134 * 256];
+
+    assert_eq!(
+        segmenter.segment(&template(4), &text, SegmentationMode::Udh8Bit),
+        Err(SegmentationError::TooLong)
+    );
+}
+
+#[test]
+fn deliver_sm_pdus_follow_esme_preference() {
+    let mut segmenter = Segmenter::new(SegmentationMode::Udh8Bit);
+    segmenter
+        .set_preference(esme_id("payload"), SegmentationMode::MessagePayload);
+    let text = [b'x'; 300];
+
+    let pdus = segmenter
+        .deliver_sm_pdus(&esme_id("udh"), &template(0), &text, 0x7fffffff)
+        .unwrap();
+    assert_eq!(pdus.len(), 2);
+    assert_eq!(pdus[0].sequence_number.value, 0x7fffffff);
+    assert_eq!(pdus[1].sequence_number.value, 1);
+    assert!(matches!(pdus[0].body(), PduBody::DeliverSm(_)));
+
+    let pdus = segmenter
+        .deliver_sm_pdus(&esme_id("payload"), &template(0), &text, 5)
+        .unwrap();
+    assert_eq!(pdus.len(), 1);
+}
+
+#[test]
+fn segmented_messages_can_be_reassembled() {
+    let segmenter = Segmenter::new(SegmentationMode::Udh16Bit);
+    let text: Vec<u8> = (0..500).map(|i| b'a' + (i % 26) as u8).collect();
+    let esme_id = esme_id("esme");
+    let mut reassembler = Reassembler::new(Duration::from_secs(10));
+
+    let parts = segmenter
+        .segment(&template(3), &text, SegmentationMode::Udh16Bit)
+        .unwrap();
+    let mut complete = None;
+    for (i, part) in parts.iter().rev().enumerate() {
+        let submit_sm = part.to_submit_sm().unwrap();
+        let info = ConcatInfo::from_sm_data(&submit_sm.0).unwrap();
+        complete = reassembler.add_part(
+            &esme_id,
+            &submit_sm,
+            &info,
+            format!("id{}", i),
+        );
+    }
+
+    assert_eq!(complete.unwrap().user_data(), text);
+}
+
+fn template(data_coding: u8) -> SmFields {
+    SmFields {
+        source_addr: String::from("447000123123"),
+        destination_addr: String::from("447111222222"),
+        data_coding,
+        ..SmFields::default()
+    }
+}
+
+fn concat_info(fields: &SmFields) -> ConcatInfo {
+    ConcatInfo::from_sm_data(&fields.to_deliver_sm().unwrap().0).unwrap()
+}
+
+fn esme_id(system_id: &str) -> EsmeId {
+    EsmeId {
+        system_id: system_id.parse().unwrap(),
+        system_type: "".parse().unwrap(),
+    }
+}
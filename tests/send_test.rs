@@ -0,0 +1,250 @@
+use clap::Clap;
+use smpp::extra_pdu::{AnyPdu, ExtraPdu, ExtraPduBody};
+use smpp::segmentation::SegmentationMode;
+use smpp::send::{send, SendConfig, TlvArg};
+use smpp::sm_fields::SmFields;
+use smpp::smpp_connection::SmppConnection;
+use smpp_pdu::pdu::tlvs::{KnownTlvTag, Tlv};
+use smpp_pdu::pdu::{Pdu, PduBody, SubmitSmRespPdu};
+
+mod test_utils;
+
+use test_utils::FakeSmsc;
+
+#[tokio::test]
+async fn each_message_is_sent_to_each_destination_and_responses_printed() {
+    let smsc = FakeSmsc::start().await;
+    let config = config(
+        &smsc,
+        &[
+            "--to",
+            "447700900001",
+            "--to",
+            "447700900002",
+            "--from",
+            "MyCompany",
+            "--tlv",
+            "0x1400=0102",
+            "hello",
+        ],
+    );
+    let mut output = Vec::new();
+
+    let (failures, ()) = tokio::join!(send(&config, &mut output), async {
+        let connection = smsc.accept_and_bind().await;
+        let (seq, fields) = read_submit_sm(&connection).await;
+        assert_eq!(fields.destination_addr, "447700900001");
+        assert_eq!(fields.source_addr, "MyCompany");
+        assert_eq!(fields.short_message, b"hello");
+        assert_eq!(
+            fields.tlvs,
+            vec![Tlv {
+                raw_tag: 0x1400,
+                value: vec![1, 2]
+            }]
+        );
+        write_submit_sm_resp(&connection, seq, 0, "m1").await;
+        let (seq, fields) = read_submit_sm(&connection).await;
+        assert_eq!(fields.destination_addr, "447700900002");
+        write_submit_sm_resp(&connection, seq, 0x45, "").await;
+        respond_to_unbind(&connection).await;
+    });
+
+    assert_eq!(failures.unwrap(), 1);
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "submit_sm to 447700900001: message_id=m1\n\
+        submit_sm to 447700900002: Error response with \
+        command_status=0x00000045\n"
+    );
+}
+
+#[tokio::test]
+async fn long_messages_are_split_and_drs_and_mos_printed() {
+    let smsc = FakeSmsc::start().await;
+    let config = config(
+        &smsc,
+        &[
+            "--to",
+            "447700900001",
+            "--data-coding",
+            "8",
+            "--split",
+            "sar",
+            "--registered-delivery",
+            "1",
+            "--wait-secs",
+            "30",
+            &"é".repeat(80),
+        ],
+    );
+    let mut output = Vec::new();
+
+    let (failures, ()) = tokio::join!(send(&config, &mut output), async {
+        let connection = smsc.accept_and_bind().await;
+        for (part, message_id) in [(1, "m1"), (2, "m2")] {
+            let (seq, fields) = read_submit_sm(&connection).await;
+            assert_eq!(fields.data_coding, 8);
+            assert_eq!(
+                fields.tlv(KnownTlvTag::sar_segment_seqnum).unwrap().value,
+                vec![part]
+            );
+            write_submit_sm_resp(&connection, seq, 0, message_id).await;
+        }
+        let mo = deliver_sm(0, 8, &utf16("héllo"), None);
+        deliver(&connection, 1, mo).await;
+        let stat = "stat:DELIVRD err:000 text:";
+        for (seq, id) in [(2, "m9"), (3, "m1"), (4, "m2")] {
+            let dr = deliver_sm(
+                0x04,
+                0,
+                format!("id:{} sub:001 dlvrd:001 {}", id, stat).as_bytes(),
+                Some(id),
+            );
+            deliver(&connection, seq, dr).await;
+        }
+        // Without stopping once all our DRs are in, we would wait 30s
+        respond_to_unbind(&connection).await;
+    });
+
+    assert_eq!(failures.unwrap(), 0);
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "submit_sm to 447700900001 (part 1/2): message_id=m1\n\
+        submit_sm to 447700900001 (part 2/2): message_id=m2\n\
+        MO from 447700900001 to MyCompany: héllo\n\
+        DR for message_id=m9 (not sent by us) to 447700900001: \
+        stat=DELIVRD err=000\n\
+        DR for message_id=m1 to 447700900001: stat=DELIVRD err=000\n\
+        DR for message_id=m2 to 447700900001: stat=DELIVRD err=000\n"
+    );
+}
+
+#[tokio::test]
+async fn text_that_does_not_fit_the_data_coding_is_not_sent() {
+    let smsc = FakeSmsc::start().await;
+    let config = config(&smsc, &["--to", "447700900001", "héllo"]);
+
+    let result = send(&config, &mut Vec::new()).await;
+
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Cannot encode 'héllo' with data_coding 0: try --data-coding 8, \
+        or --hex"
+    );
+}
+
+#[test]
+fn tlvs_and_split_modes_are_parsed_from_the_command_line() {
+    assert_eq!(
+        "0x0424=68 69".parse::<TlvArg>().unwrap(),
+        TlvArg(Tlv::new(KnownTlvTag::message_payload, b"hi"))
+    );
+    assert_eq!(
+        "30=6d31".parse::<TlvArg>().unwrap(),
+        TlvArg(Tlv::new(KnownTlvTag::receipted_message_id, b"m1"))
+    );
+    assert!("0x0424".parse::<TlvArg>().is_err());
+    assert!("0x0424=6".parse::<TlvArg>().is_err());
+    assert_eq!(
+        "payload".parse::<SegmentationMode>(),
+        Ok(SegmentationMode::MessagePayload)
+    );
+    assert!("udh".parse::<SegmentationMode>().is_err());
+}
+
+fn config(smsc: &FakeSmsc, args: &[&str]) -> SendConfig {
+    let mut all_args = vec![
+        "smpp-send",
+        "--smsc-address",
+        &smsc.bind_address,
+        "--system-id",
+        "esmeid",
+        "--password",
+        "password",
+    ];
+    all_args.extend_from_slice(args);
+    SendConfig::parse_from(all_args)
+}
+
+async fn read_submit_sm(connection: &SmppConnection) -> (u32, SmFields) {
+    match connection.read_any_pdu().await.unwrap().unwrap() {
+        AnyPdu::Pdu(pdu) => match pdu.body() {
+            PduBody::SubmitSm(body) => {
+                (pdu.sequence_number.value, SmFields::from_sm_data(&body.0))
+            }
+            _ => panic!("Expected submit_sm but got {:?}", pdu),
+        },
+        other => panic!("Expected submit_sm but got {:?}", other),
+    }
+}
+
+async fn write_submit_sm_resp(
+    connection: &SmppConnection,
+    sequence_number: u32,
+    command_status: u32,
+    message_id: &str,
+) {
+    let body = if command_status == 0 {
+        SubmitSmRespPdu::new(message_id).unwrap()
+    } else {
+        SubmitSmRespPdu::new_error()
+    };
+    let pdu = Pdu::new(command_status, sequence_number, body.into()).unwrap();
+    connection.write_pdu(&pdu).await.unwrap();
+}
+
+fn deliver_sm(
+    esm_class: u8,
+    data_coding: u8,
+    short_message: &[u8],
+    receipted_message_id: Option<&str>,
+) -> SmFields {
+    let mut fields = SmFields {
+        source_addr_ton: 1,
+        source_addr_npi: 1,
+        source_addr: String::from("447700900001"),
+        destination_addr: String::from("MyCompany"),
+        esm_class,
+        data_coding,
+        short_message: short_message.to_vec(),
+        ..Default::default()
+    };
+    if let Some(id) = receipted_message_id {
+        fields.set_tlv(Tlv::new(
+            KnownTlvTag::receipted_message_id,
+            id.as_bytes(),
+        ));
+    }
+    fields
+}
+
+/// Send a deliver_sm and read the deliver_sm_resp
+async fn deliver(
+    connection: &SmppConnection,
+    sequence_number: u32,
+    fields: SmFields,
+) {
+    let pdu =
+        Pdu::new(0, sequence_number, fields.to_deliver_sm().unwrap().into())
+            .unwrap();
+    connection.write_pdu(&pdu).await.unwrap();
+    let resp = connection.read_any_pdu().await.unwrap().unwrap();
+    assert_eq!(resp.command_id(), 0x80000005);
+    assert_eq!(resp.sequence_number(), sequence_number);
+}
+
+async fn respond_to_unbind(connection: &SmppConnection) {
+    let unbind = connection.read_any_pdu().await.unwrap().unwrap();
+    assert_eq!(unbind.command_id(), 0x00000006);
+    let resp =
+        ExtraPdu::new(0, unbind.sequence_number(), ExtraPduBody::UnbindResp)
+            .unwrap();
+    connection.write_extra_pdu(&resp).await.unwrap();
+}
+
+fn utf16(text: &str) -> Vec<u8> {
+    text.encode_utf16()
+        .flat_map(|unit| unit.to_be_bytes())
+        .collect()
+}
@@ -0,0 +1,25 @@
+use smpp::sequence_number_generator::SequenceNumberGenerator;
+
+#[test]
+fn sequence_numbers_count_up_from_1() {
+    let generator = SequenceNumberGenerator::new();
+    assert_eq!(generator.next_sequence_number(), 1);
+    assert_eq!(generator.next_sequence_number(), 2);
+    assert_eq!(generator.next_sequence_number(), 3);
+}
+
+#[test]
+fn sequence_numbers_wrap_round_after_0x7fffffff() {
+    let generator = SequenceNumberGenerator::starting_after(0x7ffffffe);
+    assert_eq!(generator.next_sequence_number(), 0x7fffffff);
+    assert_eq!(generator.next_sequence_number(), 1);
+    assert_eq!(generator.next_sequence_number(), 2);
+}
+
+#[test]
+fn a_counter_near_u32_max_wraps_round_to_1() {
+    let generator = SequenceNumberGenerator::starting_after(u32::MAX - 1);
+    let sequence_numbers: Vec<u32> =
+        (0..4).map(|_| generator.next_sequence_number()).collect();
+    assert_eq!(sequence_numbers, vec![1, 2, 3, 4]);
+}
@@ -0,0 +1,199 @@
+use async_trait::async_trait;
+use smpp::esme::{
+    DeliverSmError, EsmeClient, EsmeConfig, EsmeError, EsmeLogic,
+};
+use smpp::simulator::{DrDelay, DrOutcome, DrState, Simulator, SimulatorRule};
+use smpp::sm_fields::SmFields;
+use smpp::smsc::SmscConfig;
+use smpp_pdu::pdu::tlvs::KnownTlvTag;
+use smpp_pdu::pdu::DeliverSmPdu;
+use tokio::sync::mpsc;
+
+mod test_utils;
+
+use test_utils::{TempFile, TestServer};
+
+#[tokio::test]
+async fn rules_choose_errors_and_dr_outcomes_by_prefix_and_account() {
+    let mut config = load(&[]);
+    config.simulator.rules = vec![
+        SimulatorRule {
+            prefix: String::from("4477009001"),
+            error_rate: Some(1.0),
+            ..Default::default()
+        },
+        SimulatorRule {
+            prefix: String::from("4477009003"),
+            dr_outcomes: Some(vec!["UNDELIV:034".parse().unwrap()]),
+            ..Default::default()
+        },
+        // Without this, DRs would wait the default 1 second
+        SimulatorRule {
+            account: Some(String::from("acme")),
+            dr_delay: Some(DrDelay::Fixed { ms: 0 }),
+            ..Default::default()
+        },
+    ];
+    let simulator = Simulator::new(&config).unwrap();
+    let server =
+        TestServer::start_with_logic_and_smsc_config(simulator, |_| {})
+            .await
+            .unwrap();
+    let (tx, mut drs) = mpsc::unbounded_channel();
+    let client = EsmeClient::connect(
+        EsmeConfig::new(&server.bind_address, "acme", "acme"),
+        ChannelLogic { tx },
+    )
+    .await
+    .unwrap();
+
+    let rejected = client.submit_sm(submit_sm("447700900111", 1)).await;
+    assert!(matches!(rejected, Err(EsmeError::ErrorResponse(0x45))));
+
+    // DRs on failure only, so none for this message, which is delivered
+    client
+        .submit_sm(submit_sm("447700900222", 2))
+        .await
+        .unwrap();
+    let message_id = client
+        .submit_sm(submit_sm("447700900333", 1))
+        .await
+        .unwrap();
+
+    let (fields, receipted_message_id) = drs.recv().await.unwrap();
+    assert_eq!(receipted_message_id, Some(message_id.clone()));
+    assert_eq!(fields.source_addr, "447700900333");
+    assert_eq!(
+        fields.tlv(KnownTlvTag::message_state).unwrap().value,
+        vec![5]
+    );
+    let text = String::from_utf8(fields.short_message).unwrap();
+    assert!(text.starts_with(&format!("id:{} sub:001 dlvrd:000", message_id)));
+    assert!(text.ends_with("stat:UNDELIV err:034 text:"));
+}
+
+#[test]
+fn rules_are_read_from_the_config_file() {
+    let file = TempFile::new(
+        "simulator.toml",
+        r#"
+[[simulator]]
+prefix = "44"
+error_rate = 0.05
+dr_outcomes = ["DELIVRD=90", "EXPIRED:027=10"]
+dr_delay = "exponential:2000"
+
+[[simulator]]
+account = "acme"
+"#,
+    );
+
+    let config = load(&[
+        "--config",
+        file.path_str(),
+        "--sim-dr-outcomes",
+        "DELIVRD=3,REJECTD:001",
+    ]);
+
+    assert_eq!(
+        config.simulator.rules,
+        vec![
+            SimulatorRule {
+                prefix: String::from("44"),
+                account: None,
+                error_rate: Some(0.05),
+                dr_outcomes: Some(vec![
+                    outcome(DrState::Delivered, 0, 90),
+                    outcome(DrState::Expired, 27, 10),
+                ]),
+                dr_delay: Some(DrDelay::Exponential { mean_ms: 2000 }),
+            },
+            SimulatorRule {
+                account: Some(String::from("acme")),
+                ..Default::default()
+            },
+        ]
+    );
+    assert_eq!(
+        config.simulator.sim_dr_outcomes,
+        vec![
+            outcome(DrState::Delivered, 0, 3),
+            outcome(DrState::Rejected, 1, 1),
+        ]
+    );
+    assert_eq!(config.simulator.sim_dr_delay, DrDelay::Fixed { ms: 1000 });
+
+    file.write("[[simulator]]\nerror_rate = 2\n");
+    let error =
+        SmscConfig::load_from(vec!["smsc", "--config", file.path_str()])
+            .unwrap_err();
+    assert!(error
+        .to_string()
+        .ends_with("simulator error_rate 2 must be from 0 to 1"));
+}
+
+#[test]
+fn outcomes_and_delays_are_parsed_from_strings() {
+    assert_eq!(
+        "undeliv:034=5".parse::<DrOutcome>(),
+        Ok(outcome(DrState::Undeliverable, 34, 5))
+    );
+    assert!("DELIVERED".parse::<DrOutcome>().is_err());
+    assert!("DELIVRD:1000".parse::<DrOutcome>().is_err());
+    assert_eq!(
+        "uniform:500-2000".parse::<DrDelay>(),
+        Ok(DrDelay::Uniform {
+            min_ms: 500,
+            max_ms: 2000
+        })
+    );
+    assert!("uniform:2000-500".parse::<DrDelay>().is_err());
+    assert!("normal:100".parse::<DrDelay>().is_err());
+}
+
+fn load(args: &[&str]) -> SmscConfig {
+    SmscConfig::load_from(std::iter::once("smsc").chain(args.iter().cloned()))
+        .unwrap()
+}
+
+fn outcome(state: DrState, err: u16, weight: u32) -> DrOutcome {
+    DrOutcome { state, err, weight }
+}
+
+fn submit_sm(
+    destination_addr: &str,
+    registered_delivery: u8,
+) -> smpp_pdu::pdu::SubmitSmPdu {
+    SmFields {
+        source_addr: String::from("MyCompany"),
+        dest_addr_ton: 1,
+        dest_addr_npi: 1,
+        destination_addr: String::from(destination_addr),
+        registered_delivery,
+        short_message: b"hello".to_vec(),
+        ..Default::default()
+    }
+    .to_submit_sm()
+    .unwrap()
+}
+
+/// Passes the fields and receipted_message_id of each deliver_sm to a
+/// channel
+struct ChannelLogic {
+    tx: mpsc::UnboundedSender<(SmFields, Option<String>)>,
+}
+
+#[async_trait]
+impl EsmeLogic for ChannelLogic {
+    async fn deliver_sm(
+        &mut self,
+        pdu: &DeliverSmPdu,
+    ) -> Result<(), DeliverSmError> {
+        self.tx
+            .send((
+                SmFields::from_sm_data(&pdu.0),
+                pdu.extract_receipted_message_id(),
+            ))
+            .map_err(|_| DeliverSmError::InternalError)
+    }
+}
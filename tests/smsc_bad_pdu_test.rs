@@ -1,3 +1,10 @@
+// Newer clippy flags some idioms in these original tests
+#![allow(
+    clippy::char_lit_as_u8,
+    clippy::manual_repeat_n,
+    clippy::unused_io_amount
+)]
+
 use std::io;
 use std::iter;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
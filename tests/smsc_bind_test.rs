@@ -1,3 +1,6 @@
+// Newer clippy flags some idioms in these original tests
+#![allow(clippy::diverging_sub_expression)]
+
 use async_trait::async_trait;
 use smpp::message_unique_key::MessageUniqueKey;
 use smpp::smsc::{BindData, BindError, Smsc, SmscLogic, SubmitSmError};
@@ -132,6 +135,20 @@ async fn when_we_receive_enquire_link_we_respond_with_resp() {
         .await;
 }
 
+#[tokio::test]
+async fn when_we_receive_unbind_we_respond_with_resp() {
+    let mut t = TestSetup::new().await;
+    t.client.bind_transceiver().await;
+    t.client
+        .send_and_expect_response(
+            // When client sends unbind
+            b"\x00\x00\x00\x10\x00\x00\x00\x06\x00\x00\x00\x00\x00\x00\x00\x13",
+            // Then server responds unbind_resp
+            b"\x00\x00\x00\x10\x80\x00\x00\x06\x00\x00\x00\x00\x00\x00\x00\x13",
+        )
+        .await;
+}
+
 #[tokio::test]
 async fn when_we_receive_multiple_binds_we_can_keep_track() {
     struct TrackingLogic {
@@ -0,0 +1,430 @@
+use async_trait::async_trait;
+use smpp::concatenation::{ConcatInfo, ESM_CLASS_UDHI};
+use smpp::message_unique_key::MessageUniqueKey;
+use smpp::smpp_connection::EsmeId;
+use smpp::smsc::{
+    BindData, BindError, ConcatenatedSm, Reassembler, Smsc, SmscLogic,
+    SubmitSmError,
+};
+use smpp_pdu::pdu::tlvs::{KnownTlvTag, Tlv, Tlvs};
+use smpp_pdu::pdu::{
+    DeliverEsmClass, DeliverSmPdu, Pdu, PduBody, SubmitSmPdu, SubmitSmRespPdu,
+};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+mod test_utils;
+
+use test_utils::{with_sequence_number, TestClient, TestServer};
+
+#[tokio::test]
+async fn parts_with_udh_are_joined_and_each_gets_a_message_id() {
+    let messages = Arc::new(Mutex::new(Vec::new()));
+    let mut client = start(Arc::clone(&messages)).await;
+
+    // Parts may arrive in any order
+    let id2 =
+        submit_part(&mut client, 2, udh_part(0x42, 2, 2, b" world")).await;
+    assert!(messages.lock().await.is_empty());
+    let id1 = submit_part(&mut client, 3, udh_part(0x42, 2, 1, b"hello")).await;
+    assert_ne!(id1, id2);
+
+    let messages = messages.lock().await;
+    assert_eq!(messages.len(), 1);
+    let message = &messages[0];
+    assert_eq!(message.user_data(), b"hello world");
+    assert_eq!(message.message_ids(), vec![id1, id2]);
+    assert_eq!(message.reference, 0x42);
+    assert_eq!(message.destination_addr, "447777222222");
+    assert_eq!(message.esm_class & ESM_CLASS_UDHI, 0);
+}
+
+#[tokio::test]
+async fn parts_with_sar_tlvs_are_joined() {
+    let messages = Arc::new(Mutex::new(Vec::new()));
+    let mut client = start(Arc::clone(&messages)).await;
+
+    submit_part(&mut client, 2, sar_part(0x1234, 3, 1, b"a")).await;
+    submit_part(&mut client, 3, sar_part(0x1234, 3, 3, b"c")).await;
+    // A different reference is a different message
+    submit_part(&mut client, 4, sar_part(0x1235, 3, 2, b"x")).await;
+    assert!(messages.lock().await.is_empty());
+    submit_part(&mut client, 5, sar_part(0x1234, 3, 2, b"b")).await;
+
+    let messages = messages.lock().await;
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].user_data(), b"abc");
+    assert_eq!(messages[0].reference, 0x1234);
+    // Forwarded parts keep their SAR TLVs
+    assert_eq!(
+        messages[0].part_pdu(&messages[0].parts[1]).unwrap(),
+        sar_part(0x1234, 3, 2, b"b")
+    );
+}
+
+#[tokio::test]
+async fn drs_for_any_part_are_delivered_to_the_esme() {
+    let messages = Arc::new(Mutex::new(Vec::new()));
+    let logic = Logic {
+        messages: Arc::clone(&messages),
+    };
+    let server = TestServer::start_with_logic_and_smsc_config(logic, |c| {
+        c.concat_timeout_secs = Some(60)
+    })
+    .await
+    .unwrap();
+    let mut client = TestClient::connect_to(&server).await.unwrap();
+    client.bind_transceiver().await;
+
+    let id1 = submit_part(&mut client, 2, udh_part(7, 2, 1, b"hel")).await;
+    let id2 = submit_part(&mut client, 3, udh_part(7, 2, 2, b"lo")).await;
+
+    for (i, id) in [id2, id1].iter().enumerate() {
+        let dr = dr_pdu(id);
+        let dr_bytes =
+            with_sequence_number(&pdu_bytes(&dr).await, i as u32 + 1);
+        server.receive_pdu("concattest", dr).await.unwrap();
+        client.expect_to_receive(&dr_bytes).await;
+    }
+}
+
+#[tokio::test]
+async fn without_a_timeout_configured_parts_go_to_submit_sm() {
+    let messages = Arc::new(Mutex::new(Vec::new()));
+    let logic = Logic {
+        messages: Arc::clone(&messages),
+    };
+    let server = TestServer::start_with_logic(logic).await.unwrap();
+    let mut client = TestClient::connect_to(&server).await.unwrap();
+    client.bind_transceiver().await;
+
+    let id = submit_part(&mut client, 2, udh_part(7, 2, 1, b"hel")).await;
+    assert_eq!(id, "single");
+    assert!(messages.lock().await.is_empty());
+}
+
+#[test]
+fn incomplete_messages_are_discarded_after_the_timeout() {
+    let esme_id = EsmeId {
+        system_id: "esme".parse().unwrap(),
+        system_type: "".parse().unwrap(),
+    };
+    let part = udh_part(1, 2, 1, b"hel");
+    let concat_info = ConcatInfo::from_sm_data(&part.0).unwrap();
+
+    let mut reassembler = Reassembler::new(Duration::from_secs(10));
+    assert!(reassembler
+        .add_part(&esme_id, &part, &concat_info, String::from("id1"))
+        .is_none());
+    assert_eq!(reassembler.num_pending(), 1);
+
+    assert!(reassembler.remove_expired(Instant::now()).is_empty());
+    let expired =
+        reassembler.remove_expired(Instant::now() + Duration::from_secs(11));
+    assert_eq!(expired.len(), 1);
+    assert_eq!(expired[0].reference, 1);
+    assert_eq!(expired[0].message_ids(), vec![String::from("id1")]);
+    assert_eq!(reassembler.num_pending(), 0);
+}
+
+#[tokio::test]
+async fn by_default_each_part_goes_to_submit_sm_and_drs_use_our_ids() {
+    let short_messages = Arc::new(Mutex::new(Vec::new()));
+    let logic = PartsLogic {
+        short_messages: Arc::clone(&short_messages),
+        expired: Arc::new(Mutex::new(Vec::new())),
+    };
+    let server = TestServer::start_with_logic_and_smsc_config(logic, |c| {
+        c.concat_timeout_secs = Some(60)
+    })
+    .await
+    .unwrap();
+    let mut client = TestClient::connect_to(&server).await.unwrap();
+    client.bind_transceiver().await;
+
+    let id1 = submit_part(&mut client, 2, udh_part(9, 2, 1, b"hel")).await;
+    assert!(short_messages.lock().await.is_empty());
+    let id2 = submit_part(&mut client, 3, udh_part(9, 2, 2, b"lo")).await;
+
+    // Each part reaches submit_sm with its UDH
+    assert_eq!(
+        *short_messages.lock().await,
+        vec![
+            udh_part(9, 2, 1, b"hel").0.short_message.value,
+            udh_part(9, 2, 2, b"lo").0.short_message.value
+        ]
+    );
+
+    // DRs with the logic's message IDs reach the ESME with ours
+    for (i, (supplier_id, our_id)) in [("supplier1", &id1), ("supplier2", &id2)]
+        .iter()
+        .enumerate()
+    {
+        let dr_bytes = with_sequence_number(
+            &pdu_bytes(&dr_pdu(our_id)).await,
+            i as u32 + 1,
+        );
+        server
+            .receive_pdu("concattest", dr_pdu(supplier_id))
+            .await
+            .unwrap();
+        client.expect_to_receive(&dr_bytes).await;
+    }
+}
+
+#[tokio::test]
+async fn the_logic_is_told_about_incomplete_messages_that_expire() {
+    let expired = Arc::new(Mutex::new(Vec::new()));
+    let logic = PartsLogic {
+        short_messages: Arc::new(Mutex::new(Vec::new())),
+        expired: Arc::clone(&expired),
+    };
+    let server = TestServer::start_with_logic_and_smsc_config(logic, |c| {
+        c.concat_timeout_secs = Some(0)
+    })
+    .await
+    .unwrap();
+    let mut client = TestClient::connect_to(&server).await.unwrap();
+    client.bind_transceiver().await;
+
+    let id = submit_part(&mut client, 2, udh_part(3, 2, 1, b"hel")).await;
+
+    // Without any more parts arriving
+    let start = Instant::now();
+    while expired.lock().await.is_empty() {
+        assert!(start.elapsed() < Duration::from_secs(5));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    let expired = expired.lock().await;
+    assert_eq!(expired.len(), 1);
+    assert_eq!(expired[0].reference, 3);
+    assert_eq!(expired[0].message_ids(), vec![id]);
+}
+
+#[test]
+fn udh_without_concatenation_element_is_not_a_part() {
+    // UDH containing only a port addressing element
+    let pdu = submit_sm(
+        ESM_CLASS_UDHI,
+        b"\x06\x05\x04\x0b\x84\x23\xf0hi",
+        Tlvs::new(),
+    );
+    assert_eq!(ConcatInfo::from_sm_data(&pdu.0), None);
+
+    // Part 3 of 2 makes no sense
+    assert_eq!(ConcatInfo::from_sm_data(&udh_part(1, 2, 3, b"x").0), None);
+}
+
+struct Logic {
+    messages: Arc<Mutex<Vec<ConcatenatedSm>>>,
+}
+
+#[async_trait]
+impl SmscLogic for Logic {
+    async fn bind(&mut self, _bind_data: &BindData) -> Result<(), BindError> {
+        Ok(())
+    }
+
+    async fn submit_sm(
+        &mut self,
+        _smsc: Arc<Mutex<Smsc>>,
+        pdu: &SubmitSmPdu,
+        _sequence_number: u32,
+    ) -> Result<(SubmitSmRespPdu, MessageUniqueKey), SubmitSmError> {
+        Ok((
+            SubmitSmRespPdu::new("single").unwrap(),
+            MessageUniqueKey::new(
+                String::from("concattest"),
+                String::from("single"),
+                pdu.destination_addr(),
+            ),
+        ))
+    }
+
+    async fn submit_concatenated_sm(
+        &mut self,
+        _smsc: Arc<Mutex<Smsc>>,
+        message: &ConcatenatedSm,
+    ) -> Result<Vec<MessageUniqueKey>, SubmitSmError> {
+        self.messages.lock().await.push(message.clone());
+        Ok(message
+            .message_ids()
+            .into_iter()
+            .map(|id| {
+                MessageUniqueKey::new(
+                    String::from("concattest"),
+                    id,
+                    message.destination_addr.clone(),
+                )
+            })
+            .collect())
+    }
+}
+
+/// Only implements submit_sm, so concatenated MTs get the default
+/// treatment
+struct PartsLogic {
+    /// The short_message of each submit_sm
+    short_messages: Arc<Mutex<Vec<Vec<u8>>>>,
+    expired: Arc<Mutex<Vec<ConcatenatedSm>>>,
+}
+
+#[async_trait]
+impl SmscLogic for PartsLogic {
+    async fn bind(&mut self, _bind_data: &BindData) -> Result<(), BindError> {
+        Ok(())
+    }
+
+    async fn submit_sm(
+        &mut self,
+        _smsc: Arc<Mutex<Smsc>>,
+        pdu: &SubmitSmPdu,
+        _sequence_number: u32,
+    ) -> Result<(SubmitSmRespPdu, MessageUniqueKey), SubmitSmError> {
+        let mut short_messages = self.short_messages.lock().await;
+        let message_id = format!("supplier{}", short_messages.len() + 1);
+        let key = MessageUniqueKey::new(
+            String::from("concattest"),
+            message_id.clone(),
+            pdu.destination_addr(),
+        );
+        short_messages.push(pdu.0.short_message.value.clone());
+        Ok((SubmitSmRespPdu::new(&message_id).unwrap(), key))
+    }
+
+    async fn concatenated_sm_expired(
+        &mut self,
+        _smsc: Arc<Mutex<Smsc>>,
+        message: &ConcatenatedSm,
+    ) {
+        self.expired.lock().await.push(message.clone());
+    }
+}
+
+async fn start(messages: Arc<Mutex<Vec<ConcatenatedSm>>>) -> TestClient {
+    let logic = Logic { messages };
+    let server = TestServer::start_with_logic_and_smsc_config(logic, |c| {
+        c.concat_timeout_secs = Some(60)
+    })
+    .await
+    .unwrap();
+    let mut client = TestClient::connect_to(&server).await.unwrap();
+    client.bind_transceiver().await;
+    client
+}
+
+/// Send a submit_sm and return the message_id from the response
+async fn submit_part(
+    client: &mut TestClient,
+    sequence_number: u32,
+    body: SubmitSmPdu,
+) -> String {
+    let pdu = Pdu::new(0, sequence_number, body.into()).unwrap();
+    client
+        .stream
+        .write_all(&pdu_bytes(&pdu).await)
+        .await
+        .unwrap();
+
+    let resp = client.read_pdu().await;
+    assert_eq!(resp.command_status.value, 0);
+    assert_eq!(resp.sequence_number.value, sequence_number);
+    match resp.body() {
+        PduBody::SubmitSmResp(body) => body.message_id().unwrap(),
+        _ => panic!("Expected submit_sm_resp but got {:?}", resp),
+    }
+}
+
+fn udh_part(
+    reference: u8,
+    total_parts: u8,
+    part_number: u8,
+    text: &[u8],
+) -> SubmitSmPdu {
+    let mut short_message =
+        vec![0x05, 0x00, 0x03, reference, total_parts, part_number];
+    short_message.extend(text);
+    submit_sm(ESM_CLASS_UDHI, &short_message, Tlvs::new())
+}
+
+fn sar_part(
+    reference: u16,
+    total_parts: u8,
+    part_number: u8,
+    text: &[u8],
+) -> SubmitSmPdu {
+    submit_sm(
+        0,
+        text,
+        Tlvs::from(&[
+            Tlv::new(KnownTlvTag::sar_msg_ref_num, &reference.to_be_bytes()),
+            Tlv::new(KnownTlvTag::sar_total_segments, &[total_parts]),
+            Tlv::new(KnownTlvTag::sar_segment_seqnum, &[part_number]),
+        ]),
+    )
+}
+
+fn submit_sm(esm_class: u8, short_message: &[u8], tlvs: Tlvs) -> SubmitSmPdu {
+    SubmitSmPdu::new(
+        "",
+        0,
+        0,
+        "MyCompany",
+        1,
+        1,
+        "447777222222",
+        esm_class,
+        0,
+        1,
+        "",
+        "",
+        1,
+        0,
+        0,
+        0,
+        short_message,
+        tlvs,
+    )
+    .unwrap()
+}
+
+fn dr_pdu(message_id: &str) -> Pdu {
+    Pdu::new(
+        0,
+        0x6d,
+        DeliverSmPdu::new(
+            "",
+            1,
+            1,
+            "447777222222",
+            0,
+            0,
+            "MyCompany",
+            DeliverEsmClass::SmscDeliveryReceipt as u8,
+            0,
+            1,
+            "",
+            "",
+            0,
+            0,
+            0,
+            0,
+            b"",
+            Tlvs::from(&[Tlv::new(
+                KnownTlvTag::receipted_message_id,
+                message_id.as_bytes(),
+            )]),
+        )
+        .unwrap()
+        .into(),
+    )
+    .unwrap()
+}
+
+async fn pdu_bytes(pdu: &Pdu) -> Vec<u8> {
+    let mut ret = Vec::new();
+    pdu.write(&mut ret).await.unwrap();
+    ret
+}
@@ -1,5 +1,10 @@
+use ascii::AsciiString;
 use async_trait::async_trait;
+use smpp::address::Address;
+use smpp::logging::{set_redaction, Redaction};
 use smpp::message_unique_key::MessageUniqueKey;
+use smpp::smpp_connection::EsmeId;
+use smpp::smsc::reassembly::PartsKey;
 use smpp::smsc::{BindData, BindError, Smsc, SmscLogic, SubmitSmError};
 use smpp_pdu::pdu::tlvs::{KnownTlvTag, Tlv, Tlvs};
 use smpp_pdu::pdu::{
@@ -11,7 +16,9 @@ use tokio::sync::Mutex;
 
 mod test_utils;
 
-use test_utils::{bytes_as_string, TestSetup};
+use test_utils::{
+    bytes_as_string, with_sequence_number, TestClient, TestServer, TestSetup,
+};
 
 #[tokio::test]
 async fn when_we_receive_deliver_sm_for_a_message_we_provide_it_to_client() {
@@ -40,6 +47,8 @@ async fn when_we_receive_deliver_sm_for_a_message_we_provide_it_to_client() {
         .await
         .unwrap();
 
+    // The first PDU the SMSC sends on this connection
+    let deliver_sm = with_sequence_number(&deliver_sm, 1);
     let resp = t.client.read_n(deliver_sm.len()).await;
     assert_eq!(bytes_as_string(&resp), bytes_as_string(&deliver_sm));
 }
@@ -75,10 +84,52 @@ async fn when_we_receive_deliver_sm_with_tlv_for_a_message_we_provide_it() {
         .await
         .unwrap();
 
+    // The first PDU the SMSC sends on this connection
+    let deliver_sm = with_sequence_number(&deliver_sm, 1);
     let resp = t.client.read_n(deliver_sm.len()).await;
     assert_eq!(bytes_as_string(&resp), bytes_as_string(&deliver_sm));
 }
 
+#[tokio::test]
+async fn deliver_sm_matches_even_if_supplier_formats_the_number_differently() {
+    let msgid = "ab87J";
+    let submit_sm = new_submit_sm(0x2f).await;
+    let submit_sm_resp = new_submit_sm_resp(0x2f, msgid).await;
+    let logic = Logic {
+        msgid: String::from(msgid),
+    };
+    let server = TestServer::start_with_logic_and_smsc_config(logic, |c| {
+        c.normalization_rules.country_code = Some(String::from("44"))
+    })
+    .await
+    .unwrap();
+    let mut client = TestClient::connect_to(&server).await.unwrap();
+    client.bind_transceiver().await;
+
+    client
+        .send_and_expect_response(&submit_sm, &submit_sm_resp)
+        .await;
+
+    // National format, with the national prefix
+    let deliver_sm_pdu = new_deliver_sm_pdu_from(
+        0,
+        1,
+        "07777222222",
+        format!("id:{} submit date:2103301649", msgid).as_bytes(),
+    );
+    let mut deliver_sm = Vec::new();
+    deliver_sm_pdu.write(&mut deliver_sm).await.unwrap();
+
+    server
+        .receive_pdu("testsystem", deliver_sm_pdu)
+        .await
+        .unwrap();
+
+    let deliver_sm = with_sequence_number(&deliver_sm, 1);
+    let resp = client.read_n(deliver_sm.len()).await;
+    assert_eq!(bytes_as_string(&resp), bytes_as_string(&deliver_sm));
+}
+
 struct Logic {
     msgid: String,
 }
@@ -97,28 +148,91 @@ impl SmscLogic for Logic {
     ) -> Result<(SubmitSmRespPdu, MessageUniqueKey), SubmitSmError> {
         Ok((
             SubmitSmRespPdu::new(&self.msgid).unwrap(),
-            MessageUniqueKey::new(
+            MessageUniqueKey::from_submit_sm(
                 String::from("testsystem"),
                 self.msgid.clone(),
-                pdu.destination_addr(),
+                pdu,
             ),
         ))
     }
 }
 
+#[tokio::test]
+async fn deliver_sm_for_an_unknown_message_is_an_error_that_hides_the_number() {
+    set_redaction(Redaction::Mask);
+    let server = TestServer::start().await.unwrap();
+
+    let error = server
+        .receive_pdu("ns", new_deliver_sm_pdu(b"id:unknown"))
+        .await
+        .unwrap_err()
+        .to_string();
+
+    assert!(error.contains("********2222"), "{}", error);
+    assert!(!error.contains("447777222222"), "{}", error);
+}
+
+#[test]
+fn addresses_in_routing_and_reassembly_logs_are_redacted() {
+    set_redaction(Redaction::Mask);
+
+    assert_eq!(
+        Address::new(1, 1, "447777222222").logged(),
+        "********2222 (TON=1 NPI=1)"
+    );
+    let key = PartsKey {
+        esme_id: EsmeId {
+            system_id: AsciiString::from_ascii("acme").unwrap(),
+            system_type: AsciiString::new(),
+        },
+        source_addr: String::from("447777111111"),
+        destination_addr: String::from("447777222222"),
+        reference: 7,
+    };
+    assert_eq!(
+        key.to_string(),
+        "from ********1111 (acme) to ********2222 with reference 7"
+    );
+}
+
 fn new_deliver_sm_pdu(short_message: &[u8]) -> Pdu {
     new_deliver_sm_pdu_with_tlvs(short_message, Tlvs::new())
 }
 
 fn new_deliver_sm_pdu_with_tlvs(short_message: &[u8], tlvs: Tlvs) -> Pdu {
+    new_deliver_sm_pdu_from_with_tlvs(0, 0, "447777222222", short_message, tlvs)
+}
+
+fn new_deliver_sm_pdu_from(
+    source_addr_ton: u8,
+    source_addr_npi: u8,
+    source_addr: &str,
+    short_message: &[u8],
+) -> Pdu {
+    new_deliver_sm_pdu_from_with_tlvs(
+        source_addr_ton,
+        source_addr_npi,
+        source_addr,
+        short_message,
+        Tlvs::new(),
+    )
+}
+
+fn new_deliver_sm_pdu_from_with_tlvs(
+    source_addr_ton: u8,
+    source_addr_npi: u8,
+    source_addr: &str,
+    short_message: &[u8],
+    tlvs: Tlvs,
+) -> Pdu {
     Pdu::new(
         0x00,
         0x6d,
         DeliverSmPdu::new(
             "",
-            0,
-            0,
-            "447777222222",
+            source_addr_ton,
+            source_addr_npi,
+            source_addr,
             0,
             0,
             "MyCompany",
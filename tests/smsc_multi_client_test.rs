@@ -7,11 +7,13 @@ use smpp_pdu::pdu::{
     SubmitSmRespPdu,
 };
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::Mutex;
+use tokio::time::sleep;
 
 mod test_utils;
 
-use test_utils::{TestClient, TestServer};
+use test_utils::{with_sequence_number, TestClient, TestServer};
 
 #[tokio::test]
 async fn when_multiple_clients_send_mts_we_deliver_drs_to_the_right_one() {
@@ -47,8 +49,10 @@ async fn when_multiple_clients_send_mts_we_deliver_drs_to_the_right_one() {
         .receive_pdu("multiclienttestsystem", dr(3))
         .await
         .unwrap();
-    // and it received it
-    client3.expect_to_receive(&write(dr(3)).await).await;
+    // and it received it, numbered in the SMSC's own sequence for client3
+    client3
+        .expect_to_receive(&with_sequence_number(&write(dr(3)).await, 1))
+        .await;
 
     // Then the others, and each goes to the client that sent the relevant MT
     server
@@ -65,9 +69,15 @@ async fn when_multiple_clients_send_mts_we_deliver_drs_to_the_right_one() {
         .unwrap();
 
     // Reading in clients out-of-order is fine
-    client2.expect_to_receive(&write(dr(2)).await).await;
-    client1.expect_to_receive(&write(dr(1)).await).await;
-    client2.expect_to_receive(&write(dr(4)).await).await;
+    client2
+        .expect_to_receive(&with_sequence_number(&write(dr(2)).await, 1))
+        .await;
+    client1
+        .expect_to_receive(&with_sequence_number(&write(dr(1)).await, 1))
+        .await;
+    client2
+        .expect_to_receive(&with_sequence_number(&write(dr(4)).await, 2))
+        .await;
 }
 
 #[tokio::test]
@@ -106,8 +116,44 @@ async fn when_client_disconnects_and_reconnects_they_can_receive_drs() {
         .unwrap();
 
     // And the clients receive them
-    client3.expect_to_receive(&write(dr(1)).await).await;
-    client2.expect_to_receive(&write(dr(2)).await).await;
+    client3
+        .expect_to_receive(&with_sequence_number(&write(dr(1)).await, 1))
+        .await;
+    client2
+        .expect_to_receive(&with_sequence_number(&write(dr(2)).await, 1))
+        .await;
+}
+
+#[tokio::test]
+async fn an_esme_can_bind_several_sessions_and_close_one() {
+    let logic = Logic::new(vec![1]);
+    let server = TestServer::start_with_logic_and_config(logic, 2)
+        .await
+        .unwrap();
+    let mut client2 = TestClient::connect_to(&server).await.unwrap();
+    {
+        let mut client1 = TestClient::connect_to(&server).await.unwrap();
+
+        // Two sessions with the same system_id
+        client1.bind_transceiver_as("client1").await;
+        client2.bind_transceiver_as("client1").await;
+
+        client1
+            .send_and_expect_response(&mt(1).await, &mt_resp(1).await)
+            .await;
+
+        // The first session closes because we let it go out of scope here
+    }
+    sleep(Duration::from_millis(100)).await;
+
+    // The second is still bound, so the DR goes to it
+    server
+        .receive_pdu("multiclienttestsystem", dr(1))
+        .await
+        .unwrap();
+    client2
+        .expect_to_receive(&with_sequence_number(&write(dr(1)).await, 1))
+        .await;
 }
 
 struct Logic {
@@ -0,0 +1,79 @@
+use smpp::extra_pdu::{ExtraPdu, ExtraPduBody};
+use smpp::smpp_connection::EsmeId;
+use smpp_pdu::pdu::{EnquireLinkPdu, Pdu};
+
+mod test_utils;
+
+use test_utils::TestSetup;
+
+#[tokio::test]
+async fn requests_we_send_use_our_own_sequence_numbers() {
+    let mut t = TestSetup::new().await;
+    t.client.bind_transceiver().await;
+    let esme_id = EsmeId {
+        system_id: "esmeid".parse().unwrap(),
+        system_type: "type".parse().unwrap(),
+    };
+
+    // Whatever sequence_number we supply is replaced
+    let enquire_link = Pdu::new(0, 0x07, EnquireLinkPdu::new().into()).unwrap();
+    let sequence_number = t
+        .server
+        .smsc
+        .lock()
+        .await
+        .send_request(&esme_id, enquire_link.into())
+        .await
+        .unwrap();
+    assert_eq!(sequence_number, 1);
+    t.client
+        .expect_to_receive(
+            b"\x00\x00\x00\x10\x00\x00\x00\x15\x00\x00\x00\x00\x00\x00\x00\x01",
+        )
+        .await;
+
+    // The ESME's enquire_link_resp is accepted without a response, and the
+    // connection stays open
+    t.client
+        .send_and_expect_response(
+            b"\x00\x00\x00\x10\x80\x00\x00\x15\x00\x00\x00\x00\x00\x00\x00\x01\
+            \x00\x00\x00\x10\x00\x00\x00\x15\x00\x00\x00\x00\x00\x00\x00\x07",
+            b"\x00\x00\x00\x10\x80\x00\x00\x15\x00\x00\x00\x00\x00\x00\x00\x07",
+        )
+        .await;
+
+    let unbind = ExtraPdu::new(0, 0x07, ExtraPduBody::Unbind).unwrap();
+    let sequence_number = t
+        .server
+        .smsc
+        .lock()
+        .await
+        .send_request(&esme_id, unbind.into())
+        .await
+        .unwrap();
+    assert_eq!(sequence_number, 2);
+    t.client
+        .expect_to_receive(
+            b"\x00\x00\x00\x10\x00\x00\x00\x06\x00\x00\x00\x00\x00\x00\x00\x02",
+        )
+        .await;
+}
+
+#[tokio::test]
+async fn requests_to_unknown_esmes_fail() {
+    let t = TestSetup::new().await;
+    let esme_id = EsmeId {
+        system_id: "nobody".parse().unwrap(),
+        system_type: "".parse().unwrap(),
+    };
+
+    let enquire_link = Pdu::new(0, 1, EnquireLinkPdu::new().into()).unwrap();
+    let result = t
+        .server
+        .smsc
+        .lock()
+        .await
+        .send_request(&esme_id, enquire_link.into())
+        .await;
+    assert!(result.is_err());
+}
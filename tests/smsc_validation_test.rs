@@ -0,0 +1,296 @@
+use smpp::concatenation::ESM_CLASS_UDHI;
+use smpp::sm_fields::SmFields;
+use smpp::smsc::{SubmitSmChecks, SubmitSmError};
+use smpp_pdu::pdu::tlvs::{KnownTlvTag, Tlv};
+use smpp_pdu::pdu::{Pdu, PduBody};
+use tokio::io::AsyncWriteExt;
+
+mod test_utils;
+
+use test_utils::{DefaultLogic, TestClient, TestServer};
+
+#[test]
+fn valid_messages_pass_all_checks() {
+    assert_eq!(SubmitSmChecks::all().validate(&submit_sm(&valid())), Ok(()));
+
+    let empty_source = SmFields {
+        source_addr: String::new(),
+        ..valid()
+    };
+    assert_eq!(
+        SubmitSmChecks::all().validate(&submit_sm(&empty_source)),
+        Ok(())
+    );
+
+    let alphanumeric_source = SmFields {
+        source_addr_ton: 5,
+        source_addr_npi: 0,
+        source_addr: String::from("MyCompany"),
+        ..valid()
+    };
+    assert_eq!(
+        SubmitSmChecks::all().validate(&submit_sm(&alphanumeric_source)),
+        Ok(())
+    );
+}
+
+#[test]
+fn checks_that_are_off_are_not_made() {
+    let fields = SmFields {
+        dest_addr_ton: 9,
+        priority_flag: 7,
+        ..valid()
+    };
+    assert_eq!(
+        SubmitSmChecks::default().validate(&submit_sm(&fields)),
+        Ok(())
+    );
+
+    let checks = SubmitSmChecks {
+        check_priority_flag: true,
+        ..SubmitSmChecks::default()
+    };
+    assert_eq!(
+        checks.validate(&submit_sm(&fields)),
+        Err(SubmitSmError::InvalidPriorityFlag)
+    );
+}
+
+#[test]
+fn bad_tons_and_npis_are_rejected() {
+    assert_invalid(
+        SmFields {
+            source_addr_ton: 7,
+            ..valid()
+        },
+        SubmitSmError::InvalidSourceTon,
+    );
+    assert_invalid(
+        SmFields {
+            source_addr_npi: 2,
+            ..valid()
+        },
+        SubmitSmError::InvalidSourceNpi,
+    );
+    assert_invalid(
+        SmFields {
+            dest_addr_ton: 7,
+            ..valid()
+        },
+        SubmitSmError::InvalidDestinationTon,
+    );
+    assert_invalid(
+        SmFields {
+            dest_addr_npi: 5,
+            ..valid()
+        },
+        SubmitSmError::InvalidDestinationNpi,
+    );
+}
+
+#[test]
+fn addresses_must_match_their_ton() {
+    assert_invalid(
+        SmFields {
+            source_addr: String::from("44700012312312312"),
+            ..valid()
+        },
+        SubmitSmError::InvalidSourceAddress,
+    );
+    assert_invalid(
+        SmFields {
+            source_addr_ton: 5,
+            source_addr: String::from("MyVeryLongCompany"),
+            ..valid()
+        },
+        SubmitSmError::InvalidSourceAddress,
+    );
+    assert_invalid(
+        SmFields {
+            destination_addr: String::from("4477ABC"),
+            ..valid()
+        },
+        SubmitSmError::InvalidDestinationAddress,
+    );
+    assert_invalid(
+        SmFields {
+            destination_addr: String::new(),
+            ..valid()
+        },
+        SubmitSmError::InvalidDestinationAddress,
+    );
+    assert_invalid(
+        SmFields {
+            dest_addr_ton: 5,
+            destination_addr: String::from("MyCompany"),
+            ..valid()
+        },
+        SubmitSmError::InvalidDestinationAddress,
+    );
+
+    let plus_prefixed = SmFields {
+        destination_addr: String::from("+447777222222"),
+        ..valid()
+    };
+    assert_eq!(
+        SubmitSmChecks::all().validate(&submit_sm(&plus_prefixed)),
+        Ok(())
+    );
+}
+
+#[test]
+fn message_length_depends_on_data_coding() {
+    let gsm = SmFields {
+        short_message: vec![b'a'; 160],
+        ..valid()
+    };
+    assert_eq!(SubmitSmChecks::all().validate(&submit_sm(&gsm)), Ok(()));
+    assert_invalid(
+        SmFields {
+            short_message: vec![b'a'; 161],
+            ..valid()
+        },
+        SubmitSmError::InvalidMessageLength,
+    );
+    assert_invalid(
+        SmFields {
+            data_coding: 4,
+            short_message: vec![b'a'; 141],
+            ..valid()
+        },
+        SubmitSmError::InvalidMessageLength,
+    );
+    assert_invalid(
+        SmFields {
+            data_coding: 8,
+            short_message: vec![0; 5],
+            ..valid()
+        },
+        SubmitSmError::InvalidMessageLength,
+    );
+
+    let mut with_udh = vec![0x05, 0x00, 0x03, 0x01, 0x02, 0x01];
+    with_udh.extend(vec![b'a'; 154]);
+    assert_invalid(
+        SmFields {
+            esm_class: ESM_CLASS_UDHI,
+            short_message: with_udh,
+            ..valid()
+        },
+        SubmitSmError::InvalidMessageLength,
+    );
+}
+
+#[test]
+fn short_message_and_message_payload_are_exclusive() {
+    let mut both = valid();
+    both.set_tlv(Tlv::new(KnownTlvTag::message_payload, b"hello"));
+    assert_invalid(both, SubmitSmError::InvalidMessageLength);
+
+    let mut payload_only = SmFields {
+        short_message: Vec::new(),
+        ..valid()
+    };
+    payload_only.set_tlv(Tlv::new(KnownTlvTag::message_payload, &[b'a'; 1000]));
+    assert_eq!(
+        SubmitSmChecks::all().validate(&submit_sm(&payload_only)),
+        Ok(())
+    );
+}
+
+#[test]
+fn esm_class_must_be_consistent() {
+    // UDHI set, but the UDH length runs past the end of the message
+    assert_invalid(
+        SmFields {
+            esm_class: ESM_CLASS_UDHI,
+            short_message: vec![0x09, 0x00, 0x03, 0x01],
+            ..valid()
+        },
+        SubmitSmError::InvalidEsmClass,
+    );
+    // Message type "delivery receipt" can't be submitted
+    assert_invalid(
+        SmFields {
+            esm_class: 0x04,
+            ..valid()
+        },
+        SubmitSmError::InvalidEsmClass,
+    );
+}
+
+#[test]
+fn priority_and_registered_delivery_must_be_in_range() {
+    assert_invalid(
+        SmFields {
+            priority_flag: 4,
+            ..valid()
+        },
+        SubmitSmError::InvalidPriorityFlag,
+    );
+    assert_invalid(
+        SmFields {
+            registered_delivery: 3,
+            ..valid()
+        },
+        SubmitSmError::InvalidRegisteredDelivery,
+    );
+    assert_invalid(
+        SmFields {
+            registered_delivery: 0x21,
+            ..valid()
+        },
+        SubmitSmError::InvalidRegisteredDelivery,
+    );
+}
+
+#[tokio::test]
+async fn invalid_submit_sm_gets_a_precise_error_code() {
+    let server =
+        TestServer::start_with_logic_and_smsc_config(DefaultLogic {}, |c| {
+            c.submit_sm_checks = SubmitSmChecks::all()
+        })
+        .await
+        .unwrap();
+    let mut client = TestClient::connect_to(&server).await.unwrap();
+    client.bind_transceiver().await;
+
+    let fields = SmFields {
+        destination_addr: String::from("not a number"),
+        ..valid()
+    };
+    let pdu = Pdu::new(0, 2, submit_sm(&fields).into()).unwrap();
+    let mut bytes = Vec::new();
+    pdu.write(&mut bytes).await.unwrap();
+    client.stream.write_all(&bytes).await.unwrap();
+
+    let resp = client.read_pdu().await;
+    assert_eq!(resp.command_status.value, 0x0000000B); // ESME_RINVDSTADR
+    assert_eq!(resp.sequence_number.value, 2);
+    assert!(matches!(resp.body(), PduBody::SubmitSmResp(_)));
+}
+
+fn assert_invalid(fields: SmFields, expected: SubmitSmError) {
+    assert_eq!(
+        SubmitSmChecks::all().validate(&submit_sm(&fields)),
+        Err(expected)
+    );
+}
+
+fn submit_sm(fields: &SmFields) -> smpp_pdu::pdu::SubmitSmPdu {
+    fields.to_submit_sm().unwrap()
+}
+
+fn valid() -> SmFields {
+    SmFields {
+        source_addr_ton: 1,
+        source_addr_npi: 1,
+        source_addr: String::from("447000123123"),
+        dest_addr_ton: 1,
+        dest_addr_npi: 1,
+        destination_addr: String::from("447777222222"),
+        registered_delivery: 1,
+        short_message: b"hello".to_vec(),
+        ..SmFields::default()
+    }
+}
@@ -1,18 +1,37 @@
 use async_trait::async_trait;
 use once_cell::sync::Lazy;
+use serde_json::Value;
+use smpp::address::NormalizationRules;
 use smpp::async_result::AsyncResult;
+use smpp::capture::CaptureConfig;
+use smpp::esme::{
+    BindType, DeliverSmError, EsmeClient, EsmeConfig, EsmeLogic,
+    UnacknowledgedPolicy,
+};
+use smpp::extra_pdu::AnyPdu;
+use smpp::faults::FaultConfig;
+use smpp::logging::{LogFormat, Redaction};
 use smpp::message_unique_key::MessageUniqueKey;
+use smpp::simulator::SimulatorConfig;
+use smpp::smpp_connection::SmppConnection;
 use smpp::smsc::{
-    BindData, BindError, Smsc, SmscConfig, SmscLogic, SubmitSmError,
+    BindData, BindError, Smsc, SmscConfig, SmscLogic, SubmitSmChecks,
+    SubmitSmError,
+};
+use smpp_pdu::pdu::{
+    BindTransceiverRespPdu, DeliverSmPdu, Pdu, PduBody, SubmitSmPdu,
+    SubmitSmRespPdu,
 };
-use smpp_pdu::pdu::{Pdu, SubmitSmPdu, SubmitSmRespPdu};
+use std::fs;
 use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
+use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::Mutex;
-use tokio::time::{sleep, Duration};
+use tokio::time::{sleep, timeout, Duration};
 
 const TEST_BIND_URL: &str = "127.0.0.1";
 
@@ -65,7 +84,13 @@ impl TestSetup {
 }
 
 fn next_port() -> usize {
-    return PORT.fetch_add(1, Ordering::Relaxed);
+    PORT.fetch_add(1, Ordering::Relaxed)
+}
+
+/// A local address that no other test in this binary is using
+#[allow(dead_code)]
+pub fn test_bind_address() -> String {
+    format!("{}:{}", TEST_BIND_URL, next_port())
 }
 
 /// A test server listening on the test port
@@ -92,19 +117,51 @@ impl TestServer {
     >(
         smsc_logic: L,
         max_open_sockets: usize,
+    ) -> AsyncResult<Self> {
+        TestServer::start_with_logic_and_smsc_config(smsc_logic, |config| {
+            config.max_open_sockets = max_open_sockets
+        })
+        .await
+    }
+
+    pub async fn start_with_logic_and_smsc_config<
+        L: SmscLogic + Send + Sync + 'static,
+        F: FnOnce(&mut SmscConfig),
+    >(
+        smsc_logic: L,
+        configure: F,
     ) -> AsyncResult<Self> {
         let _ = env_logger::builder()
             .filter_level(log::LevelFilter::Trace)
             .is_test(true)
             .try_init();
 
-        let bind_address = format!("{}:{}", TEST_BIND_URL, next_port());
+        let bind_address = test_bind_address();
 
-        let smsc_config = SmscConfig {
+        let mut smsc_config = SmscConfig {
             bind_address: String::from(&bind_address),
-            max_open_sockets,
+            max_open_sockets: 2,
             system_id: String::from("TestServer"),
+            concat_timeout_secs: None,
+            unbind_timeout_secs: 10,
+            submit_sm_checks: SubmitSmChecks::default(),
+            normalization_rules: NormalizationRules::default(),
+            capture: CaptureConfig::default(),
+            simulator: SimulatorConfig::default(),
+            faults: FaultConfig::default(),
+            config: None,
+            accounts_file: None,
+            script: None,
+            admin_address: None,
+            metrics_address: None,
+            log_level: String::from("info"),
+            log_format: LogFormat::Text,
+            log_redaction: Redaction::None,
+            additional_bind_addresses: Vec::new(),
+            accounts: Vec::new(),
+            routes: Vec::new(),
         };
+        configure(&mut smsc_config);
 
         let smsc = Smsc::start(smsc_config, smsc_logic).await.unwrap();
 
@@ -208,7 +265,7 @@ impl TestClient {
     }
 
     async fn send_exp(&mut self, input: &[u8], expected_output: &[u8]) {
-        self.stream.write(input).await.unwrap();
+        self.stream.write_all(input).await.unwrap();
         self.expect_to_receive(expected_output).await;
     }
 
@@ -262,6 +319,14 @@ impl TestClient {
         Ok(bytes)
     }
 
+    /// Read a whole PDU, whatever its length
+    pub async fn read_pdu(&mut self) -> Pdu {
+        let mut bytes = self.read_n(4).await;
+        let len = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        bytes.extend(self.read_n(len as usize - 4).await);
+        Pdu::parse(&mut io::Cursor::new(bytes)).unwrap()
+    }
+
     pub async fn read_n(&mut self, n: usize) -> Vec<u8> {
         self.read_n_maybe(n)
             .await
@@ -274,6 +339,16 @@ impl TestClient {
     }
 }
 
+/// A copy of the PDU in bytes, with its sequence_number replaced.  The
+/// SMSC numbers the PDUs it originates itself, so e.g. a DR reaches the
+/// ESME with a different sequence_number from the one the supplier used.
+#[allow(dead_code)]
+pub fn with_sequence_number(bytes: &[u8], sequence_number: u32) -> Vec<u8> {
+    let mut ret = bytes.to_vec();
+    ret[12..16].copy_from_slice(&sequence_number.to_be_bytes());
+    ret
+}
+
 #[allow(dead_code)]
 pub fn bytes_as_string(arr: &[u8]) -> String {
     arr.iter()
@@ -281,3 +356,209 @@ pub fn bytes_as_string(arr: &[u8]) -> String {
         .collect::<Vec<String>>()
         .join("")
 }
+
+/// A bare SMPP server for testing the ESME client.  It accepts connections
+/// and binds, then lets each test decide how to behave on each connection.
+pub struct FakeSmsc {
+    pub listener: TcpListener,
+    pub bind_address: String,
+}
+
+#[allow(dead_code)]
+impl FakeSmsc {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let bind_address = listener.local_addr().unwrap().to_string();
+        Self {
+            listener,
+            bind_address,
+        }
+    }
+
+    /// Connect an EsmeClient, and accept its connection
+    pub async fn connect(
+        &self,
+        config: EsmeConfig,
+    ) -> (EsmeClient, SmppConnection) {
+        let (client, connection) = tokio::join!(
+            EsmeClient::connect(config, NullEsmeLogic {}),
+            self.accept_and_bind(),
+        );
+        (client.unwrap(), connection)
+    }
+
+    pub async fn accept_and_bind(&self) -> SmppConnection {
+        let (tcp_stream, socket_addr) =
+            timeout(Duration::from_secs(5), self.listener.accept())
+                .await
+                .expect("Client did not connect")
+                .unwrap();
+        let connection = SmppConnection::new(tcp_stream, socket_addr);
+        let bind = connection.read_any_pdu().await.unwrap().unwrap();
+        assert_eq!(bind.command_id(), 0x00000009);
+        connection
+            .write_pdu(
+                &Pdu::new(
+                    0,
+                    bind.sequence_number(),
+                    BindTransceiverRespPdu::new("fakesmsc").unwrap().into(),
+                )
+                .unwrap(),
+            )
+            .await
+            .unwrap();
+        connection
+    }
+}
+
+/// Read a submit_sm, returning its sequence_number and destination_addr
+#[allow(dead_code)]
+pub async fn read_submit_sm(connection: &SmppConnection) -> (u32, String) {
+    match connection.read_any_pdu().await.unwrap().unwrap() {
+        AnyPdu::Pdu(pdu) => match pdu.body() {
+            PduBody::SubmitSm(body) => {
+                (pdu.sequence_number.value, body.destination_addr())
+            }
+            _ => panic!("Expected submit_sm but got {:?}", pdu),
+        },
+        other => panic!("Expected submit_sm but got {:?}", other),
+    }
+}
+
+#[allow(dead_code)]
+pub async fn write_submit_sm_resp(
+    connection: &SmppConnection,
+    sequence_number: u32,
+    message_id: &str,
+) {
+    connection
+        .write_pdu(
+            &Pdu::new(
+                0,
+                sequence_number,
+                SubmitSmRespPdu::new(message_id).unwrap().into(),
+            )
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+}
+
+#[allow(dead_code)]
+pub async fn respond_to_submit_sm(
+    connection: &SmppConnection,
+    message_id: &str,
+) {
+    let (sequence_number, _) = read_submit_sm(connection).await;
+    write_submit_sm_resp(connection, sequence_number, message_id).await;
+}
+
+#[allow(dead_code)]
+pub fn esme_config(smsc: &FakeSmsc) -> EsmeConfig {
+    EsmeConfig {
+        smsc_address: smsc.bind_address.clone(),
+        system_id: String::from("esmeid"),
+        password: String::from("password"),
+        system_type: String::new(),
+        bind_type: BindType::Transceiver,
+        enquire_link_interval_secs: 30,
+        enquire_link_timeout_secs: 10,
+        reconnect_initial_delay_ms: 10,
+        reconnect_max_delay_ms: 100,
+        unacknowledged_policy: UnacknowledgedPolicy::Fail,
+        window_size: 10,
+        response_timeout_secs: 30,
+        connect_timeout_secs: 10,
+    }
+}
+
+pub struct NullEsmeLogic {}
+
+#[async_trait]
+impl EsmeLogic for NullEsmeLogic {
+    async fn deliver_sm(
+        &mut self,
+        _pdu: &DeliverSmPdu,
+    ) -> Result<(), DeliverSmError> {
+        Ok(())
+    }
+}
+
+/// Make one HTTP/1.1 request, returning the status and JSON body
+#[allow(dead_code)]
+pub async fn http(
+    address: SocketAddr,
+    method: &str,
+    path: &str,
+    body: Option<Value>,
+) -> (u16, Value) {
+    let body = body.map(|b| b.to_string()).unwrap_or_default();
+    let (status, body) = http_text(address, method, path, &body).await;
+    (status, serde_json::from_str(&body).unwrap())
+}
+
+/// Make one HTTP/1.1 request, returning the status and body
+#[allow(dead_code)]
+pub async fn http_text(
+    address: SocketAddr,
+    method: &str,
+    path: &str,
+    body: &str,
+) -> (u16, String) {
+    let request = format!(
+        "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+        Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+        method,
+        path,
+        body.len(),
+        body
+    );
+    let mut stream = TcpStream::connect(address).await.unwrap();
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    (head[9..12].parse().unwrap(), String::from(body))
+}
+
+/// A file in the temp directory that is deleted when dropped
+#[allow(dead_code)]
+pub struct TempFile {
+    path: PathBuf,
+}
+
+#[allow(dead_code)]
+impl TempFile {
+    /// name must be unique within the test binary, and keeps its
+    /// extension so the file's format can be told from it
+    pub fn new(name: &str, contents: &str) -> Self {
+        let file = Self {
+            path: std::env::temp_dir().join(format!(
+                "smpp_test_{}_{}",
+                std::process::id(),
+                name
+            )),
+        };
+        file.write(contents);
+        file
+    }
+
+    pub fn write(&self, contents: &str) {
+        fs::write(&self.path, contents).unwrap();
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn path_str(&self) -> &str {
+        self.path.to_str().unwrap()
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}